@@ -0,0 +1,77 @@
+//! Batch commands read from the standard input.
+
+use std::time::Duration;
+
+use scs_servo::device::scs0009::{Scs0009ServoControl, SafeLimits, REGISTER_TARGET_POSITION_H, SPEED_ENCODING, WORD_ORDER};
+use scs_servo::device::{timeout_after, RawSpeed, ServoControl};
+use scs_servo::protocol::{BulkMaster, ProtocolMasterConfig, SyncWriteCommand, MAX_PACKET_SIZE};
+
+use scs_servo::transport::serialport::SerialPortStream;
+
+use crate::DeviceModel;
+
+/// Target Position, Target Period and Target Speed.
+const TARGET_LENGTH: usize = 6;
+
+/// A line of `set-position --stdin`: `id,ratio[,time][,speed]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionLine {
+    pub id: u8,
+    pub ratio: f64,
+    pub time: Option<f64>,
+    pub speed: Option<f64>,
+}
+
+pub fn parse_position_line(line: &str) -> Result<PositionLine, String> {
+    let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+    if !(2..=4).contains(&fields.len()) {
+        return Err("Expected id,ratio[,time][,speed]".to_string());
+    }
+    let optional = |index: usize| match fields.get(index) {
+        None | Some(&"") => Ok(None),
+        Some(field) => field.parse::<f64>().map(Some).map_err(|_| format!("Invalid number {}", field)),
+    };
+    Ok(PositionLine {
+        id: crate::id_in_range(fields[0])?,
+        ratio: crate::valid_ratio(fields[1])?,
+        time: optional(2)?,
+        speed: optional(3)?,
+    })
+}
+
+/// Moves the servos in `lines` to their positions with SYNC WRITE, so they start at the same time.
+/// The position limits of each servo are read first, and no position is written if any servo fails.
+pub fn set_positions(serial: &SerialPortStream, config: ProtocolMasterConfig, timeout: Duration, model: DeviceModel, apply_safe_defaults: bool, lines: &[PositionLine]) -> Result<(), String> {
+    let mut targets = Vec::with_capacity(lines.len());
+    for line in lines {
+        let mut servo_control = Scs0009ServoControl::<_, _, std::time::Instant>::new(line.id, serial.reader(), serial.writer(), config.clone(), timeout);
+        if apply_safe_defaults {
+            let limits = SafeLimits::conservative();
+            servo_control.apply_limits(&limits).map_err(|err| format!("ID {}: failed to apply safe limits: {:?}", line.id, err))?;
+            log::info!("Applied safe limits to servo {}: {:?}", line.id, limits);
+        }
+        let period = line.time.map_or(Ok(0), |time| crate::target_period(&servo_control, model, time)).map_err(|err| format!("ID {}: {}", line.id, err))?;
+        let speed = line.speed.map_or(Ok(0), |speed| crate::target_speed(&servo_control, model, speed)).map_err(|err| format!("ID {}: {}", line.id, err))?;
+        let lower_limit = servo_control.position_lower_limit().map_err(|err| format!("ID {}: failed to get lower limit: {:?}", line.id, err))? as f64;
+        let upper_limit = servo_control.position_upper_limit().map_err(|err| format!("ID {}: failed to get upper limit: {:?}", line.id, err))? as f64;
+        let position = ((upper_limit - lower_limit) * line.ratio + lower_limit) as u16;
+
+        let mut data = [0; TARGET_LENGTH];
+        data[0..2].copy_from_slice(&WORD_ORDER.to_bytes(position));
+        data[2..4].copy_from_slice(&WORD_ORDER.to_bytes(period));
+        data[4..6].copy_from_slice(&WORD_ORDER.to_bytes(RawSpeed::from_signed(speed, SPEED_ENCODING).0));
+        targets.push((line.id, data));
+    }
+
+    let mut master = BulkMaster::new(config);
+    let (mut reader, mut writer) = serial.split();
+    let commands = SyncWriteCommand::<{ MAX_PACKET_SIZE + 2 }>::split(REGISTER_TARGET_POSITION_H.address, TARGET_LENGTH, targets)
+        .map_err(|err| format!("Failed to encode the command: {:?}", err))?;
+    for command in commands {
+        let command = command.map_err(|err| format!("Failed to encode the command: {:?}", err))?;
+        master.sync_write(&mut reader, &mut writer, &command, timeout_after::<std::time::Instant>(timeout))
+            .map_err(|err| format!("Failed to write the positions: {:?}", err))?;
+        log::info!("Moved {} servos", command.count());
+    }
+    Ok(())
+}
@@ -2,7 +2,12 @@ use std::io::{Write};
 
 use clap::{Parser, Subcommand};
 use indicatif::{ProgressBar, ProgressStyle};
-use scs_servo::{device::{scs0009::Scs0009ServoControl, ServoControl}, protocol::ProtocolMasterConfig};
+use scs_servo::{device::{scs0009::Scs0009ServoControl, timeout_after, AngleScale, ServoControl}, protocol::{ProtocolMasterConfig, RetryPolicy}};
+use scs_servo::transport::serialport::SerialPortStream;
+
+mod batch;
+mod simulate;
+mod timing;
 
 
 #[derive(Debug, Parser)]
@@ -11,16 +16,29 @@ struct Cli {
     #[clap(subcommand)]
     subcommand: SubCommands,
 
-    #[clap(short, long, help = "The serial port to use")]
-    port: String,
+    #[clap(short, long, help = "The serial port to use", required_unless_present = "simulate")]
+    port: Option<String>,
+    #[clap(long, value_name = "COUNT", help = "Run against COUNT emulated servos with IDs from 1 instead of a serial port", num_args = 0..=1, require_equals = true, default_missing_value = "6", value_parser = clap::value_parser!(u8).range(1..=scs_servo::simulate::MAX_SIMULATED_SERVOS as i64))]
+    simulate: Option<u8>,
+    #[clap(long, value_name = "FILE", help = "Make the emulated servos fail as scripted in FILE", requires = "simulate")]
+    scenario: Option<String>,
     #[clap(short, long, help = "The baud rate to use", default_value = "1000000")]
     baud: u32,
     #[clap(short, long, help = "The serial adapter echoes back sent data", default_value = "false")]
     echo: bool,
-    #[clap(short, long, help = "The timeout in milliseconds every ID", default_value = "10")]
+    #[clap(short, long, help = "The timeout in milliseconds of each transaction", default_value = "10")]
     timeout_ms: u32,
+    #[clap(long, help = "Send a read or write again up to RETRIES times if its response is corrupted or missing", default_value = "0")]
+    retries: u8,
+    #[clap(long, help = "The time in milliseconds to wait before a retry", default_value = "0")]
+    retry_backoff_ms: u16,
+    #[clap(long, help = "Print where the time of each transaction is spent")]
+    verbose_timing: bool,
 }
 
+/// Time a read of the port waits for data. Short, so that transactions end at their deadlines.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
 #[derive(Debug, Clone)]
 enum Format {
     Raw,
@@ -46,9 +64,96 @@ enum DeviceModel {
     Scs0009,
 }
 
+impl DeviceModel {
+    fn angle_scale(self) -> AngleScale {
+        match self {
+            DeviceModel::Scs0009 => scs_servo::device::scs0009::ANGLE_SCALE,
+        }
+    }
+}
+
+/// Converts `speed` in deg/s to the target speed of `servo_control` and logs the speed which is actually written.
+fn target_speed<C: ServoControl<Id = u8, Speed = i16>>(servo_control: &C, model: DeviceModel, speed: f64) -> Result<i16, String> {
+    let scale = model.angle_scale();
+    let raw = servo_control.to_speed(speed).map_err(|_| {
+        let (min, max) = (scale.speed_to_degrees(servo_control.min_speed()), scale.speed_to_degrees(servo_control.max_speed()));
+        format!("Speed {} deg/s is out of the range of {:?}: {:.2} to {:.2} deg/s", speed, model, min, max)
+    })?;
+    log::info!("Servo {}: speed {} deg/s, writing {} ({:.2} deg/s)", servo_control.id(), speed, raw, scale.speed_to_degrees(raw));
+    Ok(raw)
+}
+
+/// Converts `time` in seconds to the target period of `servo_control` and logs the period which is actually written.
+fn target_period<C: ServoControl<Id = u8, Period = u16>>(servo_control: &C, model: DeviceModel, time: f64) -> Result<u16, String> {
+    let period = servo_control.to_period(time).map_err(|_| {
+        format!("Time {} s is out of the range of {:?}: 0 to {:.3} s", time, model, servo_control.max_period() as f64 / 1000.0)
+    })?;
+    log::info!("Servo {}: time {} s, writing {} ({:.3} s)", servo_control.id(), time, period, period as f64 / 1000.0);
+    Ok(period)
+}
+
 #[derive(Debug, Subcommand)]
 enum SubCommands {
-    Scan,
+    Scan {
+        #[clap(long, help = "Send a broadcast ping first. Only firmware which answers broadcast pings responds to it")]
+        broadcast: bool,
+        #[clap(long, help = "IDs to probe before the rest of the range, e.g. from a previous scan", value_delimiter = ',', value_parser = id_in_range)]
+        known: Vec<u8>,
+        #[clap(long, help = "Skip the rest of the range if every known ID answers")]
+        known_only: bool,
+        #[clap(long, help = "The file to save the servos found to")]
+        save: Option<String>,
+        #[clap(long, help = "A file saved by --save. The scan is skipped if all servos in it still answer")]
+        cached: Option<String>,
+    },
+    Doctor {
+        #[clap(long, help = "The number of status reads per servo", default_value = "20")]
+        transactions: usize,
+    },
+    Bench {
+        #[clap(long, help = "The servo IDs on the bus", required = true, value_delimiter = ',', value_parser = id_in_range)]
+        ids: Vec<u8>,
+        #[clap(long, help = "Only compute the bus budget with --turnaround-us instead of measuring the turnaround on the bus")]
+        plan: bool,
+        #[clap(long, help = "The telemetry reads per second of each servo", default_value = "100")]
+        telemetry_hz: f32,
+        #[clap(long, help = "The number of registers in each telemetry read", default_value = "8")]
+        telemetry_length: u8,
+        #[clap(long, help = "The commands per second to each servo", default_value = "50")]
+        command_hz: f32,
+        #[clap(long, help = "The number of registers in each command", default_value = "2")]
+        command_length: u8,
+        #[clap(long, help = "The servos do not answer writes")]
+        no_write_response: bool,
+        #[clap(long, help = "The turnaround of the servos in microseconds used with --plan", default_value = "100")]
+        turnaround_us: u64,
+        #[clap(long, help = "The number of status reads per servo to measure the turnaround", default_value = "50")]
+        transactions: usize,
+    },
+    SelfTest {
+        #[clap(long, help = "The servo IDs to test", required = true, value_delimiter = ',', value_parser = id_in_range)]
+        ids: Vec<u8>,
+        #[clap(long, help = "The lowest accepted supply voltage in 0.1 V", default_value = "45")]
+        min_voltage: u8,
+        #[clap(long, help = "The highest accepted supply voltage in 0.1 V", default_value = "70")]
+        max_voltage: u8,
+        #[clap(long, help = "The size of the test motion in position steps. 0 skips the motion", default_value = "20")]
+        motion: u16,
+    },
+    Monitor {
+        #[clap(long, help = "The servo IDs to watch", required = true, value_delimiter = ',', value_parser = id_in_range)]
+        ids: Vec<u8>,
+        #[clap(long, help = "The polling interval in seconds", value_parser = valid_sampling_interval, default_value = "0.1")]
+        interval: f64,
+        #[clap(long, help = "Stop after this many seconds. Runs until interrupted if omitted")]
+        duration: Option<f64>,
+        #[clap(long, help = "Exit with status 1 as soon as a watched servo sets an alarm")]
+        fail_on_alarm: bool,
+    },
+    Sniff {
+        #[clap(long, help = "Stop after this many seconds. Runs until interrupted if omitted")]
+        duration: Option<f64>,
+    },
     Read {
         #[clap(short, long, help = "The servo ID to read from", value_parser = id_in_range)]
         id: u8,
@@ -60,6 +165,8 @@ enum SubCommands {
         format: Format,
         #[clap(short, long, help = "The file to write the output to")]
         output: Option<String>,
+        #[clap(long, help = "Output only the hex string, without the decoded registers")]
+        raw: bool,
     },
     Write {
         #[clap(short, long, help = "The servo ID to write to", value_parser = id_in_range)]
@@ -72,16 +179,36 @@ enum SubCommands {
         format: Format,
         #[clap(short = 'r', long, help = "The file to read the input from")]
         input: Option<String>,
+        #[clap(long, help = "Read the registers back and check that they hold the data written")]
+        verify: bool,
     },
-    Control {
-        #[clap(short, long, help = "The servo ID", value_parser = id_in_range)]
+    WriteParams {
+        #[clap(short, long, help = "The servo ID to write the parameters to", value_parser = id_in_range)]
         id: u8,
+        #[clap(short, long, help = "The register address of the first byte in the image", default_value = "0", value_parser = clap_num::maybe_hex::<u8>)]
+        address: u8,
+        #[clap(short = 'r', long, help = "The raw parameter image to write")]
+        input: String,
+        #[clap(long, help = "The file to dump the recent bus transactions to if writing fails")]
+        event_log: Option<String>,
+    },
+    Control {
+        #[clap(short, long, help = "The servo ID. Not used by set-position --stdin", value_parser = id_in_range)]
+        id: Option<u8>,
         #[clap(short, long, help = "The device model")]
         model: DeviceModel,
+        #[clap(long, help = "Write conservative temperature, voltage and torque limits to the servo before the command")]
+        apply_safe_defaults: bool,
 
         #[clap(subcommand)]
         control: Control,
     },
+    Emulate {
+        #[clap(short, long, help = "The number of servos to emulate", default_value = "6", value_parser = clap::value_parser!(u8).range(1..=scs_servo::simulate::MAX_SIMULATED_SERVOS as i64))]
+        count: u8,
+        #[clap(short, long, help = "The ID of the first servo", default_value = "1", value_parser = id_in_range)]
+        base_id: u8,
+    },
 }
 
 fn valid_range(s: &str, min: f64, max: f64) -> Result<f64, String> {
@@ -112,8 +239,10 @@ enum Control {
         new_id: u8,
     },
     SetPosition {
-        #[clap(short, long, help = "The new position", value_parser = valid_ratio)]
-        position: f64,
+        #[clap(short, long, help = "The new position", value_parser = valid_ratio, required_unless_present = "stdin")]
+        position: Option<f64>,
+        #[clap(long, help = "Read lines of id,ratio[,time][,speed] from the standard input and move all the servos at once", conflicts_with_all = ["position", "time", "speed", "sampling_interval"])]
+        stdin: bool,
         #[clap(short, long, help = "The time to reach the position in seconds")]
         time: Option<f64>,
         #[clap(short, long, help = "The speed to reach the position in degrees per second")]
@@ -127,69 +256,353 @@ enum Control {
     },
 }
 
-struct SerialReader<'a> {
-    serial: &'a std::cell::RefCell<Box<dyn serialport::SerialPort>>
-}
-struct SerialWriter<'a> {
-    serial: &'a std::cell::RefCell<Box<dyn serialport::SerialPort>>,
-}
-impl<'a> scs_servo::protocol::StreamReader for SerialReader<'a> {
-    type Error = serialport::Error;
-    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
-        self.serial.borrow_mut().read(data).map_err(|err| nb::Error::Other(serialport::Error::from(err)))
-    }
-}
-impl<'a> scs_servo::protocol::StreamWriter for SerialWriter<'a> {
-    type Error = serialport::Error;
-    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
-        self.serial.borrow_mut().write(data).map_err(|err| nb::Error::Other(serialport::Error::from(err)))
-    }
-}
-
 fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
         .init();
     let cli = Cli::parse();
 
-    let serial = serialport::new(&cli.port, cli.baud)
-        .open()
-        .expect("Failed to open serial port");
-    let serial = std::cell::RefCell::new(serial);
-    serial.borrow_mut().set_timeout(std::time::Duration::from_millis(cli.timeout_ms as u64)).expect("Failed to set timeout");
-    let mut reader = SerialReader { serial: &serial };
-    let mut writer = SerialWriter { serial: &serial };
-    let config = scs_servo::protocol::ProtocolMasterConfig {
-        echo_back: cli.echo,
+    let port = cli.port.clone().unwrap_or_else(|| "simulated".to_string());
+    let serial: Box<dyn serialport::SerialPort> = match cli.simulate {
+        Some(_) if matches!(cli.subcommand, SubCommands::Emulate { .. }) => {
+            log::error!("emulate needs a serial port");
+            return;
+        }
+        Some(count) => {
+            let scenario = match &cli.scenario {
+                Some(path) => {
+                    let scenario = std::fs::read_to_string(path)
+                        .map_err(|err| format!("{:?}", err))
+                        .and_then(|text| scs_servo::scenario::Scenario::parse(&text).map_err(|err| format!("{:?}", err)));
+                    match scenario {
+                        Ok(scenario) => scenario,
+                        Err(err) => {
+                            log::error!("Failed to load {}: {}", path, err);
+                            return;
+                        }
+                    }
+                }
+                None => Default::default(),
+            };
+            Box::new(simulate::SimulatedPort::new(count as usize, cli.baud, scenario))
+        }
+        None => serialport::new(&port, cli.baud)
+            .open()
+            .expect("Failed to open serial port"),
     };
+    let serial = SerialPortStream::new(serial).expect("Failed to set timeout");
+    serial.set_poll_interval(POLL_INTERVAL).expect("Failed to set timeout");
+    let _timing = cli.verbose_timing.then(|| timing::TimingReport::new(&serial));
+    let (mut reader, mut writer) = serial.split();
+    let config = ProtocolMasterConfig::builder()
+        .echo_back(cli.echo)
+        .retry(RetryPolicy {
+            retries: cli.retries,
+            backoff_ms: cli.retry_backoff_ms,
+        })
+        .build();
 
     match cli.subcommand {
-        SubCommands::Scan => {
-            let mut master: scs_servo::protocol::ProtocolMaster<8> = scs_servo::protocol::ProtocolMaster::new(config);
-            log::info!("Scanning for servos on port {} at baud rate {}", &cli.port, cli.baud);
-            let progress_bar = ProgressBar::new(254);
+        SubCommands::Scan { broadcast, known, known_only, save, cached } => {
+            if let Some(cached) = &cached {
+                let inventory = std::fs::read_to_string(cached)
+                    .map_err(|err| format!("{:?}", err))
+                    .and_then(|json| serde_json::from_str::<scs_servo::inventory::Inventory>(&json).map_err(|err| format!("{}", err)));
+                match inventory {
+                    Ok(inventory) if inventory.is_for(&port, cli.baud) => {
+                        let bus_config = scs_servo::bus::BusConfig {
+                            master: config.clone(),
+                            timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                            mode: scs_servo::bus::BusMode::Normal,
+                        };
+                        let mut bus = scs_servo::bus::Bus::<_, _, std::time::Instant>::new(serial.reader(), serial.writer(), bus_config);
+                        match inventory.verify(&mut bus) {
+                            Ok(missing) if missing.is_empty() => {
+                                for servo in &inventory.servos {
+                                    match servo.firmware_version {
+                                        Some(version) => log::info!("Found servo with ID {} version {:02X} {:02X} (cached)", servo.id, version[0], version[1]),
+                                        None => log::info!("Found servo with ID {} (cached)", servo.id),
+                                    }
+                                }
+                                return;
+                            }
+                            Ok(missing) => log::warn!("Servos {:?} in {} did not answer. Scanning", missing, cached),
+                            Err(err) => {
+                                log::error!("Failed to verify {}: {:?}", cached, err);
+                                return;
+                            }
+                        }
+                    }
+                    Ok(inventory) => log::warn!("{} was saved on port {} at baud rate {}. Scanning", cached, inventory.port, inventory.baud_rate),
+                    Err(err) => log::warn!("Failed to load {}: {}. Scanning", cached, err),
+                }
+            }
+            log::info!("Scanning for servos on port {} at baud rate {}", &port, cli.baud);
+            let scan_config = scs_servo::scan::ScanConfig {
+                broadcast_ping: broadcast,
+                known_ids: scs_servo::protocol::IdSet::from_ids(&known),
+                known_only,
+                initial_timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                ..Default::default()
+            };
+            let progress_bar = ProgressBar::new(scan_config.ids.len() as u64);
             progress_bar.set_style(ProgressStyle::default_bar().template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}").unwrap());
             progress_bar.set_message("Scanning...");
 
-            for id in 1..254 {
-                let start = std::time::Instant::now();
-                let mut buffer = [0; 3];
-                match master.read_register(&mut reader, &mut writer, id, 0x03, &mut buffer, || start.elapsed().as_millis() > cli.timeout_ms as u128) {
+            let scanner_master_config = config.clone();
+            // A missing servo would be probed once for every retry.
+            let mut scanner: scs_servo::scan::Scanner<{ scs_servo::protocol::SMALL_BUFFER_SIZE }, std::time::Instant> = scs_servo::scan::Scanner::new(config.to_builder().retry(RetryPolicy::NONE).build(), scan_config);
+            let result = scanner.scan(&mut reader, &mut writer, |id, found| {
+                if !found {
+                    log::debug!("No response from ID {}", id);
+                }
+                progress_bar.inc(1);
+            });
+            progress_bar.finish_and_clear();
+            let found = match result {
+                Ok(found) => found,
+                Err(err) => {
+                    log::error!("Scan failed: {:?}", err);
+                    return;
+                }
+            };
+            let mut master = scs_servo::protocol::SmallMaster::new(scanner_master_config);
+            let mut inventory = scs_servo::inventory::Inventory::new(&port, cli.baud);
+            for id in found.iter() {
+                let mut buffer = [0; 2];
+                let firmware_version = match master.read_register(&mut reader, &mut writer, id, 0x03, &mut buffer, timeout_after::<std::time::Instant>(std::time::Duration::from_millis(cli.timeout_ms as u64))) {
                     Ok(_) => {
                         log::info!("Found servo with ID {} version {:02X} {:02X}", id, buffer[0], buffer[1]);
+                        Some(buffer)
                     }
                     Err(err) => {
-                        log::debug!("Err with ID {} {:?}", id, err);
+                        log::info!("Found servo with ID {} (failed to read version: {:?})", id, err);
+                        None
                     }
+                };
+                inventory.servos.push(scs_servo::inventory::InventoryEntry { id, model: None, firmware_version });
+            }
+            if let Some(save) = save {
+                let json = serde_json::to_string_pretty(&inventory).expect("Failed to serialize the inventory");
+                match std::fs::write(&save, json) {
+                    Ok(_) => log::info!("Saved {} servos to {}", inventory.servos.len(), save),
+                    Err(err) => log::error!("Error writing {}: {:?}", save, err),
+                }
+            }
+        },
+        SubCommands::Doctor { transactions } => {
+            log::info!("Diagnosing the bus on port {} at baud rate {}", &port, cli.baud);
+            let diagnose_config = scs_servo::diagnose::DiagnoseConfig {
+                baud_rate: cli.baud,
+                echo_back: cli.echo,
+                scan: scs_servo::scan::ScanConfig {
+                    initial_timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                    ..Default::default()
+                },
+                transactions,
+                ..Default::default()
+            };
+            let progress_bar = ProgressBar::new_spinner();
+            progress_bar.set_message("Diagnosing...");
+            progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
+            let result = scs_servo::diagnose::diagnose::<std::time::Instant, _, _, _>(&mut reader, &mut writer, |baud_rate| {
+                progress_bar.set_message(format!("Probing baud rate {}...", baud_rate));
+                if let Err(err) = serial.port().set_baud_rate(baud_rate) {
+                    log::error!("Failed to set baud rate {}: {:?}", baud_rate, err);
+                }
+            }, &diagnose_config);
+            progress_bar.finish_and_clear();
+            let report = match result {
+                Ok(report) => report,
+                Err(err) => {
+                    log::error!("Diagnosis failed: {:?}", err);
+                    return;
+                }
+            };
+            log::info!("Echo back: {}", if report.echo_back { "detected" } else { "not detected" });
+            match report.baud_rate {
+                Some(baud_rate) => log::info!("Servos answer at baud rate {}: {:?}", baud_rate, report.servos.iter().collect::<Vec<_>>()),
+                None => log::info!("No servo answers at any baud rate"),
+            }
+            if report.transactions > 0 {
+                log::info!("Transactions: {}, timeouts: {}, corrupted: {}", report.transactions, report.timeouts, report.corrupted);
+            }
+            if let Some((id, voltage)) = report.lowest_voltage {
+                log::info!("Lowest voltage: {:.1} V (ID {})", voltage as f64 / 10.0, id);
+            }
+            if report.is_healthy() {
+                log::info!("No problem found");
+            }
+            for cause in report.likely_causes() {
+                use scs_servo::diagnose::LikelyCause;
+                match cause {
+                    LikelyCause::TxRxSwapped => log::warn!("Nothing was received, not even an echo. Check that TX and RX are not swapped and the adapter is connected"),
+                    LikelyCause::NoServo => log::warn!("No servo answers. Check the power supply and the servo cable"),
+                    LikelyCause::EchoMismatch { detected: true } => log::warn!("The adapter echoes back the sent data. Use --echo"),
+                    LikelyCause::EchoMismatch { detected: false } => log::warn!("The adapter does not echo back the sent data. Remove --echo"),
+                    LikelyCause::WrongBaudRate { baud_rate } => log::warn!("The servos answer at baud rate {}. Use --baud {}", baud_rate, baud_rate),
+                    LikelyCause::DuplicateId => log::warn!("More than one servo answers ID {:?}. Connect them one by one and change their IDs", report.duplicate_ids.iter().collect::<Vec<_>>()),
+                    LikelyCause::PowerSag { id, voltage } => log::warn!("Supply voltage of ID {} is {:.1} V. Check the power supply", id, voltage as f64 / 10.0),
+                    LikelyCause::Noise { error_rate } => log::warn!("{:.1}% of the transactions failed. Check the wiring, shorten the bus or lower the baud rate", error_rate * 100.0),
                 }
-                progress_bar.inc(1);
             }
         },
-        SubCommands::Read { id, address, length, format, output } => {
+        SubCommands::Bench { ids, plan, telemetry_hz, telemetry_length, command_hz, command_length, no_write_response, turnaround_us, transactions } => {
+            let mut budget_config = scs_servo::budget::BudgetConfig {
+                baud_rate: cli.baud,
+                turnaround: std::time::Duration::from_micros(turnaround_us),
+                ..Default::default()
+            };
+            if !plan {
+                log::info!("Measuring the turnaround of {} servos on port {} at baud rate {}", ids.len(), &port, cli.baud);
+                let bus_config = scs_servo::bus::BusConfig {
+                    master: config,
+                    timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                    mode: scs_servo::bus::BusMode::Normal,
+                };
+                let mut bus = scs_servo::bus::Bus::<_, _, std::time::Instant>::new(reader, writer, bus_config);
+                let mut total = std::time::Duration::ZERO;
+                let mut max = std::time::Duration::ZERO;
+                let mut completed = 0u32;
+                let mut failed = 0usize;
+                for _ in 0..transactions {
+                    for &id in &ids {
+                        let start = std::time::Instant::now();
+                        match bus.read_status_block(id) {
+                            Ok(_) => {
+                                let elapsed = start.elapsed();
+                                total += elapsed;
+                                max = max.max(elapsed);
+                                completed += 1;
+                            }
+                            Err(err) => {
+                                log::debug!("Failed to read the status of ID {}: {:?}", id, err);
+                                failed += 1;
+                            }
+                        }
+                    }
+                }
+                if completed == 0 {
+                    log::error!("No servo answered");
+                    return;
+                }
+                let mean = total / completed;
+                let status = scs_servo::budget::Transaction::Read { length: scs_servo::device::StatusBlock::LENGTH as u8 };
+                let wire = scs_servo::budget::BudgetConfig { turnaround: std::time::Duration::ZERO, ..budget_config.clone() }.transaction_time(&status);
+                budget_config.turnaround = mean.saturating_sub(wire);
+                log::info!("Status reads: {}, failed: {}, mean: {} us, max: {} us, turnaround: {} us", completed, failed, mean.as_micros(), max.as_micros(), budget_config.turnaround.as_micros());
+            }
+            let subscription = scs_servo::budget::Subscription { servos: ids.len() as u8, length: telemetry_length, rate: telemetry_hz };
+            let command = scs_servo::budget::CommandStream { servos: ids.len() as u8, length: command_length, rate: command_hz, response: !no_write_response };
+            let result = scs_servo::budget::plan(&budget_config, &[subscription], &[command]);
+            log::info!("Bus utilization: {:.1}% (commands {:.1}%, telemetry {:.1}%, limit {:.1}%)", result.utilization() * 100.0, result.command_utilization * 100.0, result.telemetry_utilization * 100.0, result.max_utilization * 100.0);
+            if result.is_feasible() {
+                log::info!("The baud rate {} sustains the requested rates", cli.baud);
+            } else {
+                match result.suggested_interval(&subscription) {
+                    Some(interval) => log::warn!("The baud rate {} does not sustain the requested rates. Poll the telemetry of each servo every {:.1} ms ({:.1} Hz) or less often", cli.baud, interval.as_secs_f64() * 1000.0, result.suggested_rate(&subscription)),
+                    None => log::warn!("The commands alone exceed the bus budget at baud rate {}. Lower the command rate or raise the baud rate", cli.baud),
+                }
+            }
+        },
+        SubCommands::SelfTest { ids, min_voltage, max_voltage, motion } => {
+            log::info!("Testing {} servos on port {} at baud rate {}", ids.len(), &port, cli.baud);
+            let bus_config = scs_servo::bus::BusConfig {
+                master: config,
+                timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                mode: scs_servo::bus::BusMode::Normal,
+            };
+            let mut bus = scs_servo::bus::Bus::<_, _, std::time::Instant>::new(reader, writer, bus_config);
+            let test_config = scs_servo::selftest::SelfTestConfig {
+                min_voltage,
+                max_voltage,
+                motion,
+                ..Default::default()
+            };
+            let passed = scs_servo::selftest::self_test(&mut bus, &ids, &test_config, |report| {
+                let voltage = report.measured_voltage.map(|voltage| format!("{:.1} V", voltage as f64 / 10.0)).unwrap_or_else(|| "-".into());
+                let summary = format!("ID {}: communication {:?}, voltage {:?} ({}), torque {:?}, motion {:?}", report.id, report.communication, report.voltage, voltage, report.torque, report.motion);
+                if report.passed() {
+                    log::info!("{}", summary);
+                } else {
+                    log::error!("{}", summary);
+                }
+            });
+            if passed {
+                log::info!("All servos passed");
+            } else {
+                log::error!("Self-test failed");
+                std::process::exit(1);
+            }
+        },
+        SubCommands::Monitor { ids, interval, duration, fail_on_alarm } => {
+            log::info!("Monitoring {} servos on port {} at baud rate {}", ids.len(), &port, cli.baud);
+            let bus_config = scs_servo::bus::BusConfig {
+                master: config,
+                timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                mode: scs_servo::bus::BusMode::Normal,
+            };
+            let mut bus = scs_servo::bus::Bus::<_, _, std::time::Instant>::new(reader, writer, bus_config);
+            let interval = std::time::Duration::from_secs_f64(interval);
+            let duration = duration.map(std::time::Duration::from_secs_f64);
+            let mut previous_alarms = vec![0u8; ids.len()];
+            let start_time = std::time::Instant::now();
+            println!("elapsed,id,position,speed,load,voltage,temperature,alarms");
+            let mut next_poll = start_time;
+            while duration.is_none_or(|duration| start_time.elapsed() < duration) {
+                while std::time::Instant::now() < next_poll {}
+                next_poll += interval;
+                for (id, previous_alarms) in ids.iter().zip(previous_alarms.iter_mut()) {
+                    let status = match bus.read_status_block(*id) {
+                        Ok(status) => status,
+                        Err(err) => {
+                            log::warn!("ID {}: no status: {:?}", id, err);
+                            continue;
+                        }
+                    };
+                    let alarms = bus.response_status().map_or(0, |status| status.bits());
+                    let speed = status.speed.to_signed(scs_servo::device::scs0009::SPEED_ENCODING);
+                    let load = status.load.to_signed(scs_servo::device::scs0009::LOAD_ENCODING);
+                    println!("{},{},{},{},{},{},{},0x{:02x}", start_time.elapsed().as_secs_f64(), id, status.position, speed, load, status.voltage, status.temperature, alarms);
+                    let set = alarms & !*previous_alarms;
+                    *previous_alarms = alarms;
+                    if set != 0 {
+                        let decoded = scs_servo::device::scs0009::RegisterValue::Alarms(scs_servo::device::scs0009::AlarmFlags(set));
+                        if fail_on_alarm {
+                            log::error!("ID {}: alarm {}", id, decoded);
+                            std::process::exit(1);
+                        }
+                        log::warn!("ID {}: alarm {}", id, decoded);
+                    }
+                }
+            }
+        },
+        SubCommands::Sniff { duration } => {
+            log::info!("Listening on port {} at baud rate {}", &port, cli.baud);
+            let mut monitor = scs_servo::monitor::ProtocolMonitor::<std::time::Instant>::new(std::time::Duration::from_millis(cli.timeout_ms as u64));
+            let duration = duration.map(std::time::Duration::from_secs_f64);
+            let start_time = std::time::Instant::now();
+            let hex = |data: &[u8]| data.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+            println!("elapsed,id,request,response,latency_ms");
+            while duration.is_none_or(|duration| start_time.elapsed() < duration) {
+                let result = monitor.poll(&mut reader, |event| match event {
+                    scs_servo::monitor::MonitorEvent::Transaction(transaction) => {
+                        let response = transaction.response.and_then(|response| response.data().ok().map(hex)).unwrap_or_default();
+                        let latency = transaction.latency.map(|latency| format!("{:.3}", latency.as_secs_f64() * 1000.0)).unwrap_or_default();
+                        println!("{},{},{},{},{}", transaction.timestamp.as_secs_f64(), transaction.request.id_unchecked(), transaction.request.data().map(hex).unwrap_or_default(), response, latency);
+                    }
+                    scs_servo::monitor::MonitorEvent::Corrupted { timestamp, frame } => log::warn!("{}: corrupted frame {}", timestamp.as_secs_f64(), hex(frame)),
+                });
+                if let Err(err) = result {
+                    log::error!("Failed to read the port: {:?}", err);
+                    return;
+                }
+            }
+        },
+        SubCommands::Read { id, address, length, format, output, raw } => {
             let mut buffer = vec![0; length as usize];
-            let start = std::time::Instant::now();
-            let mut master: scs_servo::protocol::ProtocolMaster<8> = scs_servo::protocol::ProtocolMaster::new(config);
-            match master.read_register(&mut reader, &mut writer, id, address, &mut buffer, || start.elapsed().as_millis() > cli.timeout_ms as u128) {
+            let mut master = scs_servo::protocol::BulkMaster::new(config);
+            match master.read_register(&mut reader, &mut writer, id, address, &mut buffer, timeout_after::<std::time::Instant>(std::time::Duration::from_millis(cli.timeout_ms as u64))) {
                 Ok(_) => {
                     let output_writer = match output {
                         Some(path) => {
@@ -214,6 +627,11 @@ fn main() {
                         Format::Hex => {
                             let hex_string = hex::encode(&buffer);
                             output_writer.write_all(hex_string.as_bytes()).expect("Failed to write to output");
+                            if !raw {
+                                for register in scs_servo::device::scs0009::decode_registers(address, &buffer) {
+                                    write!(output_writer, "\n0x{:02x} {}: {}", register.address, register.name, register.value).expect("Failed to write to output");
+                                }
+                            }
                         }
                     }
                     println!();
@@ -223,7 +641,7 @@ fn main() {
                 }
             }
         },
-        SubCommands::Write { id, address, format, input } => {
+        SubCommands::Write { id, address, format, input, verify } => {
             let input_reader = match input {
                 Some(path) => {
                     match std::fs::File::open(path) {
@@ -252,45 +670,125 @@ fn main() {
                         Some((i, _)) => &buffer[..buffer.len() - i],
                         None => &buffer,
                     };
-                    hex::decode(&buffer).expect("Failed to decode hex")
+                    hex::decode(buffer).expect("Failed to decode hex")
                 }
             };
-            let start = std::time::Instant::now();
-            let mut command = scs_servo::protocol::WriteRegisterCommand::<260>::new(id, address, data.len());
-            {
-                let mut writer = command.writer();
-                writer.data_mut().unwrap()[2..2 + data.len()].copy_from_slice(&data);
-                writer.update_checksum().expect("Failed to update checksum");
-            }
-            let mut master: scs_servo::protocol::ProtocolMaster<8> = scs_servo::protocol::ProtocolMaster::new(config);
-            match master.write_register(&mut reader, &mut writer, &command, || start.elapsed().as_millis() > cli.timeout_ms as u128) {
+            type Command = scs_servo::protocol::WriteRegisterCommand<{ 2 + scs_servo::protocol::MAX_PACKET_SIZE }>;
+            let command = match Command::builder(id).address(address).data(&data).build() {
+                Ok(command) => command,
+                Err(_) => {
+                    log::error!("Too much data to write: {} bytes (max {} bytes)", data.len(), Command::MAX_LENGTH);
+                    return;
+                }
+            };
+            let mut master = scs_servo::protocol::BulkMaster::new(config);
+            let timeout = timeout_after::<std::time::Instant>(std::time::Duration::from_millis(cli.timeout_ms as u64));
+            let result = if verify {
+                master.write_register_verified(&mut reader, &mut writer, &command, timeout)
+            } else {
+                master.write_register(&mut reader, &mut writer, &command, timeout)
+            };
+            match result {
                 Ok(_) => {
                     log::info!("Wrote {} bytes to register {:02X} on servo {}", data.len(), address, id);
                 }
+                Err(scs_servo::protocol::ProtocolHandlerError::VerificationFailed { observed, length }) => {
+                    log::error!("The registers read back differ from the data written: {}", hex::encode(&observed[..length]));
+                }
                 Err(err) => {
                     log::error!("Error writing register: {:?}", err);
                 }
             }
         }
-        SubCommands::Control { id, model, control } => {
-            let _model = model; // Currently unused.
-            let mut servo_control = Scs0009ServoControl::<_, _, std::time::Instant>::new(id, reader, writer, ProtocolMasterConfig { echo_back: cli.echo }, std::time::Duration::from_secs(2));
+        SubCommands::WriteParams { id, address, input, event_log } => {
+            let image = match std::fs::read(&input) {
+                Ok(image) => image,
+                Err(err) => {
+                    log::error!("Error opening file: {:?}", err);
+                    return;
+                }
+            };
+            let bus_config = scs_servo::bus::BusConfig {
+                master: config,
+                timeout: std::time::Duration::from_millis(cli.timeout_ms as u64),
+                mode: scs_servo::bus::BusMode::Normal,
+            };
+            let mut bus = scs_servo::bus::Bus::<_, _, std::time::Instant>::new(reader, writer, bus_config);
+            let progress_bar = ProgressBar::new(0);
+            progress_bar.set_style(ProgressStyle::default_bar().template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}").unwrap());
+            let image = scs_servo::parameters::ParameterImage { address, data: &image };
+            let result = scs_servo::parameters::write_parameters(&mut bus, id, &scs_servo::device::scs0009::PARAMETER_MAP, &image, |progress| {
+                progress_bar.set_message(format!("{:?}", progress.stage));
+                progress_bar.set_length(progress.total as u64);
+                progress_bar.set_position(progress.done as u64);
+            });
+            progress_bar.finish_and_clear();
+            match result {
+                Ok(_) => log::info!("Wrote and verified parameters of servo {}", id),
+                Err(err) => {
+                    log::error!("Error writing parameters: {:?}", err);
+                    if let Some(event_log) = event_log {
+                        let mut dump = String::new();
+                        bus.events().dump(&mut dump).unwrap();
+                        match std::fs::write(&event_log, dump) {
+                            Ok(_) => log::info!("Dumped the recent bus transactions to {}", event_log),
+                            Err(err) => log::error!("Error writing the bus transactions: {:?}", err),
+                        }
+                    }
+                }
+            }
+        },
+        SubCommands::Control { id, model, apply_safe_defaults, control } => {
+            if let Control::SetPosition { stdin: true, .. } = control {
+                let mut lines = Vec::new();
+                for (number, line) in std::io::stdin().lines().enumerate() {
+                    let line = line.expect("Failed to read the standard input");
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match batch::parse_position_line(&line) {
+                        Ok(line) => lines.push(line),
+                        Err(err) => {
+                            log::error!("Line {}: {}", number + 1, err);
+                            return;
+                        }
+                    }
+                }
+                let timeout = std::time::Duration::from_millis(cli.timeout_ms as u64);
+                if let Err(err) = batch::set_positions(&serial, config.clone(), timeout, model, apply_safe_defaults, &lines) {
+                    log::error!("{}", err);
+                }
+                return;
+            }
+            let Some(id) = id else {
+                log::error!("--id is required");
+                return;
+            };
+            let mut servo_control = Scs0009ServoControl::<_, _, std::time::Instant>::new(id, reader, writer, config, std::time::Duration::from_secs(2));
+            if apply_safe_defaults {
+                let limits = scs_servo::device::scs0009::SafeLimits::conservative();
+                servo_control.apply_limits(&limits).expect("Failed to apply safe limits");
+                log::info!("Applied safe limits to servo {}: {:?}", id, limits);
+            }
             match control {
                 Control::SetId { new_id } => {
                     servo_control.set_id(new_id).expect("Failed to set ID");
                 }
-                Control::SetPosition { position, time, speed, sampling_interval , sampling_timeout, sampling_output} => {
-                    let period = match time {
-                        Some(time) => {
-                            servo_control.to_period(time).expect("Invalid time")
-                        },
-                        None => { 0 },
+                Control::SetPosition { position, time, speed, sampling_interval , sampling_timeout, sampling_output, .. } => {
+                    let position = position.expect("--position is required");
+                    let period = match time.map_or(Ok(0), |time| target_period(&servo_control, model, time)) {
+                        Ok(period) => period,
+                        Err(err) => {
+                            log::error!("{}", err);
+                            return;
+                        }
                     };
-                    let speed = match speed {
-                        Some(speed) => {
-                            servo_control.to_speed(speed).expect("Invalid speed")
-                        },
-                        None => { 0 },
+                    let speed = match speed.map_or(Ok(0), |speed| target_speed(&servo_control, model, speed)) {
+                        Ok(speed) => speed,
+                        Err(err) => {
+                            log::error!("{}", err);
+                            return;
+                        }
                     };
                     servo_control.set_target_period(period).expect("Failed to set period");
                     servo_control.set_target_speed(speed).expect("Failed to set speed");
@@ -343,5 +841,23 @@ fn main() {
             }
 
         }
+        SubCommands::Emulate { count, base_id } => {
+            if base_id as usize + count as usize - 1 > 253 {
+                log::error!("IDs of the emulated servos must be in 1..=253");
+                return;
+            }
+            log::info!("Emulating {} servos (ID {} to {}) on port {}", count, base_id, base_id + count - 1, &port);
+            let mut emulator = scs_servo::emulator::BusEmulator::<{ scs_servo::simulate::MAX_SIMULATED_SERVOS }>::new(base_id, count as usize);
+            let mut last_update = std::time::Instant::now();
+            loop {
+                if let Err(err) = emulator.process(&mut reader, &mut writer) {
+                    log::error!("Error processing bus traffic: {:?}", err);
+                    emulator.reset();
+                }
+                let now = std::time::Instant::now();
+                emulator.update(now.duration_since(last_update));
+                last_update = now;
+            }
+        }
     }
 }
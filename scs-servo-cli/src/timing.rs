@@ -0,0 +1,39 @@
+//! `--verbose-timing`: where the time of each transaction is spent.
+
+use scs_servo::transport::serialport::{SerialPortStream, TransportStats};
+
+/// Prints the timing of each transaction on the port, and the totals when dropped.
+pub struct TimingReport<'a> {
+    serial: &'a SerialPortStream,
+}
+
+impl<'a> TimingReport<'a> {
+    pub fn new(serial: &'a SerialPortStream) -> Self {
+        serial.take_stats();
+        serial.set_transaction_hook(Some(print_transaction));
+        Self { serial }
+    }
+}
+
+impl Drop for TimingReport<'_> {
+    fn drop(&mut self) {
+        self.serial.set_transaction_hook(None);
+        let stats = self.serial.stats();
+        log::info!(
+            "Total: {} writes of {} bytes in {:.3} ms, {} reads of {} bytes in {:.3} ms ({} without data)",
+            stats.writes, stats.bytes_written, ms(stats.write_time), stats.reads, stats.bytes_read, ms(stats.read_time), stats.empty_reads,
+        );
+    }
+}
+
+fn ms(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn print_transaction(stats: &TransportStats, elapsed: std::time::Duration) {
+    let other = elapsed.saturating_sub(stats.write_time + stats.read_time);
+    log::info!(
+        "Transaction {:.3} ms: wrote {} bytes in {:.3} ms, read {} bytes in {:.3} ms ({} reads without data), {:.3} ms in between",
+        ms(elapsed), stats.bytes_written, ms(stats.write_time), stats.bytes_read, ms(stats.read_time), stats.empty_reads, ms(other),
+    );
+}
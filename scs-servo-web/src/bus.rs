@@ -0,0 +1,107 @@
+//! Bus object for the interactive controls of the page.
+//!
+//! Sliders report a new position on every input event, much faster than the bus can write them. [`JsBus`] keeps only
+//! the latest target of each servo and writes the pending targets with a single SYNC WRITE at most once per
+//! interval, so the Web Serial write queue never backs up behind stale positions.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use scs_servo::device::scs0009::{REGISTER_TARGET_POSITION_H, WORD_ORDER};
+use scs_servo::device::timeout_after;
+use scs_servo::protocol::{BulkMaster, ProtocolMasterConfig, SyncWriteCommand, MAX_PACKET_SIZE};
+use wasm_bindgen::prelude::*;
+use wasm_streams::{ReadableStream, WritableStream};
+use web_sys::SerialPort;
+
+use crate::{delay_ms, JsProtocolMasterConfig, ReadableStreamWrapper, WebTimer, WritableStreamWrapper};
+
+/// Target Position.
+const TARGET_LENGTH: usize = 2;
+
+struct Shared {
+    port: SerialPort,
+    config: ProtocolMasterConfig,
+    interval_ms: Cell<u32>,
+    /// Latest target position of each servo which has not been written yet.
+    pending: RefCell<BTreeMap<u8, u16>>,
+    flushing: Cell<bool>,
+}
+
+#[wasm_bindgen]
+pub struct JsBus {
+    shared: Rc<Shared>,
+}
+
+#[wasm_bindgen]
+impl JsBus {
+    /// Writes throttled targets to `port` at most once every `interval_ms` milliseconds.
+    #[wasm_bindgen(constructor)]
+    pub fn new(port: SerialPort, config: JsProtocolMasterConfig, interval_ms: u32) -> Self {
+        let shared = Shared {
+            port,
+            config: config.into(),
+            interval_ms: Cell::new(interval_ms),
+            pending: RefCell::new(BTreeMap::new()),
+            flushing: Cell::new(false),
+        };
+        Self { shared: Rc::new(shared) }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn interval_ms(&self) -> u32 {
+        self.shared.interval_ms.get()
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_interval_ms(&self, interval_ms: u32) {
+        self.shared.interval_ms.set(interval_ms);
+    }
+
+    /// Number of servos whose latest target has not been written yet.
+    #[wasm_bindgen(getter)]
+    pub fn pending(&self) -> usize {
+        self.shared.pending.borrow().len()
+    }
+
+    /// Sets the target position of servo `id`. The first target after an idle interval is written immediately, later
+    /// ones replace the pending target of the servo and are written together at the end of the interval.
+    #[wasm_bindgen(js_name = setTargetThrottled)]
+    pub fn set_target_throttled(&self, id: u8, position: u16) {
+        self.shared.pending.borrow_mut().insert(id, position);
+        if !self.shared.flushing.replace(true) {
+            wasm_bindgen_futures::spawn_local(flush(self.shared.clone()));
+        }
+    }
+}
+
+async fn flush(shared: Rc<Shared>) {
+    loop {
+        let targets = core::mem::take(&mut *shared.pending.borrow_mut());
+        if targets.is_empty() {
+            shared.flushing.set(false);
+            return;
+        }
+        if let Err(err) = write_targets(&shared, &targets).await {
+            // A later target is written anyway, so the failure is not reported to the slider.
+            log::error!("Failed to write the targets: {:?}", err);
+        }
+        delay_ms(shared.interval_ms.get() as i32).await;
+    }
+}
+
+async fn write_targets(shared: &Shared, targets: &BTreeMap<u8, u16>) -> Result<(), JsValue> {
+    let mut reader = ReadableStreamWrapper::new(ReadableStream::from_raw(shared.port.readable()));
+    let mut writer = WritableStreamWrapper::new(WritableStream::from_raw(shared.port.writable()));
+    let mut master = BulkMaster::new(shared.config.clone());
+
+    let targets = targets.iter().map(|(id, position)| (*id, WORD_ORDER.to_bytes(*position)));
+    let commands = SyncWriteCommand::<{ MAX_PACKET_SIZE + 2 }>::split(REGISTER_TARGET_POSITION_H.address, TARGET_LENGTH, targets)
+        .map_err(|err| JsValue::from_str(&format!("Failed to encode the command: {:?}", err)))?;
+    for command in commands {
+        let command = command.map_err(|err| JsValue::from_str(&format!("Failed to encode the command: {:?}", err)))?;
+        master.sync_write_async(&mut reader, &mut writer, &command, timeout_after::<WebTimer>(core::time::Duration::from_millis(100))).await
+            .map_err(|err| JsValue::from_str(&format!("{:?}", err)))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! Bus inventory for the page to keep in IndexedDB.
+//!
+//! The page stores the JSON of a [`JsInventory`] per port and baud rate after a scan, and restores it on the next
+//! visit. [`verify_inventory`] pings the restored servos, so the scan is only repeated if one of them is missing.
+
+use scs_servo::device::timeout_after;
+use scs_servo::inventory::{Inventory, InventoryEntry};
+use scs_servo::protocol::{ProtocolHandlerError, ProtocolMasterConfig, ProtocolReaderError, SmallMaster};
+use wasm_bindgen::prelude::*;
+use wasm_streams::{ReadableStream, WritableStream};
+use web_sys::SerialPort;
+
+use crate::{JsProtocolMasterConfig, ReadableStreamWrapper, WebTimer, WritableStreamWrapper};
+
+#[wasm_bindgen]
+pub struct JsInventory {
+    inner: Inventory,
+}
+
+#[wasm_bindgen]
+impl JsInventory {
+    /// `port` identifies the serial port, e.g. from the USB vendor and product IDs, since Web Serial ports have no name.
+    #[wasm_bindgen(constructor)]
+    pub fn new(port: &str, baud_rate: u32) -> Self {
+        Self { inner: Inventory::new(port, baud_rate) }
+    }
+
+    /// Restores an inventory saved with `to_json`.
+    pub fn from_json(json: &str) -> Result<JsInventory, JsValue> {
+        serde_json::from_str(json)
+            .map(|inner| Self { inner })
+            .map_err(|err| JsValue::from_str(&format!("Invalid inventory - {}", err)))
+    }
+
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.inner).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn port(&self) -> String {
+        self.inner.port.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn baud_rate(&self) -> u32 {
+        self.inner.baud_rate
+    }
+
+    pub fn is_for(&self, port: &str, baud_rate: u32) -> bool {
+        self.inner.is_for(port, baud_rate)
+    }
+
+    /// Adds or replaces a servo with its version registers, as reported by `scan_servo`.
+    pub fn add_servo(&mut self, id: u8, version_h: u8, version_l: u8) {
+        let firmware_version = Some([version_h, version_l]);
+        match self.inner.servos.iter_mut().find(|servo| servo.id == id) {
+            Some(servo) => servo.firmware_version = firmware_version,
+            None => self.inner.servos.push(InventoryEntry { id, model: None, firmware_version }),
+        }
+    }
+
+    /// Names the model of servo `id`, which the servos do not report themselves.
+    pub fn set_model(&mut self, id: u8, model: Option<String>) {
+        if let Some(servo) = self.inner.servos.iter_mut().find(|servo| servo.id == id) {
+            servo.model = model;
+        }
+    }
+
+    pub fn model(&self, id: u8) -> Option<String> {
+        self.inner.servos.iter().find(|servo| servo.id == id).and_then(|servo| servo.model.clone())
+    }
+
+    pub fn ids(&self) -> Vec<u8> {
+        self.inner.servos.iter().map(|servo| servo.id).collect()
+    }
+}
+
+/// Pings the servos `ids` of an inventory and returns the IDs which did not answer.
+#[wasm_bindgen]
+pub async fn verify_inventory(port: SerialPort, config: JsProtocolMasterConfig, ids: Vec<u8>) -> Result<JsValue, JsValue> {
+    let mut reader = ReadableStreamWrapper::new(ReadableStream::from_raw(port.readable()));
+    let mut writer = WritableStreamWrapper::new(WritableStream::from_raw(port.writable()));
+
+    let config: ProtocolMasterConfig = config.into();
+    let mut master = SmallMaster::new(config);
+    let missing = js_sys::Array::new();
+    for id in ids {
+        match master.ping_async(&mut reader, &mut writer, id, timeout_after::<WebTimer>(core::time::Duration::from_millis(100))).await {
+            Ok(_) => {}
+            // Transport errors say nothing about the servos.
+            Err(ProtocolHandlerError::ReaderError(err)) | Err(ProtocolHandlerError::WriterError(err)) | Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(err))) => return Err(err),
+            Err(err) => {
+                log::info!("Servo {} in the inventory did not answer: {:?}", id, err);
+                master.reset();
+                missing.push(&JsValue::from_f64(id as f64));
+            }
+        }
+    }
+    Ok(missing.into())
+}
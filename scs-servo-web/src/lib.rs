@@ -1,25 +1,34 @@
+mod bus;
+mod inventory;
+mod telemetry;
 mod utils;
 
-use std::convert::TryFrom;
-use futures::{pin_mut, FutureExt};
-use web_time::{Duration, Instant, SystemTime};
+use futures::{pin_mut, FutureExt, StreamExt};
+use web_time::Instant;
 
-use js_sys::{Uint16Array, Uint8Array};
-use scs_servo::protocol::{ProtocolMaster, ProtocolMasterConfig, StreamReader, StreamReaderAsync, StreamWriterAsync, WriteRegisterCommand};
+use scs_servo::device::scs0009::Scs0009ServoControlAsync;
+use scs_servo::device::ServoControlAsync;
+use scs_servo::protocol::{ProtocolMasterConfig, StreamReaderAsync, StreamWriterAsync, SMALL_BUFFER_SIZE};
+use scs_servo::scan::{ScanConfig, Scanner};
 use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::{spawn_local, JsFuture};
+use wasm_bindgen_futures::JsFuture;
 
 use wasm_streams::{ReadableStream, WritableStream};
-use web_sys::{SerialPort, SerialOptions};
+use web_sys::SerialPort;
+
+pub use bus::JsBus;
+pub use inventory::{verify_inventory, JsInventory};
+pub use telemetry::{Channel, JsTelemetry, JsTelemetryFrame};
 
 #[wasm_bindgen]
 pub fn start() {
+    utils::set_panic_hook();
     wasm_logger::init(wasm_logger::Config::default());
 }
 
 pub async fn delay_ms(ms: i32) {
     let promise = js_sys::Promise::new(&mut |resolve, _| {
-        web_sys::Window::set_timeout_with_callback_and_timeout_and_arguments_0(&web_sys::window().unwrap(), &resolve, ms);
+        web_sys::Window::set_timeout_with_callback_and_timeout_and_arguments_0(&web_sys::window().unwrap(), &resolve, ms).ok();
     });
     let _ = JsFuture::from(promise).await;
 }
@@ -39,7 +48,7 @@ impl ReadableStreamWrapper {
 impl StreamReaderAsync for ReadableStreamWrapper {
     type Error = JsValue;
     async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
-        if data.len() == 0 {
+        if data.is_empty() {
             return Ok(0);
         }
         let bytes_remaining = self.buffer.len() - self.position;
@@ -53,7 +62,8 @@ impl StreamReaderAsync for ReadableStreamWrapper {
                     futures::select! {
                         result = read_future => {
                             if let Some(chunk) = result? {
-                                if let Ok(buffer) = js_sys::Uint8Array::try_from(chunk) {
+                                // The stream may yield other values than bytes, which are skipped.
+                                if let Ok(buffer) = chunk.dyn_into::<js_sys::Uint8Array>() {
                                     let length = buffer.length() as usize;
                                     let prev_len = self.buffer.len();
                                     self.buffer.resize(prev_len + length, 0);
@@ -68,7 +78,8 @@ impl StreamReaderAsync for ReadableStreamWrapper {
                     }
                 };
                 if timed_out {
-                    //self.stream.get_reader().cancel().await?;
+                    // The reader is released with the pending read. Cancelling the stream instead would close the
+                    // port for good, so the transaction is cancelled by the deadline or a `Cancellable` wrapper.
                     return Ok(0);
                 }
             }
@@ -76,7 +87,7 @@ impl StreamReaderAsync for ReadableStreamWrapper {
 
         let bytes_remaining = self.buffer.len() - self.position;
         if bytes_remaining == 0 {
-            return Ok(0);
+            Ok(0)
         } else if bytes_remaining < data.len() {
             data[..bytes_remaining].copy_from_slice(&self.buffer[self.position..]);
             self.position = 0;
@@ -88,6 +99,11 @@ impl StreamReaderAsync for ReadableStreamWrapper {
             Ok(data.len())
         }
     }
+    async fn purge(&mut self) -> Result<(), Self::Error> {
+        self.position = 0;
+        self.buffer.clear();
+        Ok(())
+    }
 }
 
 struct WritableStreamWrapper {
@@ -122,37 +138,53 @@ impl JsProtocolMasterConfig {
         Self { echo_back }
     }
 }
-impl Into<ProtocolMasterConfig> for JsProtocolMasterConfig {
-    fn into(self) -> ProtocolMasterConfig {
-        ProtocolMasterConfig {
-            echo_back: self.echo_back,
-        }
+impl From<JsProtocolMasterConfig> for ProtocolMasterConfig {
+    fn from(val: JsProtocolMasterConfig) -> Self {
+        ProtocolMasterConfig::builder()
+            .echo_back(val.echo_back)
+            .build()
+    }
+}
+
+/// `Timer` backed by the browser clock.
+struct WebTimer;
+struct WebInstant(Instant);
+
+impl scs_servo::device::Instant for WebInstant {
+    fn elapsed(&self) -> core::time::Duration {
+        self.0.elapsed()
     }
 }
 
+impl scs_servo::device::Timer for WebTimer {
+    type Instant = WebInstant;
+    fn now() -> Self::Instant {
+        WebInstant(Instant::now())
+    }
+}
+
+/// Scans the bus. `cb` is called with the ID, model and version of each servo as soon as it is found.
 #[wasm_bindgen]
 pub async fn scan_servo(port: SerialPort, config: JsProtocolMasterConfig, cb: &js_sys::Function) -> Result<JsValue, JsValue> {
     let mut reader = ReadableStreamWrapper::new(ReadableStream::from_raw(port.readable()));
     let mut writer = WritableStreamWrapper::new(WritableStream::from_raw(port.writable()));
     
     let config: ProtocolMasterConfig = config.into();
-    log::info!("echo_back: {}", config.echo_back);
-    let mut master = ProtocolMaster::<300>::new(config);
-    let mut found_ids = js_sys::Array::new();
-    for id in 1..254 {
-        cb.call1(&JsValue::null(), &JsValue::from_f64(id as f64)).ok();
-
-        log::info!("Scanning {}", id);
-        let start = Instant::now();
-        let mut timeout_counter = 0;
-        let mut buffer = [0; 3];
-        match master.read_register_async(&mut reader, &mut writer, id, 0x03, &mut buffer, || { start.elapsed().as_millis() > 10 }).await {
-            Ok(_) => {
-                found_ids.push(&JsValue::from_f64(id as f64));
-                log::info!("Found servo with ID {} version {:02X} {:02X}", id, buffer[0], buffer[1]);
+    log::info!("echo_back: {}", config.echo_back());
+    let mut scanner = Scanner::<SMALL_BUFFER_SIZE, WebTimer>::new(config, ScanConfig::default());
+    let found_ids = js_sys::Array::new();
+    let discoveries = scanner.discover_async(&mut reader, &mut writer);
+    pin_mut!(discoveries);
+    while let Some(discovered) = discoveries.next().await {
+        match discovered {
+            Ok(discovered) => {
+                log::info!("Found servo with ID {} version {:02X} {:02X}", discovered.id, discovered.model, discovered.version);
+                found_ids.push(&JsValue::from_f64(discovered.id as f64));
+                cb.call3(&JsValue::null(), &JsValue::from_f64(discovered.id as f64), &JsValue::from_f64(discovered.model as f64), &JsValue::from_f64(discovered.version as f64)).ok();
             }
             Err(err) => {
-                log::debug!("Err with ID {} {:?}", id, err);
+                log::error!("Scan failed: {:?}", err);
+                return Err(JsValue::from_str(&format!("{:?}", err)));
             }
         }
     }
@@ -161,34 +193,13 @@ pub async fn scan_servo(port: SerialPort, config: JsProtocolMasterConfig, cb: &j
 
 #[wasm_bindgen]
 pub async fn change_servo_id(port: SerialPort, config: JsProtocolMasterConfig, old_id: u8, new_id: u8) -> Result<JsValue, JsValue> {
-    let mut reader = ReadableStreamWrapper::new(ReadableStream::from_raw(port.readable()));
-    let mut writer = WritableStreamWrapper::new(WritableStream::from_raw(port.writable()));
-    
-    let mut master = ProtocolMaster::<300>::new(config.into());
-
-    // Unlock the EEPROM by writing 0 to register 0x30
-    let start = Instant::now();
-    let mut command = WriteRegisterCommand::<10>::new(old_id, 0x30, 1);
-    command.writer().data_mut().unwrap()[2] = 0;
-    command.writer().update_checksum().unwrap();
-    master.write_register_async(&mut reader, &mut writer, &command, || start.elapsed().as_millis() > 100).await
-        .map_err(|err| JsValue::from_str(&format!("Failed to unlocking the EEPROM - {:?}", err)))?;
-
-    // Write New ID to register 0x05
-    let start = Instant::now();
-    let mut command = WriteRegisterCommand::<10>::new(old_id, 0x05, 1);
-    command.writer().data_mut().unwrap()[2] = new_id;
-    command.writer().update_checksum().unwrap();
-    master.write_register_async(&mut reader, &mut writer, &command, || start.elapsed().as_millis() > 100).await
-        .map_err(|err| JsValue::from_str(&format!("Failed to updating ID register - {:?}", err)))?;
-
-    // Lock the EEPROM by writing 1 to register 0x30
-    let start = Instant::now();
-    let mut command = WriteRegisterCommand::<10>::new(new_id, 0x30, 1);
-    command.writer().data_mut().unwrap()[2] = 1;
-    command.writer().update_checksum().unwrap();
-    master.write_register_async(&mut reader, &mut writer, &command, || start.elapsed().as_millis() > 100).await
-        .map_err(|err| JsValue::from_str(&format!("Failed to locking the EEPROM - {:?}", err)))?;
+    let reader = ReadableStreamWrapper::new(ReadableStream::from_raw(port.readable()));
+    let writer = WritableStreamWrapper::new(WritableStream::from_raw(port.writable()));
+
+    // Unlocks the EEPROM, writes the new ID and locks the EEPROM again through the new ID.
+    let mut control = Scs0009ServoControlAsync::<_, _, WebTimer>::new(old_id, reader, writer, config.into(), core::time::Duration::from_millis(100));
+    control.set_id(new_id).await
+        .map_err(|err| JsValue::from_str(&format!("Failed to change the ID - {:?}", err)))?;
 
     Ok(JsValue::undefined())
 }
\ No newline at end of file
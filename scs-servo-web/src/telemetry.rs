@@ -0,0 +1,182 @@
+//! Telemetry for the charts of the page.
+//!
+//! Polling a few servos at a few hundred hertz would create thousands of JS objects per second if every sample was
+//! handed over on its own. [`JsTelemetry`] polls in the background and keeps the samples in Rust until the page takes
+//! them once per animation frame with [`JsTelemetry::take_frame`], as one `Float64Array` per channel.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use scs_servo::device::scs0009::{LOAD_ENCODING, REGISTER_CURRENT_POSITION_H, SPEED_ENCODING};
+use scs_servo::device::{timeout_after, StatusBlock};
+use scs_servo::protocol::{ProtocolHandlerError, ProtocolMasterConfig, ProtocolReaderError, SmallMaster};
+use wasm_bindgen::prelude::*;
+use wasm_streams::{ReadableStream, WritableStream};
+use web_sys::SerialPort;
+use web_time::Instant;
+
+use crate::{delay_ms, JsProtocolMasterConfig, ReadableStreamWrapper, WebTimer, WritableStreamWrapper};
+
+/// Rows kept while the page takes no frames, e.g. in a background tab. Older rows are dropped.
+const MAX_ROWS: usize = 4096;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Current position in steps.
+    Position = 0,
+    /// Current speed in steps/s, signed.
+    Speed = 1,
+    /// Current load, signed.
+    Load = 2,
+    /// Voltage in 0.1 V.
+    Voltage = 3,
+    /// Temperature in degC.
+    Temperature = 4,
+}
+
+const CHANNELS: usize = 5;
+
+/// Samples of the rows polled since the last frame. Samples of servos which did not answer are NaN.
+#[wasm_bindgen]
+pub struct JsTelemetryFrame {
+    time: Vec<f64>,
+    channels: Vec<[Vec<f64>; CHANNELS]>,
+}
+
+impl JsTelemetryFrame {
+    fn new(servos: usize) -> Self {
+        Self { time: Vec::new(), channels: (0..servos).map(|_| Default::default()).collect() }
+    }
+
+    fn push(&mut self, time: f64, statuses: &[Option<StatusBlock>]) {
+        if self.time.len() >= MAX_ROWS {
+            self.time.remove(0);
+            self.channels.iter_mut().flatten().for_each(|channel| { channel.remove(0); });
+        }
+        self.time.push(time);
+        for (channels, status) in self.channels.iter_mut().zip(statuses) {
+            let values = match status {
+                Some(status) => [
+                    status.position as f64,
+                    status.speed.to_signed(SPEED_ENCODING) as f64,
+                    status.load.to_signed(LOAD_ENCODING) as f64,
+                    status.voltage as f64,
+                    status.temperature as f64,
+                ],
+                None => [f64::NAN; CHANNELS],
+            };
+            for (channel, value) in channels.iter_mut().zip(values) {
+                channel.push(value);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl JsTelemetryFrame {
+    /// Number of rows in the frame.
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.time.len()
+    }
+
+    /// Start of each row in milliseconds since the start of the telemetry.
+    pub fn time(&self) -> js_sys::Float64Array {
+        js_sys::Float64Array::from(&self.time[..])
+    }
+
+    /// Samples of `channel` of the servo at `index` in the IDs of the telemetry.
+    pub fn channel(&self, index: usize, channel: Channel) -> Result<js_sys::Float64Array, JsValue> {
+        let channels = self.channels.get(index).ok_or_else(|| JsValue::from_str(&format!("No servo at index {}", index)))?;
+        Ok(js_sys::Float64Array::from(&channels[channel as usize][..]))
+    }
+}
+
+struct Shared {
+    ids: Vec<u8>,
+    period_ms: Cell<u32>,
+    epoch: Instant,
+    running: Cell<bool>,
+    frame: RefCell<JsTelemetryFrame>,
+}
+
+#[wasm_bindgen]
+pub struct JsTelemetry {
+    shared: Rc<Shared>,
+}
+
+#[wasm_bindgen]
+impl JsTelemetry {
+    /// Polls the status of `ids` every `period_ms` milliseconds once started. A zero period polls back to back.
+    #[wasm_bindgen(constructor)]
+    pub fn new(ids: Vec<u8>, period_ms: u32) -> Self {
+        let shared = Shared {
+            frame: RefCell::new(JsTelemetryFrame::new(ids.len())),
+            ids,
+            period_ms: Cell::new(period_ms),
+            epoch: Instant::now(),
+            running: Cell::new(false),
+        };
+        Self { shared: Rc::new(shared) }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn ids(&self) -> Vec<u8> {
+        self.shared.ids.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn running(&self) -> bool {
+        self.shared.running.get()
+    }
+
+    /// Starts polling `port` in the background until `stop` is called or the port fails.
+    pub fn start(&self, port: SerialPort, config: JsProtocolMasterConfig) {
+        if !self.shared.running.replace(true) {
+            wasm_bindgen_futures::spawn_local(poll(self.shared.clone(), port, config.into()));
+        }
+    }
+    /// Stops polling after the current row.
+    pub fn stop(&self) {
+        self.shared.running.set(false);
+    }
+
+    /// Takes the rows polled since the previous call, e.g. from `requestAnimationFrame`.
+    pub fn take_frame(&self) -> JsTelemetryFrame {
+        let servos = self.shared.ids.len();
+        self.shared.frame.replace(JsTelemetryFrame::new(servos))
+    }
+}
+
+async fn poll(shared: Rc<Shared>, port: SerialPort, config: ProtocolMasterConfig) {
+    let mut reader = ReadableStreamWrapper::new(ReadableStream::from_raw(port.readable()));
+    let mut writer = WritableStreamWrapper::new(WritableStream::from_raw(port.writable()));
+    let mut master = SmallMaster::new(config);
+    let mut statuses = vec![None; shared.ids.len()];
+
+    while shared.running.get() {
+        let start = shared.epoch.elapsed();
+        for (status, id) in statuses.iter_mut().zip(shared.ids.iter().copied()) {
+            let mut registers = [0; StatusBlock::LENGTH];
+            let result = master.read_register_async(&mut reader, &mut writer, id, REGISTER_CURRENT_POSITION_H.address, &mut registers, timeout_after::<WebTimer>(core::time::Duration::from_millis(100))).await;
+            *status = match result {
+                Ok(_) => Some(StatusBlock::from_registers(&registers)),
+                Err(ProtocolHandlerError::ReaderError(err)) | Err(ProtocolHandlerError::WriterError(err)) | Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(err))) => {
+                    log::error!("Telemetry stopped: {:?}", err);
+                    shared.running.set(false);
+                    return;
+                }
+                Err(_) => {
+                    master.reset();
+                    None
+                }
+            };
+        }
+        shared.frame.borrow_mut().push(start.as_secs_f64() * 1000.0, &statuses);
+
+        let elapsed = shared.epoch.elapsed() - start;
+        let period = core::time::Duration::from_millis(shared.period_ms.get() as u64);
+        // Yields to the page even when polling back to back.
+        delay_ms(period.saturating_sub(elapsed).as_millis() as i32).await;
+    }
+}
@@ -11,3 +11,24 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn inventory_round_trip() {
+    let mut inventory = scs_servo_web::JsInventory::new("1027:24577", 1_000_000);
+    inventory.add_servo(1, 0x05, 0x03);
+    inventory.set_model(1, Some("SCS0009".into()));
+    let restored = scs_servo_web::JsInventory::from_json(&inventory.to_json().unwrap()).unwrap();
+    assert!(restored.is_for("1027:24577", 1_000_000));
+    assert_eq!(restored.ids(), [1]);
+    assert_eq!(restored.model(1).as_deref(), Some("SCS0009"));
+}
+
+#[wasm_bindgen_test]
+fn telemetry_frame_before_start() {
+    let telemetry = scs_servo_web::JsTelemetry::new(vec![1, 2], 10);
+    assert!(!telemetry.running());
+    let frame = telemetry.take_frame();
+    assert_eq!(frame.length(), 0);
+    assert_eq!(frame.channel(1, scs_servo_web::Channel::Position).unwrap().length(), 0);
+    assert!(frame.channel(2, scs_servo_web::Channel::Position).is_err());
+}
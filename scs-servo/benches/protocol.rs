@@ -0,0 +1,128 @@
+//! Benchmarks of the frame encoder/decoder and the master transaction path.
+//!
+//! The control loop benchmark models a 1 kHz loop driving 12 servos: each cycle reads the status block
+//! (8 bytes) and writes the target position of every servo. It has to stay well below 1 ms of CPU time
+//! on the host for the same loop to fit on a Cortex-M0+.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use scs_servo::packet::{PacketReader, PacketWriter};
+use scs_servo::protocol::{ProtocolMaster, ProtocolMasterConfig, ProtocolReader, ReadRegisterCommand, StreamReader, StreamWriter, WriteRegisterCommand};
+
+const SERVOS: u8 = 12;
+
+struct SliceReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl StreamReader for SliceReader<'_> {
+    type Error = ();
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let length = data.len().min(self.data.len() - self.position);
+        if length == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        data[..length].copy_from_slice(&self.data[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Reader which returns at most one byte per call, like a UART without FIFO.
+struct ByteReader<'a> {
+    inner: SliceReader<'a>,
+}
+
+impl StreamReader for ByteReader<'_> {
+    type Error = ();
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let length = data.len().min(1);
+        self.inner.read(&mut data[..length])
+    }
+}
+
+struct NullWriter;
+
+impl StreamWriter for NullWriter {
+    type Error = ();
+    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+        Ok(data.len())
+    }
+}
+
+fn build_frame(id: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xff, 0xff, 0, 0];
+    frame.extend_from_slice(data);
+    frame.push(0);
+    let mut writer = PacketWriter::new(&mut frame[2..]);
+    writer.set_id(id).unwrap();
+    writer.set_length(data.len() as u8 + 1).unwrap();
+    writer.update_checksum().unwrap();
+    frame
+}
+
+fn status_response(id: u8) -> Vec<u8> {
+    build_frame(id, &[0x00, 0x01, 0xff, 0x00, 0x00, 0x00, 0x00, 0x46, 0x1e])
+}
+
+fn bench_packet(c: &mut Criterion) {
+    c.bench_function("encode_read_register", |b| {
+        b.iter(|| ReadRegisterCommand::new(black_box(1), black_box(0x38), black_box(8)))
+    });
+    c.bench_function("encode_write_register", |b| {
+        b.iter(|| {
+            let mut command = WriteRegisterCommand::<16>::new(black_box(1), 0x2a, 2);
+            command.body_mut().copy_from_slice(&black_box([0x01, 0x00]));
+            command.update_checksum().unwrap();
+            command
+        })
+    });
+    let frame = status_response(1);
+    c.bench_function("verify_checksum", |b| {
+        b.iter(|| PacketReader::new(black_box(&frame[2..])).verify_checksum().is_ok())
+    });
+}
+
+fn bench_reader(c: &mut Criterion) {
+    let mut stream = vec![0x00, 0xff, 0x12];
+    stream.extend(status_response(1));
+    let mut reader = ProtocolReader::<16>::new();
+    c.bench_function("parse_frame", |b| {
+        b.iter(|| {
+            let mut stream = SliceReader { data: black_box(&stream), position: 0 };
+            reader.read(&mut stream).unwrap()
+        })
+    });
+    c.bench_function("parse_frame_byte_by_byte", |b| {
+        b.iter(|| {
+            let mut stream = ByteReader { inner: SliceReader { data: black_box(&stream), position: 0 } };
+            while !reader.read(&mut stream).unwrap() {}
+        })
+    });
+}
+
+fn bench_control_loop(c: &mut Criterion) {
+    let mut responses = Vec::new();
+    for id in 1..=SERVOS {
+        responses.extend(status_response(id));
+        responses.extend(build_frame(id, &[0x00]));
+    }
+    let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+    c.bench_function("control_loop_12_servos", |b| {
+        b.iter(|| {
+            let mut reader = SliceReader { data: black_box(&responses), position: 0 };
+            let mut writer = NullWriter;
+            let mut status = [0u8; 8];
+            for id in 1..=SERVOS {
+                master.read_register(&mut reader, &mut writer, id, 0x38, &mut status, || false).unwrap();
+                let mut command = WriteRegisterCommand::<16>::new(id, 0x2a, 2);
+                command.body_mut().copy_from_slice(&status[..2]);
+                command.update_checksum().unwrap();
+                master.write_register(&mut reader, &mut writer, &command, || false).unwrap();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_packet, bench_reader, bench_control_loop);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+//! Moves a simulated servo to a quarter of its range and prints the position until it arrives.
+//!
+//! Run with `cargo run -p scs-servo --example simulated_move --features simulate`.
+
+use std::time::{Duration, Instant};
+
+use scs_servo::device::scs0009::Scs0009ServoControl;
+use scs_servo::device::ServoControl;
+use scs_servo::protocol::ProtocolMasterConfig;
+use scs_servo::simulate::Simulation;
+
+fn main() {
+    let (_simulation, reader, writer) = Simulation::start(1, 1);
+    let mut servo = Scs0009ServoControl::<_, _, Instant>::new(1, reader, writer, ProtocolMasterConfig::default(), Duration::from_millis(50));
+
+    let target = servo.position_upper_limit().expect("failed to read the limit") / 4;
+    servo.output_enable().expect("failed to enable the output");
+    // Take 500 ms to get there.
+    servo.set_target_period(500).expect("failed to set the period");
+    servo.set_target_position(target).expect("failed to set the target");
+
+    let start = Instant::now();
+    loop {
+        servo.update().expect("failed to read the status");
+        let position = servo.current_position().expect("no position");
+        println!("{:5} ms: position {}", start.elapsed().as_millis(), position);
+        if position == target {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    servo.output_disable().expect("failed to disable the output");
+}
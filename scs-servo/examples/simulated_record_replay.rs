@@ -0,0 +1,61 @@
+//! Records the motion of one simulated servo and replays it on another.
+//!
+//! Servo 1 is moved through a few waypoints while its position is sampled every 20 ms. The recorded
+//! positions are then sent to servo 2 at the same pace, so it follows the same trajectory.
+//!
+//! Run with `cargo run -p scs-servo --example simulated_record_replay --features simulate`.
+
+use std::time::{Duration, Instant};
+
+use scs_servo::bus::{Bus, BusConfig, BusMode};
+use scs_servo::device::scs0009::{REGISTER_TARGET_PERIOD_H, REGISTER_TARGET_POSITION_H, REGISTER_TORQUE_SWITCH};
+use scs_servo::protocol::ProtocolMasterConfig;
+use scs_servo::simulate::Simulation;
+
+const SAMPLE_PERIOD: Duration = Duration::from_millis(20);
+
+fn main() {
+    let (simulation, reader, writer) = Simulation::start(1, 2);
+    let config = BusConfig {
+        master: ProtocolMasterConfig::default(),
+        timeout: Duration::from_millis(50),
+        mode: BusMode::Normal,
+    };
+    let mut bus = Bus::<_, _, Instant>::new(reader, writer, config);
+    for id in [1, 2] {
+        bus.write_register(id, REGISTER_TORQUE_SWITCH.address, &[1]).expect("failed to enable the output");
+    }
+
+    // Record servo 1 moving through the waypoints, 300 ms each.
+    let mut recording = Vec::new();
+    for waypoint in [0x0280u16, 0x0180, 0x0200] {
+        bus.write_register(1, REGISTER_TARGET_PERIOD_H.address, &300u16.to_be_bytes()).expect("failed to set the period");
+        bus.write_register(1, REGISTER_TARGET_POSITION_H.address, &waypoint.to_be_bytes()).expect("failed to set the target");
+        loop {
+            let position = bus.read_status_block(1).expect("failed to read the status").position;
+            recording.push(position);
+            if position == waypoint {
+                break;
+            }
+            std::thread::sleep(SAMPLE_PERIOD);
+        }
+    }
+    println!("Recorded {} samples", recording.len());
+
+    // Replay on servo 2. Each sample is reached within one sample period.
+    bus.write_register(2, REGISTER_TARGET_PERIOD_H.address, &(SAMPLE_PERIOD.as_millis() as u16).to_be_bytes()).expect("failed to set the period");
+    for (index, position) in recording.iter().enumerate() {
+        bus.write_register(2, REGISTER_TARGET_POSITION_H.address, &position.to_be_bytes()).expect("failed to set the target");
+        std::thread::sleep(SAMPLE_PERIOD);
+        if index % 10 == 0 {
+            let replayed = bus.read_status_block(2).expect("failed to read the status").position;
+            println!("sample {:3}: recorded {:4} replayed {:4}", index, position, replayed);
+        }
+    }
+
+    // Let servo 2 settle on the last sample.
+    std::thread::sleep(Duration::from_millis(200));
+    drop(bus);
+    let emulator = simulation.stop();
+    println!("Final positions: servo 1 {} servo 2 {}", emulator.servo(1).unwrap().position(), emulator.servo(2).unwrap().position());
+}
@@ -0,0 +1,27 @@
+//! Scans a simulated bus of four servos and reads the software version of each one.
+//!
+//! Run with `cargo run -p scs-servo --example simulated_scan --features simulate`.
+
+use std::time::{Duration, Instant};
+
+use scs_servo::device::scs0009::REGISTER_VERSION_H;
+use scs_servo::protocol::{ProtocolMasterConfig, SmallMaster};
+use scs_servo::scan::{ScanConfig, Scanner};
+use scs_servo::simulate::Simulation;
+
+fn main() {
+    let (_simulation, mut reader, mut writer) = Simulation::start(1, 4);
+    let config = ProtocolMasterConfig::default();
+
+    let mut scanner = Scanner::<{ scs_servo::protocol::SMALL_BUFFER_SIZE }, Instant>::new(config.clone(), ScanConfig::default());
+    let found = scanner.scan(&mut reader, &mut writer, |_, _| {}).expect("the scan failed");
+
+    let mut master = SmallMaster::new(config);
+    for id in found.iter() {
+        let start = Instant::now();
+        let mut version = [0; 2];
+        master.read_register(&mut reader, &mut writer, id, REGISTER_VERSION_H.address, &mut version, || start.elapsed() > Duration::from_millis(10))
+            .expect("failed to read the version");
+        println!("Found servo with ID {} version {:02X} {:02X}", id, version[0], version[1]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Register map of a device on the bus.
+//!
+//! [`RegisterBank`] holds the registers of a device built on [`ProtocolSlave`](crate::protocol::ProtocolSlave),
+//! e.g. a custom sensor board or a servo emulator, with the access the master has to each of them.
+//! [`RegisterBank::handle`] answers PING, READ and WRITE, so it can be passed to
+//! [`ProtocolSlave::process`](crate::protocol::ProtocolSlave::process) as the packet handler:
+//!
+//! ```ignore
+//! slave.process(&mut reader, &mut writer, |packet, buffer| bank.handle(packet, buffer))?;
+//! ```
+//!
+//! With a [`NonVolatileStorage`] attached, the EEPROM-class registers survive a restart of the device:
+//!
+//! ```ignore
+//! let mut bank = RegisterBank::<0x40>::new(0x01).with_storage(flash, scs0009::REGISTER_LIST);
+//! bank.load()?;
+//! ```
+
+use core::ops::Range;
+
+use crate::device::{RegisterDefinition, RegisterStorage};
+use crate::packet::{PacketReader, PacketWriter};
+use crate::protocol::{ParsedInstruction, ServoStatusFlags, BROADCAST_ID};
+use crate::storage::NonVolatileStorage;
+
+/// Access the master has to a register.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permission {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Permission {
+    pub const NONE: Self = Self { read: false, write: false };
+    pub const READ_ONLY: Self = Self { read: true, write: false };
+    pub const READ_WRITE: Self = Self { read: true, write: true };
+}
+
+/// `SIZE` registers from address 0, all readable and writable until restricted with
+/// [`set_permission`](Self::set_permission). The EEPROM-class registers are persisted to `Storage`, if one is
+/// attached with [`with_storage`](Self::with_storage).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterBank<const SIZE: usize, Storage = ()> {
+    id: u8,
+    registers: [u8; SIZE],
+    readable: [u32; 8],
+    writable: [u32; 8],
+    eeprom: [u32; 8],
+    status: ServoStatusFlags,
+    storage: Storage,
+}
+
+impl<const SIZE: usize> RegisterBank<SIZE> {
+    const SIZE_CHECK: () = assert!(SIZE <= 256, "registers are addressed by 8 bits");
+
+    pub fn new(id: u8) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::SIZE_CHECK;
+        Self {
+            id,
+            registers: [0; SIZE],
+            readable: [u32::MAX; 8],
+            writable: [u32::MAX; 8],
+            eeprom: [0; 8],
+            status: ServoStatusFlags::default(),
+            storage: (),
+        }
+    }
+
+    /// Attaches `storage`, which keeps the registers of `definitions` stored in EEPROM. Writes of the master which
+    /// touch them are persisted before the response is sent. Call [`load`](RegisterBank::load) to restore them.
+    pub fn with_storage<Storage: NonVolatileStorage>(self, storage: Storage, definitions: &[RegisterDefinition]) -> RegisterBank<SIZE, Storage> {
+        let mut eeprom = [0u32; 8];
+        for definition in definitions.iter().filter(|definition| matches!(definition.storage, RegisterStorage::Eeprom)) {
+            eeprom[definition.address as usize / 32] |= 1 << (definition.address % 32);
+        }
+        RegisterBank {
+            id: self.id,
+            registers: self.registers,
+            readable: self.readable,
+            writable: self.writable,
+            eeprom,
+            status: self.status,
+            storage,
+        }
+    }
+}
+
+impl<const SIZE: usize, Storage: NonVolatileStorage> RegisterBank<SIZE, Storage> {
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    pub fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Loads the EEPROM-class registers from the storage, e.g. when the device starts.
+    pub fn load(&mut self) -> Result<(), Storage::Error> {
+        let eeprom = self.eeprom;
+        for address in (0..SIZE).filter(|address| is_set(&eeprom, *address)) {
+            self.storage.read(address as u8, &mut self.registers[address..address + 1])?;
+        }
+        Ok(())
+    }
+
+    /// Stores the EEPROM-class registers in `range` and commits them if any was written.
+    fn persist(&mut self, range: Range<usize>) -> Result<(), Storage::Error> {
+        let eeprom = self.eeprom;
+        let mut written = false;
+        for address in range.filter(|address| is_set(&eeprom, *address)) {
+            self.storage.write(address as u8, &self.registers[address..address + 1])?;
+            written = true;
+        }
+        if written {
+            self.storage.commit()?;
+        }
+        Ok(())
+    }
+
+    /// ID the device responds to.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+    pub fn set_id(&mut self, id: u8) {
+        self.id = id;
+    }
+
+    pub fn registers(&self) -> &[u8; SIZE] {
+        &self.registers
+    }
+    /// Registers as seen by the device, which may change any of them regardless of the permissions.
+    pub fn registers_mut(&mut self) -> &mut [u8; SIZE] {
+        &mut self.registers
+    }
+
+    /// Status flags sent in every response.
+    pub fn status(&self) -> ServoStatusFlags {
+        self.status
+    }
+    pub fn set_status(&mut self, status: ServoStatusFlags) {
+        self.status = status;
+    }
+
+    pub fn permission(&self, address: u8) -> Permission {
+        let (index, bit) = (address as usize / 32, 1 << (address % 32));
+        Permission { read: self.readable[index] & bit != 0, write: self.writable[index] & bit != 0 }
+    }
+    /// Sets the permission of `length` registers from `address`.
+    pub fn set_permission(&mut self, address: u8, length: usize, permission: Permission) {
+        for address in address as usize..(address as usize + length).min(0x100) {
+            let (index, bit) = (address / 32, 1 << (address % 32));
+            self.readable[index] = if permission.read { self.readable[index] | bit } else { self.readable[index] & !bit };
+            self.writable[index] = if permission.write { self.writable[index] | bit } else { self.writable[index] & !bit };
+        }
+    }
+
+    /// Returns the registers from `address` if all of them exist and are permitted.
+    fn range(&self, address: u8, length: usize, permitted: impl Fn(Permission) -> bool) -> Option<Range<usize>> {
+        let range = address as usize..address as usize + length;
+        (range.end <= SIZE && range.clone().all(|address| permitted(self.permission(address as u8)))).then_some(range)
+    }
+
+    /// Handles a packet received by a [`ProtocolSlave`](crate::protocol::ProtocolSlave) and writes the response to
+    /// `buffer`. Returns the length of the response, or `None` if there is none: the packet is addressed to
+    /// another device or broadcast, or accesses a register out of the bank or without the permission, which is
+    /// ignored as a whole. REG WRITE and ACTION are not supported. A write which cannot be persisted is not
+    /// answered either, so the master sees it fail, but the registers hold the data until the device restarts.
+    pub fn handle(&mut self, packet: &PacketReader, buffer: &mut [u8]) -> Option<usize> {
+        let id = packet.id().ok()?;
+        if id != self.id && id != BROADCAST_ID {
+            return None;
+        }
+        let response = match ParsedInstruction::parse(packet).ok()? {
+            ParsedInstruction::Ping => 0..0,
+            ParsedInstruction::ReadRegister { address, length } => self.range(address, length as usize, |permission| permission.read)?,
+            ParsedInstruction::WriteRegister { address, data } => {
+                let range = self.range(address, data.len(), |permission| permission.write)?;
+                self.registers[range.clone()].copy_from_slice(data);
+                self.persist(range).ok()?;
+                0..0
+            }
+            _ => return None,
+        };
+        if id == BROADCAST_ID {
+            return None;
+        }
+        self.write_response(buffer, response)
+    }
+
+    fn write_response(&self, buffer: &mut [u8], registers: Range<usize>) -> Option<usize> {
+        let length = registers.len() + 6;
+        if buffer.len() < length {
+            return None;
+        }
+        buffer[0] = 0xff;
+        buffer[1] = 0xff;
+        let mut writer = PacketWriter::new(&mut buffer[2..length]);
+        writer.set_length(registers.len() as u8 + 2).ok()?;
+        writer.set_id(self.id).ok()?;
+        let body = writer.data_mut().ok()?;
+        body[0] = self.status.bits();
+        body[1..].copy_from_slice(&self.registers[registers]);
+        writer.update_checksum().ok()?;
+        Some(length)
+    }
+}
+
+fn is_set(bits: &[u32; 8], address: usize) -> bool {
+    bits[address / 32] & (1 << (address % 32)) != 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::{ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, ProtocolSlave, ProtocolSlaveConfig, WriteRegisterCommand};
+    extern crate std;
+
+    #[test]
+    fn test_register_bank() {
+        let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
+        let (mut slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut bank = RegisterBank::<0x30>::new(0x01);
+            bank.registers_mut()[0x20..0x22].copy_from_slice(&[0x12, 0x34]);
+            bank.set_permission(0x20, 2, Permission::READ_ONLY);
+            bank.set_permission(0x28, 1, Permission::NONE);
+            bank.set_status(ServoStatusFlags(0x20));
+            let mut slave = ProtocolSlave::<64>::new(ProtocolSlaveConfig::default());
+            while !stop_clone.load(std::sync::atomic::Ordering::Relaxed) {
+                slave.process(&mut slave_reader, &mut slave_writer, |packet, buffer| bank.handle(packet, buffer)).unwrap();
+            }
+            bank
+        });
+
+        let mut master = ProtocolMaster::<64>::new(ProtocolMasterConfig::default());
+        let deadline = || {
+            let start = std::time::Instant::now();
+            move || start.elapsed() > std::time::Duration::from_millis(50)
+        };
+        let mut data = [0; 2];
+        let status = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x20, &mut data, deadline()).unwrap();
+        assert_eq!((data, status.overload()), ([0x12, 0x34], true));
+        let command = WriteRegisterCommand::<16>::builder(0x01).address(0x10).data(&[0x01, 0x02]).build().unwrap();
+        master.write_register(&mut master_reader, &mut master_writer, &command, deadline()).unwrap();
+
+        // Accesses without the permission or out of the bank are not answered.
+        let command = WriteRegisterCommand::<16>::builder(0x01).address(0x21).data(&[0x00]).build().unwrap();
+        let result = master.write_register(&mut master_reader, &mut master_writer, &command, deadline());
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x27, &mut data, deadline());
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2f, &mut data, deadline());
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        let bank = thread.join().unwrap();
+        assert_eq!(bank.registers()[0x10..0x12], [0x01, 0x02]);
+        assert_eq!(bank.registers()[0x20..0x22], [0x12, 0x34]);
+        assert_eq!(bank.permission(0x28), Permission::NONE);
+        assert_eq!(bank.permission(0x2f), Permission::READ_WRITE);
+    }
+
+    #[test]
+    fn test_register_bank_storage() {
+        use crate::device::scs0009::{REGISTER_ID, REGISTER_LIST, REGISTER_TORQUE_SWITCH};
+        use crate::storage::MemoryStorage;
+        let mut bank = RegisterBank::<0x40>::new(0x01).with_storage(MemoryStorage::<0x40>::new(), REGISTER_LIST);
+        let mut buffer = [0; 16];
+        // The ID is stored in EEPROM, the torque switch in RAM.
+        for (address, value) in [(REGISTER_ID.address, 0x03), (REGISTER_TORQUE_SWITCH.address, 0x01)] {
+            let command = WriteRegisterCommand::<16>::builder(0x01).address(address).data(&[value]).build().unwrap();
+            assert!(bank.handle(&command.reader(), &mut buffer).is_some());
+        }
+        assert_eq!(bank.storage().image()[REGISTER_ID.address as usize], 0x03);
+        assert_eq!(bank.storage().image()[REGISTER_TORQUE_SWITCH.address as usize], 0x00);
+
+        // A restarted device gets its EEPROM back.
+        let storage = MemoryStorage::from_image(*bank.storage().image());
+        let mut bank = RegisterBank::<0x40>::new(0x01).with_storage(storage, REGISTER_LIST);
+        bank.load().unwrap();
+        assert_eq!(bank.registers()[REGISTER_ID.address as usize], 0x03);
+        assert_eq!(bank.registers()[REGISTER_TORQUE_SWITCH.address as usize], 0x00);
+    }
+}
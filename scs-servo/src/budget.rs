@@ -0,0 +1,203 @@
+//! Bus bandwidth budgeting.
+//!
+//! The half-duplex bus carries one transaction at a time, so the telemetry and command rates a robot can
+//! sustain are bounded by the baud rate and the turnaround of the servos. [`plan`] sums the bus time of a set
+//! of periodic telemetry subscriptions and command streams, tells whether the configured baud rate sustains
+//! them, and suggests telemetry intervals which fit when it does not. Commands keep their rates; only the
+//! telemetry is slowed down.
+
+use core::time::Duration;
+
+use crate::protocol::{packet_size, write_command_size};
+
+/// Bits on the wire per byte with 8N1 framing.
+const BITS_PER_BYTE: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct BudgetConfig {
+    pub baud_rate: u32,
+    /// Time from the end of a request to the start of the response, including the return delay of the servo
+    /// and the latency of the adapter.
+    pub turnaround: Duration,
+    /// Fraction of the bus time which may be used. The rest absorbs retries and jitter.
+    pub max_utilization: f32,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: 1_000_000,
+            turnaround: Duration::from_micros(100),
+            max_utilization: 0.8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transaction {
+    /// READ of `length` registers.
+    Read { length: u8 },
+    /// WRITE of `length` registers. `response` is unset if the servos do not answer writes.
+    Write { length: u8, response: bool },
+}
+
+impl Transaction {
+    /// Size of the request including the markers.
+    pub fn request_size(&self) -> usize {
+        match self {
+            Transaction::Read { .. } => 2 + packet_size(2),
+            Transaction::Write { length, .. } => write_command_size(*length as usize),
+        }
+    }
+    /// Size of the response including the markers, or 0 if there is none.
+    pub fn response_size(&self) -> usize {
+        match self {
+            Transaction::Read { length } => 2 + packet_size(*length as usize),
+            Transaction::Write { response: true, .. } => 2 + packet_size(0),
+            Transaction::Write { response: false, .. } => 0,
+        }
+    }
+}
+
+/// Periodic read of `length` registers from each of `servos` servos.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subscription {
+    pub servos: u8,
+    pub length: u8,
+    /// Reads per second of each servo.
+    pub rate: f32,
+}
+
+impl Subscription {
+    pub fn transaction(&self) -> Transaction {
+        Transaction::Read { length: self.length }
+    }
+}
+
+/// Periodic write of `length` registers to each of `servos` servos.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandStream {
+    pub servos: u8,
+    pub length: u8,
+    /// Writes per second to each servo.
+    pub rate: f32,
+    /// Whether the servos answer the writes.
+    pub response: bool,
+}
+
+impl CommandStream {
+    pub fn transaction(&self) -> Transaction {
+        Transaction::Write { length: self.length, response: self.response }
+    }
+}
+
+impl BudgetConfig {
+    /// Bus time of a single transaction.
+    pub fn transaction_time(&self, transaction: &Transaction) -> Duration {
+        let bytes = (transaction.request_size() + transaction.response_size()) as u64;
+        let wire = Duration::from_nanos(bytes * BITS_PER_BYTE * 1_000_000_000 / self.baud_rate.max(1) as u64);
+        if transaction.response_size() > 0 { wire + self.turnaround } else { wire }
+    }
+
+    fn utilization(&self, transaction: &Transaction, servos: u8, rate: f32) -> f32 {
+        self.transaction_time(transaction).as_secs_f32() * servos as f32 * rate
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetPlan {
+    /// Fraction of the bus time used by the commands.
+    pub command_utilization: f32,
+    /// Fraction of the bus time used by the telemetry at the requested rates.
+    pub telemetry_utilization: f32,
+    pub max_utilization: f32,
+    /// Factor to scale the telemetry rates by so the total fits. 1 if the requested rates fit, 0 if the commands alone do not.
+    pub telemetry_scale: f32,
+}
+
+impl BudgetPlan {
+    /// Fraction of the bus time used at the requested rates.
+    pub fn utilization(&self) -> f32 {
+        self.command_utilization + self.telemetry_utilization
+    }
+    /// Whether the baud rate sustains the requested rates.
+    pub fn is_feasible(&self) -> bool {
+        self.utilization() <= self.max_utilization
+    }
+    /// Highest rate of `subscription` which fits, in reads per second of each servo.
+    pub fn suggested_rate(&self, subscription: &Subscription) -> f32 {
+        subscription.rate * self.telemetry_scale
+    }
+    /// Polling interval of `subscription` which fits, or `None` if no telemetry fits.
+    pub fn suggested_interval(&self, subscription: &Subscription) -> Option<Duration> {
+        let rate = self.suggested_rate(subscription);
+        if rate > 0.0 { Some(Duration::from_secs_f32(1.0 / rate)) } else { None }
+    }
+}
+
+/// Computes the bus utilization of `subscriptions` and `commands` and the telemetry rates which fit.
+pub fn plan(config: &BudgetConfig, subscriptions: &[Subscription], commands: &[CommandStream]) -> BudgetPlan {
+    let command_utilization = commands.iter()
+        .map(|command| config.utilization(&command.transaction(), command.servos, command.rate))
+        .sum::<f32>();
+    let telemetry_utilization = subscriptions.iter()
+        .map(|subscription| config.utilization(&subscription.transaction(), subscription.servos, subscription.rate))
+        .sum::<f32>();
+    let available = (config.max_utilization - command_utilization).max(0.0);
+    let telemetry_scale = if telemetry_utilization <= available {
+        1.0
+    } else {
+        available / telemetry_utilization
+    };
+    BudgetPlan {
+        command_utilization,
+        telemetry_utilization,
+        max_utilization: config.max_utilization,
+        telemetry_scale,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::StatusBlock;
+
+    #[test]
+    fn test_transaction_time() {
+        let config = BudgetConfig { baud_rate: 1_000_000, turnaround: Duration::from_micros(100), max_utilization: 0.8 };
+        let status = Transaction::Read { length: StatusBlock::LENGTH as u8 };
+        assert_eq!(status.request_size(), 8);
+        assert_eq!(status.response_size(), 14);
+        assert_eq!(config.transaction_time(&status), Duration::from_micros(220 + 100));
+        let position = Transaction::Write { length: 2, response: false };
+        assert_eq!(position.request_size(), 9);
+        assert_eq!(config.transaction_time(&position), Duration::from_micros(90));
+        let config = BudgetConfig { baud_rate: 115_200, ..config };
+        assert_eq!(config.transaction_time(&position), Duration::from_nanos(781_250));
+    }
+
+    #[test]
+    fn test_plan() {
+        let config = BudgetConfig::default();
+        let status = Subscription { servos: 12, length: StatusBlock::LENGTH as u8, rate: 100.0 };
+        let position = CommandStream { servos: 12, length: 2, rate: 100.0, response: true };
+        // 12 * 100 * (320us + 250us) = 68% of the bus.
+        let result = plan(&config, &[status], &[position]);
+        assert!(result.is_feasible());
+        assert_eq!(result.telemetry_scale, 1.0);
+        assert_eq!(result.suggested_interval(&status), Some(Duration::from_millis(10)));
+
+        let fast = Subscription { rate: 200.0, ..status };
+        let result = plan(&config, &[fast], &[position]);
+        assert!(!result.is_feasible());
+        assert!((result.utilization() - 1.068).abs() < 1e-3, "{}", result.utilization());
+        // The commands use 30%, so the telemetry gets 50% of the bus.
+        let rate = result.suggested_rate(&fast);
+        assert!((rate - 130.2).abs() < 0.1, "{}", rate);
+
+        let flood = CommandStream { rate: 1000.0, ..position };
+        let result = plan(&config, &[status], &[flood]);
+        assert_eq!(result.telemetry_scale, 0.0);
+        assert_eq!(result.suggested_interval(&status), None);
+    }
+}
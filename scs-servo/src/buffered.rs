@@ -0,0 +1,213 @@
+//! Ring-buffered reader.
+//!
+//! Transports such as USB serial adapters or Web Serial hand out the received bytes in chunks, often larger than the
+//! read of the [`ProtocolReader`](crate::protocol::ProtocolReader) which takes them. [`BufferedReader`] keeps the rest
+//! of a chunk for the next read, and lets the application look at the bytes received before they are taken, e.g. to
+//! skip the noise of a servo booting:
+//!
+//! ```ignore
+//! let mut reader = BufferedReader::<_, 64>::new(port.reader());
+//! while reader.fill().is_ok() && reader.get(0) != Some(0xff) {
+//!     reader.consume(1);
+//! }
+//! master.ping(&mut reader, &mut writer, id, timeout_after::<Instant>(timeout))?;
+//! ```
+
+use core::ops::Range;
+
+use crate::protocol::StreamReader;
+#[cfg(feature = "async")]
+use crate::protocol::StreamReaderAsync;
+
+/// Reader which buffers up to `N` bytes read from `R` in a ring buffer.
+pub struct BufferedReader<R, const N: usize> {
+    inner: R,
+    buffer: [u8; N],
+    // Index of the first byte buffered.
+    head: usize,
+    length: usize,
+}
+
+impl<R, const N: usize> BufferedReader<R, N> {
+    pub const fn new(inner: R) -> Self {
+        Self { inner, buffer: [0; N], head: 0, length: 0 }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+    /// The wrapped reader. The bytes buffered are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Number of bytes buffered.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+    pub fn is_full(&self) -> bool {
+        self.length == N
+    }
+
+    /// The byte buffered at `index` from the next one to be read, without taking it.
+    pub fn get(&self, index: usize) -> Option<u8> {
+        (index < self.length).then(|| self.buffer[(self.head + index) % N])
+    }
+    /// Copies the bytes buffered to `data` without taking them, and returns how many were copied.
+    pub fn peek(&self, data: &mut [u8]) -> usize {
+        let length = data.len().min(self.length);
+        let first = length.min(N - self.head);
+        data[..first].copy_from_slice(&self.buffer[self.head..self.head + first]);
+        data[first..length].copy_from_slice(&self.buffer[..length - first]);
+        length
+    }
+    /// Discards up to `count` bytes buffered, e.g. after looking at them with [`peek`](Self::peek).
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.length);
+        self.length -= count;
+        // Start over at the beginning, so that the next fill has the whole buffer in one piece.
+        self.head = if self.length == 0 { 0 } else { (self.head + count) % N };
+    }
+    /// Discards the bytes buffered, e.g. after a transaction failed.
+    pub fn clear(&mut self) {
+        self.consume(self.length);
+    }
+
+    /// The free part of the buffer which follows the bytes buffered without wrapping around.
+    fn free(&self) -> Range<usize> {
+        let tail = (self.head + self.length) % N;
+        let end = if tail < self.head || self.length == N { self.head } else { N };
+        tail..end
+    }
+
+    /// Takes bytes from the buffer to `data`, and returns how many were taken.
+    fn take(&mut self, data: &mut [u8]) -> usize {
+        let length = self.peek(data);
+        self.consume(length);
+        length
+    }
+}
+
+impl<R: StreamReader, const N: usize> BufferedReader<R, N> {
+    /// Reads what the wrapped reader has into the free part of the buffer, and returns the number of bytes added.
+    /// Returns zero without reading if the buffer is full.
+    pub fn fill(&mut self) -> nb::Result<usize, R::Error> {
+        if self.is_full() {
+            return Ok(0);
+        }
+        let free = self.free();
+        let bytes_read = self.inner.read(&mut self.buffer[free])?;
+        self.length += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+impl<R: StreamReader, const N: usize> StreamReader for BufferedReader<R, N> {
+    type Error = R::Error;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        if self.is_empty() {
+            self.fill()?;
+        }
+        if self.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(self.take(data))
+    }
+    /// Discards the bytes buffered and those of the wrapped reader.
+    fn purge(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        self.inner.purge()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: StreamReaderAsync, const N: usize> BufferedReader<R, N> {
+    /// Same as [`Self::fill`] with an async reader, which returns zero if no byte arrived in time.
+    pub async fn fill_async(&mut self) -> Result<usize, R::Error> {
+        if self.is_full() {
+            return Ok(0);
+        }
+        let free = self.free();
+        let bytes_read = self.inner.read(&mut self.buffer[free]).await?;
+        self.length += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: StreamReaderAsync, const N: usize> StreamReaderAsync for BufferedReader<R, N> {
+    type Error = R::Error;
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.is_empty() {
+            self.fill_async().await?;
+        }
+        Ok(self.take(data))
+    }
+    async fn purge(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        self.inner.purge().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use super::*;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// Reader which returns the chunks as they were received.
+    struct ChunkReader(VecDeque<Vec<u8>>);
+    impl StreamReader for ChunkReader {
+        type Error = ();
+        fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+            let Some(chunk) = self.0.front_mut() else {
+                return Err(nb::Error::WouldBlock);
+            };
+            let length = data.len().min(chunk.len());
+            data[..length].copy_from_slice(&chunk[..length]);
+            chunk.drain(..length);
+            if chunk.is_empty() {
+                self.0.pop_front();
+            }
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn test_buffered_reader() {
+        let chunks = VecDeque::from([std::vec![1, 2, 3, 4, 5, 6], std::vec![7, 8, 9, 10]]);
+        let mut reader = BufferedReader::<_, 8>::new(ChunkReader(chunks));
+        let mut data = [0; 2];
+        assert_eq!(reader.read(&mut data), Ok(2));
+        assert_eq!(data, [1, 2]);
+        // The rest of the chunk is kept for the next reads.
+        assert_eq!(reader.len(), 4);
+        assert_eq!((reader.get(0), reader.get(3), reader.get(4)), (Some(3), Some(6), None));
+
+        // The next chunk wraps around the end of the buffer.
+        assert_eq!(reader.fill(), Ok(2));
+        assert_eq!(reader.fill(), Ok(2));
+        assert!(reader.is_full());
+        assert_eq!(reader.fill(), Ok(0));
+        let mut data = [0; 8];
+        assert_eq!(reader.peek(&mut data), 8);
+        assert_eq!(data, [3, 4, 5, 6, 7, 8, 9, 10]);
+        reader.consume(3);
+        assert_eq!(reader.read(&mut data), Ok(5));
+        assert_eq!(data[..5], [6, 7, 8, 9, 10]);
+        assert_eq!(reader.read(&mut data), Err(nb::Error::WouldBlock));
+
+        // Bytes read after the buffer was emptied start at its beginning again.
+        reader.inner_mut().0.push_back(std::vec![0xff; 8]);
+        assert_eq!(reader.fill(), Ok(8));
+        reader.clear();
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,657 @@
+//! Bus layer which owns the transport and the master.
+//!
+//! [`Bus`] bundles the reader, the writer, a [`ProtocolMaster`] and the timeout, so the callers only
+//! pass IDs and register addresses. It can be switched to [`BusMode::FireAndForget`] for setups where
+//! every servo has its response disabled: commands are then streamed without waiting for responses,
+//! paced by a minimum interval, and every operation which needs a response fails with
+//! [`ProtocolHandlerError::ResponsesDisabled`].
+//!
+//! Every write is checked against the [`WritePolicy`] of the bus first.
+//!
+//! The last [`EVENT_LOG_SIZE`] transactions are kept in an [`EventLog`] for post-mortem analysis.
+//!
+//! The volatile configuration written to up to [`SHADOW_SIZE`] servos is kept in a [`ShadowCache`] and written
+//! back when a servo comes back from a restart, see [`crate::recovery`].
+//!
+//! On noisy lines, a [`LinkGuard`] can be enabled to distrust responses after a burst of corrupted frames,
+//! see [`crate::link`].
+//!
+//! [`Bus::check_id_conflict`] tells whether more than one servo answers an ID, the most common cause of a bus on
+//! which nothing can be read reliably.
+
+use core::marker::PhantomData;
+use core::time::Duration;
+
+use crate::device::{timeout_after, Instant, StatusBlock, Timer};
+use crate::device::scs0009::{REGISTER_CURRENT_POSITION_H, REGISTER_VERSION_H, VOLATILE_REGISTERS};
+use crate::eventlog::{BusEvent, EventLog, Operation, Outcome};
+use crate::link::{LinkGuard, LinkGuardConfig, LinkState};
+use crate::policy::{AllowAll, WriteDecision, WritePolicy};
+use crate::recovery::{RestoreResult, ServoRestarted, ShadowCache};
+use crate::protocol::{write_command_size, Deadline, IdSet, ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, ServoStatusFlags, StreamReader, StreamWriter, WriteRegisterCommand, BROADCAST_ID, STANDARD_BUFFER_SIZE};
+
+/// Maximum number of bytes `Bus::write_register` writes at once.
+pub const MAX_WRITE_LENGTH: usize = 32;
+/// Number of transactions the bus keeps in its event log.
+pub const EVENT_LOG_SIZE: usize = 32;
+/// Number of servos the bus watches for restarts.
+pub const SHADOW_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BusMode {
+    /// Every command waits for the response of the servo.
+    Normal,
+    /// Commands are sent without waiting for responses. Consecutive commands are sent at least `interval` apart,
+    /// so the servos have time to process each command before the next one arrives.
+    FireAndForget { interval: Duration },
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusConfig {
+    pub master: ProtocolMasterConfig,
+    /// Timeout of each transaction.
+    pub timeout: Duration,
+    pub mode: BusMode,
+}
+
+pub struct Bus<R, W, T: Timer, const BUFFER_SIZE: usize = STANDARD_BUFFER_SIZE, P = AllowAll> {
+    reader: R,
+    writer: W,
+    master: ProtocolMaster<BUFFER_SIZE>,
+    timeout: Duration,
+    mode: BusMode,
+    policy: P,
+    last_command: Option<T::Instant>,
+    epoch: T::Instant,
+    events: EventLog<EVENT_LOG_SIZE>,
+    shadow: ShadowCache<SHADOW_SIZE>,
+    link: Option<LinkGuard>,
+    /// Servos with their responses disabled.
+    silent: IdSet,
+    _timer: PhantomData<T>,
+}
+
+pub type BusError<R, W> = ProtocolHandlerError<<R as StreamReader>::Error, <W as StreamWriter>::Error>;
+
+impl<R: StreamReader, W: StreamWriter, T: Timer, const BUFFER_SIZE: usize> Bus<R, W, T, BUFFER_SIZE> {
+    pub fn new(reader: R, writer: W, config: BusConfig) -> Self {
+        Self {
+            reader,
+            writer,
+            master: ProtocolMaster::new(config.master),
+            timeout: config.timeout,
+            mode: config.mode,
+            policy: AllowAll,
+            last_command: None,
+            epoch: T::now(),
+            events: EventLog::new(),
+            shadow: ShadowCache::new(),
+            link: None,
+            silent: IdSet::new(),
+            _timer: PhantomData,
+        }
+    }
+}
+
+impl<R: StreamReader, W: StreamWriter, T: Timer, const BUFFER_SIZE: usize, P: WritePolicy> Bus<R, W, T, BUFFER_SIZE, P> {
+    /// Replaces the write policy.
+    pub fn with_policy<Q: WritePolicy>(self, policy: Q) -> Bus<R, W, T, BUFFER_SIZE, Q> {
+        Bus {
+            reader: self.reader,
+            writer: self.writer,
+            master: self.master,
+            timeout: self.timeout,
+            mode: self.mode,
+            policy,
+            last_command: self.last_command,
+            epoch: self.epoch,
+            events: self.events,
+            shadow: self.shadow,
+            link: self.link,
+            silent: self.silent,
+            _timer: PhantomData,
+        }
+    }
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    pub fn mode(&self) -> BusMode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: BusMode) {
+        self.mode = mode;
+    }
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+
+    /// Marks servo `id` as having its responses disabled (response level 0) or enabled. Writes to a servo with
+    /// responses disabled complete once they are sent, like writes to the broadcast ID. Reads and pings are still
+    /// answered by such servos.
+    pub fn set_responses_enabled(&mut self, id: u8, enabled: bool) {
+        if enabled {
+            self.silent.remove(id);
+        } else {
+            self.silent.insert(id);
+        }
+    }
+    /// Whether servo `id` answers writes. The broadcast ID never does.
+    pub fn responds_to_writes(&self, id: u8) -> bool {
+        id != BROADCAST_ID && !self.silent.contains(id)
+    }
+
+    /// Status flags of the last response received. See [`ProtocolMaster::response_status`].
+    pub fn response_status(&self) -> Option<ServoStatusFlags> {
+        self.master.response_status()
+    }
+
+    /// Recent transactions, e.g. to dump them when an operation fails.
+    pub fn events(&self) -> &EventLog<EVENT_LOG_SIZE> {
+        &self.events
+    }
+    pub fn events_mut(&mut self) -> &mut EventLog<EVENT_LOG_SIZE> {
+        &mut self.events
+    }
+
+    /// Volatile configuration cached for the restart recovery.
+    pub fn shadow(&self) -> &ShadowCache<SHADOW_SIZE> {
+        &self.shadow
+    }
+    pub fn shadow_mut(&mut self) -> &mut ShadowCache<SHADOW_SIZE> {
+        &mut self.shadow
+    }
+    /// Takes a pending [`ServoRestarted`] event.
+    pub fn take_restart(&mut self) -> Option<ServoRestarted> {
+        self.shadow.take_restart()
+    }
+
+    /// Enables the link guard with `config`, or disables it with `None`. Disabled by default.
+    pub fn set_link_guard(&mut self, config: Option<LinkGuardConfig>) {
+        self.link = config.map(LinkGuard::new);
+    }
+    /// State of the link guard. Always stable if it is disabled.
+    pub fn link_state(&self) -> LinkState {
+        self.link.as_ref().map_or(LinkState::Stable, LinkGuard::state)
+    }
+
+    /// Passes the result of a transaction through the link guard.
+    /// A valid response which arrived while the link is unstable fails with [`ProtocolHandlerError::LinkUnstable`].
+    fn guard<V>(&mut self, result: Result<V, BusError<R, W>>) -> Result<V, BusError<R, W>> {
+        let Some(link) = self.link.as_mut() else {
+            return result;
+        };
+        let valid = match Outcome::of(&result) {
+            Outcome::InvalidPacket => false,
+            Outcome::Ok | Outcome::UnexpectedPacketId(_) | Outcome::UnexpectedLength(_) => true,
+            // No frame was received.
+            _ => return result,
+        };
+        if link.frame(valid) || !valid {
+            result
+        } else {
+            Err(ProtocolHandlerError::LinkUnstable)
+        }
+    }
+
+    /// Watches servo `id` for restarts, checking its software version when it comes back.
+    /// Servos are also watched from the first write to their volatile registers, without the version check.
+    /// Returns false if the shadow cache is full.
+    pub fn track(&mut self, id: u8) -> Result<bool, BusError<R, W>> {
+        let mut version = [0; 2];
+        self.read_register(id, REGISTER_VERSION_H.address, &mut version)?;
+        Ok(self.shadow.track(id, Some(version)))
+    }
+
+    /// Updates the restart detection of servo `id` with the result of a transaction.
+    fn watch<V>(&mut self, id: u8, result: &Result<V, BusError<R, W>>) {
+        match result {
+            Err(ProtocolHandlerError::TimedOut) => self.shadow.mark_lost(id),
+            Ok(_) if self.shadow.mark_answered(id) => self.restore(id),
+            _ => {}
+        }
+    }
+
+    /// Checks that the servo which answers at `id` is the one cached, and writes its configuration back.
+    fn restore(&mut self, id: u8) {
+        let timestamp = self.epoch.elapsed();
+        // Software Version H, Software Version L and ID.
+        let mut registers = [0; 3];
+        let read = self.read_register(id, REGISTER_VERSION_H.address, &mut registers);
+        let result = match read {
+            Err(_) => RestoreResult::Failed(Outcome::of(&read)),
+            Ok(_) => {
+                let version = [registers[0], registers[1]];
+                if registers[2] != id || self.shadow.version(id).is_some_and(|expected| expected != version) {
+                    RestoreResult::Mismatch { id: registers[2], version }
+                } else {
+                    self.shadow.track(id, Some(version));
+                    let write = self.write_saved(id);
+                    match write {
+                        Ok(()) => RestoreResult::Restored,
+                        Err(_) => RestoreResult::Failed(Outcome::of(&write)),
+                    }
+                }
+            }
+        };
+        self.shadow.set_restarted(ServoRestarted { id, timestamp, result });
+    }
+
+    /// Writes the cached registers of servo `id`, merging consecutive registers into one write.
+    fn write_saved(&mut self, id: u8) -> Result<(), BusError<R, W>> {
+        let mut saved = [(0, 0); VOLATILE_REGISTERS.len()];
+        let mut count = 0;
+        for (slot, register) in saved.iter_mut().zip(self.shadow.saved(id)) {
+            *slot = register;
+            count += 1;
+        }
+        let mut data = [0; VOLATILE_REGISTERS.len()];
+        let mut index = 0;
+        while index < count {
+            let address = saved[index].0;
+            let mut length = 0;
+            while index + length < count && saved[index + length].0 == address + length as u8 {
+                data[length] = saved[index + length].1;
+                length += 1;
+            }
+            self.write_register(id, address, &data[..length])?;
+            index += length;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, operation: Operation, id: u8, address: u8, length: usize, start: Duration, outcome: Outcome) {
+        self.events.push(BusEvent {
+            timestamp: start,
+            elapsed: self.epoch.elapsed().saturating_sub(start),
+            operation,
+            id,
+            address,
+            length: length.min(u8::MAX as usize) as u8,
+            outcome,
+        });
+    }
+
+    fn require_responses(&self) -> Result<(), BusError<R, W>> {
+        match self.mode {
+            BusMode::Normal => Ok(()),
+            BusMode::FireAndForget { .. } => Err(ProtocolHandlerError::ResponsesDisabled),
+        }
+    }
+
+    /// Waits until the pacing interval since the previous command has elapsed.
+    fn pace(&mut self, interval: Duration) {
+        if let Some(last_command) = &self.last_command {
+            while last_command.elapsed() < interval {}
+        }
+        self.last_command = Some(T::now());
+    }
+
+    /// Pings servo `id`. Returns the status flags of the servo, see [`ProtocolMaster::ping`].
+    pub fn ping(&mut self, id: u8) -> Result<ServoStatusFlags, BusError<R, W>> {
+        let start = self.epoch.elapsed();
+        let result = self.require_responses()
+            .and_then(|_| self.master.ping(&mut self.reader, &mut self.writer, id, timeout_after::<T>(self.timeout)));
+        let result = self.guard(result);
+        self.record(Operation::Ping, id, 0, 0, start, Outcome::of(&result));
+        self.watch(id, &result);
+        result
+    }
+
+    /// Pings servo `id` and listens for `window` after the response for another servo answering the same ID, e.g.
+    /// after a servo with the default ID was added to the bus. Fails with [`ProtocolHandlerError::IdConflict`] if
+    /// more bytes arrive within the window, or if the response is corrupted, as simultaneous responses collide.
+    /// A corrupted response may also be noise, so check again before changing IDs.
+    pub fn check_id_conflict(&mut self, id: u8, window: Duration) -> Result<ServoStatusFlags, BusError<R, W>> {
+        let start = self.epoch.elapsed();
+        let result = self.require_responses()
+            .and_then(|_| self.master.ping(&mut self.reader, &mut self.writer, id, timeout_after::<T>(self.timeout)));
+        let collided = matches!(Outcome::of(&result), Outcome::InvalidPacket | Outcome::UnexpectedLength(_));
+        let result = match result {
+            Ok(status) => self.listen(window).and_then(|received| if received { Err(ProtocolHandlerError::IdConflict(id)) } else { Ok(status) }),
+            Err(_) if collided => Err(ProtocolHandlerError::IdConflict(id)),
+            Err(err) => Err(err),
+        };
+        self.master.reset();
+        self.record(Operation::Ping, id, 0, 0, start, Outcome::of(&result));
+        result
+    }
+
+    /// Reads whatever arrives within `window`. Returns whether anything arrived.
+    fn listen(&mut self, window: Duration) -> Result<bool, BusError<R, W>> {
+        let start = T::now();
+        let mut buffer = [0; 16];
+        let mut received = false;
+        while start.elapsed() < window {
+            match self.reader.read(&mut buffer) {
+                Ok(bytes_read) => received |= bytes_read > 0,
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(err)) => return Err(ProtocolHandlerError::ReaderError(err)),
+            }
+        }
+        Ok(received)
+    }
+
+    pub fn read_register(&mut self, id: u8, address: u8, buffer: &mut [u8]) -> Result<ServoStatusFlags, BusError<R, W>> {
+        self.read_register_until(id, address, buffer, timeout_after::<T>(self.timeout))
+    }
+
+    fn read_register_until<Timeout: Deadline>(&mut self, id: u8, address: u8, buffer: &mut [u8], timeout: Timeout) -> Result<ServoStatusFlags, BusError<R, W>> {
+        let start = self.epoch.elapsed();
+        let result = self.require_responses()
+            .and_then(|_| self.master.read_register(&mut self.reader, &mut self.writer, id, address, buffer, timeout));
+        let result = self.guard(result);
+        self.record(Operation::Read, id, address, buffer.len(), start, Outcome::of(&result));
+        self.watch(id, &result);
+        result
+    }
+
+    /// Reads registers from several servos with one deadline `total_timeout` for all of them instead of one timeout
+    /// per read. See [`ProtocolMaster::read_register_many`]. Every read still ends at the timeout of the bus.
+    pub fn read_register_many<OnResult>(&mut self, requests: &mut [(u8, u8, &mut [u8])], total_timeout: Duration, mut on_result: OnResult) -> usize
+        where OnResult: FnMut(usize, Result<ServoStatusFlags, BusError<R, W>>),
+    {
+        let mut deadline = timeout_after::<T>(total_timeout);
+        let mut succeeded = 0;
+        for (index, (id, address, buffer)) in requests.iter_mut().enumerate() {
+            if deadline.expired() {
+                on_result(index, Err(ProtocolHandlerError::TimedOut));
+                continue;
+            }
+            let mut timeout = timeout_after::<T>(self.timeout);
+            let result = self.read_register_until(*id, *address, buffer, || deadline.expired() || timeout.expired());
+            if result.is_ok() {
+                succeeded += 1;
+            } else {
+                self.master.reset();
+            }
+            on_result(index, result);
+        }
+        succeeded
+    }
+
+    pub fn read_status_block(&mut self, id: u8) -> Result<StatusBlock, BusError<R, W>> {
+        let mut registers = [0; StatusBlock::LENGTH];
+        self.read_register(id, REGISTER_CURRENT_POSITION_H.address, &mut registers)?;
+        Ok(StatusBlock::from_registers(&registers))
+    }
+
+    pub fn write_command<const SIZE: usize>(&mut self, command: &WriteRegisterCommand<SIZE>) -> Result<(), BusError<R, W>> {
+        let start = self.epoch.elapsed();
+        let (id, address, length) = (command.id(), command.address(), command.body().len());
+        let result = match self.policy.check(id, address, command.body()) {
+            WriteDecision::Transmit => match self.mode {
+                BusMode::Normal if self.responds_to_writes(id) => {
+                    let result = self.master.write_register(&mut self.reader, &mut self.writer, command, timeout_after::<T>(self.timeout));
+                    self.guard(result)
+                }
+                BusMode::Normal => self.master.write_register_no_response(&mut self.reader, &mut self.writer, command, timeout_after::<T>(self.timeout)),
+                BusMode::FireAndForget { interval } => {
+                    self.pace(interval);
+                    self.master.write_register_no_response(&mut self.reader, &mut self.writer, command, timeout_after::<T>(self.timeout))
+                }
+            },
+            WriteDecision::Reject(address) => Err(ProtocolHandlerError::WriteProtected(address)),
+            WriteDecision::Drop => {
+                self.record(Operation::Write, id, address, length, start, Outcome::Dropped);
+                return Ok(());
+            }
+        };
+        self.record(Operation::Write, id, address, length, start, Outcome::of(&result));
+        if result.is_ok() {
+            self.shadow.record_write(id, address, command.body());
+        }
+        self.watch(id, &result);
+        result
+    }
+
+    /// Writes up to `MAX_WRITE_LENGTH` bytes to consecutive registers.
+    pub fn write_register(&mut self, id: u8, address: u8, data: &[u8]) -> Result<(), BusError<R, W>> {
+        if data.len() > MAX_WRITE_LENGTH {
+            return Err(ProtocolHandlerError::UnexpectedLength(data.len()));
+        }
+        let command = WriteRegisterCommand::<{ write_command_size(MAX_WRITE_LENGTH) }>::builder(id)
+            .address(address)
+            .data(data)
+            .build()
+            .map_err(ProtocolHandlerError::PacketError)?;
+        self.write_command(&command)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{REGISTER_BAUD_RATE, REGISTER_ID, REGISTER_RESPONSE_ENABLE, REGISTER_TARGET_SPEED_H, REGISTER_TORQUE_SWITCH};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::{MismatchPolicy, RetryPolicy};
+    use crate::policy::{DryRun, ProtectedRegisters, RecordedWrite};
+    use crate::testing::emulator_thread::EmulatorThread;
+    extern crate std;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_bus_fire_and_forget() {
+        let (writer, sent) = channel();
+        let (_response_writer, reader) = channel::<u8>();
+        let interval = Duration::from_millis(2);
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(100),
+            mode: BusMode::FireAndForget { interval },
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(reader, writer, config);
+
+        assert!(matches!(bus.ping(1), Err(ProtocolHandlerError::ResponsesDisabled)));
+        assert!(matches!(bus.read_status_block(1), Err(ProtocolHandlerError::ResponsesDisabled)));
+        assert!(sent.try_recv().is_err());
+
+        let start = std::time::Instant::now();
+        for id in 1..=3 {
+            bus.write_register(id, 0x2a, &[0x01, 0x00]).unwrap();
+        }
+        assert!(start.elapsed() >= interval * 2);
+        let frames = sent.try_iter().collect::<std::vec::Vec<_>>();
+        assert_eq!(frames.len(), 3 * 9);
+        assert_eq!(&frames[..9], &[0xff, 0xff, 0x01, 0x05, 0x03, 0x2a, 0x01, 0x00, 0xcb]);
+
+        assert!(matches!(bus.write_register(1, 0x00, &[0; MAX_WRITE_LENGTH + 1]), Err(ProtocolHandlerError::UnexpectedLength(_))));
+    }
+
+    #[test]
+    fn test_bus_normal() {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<2>::new(1, 2));
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_secs(1),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        bus.ping(2).unwrap();
+        bus.write_register(2, 0x2a, &[0x01, 0x00]).unwrap();
+        let mut target = [0; 2];
+        bus.read_register(2, 0x2a, &mut target).unwrap();
+        assert_eq!(target, [0x01, 0x00]);
+        let status = bus.read_status_block(1).unwrap();
+        assert_eq!(status.position, 0x01ff);
+
+        let mut bus = bus.with_policy(DryRun::new(ProtectedRegisters::from_registers(&[REGISTER_ID, REGISTER_BAUD_RATE])));
+        assert!(matches!(bus.write_register(2, REGISTER_ID.address, &[0x03]), Err(ProtocolHandlerError::WriteProtected(0x05))));
+        bus.write_register(2, 0x2a, &[0x02, 0x00]).unwrap();
+        assert_eq!(bus.policy_mut().take_writes(), [RecordedWrite { id: 2, address: 0x2a, data: std::vec![0x02, 0x00] }]);
+        // The dry run did not reach the servo.
+        bus.read_register(2, 0x2a, &mut target).unwrap();
+        assert_eq!(target, [0x01, 0x00]);
+
+        let events = bus.events().iter().map(|event| (event.operation, event.id, event.outcome)).collect::<std::vec::Vec<_>>();
+        assert_eq!(events, [
+            (Operation::Ping, 2, Outcome::Ok),
+            (Operation::Write, 2, Outcome::Ok),
+            (Operation::Read, 2, Outcome::Ok),
+            (Operation::Read, 1, Outcome::Ok),
+            (Operation::Write, 2, Outcome::Rejected),
+            (Operation::Write, 2, Outcome::Dropped),
+            (Operation::Read, 2, Outcome::Ok),
+        ]);
+        assert_eq!(bus.events().last().unwrap().length, 2);
+        bus.ping(5).unwrap_err();
+        assert_eq!(bus.events().last().unwrap().outcome, Outcome::TimedOut);
+
+        let (mut first, mut second, mut third) = ([0; 2], [0; 2], [0; 2]);
+        let mut timed_out = std::vec::Vec::new();
+        let requests = &mut [(1, 0x38, &mut first[..]), (5, 0x38, &mut second[..]), (2, 0x38, &mut third[..])];
+        // The missing servo 5 uses up the whole deadline.
+        let succeeded = bus.read_register_many(requests, Duration::from_millis(20), |index, result| if result.is_err() { timed_out.push(index) });
+        assert_eq!((succeeded, timed_out), (1, std::vec![1, 2]));
+        assert_eq!(first, [0x01, 0xff]);
+
+        // Broadcast writes are not answered.
+        let mut bus = bus.with_policy(AllowAll);
+        bus.write_register(BROADCAST_ID, 0x2a, &[0x01, 0x80]).unwrap();
+        for id in 1..=2 {
+            bus.read_register(id, 0x2a, &mut target).unwrap();
+            assert_eq!(target, [0x01, 0x80]);
+        }
+        // Nor are writes to a servo with its responses disabled.
+        bus.write_register(1, REGISTER_RESPONSE_ENABLE.address, &[0x00]).unwrap();
+        bus.set_responses_enabled(1, false);
+        bus.write_register(1, 0x2a, &[0x01, 0x00]).unwrap();
+        bus.read_register(1, 0x2a, &mut target).unwrap();
+        assert_eq!(target, [0x01, 0x00]);
+
+        thread.stop();
+    }
+
+    #[test]
+    fn test_bus_servo_restart() {
+        let (master_writer, mut emulator_reader) = channel();
+        let (mut emulator_writer, master_reader) = channel();
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let powered = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let (stop_clone, powered_clone) = (stop.clone(), powered.clone());
+        let thread = std::thread::spawn(move || {
+            let mut emulator = BusEmulator::<2>::new(1, 2);
+            let mut restarted = false;
+            while !stop_clone.load(std::sync::atomic::Ordering::Relaxed) {
+                if powered_clone.load(std::sync::atomic::Ordering::Relaxed) {
+                    emulator.process(&mut emulator_reader, &mut emulator_writer).unwrap();
+                } else {
+                    // Servo 1 lost its power: nothing answers until it comes back with its RAM reset.
+                    emulator.reset();
+                    while emulator_reader.try_recv().is_ok() {}
+                    if !restarted {
+                        emulator.servo_mut(1).unwrap().restart();
+                        restarted = true;
+                    }
+                }
+            }
+            emulator
+        });
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        assert!(bus.track(1).unwrap());
+        bus.write_register(1, REGISTER_TARGET_SPEED_H.address, &[0x01, 0x00]).unwrap();
+        bus.write_register(1, REGISTER_TORQUE_SWITCH.address, &[1]).unwrap();
+        bus.write_register(2, REGISTER_TORQUE_SWITCH.address, &[1]).unwrap();
+        assert!(bus.shadow().is_tracked(2));
+
+        powered.store(false, std::sync::atomic::Ordering::Relaxed);
+        assert!(matches!(bus.read_status_block(1), Err(ProtocolHandlerError::TimedOut)));
+        assert_eq!(bus.take_restart(), None);
+        powered.store(true, std::sync::atomic::Ordering::Relaxed);
+        // The servo takes a moment to come back.
+        assert!((0..10).any(|_| bus.read_status_block(1).is_ok()));
+        let restart = bus.take_restart().unwrap();
+        assert_eq!((restart.id, restart.result), (1, RestoreResult::Restored));
+        assert_eq!(bus.take_restart(), None);
+
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        let emulator = thread.join().unwrap();
+        let registers = emulator.servo(1).unwrap().registers();
+        assert_eq!(registers[REGISTER_TORQUE_SWITCH.address as usize], 1);
+        assert_eq!(&registers[REGISTER_TARGET_SPEED_H.address as usize..][..2], &[0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_bus_id_conflict() {
+        let (writer, _sent) = channel();
+        let (responses, reader) = channel();
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(10),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(reader, writer, config);
+        let response = [0xff, 0xff, 0x03, 0x02, 0x00, 0xfa];
+        let window = Duration::from_millis(2);
+
+        response.iter().for_each(|byte| responses.send(*byte).unwrap());
+        bus.check_id_conflict(3, window).unwrap();
+        // A second servo answers after the first one.
+        response.iter().chain(&response).for_each(|byte| responses.send(*byte).unwrap());
+        assert!(matches!(bus.check_id_conflict(3, window), Err(ProtocolHandlerError::IdConflict(3))));
+        // Both servos answer at the same time.
+        [0xff, 0xff, 0x03, 0x02, 0x00, 0xf8].iter().for_each(|byte| responses.send(*byte).unwrap());
+        assert!(matches!(bus.check_id_conflict(3, window), Err(ProtocolHandlerError::IdConflict(3))));
+        assert!(matches!(bus.check_id_conflict(3, window), Err(ProtocolHandlerError::TimedOut)));
+        let outcomes = bus.events().iter().map(|event| event.outcome).collect::<std::vec::Vec<_>>();
+        assert_eq!(outcomes, [Outcome::Ok, Outcome::IdConflict, Outcome::IdConflict, Outcome::TimedOut]);
+    }
+
+    #[test]
+    fn test_bus_link_guard() {
+        let (writer, _sent) = channel();
+        let (response_writer, reader) = channel::<u8>();
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(reader, writer, config);
+        bus.set_link_guard(Some(LinkGuardConfig { error_burst: 2, recovery_frames: 1 }));
+        let corrupted = [0xff, 0xff, 0x01, 0x02, 0x00, 0x00];
+        let valid = [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc];
+        for response in [corrupted, corrupted, valid] {
+            response.iter().for_each(|byte| response_writer.send(*byte).unwrap());
+        }
+
+        assert!(matches!(bus.ping(1), Err(ProtocolHandlerError::PacketError(_))));
+        assert_eq!(bus.link_state(), LinkState::Stable);
+        assert!(matches!(bus.ping(1), Err(ProtocolHandlerError::PacketError(_))));
+        assert_eq!(bus.link_state(), LinkState::Unstable { valid_frames: 0 });
+        assert!(matches!(bus.ping(1), Err(ProtocolHandlerError::LinkUnstable)));
+        assert_eq!(bus.events().last().unwrap().outcome, Outcome::LinkUnstable);
+        // A timeout is not a frame.
+        assert!(matches!(bus.read_status_block(2), Err(ProtocolHandlerError::TimedOut)));
+        assert_eq!(bus.link_state(), LinkState::Unstable { valid_frames: 1 });
+        valid.iter().for_each(|byte| response_writer.send(*byte).unwrap());
+        bus.ping(1).unwrap();
+        assert_eq!(bus.link_state(), LinkState::Stable);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bus_config_serde() {
+        let config = BusConfig {
+            master: ProtocolMasterConfig::builder().echo_back(true).retry(RetryPolicy { retries: 2, backoff_ms: 5 }).mismatch(MismatchPolicy { skip: true, hook: Some(|_, _| {}) }).build(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::FireAndForget { interval: Duration::from_millis(2) },
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        let restored = serde_json::from_str::<BusConfig>(&json).unwrap();
+        assert!(restored.master.echo_back() && restored.master.mismatch().skip && restored.master.mismatch().hook.is_none());
+        assert_eq!(restored.master.retry(), config.master.retry());
+        assert_eq!((restored.timeout, restored.mode), (config.timeout, config.mode));
+    }
+}
@@ -0,0 +1,213 @@
+//! Cancellation of async transactions.
+//!
+//! A transaction of the async [`ProtocolMaster`](crate::protocol::ProtocolMaster) waits on its streams at every await
+//! point. Wrapping the streams in [`Cancellable`] makes every read and write return [`CancelError::Cancelled`] as
+//! soon as the [`CancellationToken`] is cancelled, also while the stream is pending, so the transaction ends with an
+//! error instead of waiting for its deadline.
+//!
+//! The master discards a partial response at the start of the next transaction, whether the previous one was
+//! cancelled or its future was dropped. A response which arrives after the cancellation is still on the bus, so
+//! wait for the response time of the servos before the next transaction if the IDs may be the same.
+
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+
+use futures_util::future::{select, Either};
+use futures_util::task::AtomicWaker;
+
+use crate::protocol::{Deadline, ProtocolHandlerError, ProtocolReaderError, StreamReaderAsync, StreamWriterAsync};
+
+/// Flag to cancel the transactions on a pair of [`Cancellable`] streams, e.g. from another task or an interrupt.
+/// Only the task which waits last is woken, so a token should be used by one bus at a time.
+#[derive(Debug, Default)]
+pub struct CancellationToken {
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl CancellationToken {
+    pub const fn new() -> Self {
+        Self { cancelled: AtomicBool::new(false), waker: AtomicWaker::new() }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+    /// Clears the cancellation, so that the streams can be used again.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Release);
+    }
+
+    /// Completes when the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled { token: self }
+    }
+}
+
+/// A cancelled token is an expired deadline, for transactions on streams without [`Cancellable`].
+impl Deadline for &CancellationToken {
+    fn expired(&mut self) -> bool {
+        self.is_cancelled()
+    }
+}
+
+/// Future returned by [`CancellationToken::cancelled`].
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        self.token.waker.register(context.waker());
+        // Cancelled between the check and the registration.
+        if self.token.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelError<E> {
+    Cancelled,
+    Stream(E),
+}
+
+impl<RE, WE> ProtocolHandlerError<CancelError<RE>, CancelError<WE>> {
+    /// Whether the transaction ended because its streams were cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self,
+            ProtocolHandlerError::ReaderError(CancelError::Cancelled)
+            | ProtocolHandlerError::WriterError(CancelError::Cancelled)
+            | ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(CancelError::Cancelled)))
+    }
+}
+
+/// Stream which fails with [`CancelError::Cancelled`] once `token` is cancelled.
+/// A read or write pending at the cancellation is dropped, so data it would have transferred may be lost.
+pub struct Cancellable<'a, S> {
+    inner: S,
+    token: &'a CancellationToken,
+}
+
+impl<'a, S> Cancellable<'a, S> {
+    pub fn new(inner: S, token: &'a CancellationToken) -> Self {
+        Self { inner, token }
+    }
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: StreamReaderAsync> StreamReaderAsync for Cancellable<'_, S> {
+    type Error = CancelError<S::Error>;
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.token.is_cancelled() {
+            return Err(CancelError::Cancelled);
+        }
+        match select(pin!(self.inner.read(data)), self.token.cancelled()).await {
+            Either::Left((result, _)) => result.map_err(CancelError::Stream),
+            Either::Right(_) => Err(CancelError::Cancelled),
+        }
+    }
+    async fn purge(&mut self) -> Result<(), Self::Error> {
+        self.inner.purge().await.map_err(CancelError::Stream)
+    }
+}
+
+impl<S: StreamWriterAsync> StreamWriterAsync for Cancellable<'_, S> {
+    type Error = CancelError<S::Error>;
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        if self.token.is_cancelled() {
+            return Err(CancelError::Cancelled);
+        }
+        match select(pin!(self.inner.write(data)), self.token.cancelled()).await {
+            Either::Left((result, _)) => result.map_err(CancelError::Stream),
+            Either::Right(_) => Err(CancelError::Cancelled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::{ProtocolMaster, ProtocolMasterConfig, StreamReader};
+    use crate::testing::block_on;
+    extern crate std;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Reader which pends once the received bytes are consumed, and is never woken.
+    struct Stalling(Receiver<u8>);
+    impl StreamReaderAsync for Stalling {
+        type Error = ();
+        async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+            match StreamReader::read(&mut self.0, data) {
+                Ok(bytes_read) => Ok(bytes_read),
+                Err(nb::Error::WouldBlock) => core::future::pending().await,
+                Err(nb::Error::Other(err)) => Err(err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancel_pending_read() {
+        let token = CancellationToken::new();
+        let (master_writer, _slave_reader) = channel();
+        let (slave_writer, master_reader) = channel();
+        let mut reader = Cancellable::new(Stalling(master_reader), &token);
+        let mut writer = Cancellable::new(master_writer, &token);
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let mut data = [0; 1];
+        let mut context = Context::from_waker(core::task::Waker::noop());
+
+        // The response is cut off in the middle and the read stalls until the token is cancelled.
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x00] {
+            slave_writer.send(byte).unwrap();
+        }
+        {
+            let mut read = pin!(master.read_register_async(&mut reader, &mut writer, 0x01, 0x2a, &mut data, || false));
+            assert!(read.as_mut().poll(&mut context).is_pending());
+            token.cancel();
+            assert!(block_on(read).is_err_and(|err| err.is_cancelled()));
+        }
+        // Every await point fails until the token is reset.
+        assert!(block_on(master.ping_async(&mut reader, &mut writer, 0x01, || false)).is_err_and(|err| err.is_cancelled()));
+        token.reset();
+
+        // Neither the cancelled partial response nor one of a dropped transaction corrupts the next response.
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x00] {
+            slave_writer.send(byte).unwrap();
+        }
+        assert!(pin!(master.read_register_async(&mut reader, &mut writer, 0x01, 0x2a, &mut data, || false)).poll(&mut context).is_pending());
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9] {
+            slave_writer.send(byte).unwrap();
+        }
+        block_on(master.read_register_async(&mut reader, &mut writer, 0x01, 0x2a, &mut data, || false)).unwrap();
+        assert_eq!(data, [0x12]);
+    }
+
+    #[test]
+    fn test_token_as_deadline() {
+        let token = CancellationToken::new();
+        let (mut master_writer, _slave_reader) = channel();
+        let (_slave_writer, mut master_reader) = channel::<u8>();
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        token.cancel();
+        let result = block_on(master.ping_async(&mut master_reader, &mut master_writer, 0x01, &token));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+    }
+}
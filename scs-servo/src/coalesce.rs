@@ -0,0 +1,118 @@
+//! Coalescing of writes to adjacent registers.
+//!
+//! A motion command of an SCS servo writes the target position, period and speed, three words at 0x2a to 0x2f. Sent
+//! as they come, they take three transactions. [`WriteCoalescer`] merges each write into the pending one when they
+//! cover a contiguous block of registers of the same servo, and hands out the pending write as a single command once
+//! the next write does not continue it:
+//!
+//! ```ignore
+//! let mut coalescer = WriteCoalescer::<{ write_command_size(16) }>::new();
+//! for (address, data) in writes {
+//!     if let Some(command) = coalescer.push(id, address, data)? {
+//!         master.write_register(&mut reader, &mut writer, &command, timeout_after::<Instant>(timeout))?;
+//!     }
+//! }
+//! if let Some(command) = coalescer.take() {
+//!     master.write_register(&mut reader, &mut writer, &command, timeout_after::<Instant>(timeout))?;
+//! }
+//! ```
+//!
+//! Only the last write is merged into, so the writes reach the servo in the order they were pushed.
+
+use crate::packet::PacketError;
+use crate::protocol::WriteRegisterCommand;
+
+/// Merges consecutive writes to adjacent or overlapping registers of a servo into one WRITE command of up to `SIZE`
+/// bytes.
+pub struct WriteCoalescer<const SIZE: usize> {
+    pending: Option<WriteRegisterCommand<SIZE>>,
+}
+
+impl<const SIZE: usize> WriteCoalescer<SIZE> {
+    pub const fn new() -> Self {
+        Self { pending: None }
+    }
+    /// The write built so far.
+    pub fn pending(&self) -> Option<&WriteRegisterCommand<SIZE>> {
+        self.pending.as_ref()
+    }
+
+    /// Adds a write of `data` to the registers of servo `id` from `address`. It is merged into the pending write if
+    /// both are to the same servo, their registers leave no gap between them and the merged write fits in the command.
+    /// Where they overlap, `data` wins. Otherwise returns the pending write, which has to be sent before this one,
+    /// and this one becomes pending. Fails with [`PacketError::InvalidLength`] if `data` does not fit in the command.
+    pub fn push(&mut self, id: u8, address: u8, data: &[u8]) -> Result<Option<WriteRegisterCommand<SIZE>>, PacketError> {
+        if data.len() > WriteRegisterCommand::<SIZE>::MAX_LENGTH {
+            return Err(PacketError::InvalidLength);
+        }
+        if let Some(merged) = self.pending.as_ref().and_then(|pending| merge(pending, id, address, data)) {
+            self.pending = Some(merged?);
+            return Ok(None);
+        }
+        let command = WriteRegisterCommand::builder(id).address(address).data(data).build()?;
+        Ok(self.pending.replace(command))
+    }
+
+    /// Takes the pending write, e.g. to send it at the end of a batch.
+    pub fn take(&mut self) -> Option<WriteRegisterCommand<SIZE>> {
+        self.pending.take()
+    }
+}
+
+impl<const SIZE: usize> Default for WriteCoalescer<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Merges a write into `pending`, or returns `None` if they cannot be merged.
+fn merge<const SIZE: usize>(pending: &WriteRegisterCommand<SIZE>, id: u8, address: u8, data: &[u8]) -> Option<Result<WriteRegisterCommand<SIZE>, PacketError>> {
+    let (pending_start, start) = (pending.address() as usize, address as usize);
+    let (pending_end, end) = (pending_start + pending.body().len(), start + data.len());
+    if pending.id() != id || start > pending_end || pending_start > end {
+        return None;
+    }
+    let merged_start = pending_start.min(start);
+    let merged_end = pending_end.max(end);
+    if merged_end - merged_start > WriteRegisterCommand::<SIZE>::MAX_LENGTH {
+        return None;
+    }
+    let mut merged = [0; SIZE];
+    merged[pending_start - merged_start..pending_end - merged_start].copy_from_slice(pending.body());
+    merged[start - merged_start..end - merged_start].copy_from_slice(data);
+    Some(WriteRegisterCommand::builder(id).address(merged_start as u8).data(&merged[..merged_end - merged_start]).build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::write_command_size;
+
+    #[test]
+    fn test_write_coalescer() {
+        let mut coalescer = WriteCoalescer::<{ write_command_size(8) }>::new();
+        // Target position, period and speed.
+        assert!(coalescer.push(0x01, 0x2a, &[0x01, 0x00]).unwrap().is_none());
+        assert!(coalescer.push(0x01, 0x2c, &[0x00, 0x10]).unwrap().is_none());
+        assert!(coalescer.push(0x01, 0x2e, &[0x00, 0x20]).unwrap().is_none());
+        let command = coalescer.take().unwrap();
+        assert_eq!((command.id(), command.address(), command.body()), (0x01, 0x2a, &[0x01, 0x00, 0x00, 0x10, 0x00, 0x20][..]));
+        assert!(command.reader().verify_checksum().is_ok());
+        assert!(coalescer.take().is_none());
+
+        // A write preceding the pending one is merged, and the newer data wins where they overlap.
+        coalescer.push(0x01, 0x2c, &[0x00, 0x10]).unwrap();
+        coalescer.push(0x01, 0x2b, &[0x02, 0x03]).unwrap();
+        assert_eq!((coalescer.pending().unwrap().address(), coalescer.pending().unwrap().body()), (0x2b, &[0x02, 0x03, 0x10][..]));
+
+        // A gap, another servo or a merged write too long for the command end the pending write.
+        let sent = coalescer.push(0x01, 0x30, &[0x04]).unwrap().unwrap();
+        assert_eq!((sent.address(), sent.body()), (0x2b, &[0x02, 0x03, 0x10][..]));
+        let sent = coalescer.push(0x02, 0x31, &[0x05]).unwrap().unwrap();
+        assert_eq!((sent.id(), sent.address()), (0x01, 0x30));
+        let sent = coalescer.push(0x02, 0x32, &[0; 8]).unwrap().unwrap();
+        assert_eq!((sent.id(), sent.address()), (0x02, 0x31));
+        assert!(matches!(coalescer.push(0x02, 0x40, &[0; 9]), Err(PacketError::InvalidLength)));
+        assert_eq!(coalescer.pending().unwrap().body(), [0; 8]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Load-based collision detection.
+//!
+//! [`CollisionDetector`] compares the load reported by each servo with an expected [`LoadEnvelope`]:
+//! one limit while the servo holds its position and a higher one while it moves. A load above the limit
+//! for several consecutive samples is reported as a collision, and the output of the servo can be
+//! disabled right away so an arm yields when it hits something or someone.
+
+use crate::bus::{Bus, BusError};
+use crate::device::scs0009::{LOAD_ENCODING, REGISTER_TORQUE_SWITCH, SPEED_ENCODING};
+use crate::device::{StatusBlock, Timer};
+use crate::policy::WritePolicy;
+use crate::protocol::{StreamReader, StreamWriter};
+
+/// Expected load of a servo, in the unit of the load register (0x03ff is the full torque).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadEnvelope {
+    /// Maximum load while the servo holds its position.
+    pub holding: u16,
+    /// Maximum load while the servo moves.
+    pub moving: u16,
+}
+
+impl LoadEnvelope {
+    pub fn limit(&self, moving: bool) -> u16 {
+        if moving { self.moving } else { self.holding }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CollisionConfig {
+    /// Number of consecutive samples above the limit before a collision is reported.
+    pub debounce: u8,
+    /// Disables the output of a servo as soon as a collision is detected.
+    pub auto_stop: bool,
+}
+
+impl Default for CollisionConfig {
+    fn default() -> Self {
+        Self {
+            debounce: 2,
+            auto_stop: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionEvent {
+    /// The load of the servo exceeded `limit`. `stopped` tells whether the output has been disabled.
+    Collision { id: u8, load: u16, limit: u16, stopped: bool },
+    /// The load of the servo is back within the envelope.
+    Cleared { id: u8 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ServoState {
+    id: u8,
+    envelope: LoadEnvelope,
+    over_limit: u8,
+    tripped: bool,
+}
+
+pub struct CollisionDetector<const N: usize> {
+    config: CollisionConfig,
+    servos: [ServoState; N],
+}
+
+impl<const N: usize> CollisionDetector<N> {
+    pub fn new(servos: [(u8, LoadEnvelope); N], config: CollisionConfig) -> Self {
+        Self {
+            config,
+            servos: servos.map(|(id, envelope)| ServoState { id, envelope, over_limit: 0, tripped: false }),
+        }
+    }
+
+    pub fn config(&self) -> &CollisionConfig {
+        &self.config
+    }
+    /// Whether a collision has been reported for the servo and not cleared yet.
+    pub fn is_tripped(&self, id: u8) -> bool {
+        self.servos.iter().any(|servo| servo.id == id && servo.tripped)
+    }
+
+    /// Feeds a status sample of servo `id`. Samples of unknown servos are ignored.
+    /// The servo is considered moving while its speed is not zero.
+    /// The returned collision always has `stopped` unset, since `observe` does not touch the bus.
+    pub fn observe(&mut self, id: u8, status: &StatusBlock) -> Option<CollisionEvent> {
+        let debounce = self.config.debounce.max(1);
+        let servo = self.servos.iter_mut().find(|servo| servo.id == id)?;
+        let load = status.load.magnitude(LOAD_ENCODING);
+        let limit = servo.envelope.limit(status.speed.to_signed(SPEED_ENCODING) != 0);
+        if load > limit {
+            servo.over_limit = servo.over_limit.saturating_add(1);
+            if servo.over_limit >= debounce && !servo.tripped {
+                servo.tripped = true;
+                return Some(CollisionEvent::Collision { id, load, limit, stopped: false });
+            }
+        } else {
+            servo.over_limit = 0;
+            if servo.tripped {
+                servo.tripped = false;
+                return Some(CollisionEvent::Cleared { id });
+            }
+        }
+        None
+    }
+
+    /// Reads the status of every servo, reports the events to `on_event` and stops the servos which collided if `auto_stop` is set.
+    pub fn poll<R, W, T, const BUFFER_SIZE: usize, P, OnEvent>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, mut on_event: OnEvent) -> Result<(), BusError<R, W>>
+        where R: StreamReader,
+              W: StreamWriter,
+              T: Timer,
+              P: WritePolicy,
+              OnEvent: FnMut(CollisionEvent),
+    {
+        for index in 0..N {
+            let id = self.servos[index].id;
+            let status = bus.read_status_block(id)?;
+            match self.observe(id, &status) {
+                Some(CollisionEvent::Collision { id, load, limit, .. }) if self.config.auto_stop => {
+                    bus.write_register(id, REGISTER_TORQUE_SWITCH.address, &[0x00])?;
+                    on_event(CollisionEvent::Collision { id, load, limit, stopped: true });
+                }
+                Some(event) => on_event(event),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::device::scs0009::{REGISTER_CURRENT_LOAD_H, REGISTER_CURRENT_LOAD_L};
+    use crate::device::{RawLoad, RawSpeed};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    use core::time::Duration;
+    extern crate std;
+
+    const ENVELOPE: LoadEnvelope = LoadEnvelope { holding: 0x100, moving: 0x200 };
+
+    fn status(load: u16, speed: u16) -> StatusBlock {
+        StatusBlock { load: RawLoad(load), speed: RawSpeed(speed), ..Default::default() }
+    }
+
+    #[test]
+    fn test_collision_observe() {
+        let mut detector = CollisionDetector::new([(1, ENVELOPE), (2, ENVELOPE)], CollisionConfig { debounce: 2, auto_stop: false });
+        // Within the moving envelope.
+        assert_eq!(detector.observe(1, &status(0x180, 100)), None);
+        // Above the holding envelope, debounced. The direction bit is ignored.
+        assert_eq!(detector.observe(1, &status(0x400 | 0x180, 0)), None);
+        assert_eq!(detector.observe(1, &status(0x400 | 0x180, 0)), Some(CollisionEvent::Collision { id: 1, load: 0x180, limit: 0x100, stopped: false }));
+        assert!(detector.is_tripped(1));
+        assert!(!detector.is_tripped(2));
+        assert_eq!(detector.observe(1, &status(0x180, 0)), None);
+        assert_eq!(detector.observe(1, &status(0x080, 0)), Some(CollisionEvent::Cleared { id: 1 }));
+        assert_eq!(detector.observe(3, &status(0x3ff, 0)), None);
+    }
+
+    #[test]
+    fn test_collision_auto_stop() {
+        let mut emulator = BusEmulator::<2>::new(1, 2);
+        for servo in emulator.servos_mut() {
+            servo.registers_mut()[REGISTER_TORQUE_SWITCH.address as usize] = 1;
+        }
+        let registers = emulator.servo_mut(2).unwrap().registers_mut();
+        registers[REGISTER_CURRENT_LOAD_H.address as usize] = 0x03;
+        registers[REGISTER_CURRENT_LOAD_L.address as usize] = 0x00;
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(emulator);
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_secs(1),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        let mut detector = CollisionDetector::new([(1, ENVELOPE), (2, ENVELOPE)], CollisionConfig::default());
+        let mut events = std::vec::Vec::new();
+        for _ in 0..3 {
+            detector.poll(&mut bus, |event| events.push(event)).unwrap();
+        }
+        assert_eq!(events, [CollisionEvent::Collision { id: 2, load: 0x300, limit: 0x100, stopped: true }]);
+
+        let emulator = thread.stop();
+        assert_eq!(emulator.servo(1).unwrap().registers()[REGISTER_TORQUE_SWITCH.address as usize], 1);
+        assert_eq!(emulator.servo(2).unwrap().registers()[REGISTER_TORQUE_SWITCH.address as usize], 0);
+    }
+}
@@ -0,0 +1,234 @@
+//! Heap-backed WRITE and SYNC WRITE commands.
+//!
+//! [`WriteRegisterCommand`](crate::protocol::WriteRegisterCommand) and
+//! [`SyncWriteCommand`](crate::protocol::SyncWriteCommand) take their buffer size as a const generic, which suits
+//! firmware but forces desktop and wasm code to pick a size such as `<260>` up front. The commands here keep their
+//! packet in a `Vec` sized to the data, and the master accepts them wherever it accepts the fixed-size ones.
+
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::packet::{PacketError, PacketReader, PacketWriter};
+use crate::protocol::{sync_write_command_size, write_command_size, Command, SyncWritePacket, WritePacket, BROADCAST_ID, MAX_PACKET_SIZE};
+
+/// WRITE command whose packet is allocated to fit the data.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WriteRegisterCommandVec {
+    raw: Vec<u8>,
+}
+
+impl WriteRegisterCommandVec {
+    /// Maximum number of data bytes of a WRITE.
+    pub const MAX_LENGTH: usize = MAX_PACKET_SIZE + 2 - write_command_size(0);
+
+    /// Creates the command writing `data` to the registers of servo `id` from `address`, with the checksum updated.
+    /// Fails with [`PacketError::InvalidLength`] if the data does not fit in a packet.
+    pub fn new(id: u8, address: u8, data: &[u8]) -> Result<Self, PacketError> {
+        if data.len() > Self::MAX_LENGTH {
+            return Err(PacketError::InvalidLength);
+        }
+        let mut raw = vec![0; write_command_size(data.len())];
+        raw[0] = 0xff;  // Marker1
+        raw[1] = 0xff;  // Marker2
+        let mut writer = PacketWriter::new(&mut raw[2..]);
+        writer.set_id(id)?;
+        writer.set_length(3 + data.len() as u8)?;
+        let body = writer.data_mut()?;
+        body[0] = Command::WriteRegister as u8;
+        body[1] = address;
+        body[2..].copy_from_slice(data);
+        writer.update_checksum()?;
+        Ok(Self { raw })
+    }
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+    pub fn reader(&self) -> PacketReader<'_> {
+        PacketReader::new(&self.raw[2..])
+    }
+    pub fn writer(&mut self) -> PacketWriter<'_> {
+        PacketWriter::new(&mut self.raw[2..])
+    }
+    pub fn id(&self) -> u8 {
+        self.reader().id_unchecked()
+    }
+    /// The first register address written.
+    pub fn address(&self) -> u8 {
+        self.raw[5]
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.raw[6..self.len() - 1]
+    }
+    /// The data, to be followed by [`Self::update_checksum`] when changed.
+    pub fn body_mut(&mut self) -> &mut [u8] {
+        let len = self.len();
+        &mut self.raw[6..len - 1]
+    }
+    pub fn update_checksum(&mut self) -> Result<(), PacketError> {
+        self.writer().update_checksum()
+    }
+}
+
+impl WritePacket for WriteRegisterCommandVec {
+    fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+/// SYNC WRITE command whose packet grows with each servo added, up to the largest packet.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SyncWriteCommandVec {
+    raw: Vec<u8>,
+}
+
+impl SyncWriteCommandVec {
+    /// Creates a command which writes `length` bytes starting at `address` and has no servo yet.
+    /// Fails with [`PacketError::InvalidLength`] if not even one servo fits in a packet.
+    pub fn new(address: u8, length: usize) -> Result<Self, PacketError> {
+        if sync_write_command_size(length, 1) > MAX_PACKET_SIZE + 2 {
+            return Err(PacketError::InvalidLength);
+        }
+        let mut raw = vec![0; sync_write_command_size(length, 0)];
+        raw[0] = 0xff;  // Marker1
+        raw[1] = 0xff;  // Marker2
+        let mut writer = PacketWriter::new(&mut raw[2..]);
+        writer.set_id(BROADCAST_ID)?;
+        writer.set_length(4)?;
+        let data = writer.data_mut()?;
+        data[0] = Command::SyncWrite as u8;
+        data[1] = address;
+        data[2] = length as u8;
+        writer.update_checksum()?;
+        Ok(Self { raw })
+    }
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+    pub fn reader(&self) -> PacketReader<'_> {
+        PacketReader::new(&self.raw[2..])
+    }
+    /// The first register address written.
+    pub fn address(&self) -> u8 {
+        self.raw[5]
+    }
+    /// Number of bytes written to each servo.
+    pub fn length(&self) -> usize {
+        self.raw[6] as usize
+    }
+    /// Number of servos in the command.
+    pub fn count(&self) -> usize {
+        (self.len() - sync_write_command_size(0, 0)) / (1 + self.length())
+    }
+    /// Whether another servo fits in a packet.
+    pub fn is_full(&self) -> bool {
+        self.len() + 1 + self.length() > MAX_PACKET_SIZE + 2
+    }
+
+    /// Adds the data for servo `id` and updates the checksum. Returns false if the command is full.
+    /// Panics if the length of `data` differs from the length of the command.
+    pub fn push(&mut self, id: u8, data: &[u8]) -> bool {
+        assert_eq!(data.len(), self.length(), "the data length differs from the command");
+        if self.is_full() {
+            return false;
+        }
+        let checksum = self.raw.pop();
+        self.raw.push(id);
+        self.raw.extend_from_slice(data);
+        self.raw.extend(checksum);
+        let mut writer = PacketWriter::new(&mut self.raw[2..]);
+        let length = writer.length_unchecked() + 1 + data.len() as u8;
+        writer.set_length(length).unwrap();
+        writer.update_checksum().unwrap();
+        true
+    }
+    /// The servos in the command and the data written to each of them.
+    pub fn entries(&self) -> impl Iterator<Item = (u8, &[u8])> + '_ {
+        self.raw[7..self.len() - 1].chunks_exact(1 + self.length()).map(|entry| (entry[0], &entry[1..]))
+    }
+}
+
+impl SyncWritePacket for SyncWriteCommandVec {
+    fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+// Same format as the fixed-size commands, e.g. `ID 1 WRITE 0x2a: 08 00`.
+macro_rules! impl_command_fmt {
+    ($($command:ident),*) => {$(
+        impl fmt::Display for $command {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.reader(), f)
+            }
+        }
+        impl fmt::Debug for $command {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($command)).field(&format_args!("{}", self)).finish()
+            }
+        }
+    )*};
+}
+
+impl_command_fmt!(WriteRegisterCommandVec, SyncWriteCommandVec);
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use super::*;
+    use crate::protocol::{ProtocolMaster, ProtocolMasterConfig, SyncWriteCommand, WriteRegisterCommand};
+
+    #[test]
+    fn test_write_register_command_vec() {
+        let command = WriteRegisterCommandVec::new(0x01, 0x2a, &[0x01, 0x00]).unwrap();
+        let fixed = WriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        assert_eq!(command.packet(), fixed.packet());
+        assert_eq!((command.id(), command.address(), command.body()), (0x01, 0x2a, &[0x01, 0x00][..]));
+        assert_eq!(std::format!("{:?}", command), std::format!("{:?}", fixed).replace("Command", "CommandVec"));
+        assert!(WriteRegisterCommandVec::new(0x01, 0x2a, &[0; WriteRegisterCommandVec::MAX_LENGTH]).is_ok());
+        assert!(matches!(WriteRegisterCommandVec::new(0x01, 0x2a, &[0; WriteRegisterCommandVec::MAX_LENGTH + 1]), Err(PacketError::InvalidLength)));
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let broadcast = WriteRegisterCommandVec::new(BROADCAST_ID, 0x2a, &[0x01, 0x00]).unwrap();
+        master.write_register(&mut master_reader, &mut master_writer, &broadcast, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<Vec<_>>(), broadcast.packet());
+    }
+
+    #[test]
+    fn test_sync_write_command_vec() {
+        let mut command = SyncWriteCommandVec::new(0x2a, 2).unwrap();
+        assert!(command.push(0x01, &[0x01, 0x00]));
+        assert!(command.push(0x02, &[0x02, 0x00]));
+        assert_eq!(command.count(), 2);
+        assert_eq!(command.entries().collect::<Vec<_>>(), [(0x01, &[0x01, 0x00][..]), (0x02, &[0x02, 0x00][..])]);
+        let fixed = SyncWriteCommand::<{ sync_write_command_size(2, 2) }>::builder(0x2a, 2).servo(0x01, &[0x01, 0x00]).servo(0x02, &[0x02, 0x00]).build().unwrap();
+        assert_eq!(command.packet(), fixed.packet());
+
+        // The length field of 255 holds 83 servos of 2 bytes.
+        let mut full = SyncWriteCommandVec::new(0x2a, 2).unwrap();
+        for id in 0..83 {
+            assert!(full.push(id, &[0x00, 0x00]));
+        }
+        assert!(full.is_full());
+        assert!(!full.push(83, &[0x00, 0x00]));
+        assert!(full.reader().verify_checksum().is_ok());
+        assert!(matches!(SyncWriteCommandVec::new(0x2a, 255), Err(PacketError::InvalidLength)));
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        master.sync_write(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<Vec<_>>(), command.packet());
+    }
+}
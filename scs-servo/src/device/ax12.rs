@@ -0,0 +1,146 @@
+//! Dynamixel AX-12 and servos sharing its control table, e.g. the AX-12A and the AX-18A.
+//!
+//! The servos speak Dynamixel Protocol 1.0, which has the packet format of the SCS protocol, so they are driven
+//! through a [`ProtocolMaster`](crate::protocol::ProtocolMaster) with its dialect set to
+//! [`Dialect::Dynamixel1`](crate::protocol::Dialect::Dynamixel1). Words are stored with the L register first; convert
+//! them with [`WORD_ORDER`].
+
+use super::{AngleScale, RegisterDefinition, RegisterStorage, SignEncoding};
+use crate::parameters::ParameterMap;
+use crate::protocol::WordOrder;
+
+//                            Register Name,            Address,     R,     W,        Def, Description
+define_register!(EEPROM, REGISTER_MODEL_NUMBER_L,          0x00,  true, false, Some(0x0c), "Model Number L");
+define_register!(EEPROM, REGISTER_MODEL_NUMBER_H,          0x01,  true, false, Some(0x00), "Model Number H");
+define_register!(EEPROM, REGISTER_FIRMWARE_VERSION,        0x02,  true, false, None      , "Firmware Version");
+define_register!(EEPROM, REGISTER_ID,                      0x03,  true,  true, Some(0x01), "ID");
+define_register!(EEPROM, REGISTER_BAUD_RATE,               0x04,  true,  true, Some(0x01), "Baud Rate");
+define_register!(EEPROM, REGISTER_RETURN_DELAY_TIME,       0x05,  true,  true, Some(0xfa), "Return Delay Time");
+define_register!(EEPROM, REGISTER_CW_ANGLE_LIMIT_L,        0x06,  true,  true, Some(0x00), "CW Angle Limit L");
+define_register!(EEPROM, REGISTER_CW_ANGLE_LIMIT_H,        0x07,  true,  true, Some(0x00), "CW Angle Limit H");
+define_register!(EEPROM, REGISTER_CCW_ANGLE_LIMIT_L,       0x08,  true,  true, Some(0xff), "CCW Angle Limit L");
+define_register!(EEPROM, REGISTER_CCW_ANGLE_LIMIT_H,       0x09,  true,  true, Some(0x03), "CCW Angle Limit H");
+define_register!(EEPROM, REGISTER_TEMPERATURE_LIMIT,       0x0b,  true,  true, Some(0x46), "Temperature Limit");
+define_register!(EEPROM, REGISTER_MIN_VOLTAGE_LIMIT,       0x0c,  true,  true, Some(0x3c), "Min Voltage Limit");
+define_register!(EEPROM, REGISTER_MAX_VOLTAGE_LIMIT,       0x0d,  true,  true, Some(0x8c), "Max Voltage Limit");
+define_register!(EEPROM, REGISTER_MAX_TORQUE_L,            0x0e,  true,  true, Some(0xff), "Max Torque L");
+define_register!(EEPROM, REGISTER_MAX_TORQUE_H,            0x0f,  true,  true, Some(0x03), "Max Torque H");
+define_register!(EEPROM, REGISTER_STATUS_RETURN_LEVEL,     0x10,  true,  true, Some(0x02), "Status Return Level");
+define_register!(EEPROM, REGISTER_ALARM_LED,               0x11,  true,  true, Some(0x24), "Alarm LED", bits AlarmFlags {
+    /// Input voltage out of the range.
+    voltage / set_voltage: 0,
+    /// Target position out of the angle limits.
+    angle / set_angle: 1,
+    /// Temperature above the limit.
+    overheat / set_overheat: 2,
+    /// A command with a value out of the range.
+    range / set_range: 3,
+    /// A command with a wrong checksum.
+    checksum / set_checksum: 4,
+    /// Load above the torque limit.
+    overload / set_overload: 5,
+    /// A command with an undefined instruction.
+    instruction / set_instruction: 6,
+});
+// The Shutdown register has the same bits as `AlarmFlags`.
+define_register!(EEPROM, REGISTER_SHUTDOWN,                0x12,  true,  true, Some(0x24), "Shutdown");
+define_register!(RAM,    REGISTER_TORQUE_ENABLE,           0x18,  true,  true, Some(0x00), "Torque Enable");
+define_register!(RAM,    REGISTER_LED,                     0x19,  true,  true, Some(0x00), "LED");
+define_register!(RAM,    REGISTER_CW_COMPLIANCE_MARGIN,    0x1a,  true,  true, Some(0x01), "CW Compliance Margin");
+define_register!(RAM,    REGISTER_CCW_COMPLIANCE_MARGIN,   0x1b,  true,  true, Some(0x01), "CCW Compliance Margin");
+define_register!(RAM,    REGISTER_CW_COMPLIANCE_SLOPE,     0x1c,  true,  true, Some(0x20), "CW Compliance Slope");
+define_register!(RAM,    REGISTER_CCW_COMPLIANCE_SLOPE,    0x1d,  true,  true, Some(0x20), "CCW Compliance Slope");
+define_register!(RAM,    REGISTER_GOAL_POSITION_L,         0x1e,  true,  true, None      , "Goal Position L");
+define_register!(RAM,    REGISTER_GOAL_POSITION_H,         0x1f,  true,  true, None      , "Goal Position H");
+define_register!(RAM,    REGISTER_MOVING_SPEED_L,          0x20,  true,  true, Some(0x00), "Moving Speed L");
+define_register!(RAM,    REGISTER_MOVING_SPEED_H,          0x21,  true,  true, Some(0x00), "Moving Speed H");
+define_register!(RAM,    REGISTER_TORQUE_LIMIT_L,          0x22,  true,  true, None      , "Torque Limit L");
+define_register!(RAM,    REGISTER_TORQUE_LIMIT_H,          0x23,  true,  true, None      , "Torque Limit H");
+define_register!(RAM,    REGISTER_PRESENT_POSITION_L,      0x24,  true, false, None      , "Present Position L");
+define_register!(RAM,    REGISTER_PRESENT_POSITION_H,      0x25,  true, false, None      , "Present Position H");
+define_register!(RAM,    REGISTER_PRESENT_SPEED_L,         0x26,  true, false, None      , "Present Speed L");
+define_register!(RAM,    REGISTER_PRESENT_SPEED_H,         0x27,  true, false, None      , "Present Speed H");
+define_register!(RAM,    REGISTER_PRESENT_LOAD_L,          0x28,  true, false, None      , "Present Load L");
+define_register!(RAM,    REGISTER_PRESENT_LOAD_H,          0x29,  true, false, None      , "Present Load H");
+define_register!(RAM,    REGISTER_PRESENT_VOLTAGE,         0x2a,  true, false, None      , "Present Voltage");
+define_register!(RAM,    REGISTER_PRESENT_TEMPERATURE,     0x2b,  true, false, None      , "Present Temperature");
+define_register!(RAM,    REGISTER_REGISTERED,              0x2c,  true, false, Some(0x00), "Registered");
+define_register!(RAM,    REGISTER_MOVING,                  0x2e,  true, false, Some(0x00), "Moving");
+define_register!(RAM,    REGISTER_LOCK,                    0x2f,  true,  true, Some(0x00), "Lock");
+define_register!(RAM,    REGISTER_PUNCH_L,                 0x30,  true,  true, Some(0x20), "Punch L");
+define_register!(RAM,    REGISTER_PUNCH_H,                 0x31,  true,  true, Some(0x00), "Punch H");
+
+pub const REGISTER_LIST: &[RegisterDefinition] = &[
+    REGISTER_MODEL_NUMBER_L,
+    REGISTER_MODEL_NUMBER_H,
+    REGISTER_FIRMWARE_VERSION,
+    REGISTER_ID,
+    REGISTER_BAUD_RATE,
+    REGISTER_RETURN_DELAY_TIME,
+    REGISTER_CW_ANGLE_LIMIT_L,
+    REGISTER_CW_ANGLE_LIMIT_H,
+    REGISTER_CCW_ANGLE_LIMIT_L,
+    REGISTER_CCW_ANGLE_LIMIT_H,
+    REGISTER_TEMPERATURE_LIMIT,
+    REGISTER_MIN_VOLTAGE_LIMIT,
+    REGISTER_MAX_VOLTAGE_LIMIT,
+    REGISTER_MAX_TORQUE_L,
+    REGISTER_MAX_TORQUE_H,
+    REGISTER_STATUS_RETURN_LEVEL,
+    REGISTER_ALARM_LED,
+    REGISTER_SHUTDOWN,
+    REGISTER_TORQUE_ENABLE,
+    REGISTER_LED,
+    REGISTER_CW_COMPLIANCE_MARGIN,
+    REGISTER_CCW_COMPLIANCE_MARGIN,
+    REGISTER_CW_COMPLIANCE_SLOPE,
+    REGISTER_CCW_COMPLIANCE_SLOPE,
+    REGISTER_GOAL_POSITION_L,
+    REGISTER_GOAL_POSITION_H,
+    REGISTER_MOVING_SPEED_L,
+    REGISTER_MOVING_SPEED_H,
+    REGISTER_TORQUE_LIMIT_L,
+    REGISTER_TORQUE_LIMIT_H,
+    REGISTER_PRESENT_POSITION_L,
+    REGISTER_PRESENT_POSITION_H,
+    REGISTER_PRESENT_SPEED_L,
+    REGISTER_PRESENT_SPEED_H,
+    REGISTER_PRESENT_LOAD_L,
+    REGISTER_PRESENT_LOAD_H,
+    REGISTER_PRESENT_VOLTAGE,
+    REGISTER_PRESENT_TEMPERATURE,
+    REGISTER_REGISTERED,
+    REGISTER_MOVING,
+    REGISTER_LOCK,
+    REGISTER_PUNCH_L,
+    REGISTER_PUNCH_H,
+];
+
+/// Registers which change how the servo communicates, see
+/// [`scs0009::COMMUNICATION_REGISTERS`](super::scs0009::COMMUNICATION_REGISTERS).
+pub const COMMUNICATION_REGISTERS: &[RegisterDefinition] = &[
+    REGISTER_ID,
+    REGISTER_BAUD_RATE,
+    REGISTER_STATUS_RETURN_LEVEL,
+];
+
+/// Registers for [`write_parameters`](crate::parameters::write_parameters). The EEPROM needs no unlocking, and the
+/// Lock register is left alone, as it keeps the EEPROM locked until the servo is powered off.
+pub const PARAMETER_MAP: ParameterMap<'static> = ParameterMap {
+    definitions: REGISTER_LIST,
+    protected: COMMUNICATION_REGISTERS,
+    eeprom_lock: None,
+};
+
+/// Present Speed and Present Load: the direction in bit 10, set while turning clockwise.
+pub const SPEED_ENCODING: SignEncoding = SignEncoding::SignMagnitude { sign_bit: 10 };
+pub const LOAD_ENCODING: SignEncoding = SignEncoding::SignMagnitude { sign_bit: 10 };
+/// 1024 positions over 300 degrees and speed steps of 0.111 rpm.
+pub const ANGLE_SCALE: AngleScale = AngleScale {
+    center: 512.0,
+    degrees_per_step: 300.0 / 1024.0,
+    max_position: 1023,
+    speed_unit: 0.111 * 6.0,
+};
+/// Words are stored with the L register first.
+pub const WORD_ORDER: WordOrder = WordOrder::LittleEndian;
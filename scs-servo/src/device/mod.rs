@@ -1,6 +1,9 @@
-use crate::protocol::ProtocolHandlerError;
+use crate::protocol::{Deadline, ProtocolHandlerError, WordOrder};
+
+pub use raw::{AngleScale, PositionSpace, RawLoad, RawSpeed, SignEncoding};
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RegisterStorage {
     /// EEPROM
     Eeprom,
@@ -8,7 +11,10 @@ pub enum RegisterStorage {
     Ram,
 }
 
+/// Metadata of a register. With the `serde` feature, definitions are deserialized only from `'static` input, e.g.
+/// a table embedded in the binary, as the description is borrowed.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RegisterDefinition {
     pub address: u8,
     pub storage: RegisterStorage,
@@ -40,12 +46,37 @@ impl RegisterDefinition {
 
 macro_rules! define_register {
     (RAM, $name:ident, $address:expr, $readable:expr, $writable:expr, $default:expr, $description:literal) => {
-        #[allow(dead_code)]
-        const $name: RegisterDefinition = RegisterDefinition::new($address, RegisterStorage::Ram, $readable, $writable, $default, $description);
+        pub const $name: RegisterDefinition = RegisterDefinition::new($address, RegisterStorage::Ram, $readable, $writable, $default, $description);
     };
     (EEPROM, $name:ident, $address:expr, $readable:expr, $writable:expr, $default:expr, $description:literal) => {
-        #[allow(dead_code)]
-        const $name: RegisterDefinition = RegisterDefinition::new($address, RegisterStorage::Eeprom, $readable, $writable, $default, $description);
+        pub const $name: RegisterDefinition = RegisterDefinition::new($address, RegisterStorage::Eeprom, $readable, $writable, $default, $description);
+    };
+    // A register of named bits. Each bit is declared as `getter / setter: bit` and the accessors are generated on `$bits`.
+    ($storage:ident, $name:ident, $address:expr, $readable:expr, $writable:expr, $default:expr, $description:literal, bits $bits:ident { $($(#[$meta:meta])* $get:ident / $set:ident : $bit:expr),* $(,)? }) => {
+        define_register!($storage, $name, $address, $readable, $writable, $default, $description);
+
+        #[doc = concat!("Bits of the ", $description, " register.")]
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct $bits(pub u8);
+
+        impl $bits {
+            pub const fn bits(&self) -> u8 {
+                self.0
+            }
+            $(
+                $(#[$meta])*
+                pub const fn $get(&self) -> bool {
+                    self.0 & (1 << $bit) != 0
+                }
+                pub const fn $set(&mut self, value: bool) {
+                    if value {
+                        self.0 |= 1 << $bit;
+                    } else {
+                        self.0 &= !(1 << $bit);
+                    }
+                }
+            )*
+        }
     };
 }
 
@@ -88,6 +119,51 @@ pub trait ServoControl {
     fn update(&mut self) -> Result<(), Self::Error>;
 }
 
+/// [`ServoControl`] over an async transport. The conversions and the values cached by `update` stay synchronous.
+#[cfg(feature = "async")]
+pub trait ServoControlAsync {
+    type Error;
+    type Id;
+    type Period;
+    type Position;
+    type Speed;
+    type Torque;
+
+    fn min_speed(&self) -> Self::Speed;
+    fn max_speed(&self) -> Self::Speed;
+    fn max_period(&self) -> Self::Period;
+    fn to_speed(&self, speed: f64) -> Result<Self::Speed, Self::Error>;
+    fn to_period(&self, period: f64) -> Result<Self::Period, Self::Error>;
+
+    fn id(&self) -> Self::Id;
+    fn set_id(&mut self, id: Self::Id) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+
+    fn output_enable(&mut self) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+    fn output_disable(&mut self) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+    fn position_lower_limit(&mut self) -> impl core::future::Future<Output = Result<Self::Position, Self::Error>>;
+    fn position_upper_limit(&mut self) -> impl core::future::Future<Output = Result<Self::Position, Self::Error>>;
+
+    fn target_position(&mut self) -> impl core::future::Future<Output = Result<Self::Position, Self::Error>>;
+    fn set_target_position(&mut self, position: Self::Position) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+
+    fn target_period(&mut self) -> impl core::future::Future<Output = Result<Self::Period, Self::Error>>;
+    fn set_target_period(&mut self, period: Self::Period) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+
+    fn target_speed(&mut self) -> impl core::future::Future<Output = Result<Self::Speed, Self::Error>>;
+    fn set_target_speed(&mut self, speed: Self::Speed) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+
+    fn current_position(&mut self) -> Result<Self::Position, Self::Error>;
+    fn current_speed(&mut self) -> Result<Self::Speed, Self::Error>;
+    fn current_load(&mut self) -> Result<Self::Torque, Self::Error>;
+
+    fn update(&mut self) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+}
+
+/// Controls which keep the status block read by the last `ServoControl::update`.
+pub trait StatusSource {
+    fn status(&self) -> Option<&StatusBlock>;
+}
+
 pub trait Timer {
     type Instant : Instant;
     fn now() -> Self::Instant;
@@ -96,6 +172,33 @@ pub trait Instant {
     fn elapsed(&self) -> core::time::Duration;
 }
 
+/// Deadline of a transaction which expires when a timeout has elapsed from its start. See [`timeout_after`].
+pub struct TimeoutAfter<T: Timer> {
+    start: T::Instant,
+    timeout: core::time::Duration,
+    backoff: core::time::Duration,
+}
+
+impl<T: Timer> Deadline for TimeoutAfter<T> {
+    fn expired(&mut self) -> bool {
+        self.start.elapsed() >= self.backoff + self.timeout
+    }
+    /// Every attempt of a retried transaction gets the whole timeout after the backoff.
+    fn retry(&mut self, backoff: core::time::Duration) -> bool {
+        self.start = T::now();
+        self.backoff = backoff;
+        true
+    }
+    fn elapsed(&self) -> Option<core::time::Duration> {
+        Some(self.start.elapsed())
+    }
+}
+
+/// Returns a deadline for `ProtocolMaster` which expires when `timeout` has elapsed from now.
+pub fn timeout_after<T: Timer>(timeout: core::time::Duration) -> TimeoutAfter<T> {
+    TimeoutAfter { start: T::now(), timeout, backoff: core::time::Duration::ZERO }
+}
+
 #[cfg(feature = "std")]
 extern crate std;
 
@@ -115,18 +218,312 @@ impl Timer for std::time::Instant {
     }
 }
 
+#[cfg(feature = "std")]
+std::thread_local! {
+    static SIM_TIME: core::cell::Cell<core::time::Duration> = const { core::cell::Cell::new(core::time::Duration::ZERO) };
+}
+
+/// Simulated clock for deterministic tests.
+/// The time only advances when `SimTimer::advance` is called. Each thread has its own clock.
+#[cfg(feature = "std")]
+pub struct SimTimer;
+
+#[cfg(feature = "std")]
+impl SimTimer {
+    pub fn advance(duration: core::time::Duration) {
+        SIM_TIME.with(|time| time.set(time.get() + duration));
+    }
+    pub fn reset() {
+        SIM_TIME.with(|time| time.set(core::time::Duration::ZERO));
+    }
+    pub fn time() -> core::time::Duration {
+        SIM_TIME.with(|time| time.get())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg(feature = "std")]
+pub struct SimInstant(core::time::Duration);
+
+#[cfg(feature = "std")]
+impl Instant for SimInstant {
+    fn elapsed(&self) -> core::time::Duration {
+        SimTimer::time().saturating_sub(self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Timer for SimTimer {
+    type Instant = SimInstant;
+
+    fn now() -> Self::Instant {
+        SimInstant(SimTimer::time())
+    }
+}
+
+/// Telemetry from the current position to the current temperature (0x38-0x3f), read in one transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusBlock {
+    pub position: u16,
+    pub speed: RawSpeed,
+    pub load: RawLoad,
+    pub voltage: u8,
+    pub temperature: u8,
+}
+
+impl StatusBlock {
+    /// Number of registers in the block.
+    pub const LENGTH: usize = 8;
+
+    /// Decodes the big-endian register image of SCS servos. The speed and the load are kept as read, since their sign encoding depends on the model.
+    pub fn from_registers(registers: &[u8; Self::LENGTH]) -> Self {
+        Self::from_registers_with_order(registers, WordOrder::BigEndian)
+    }
+    /// Decodes the register image with words stored in `order`, e.g. [`sts::WORD_ORDER`] for STS servos.
+    pub fn from_registers_with_order(registers: &[u8; Self::LENGTH], order: WordOrder) -> Self {
+        Self {
+            position: order.from_bytes([registers[0], registers[1]]),
+            speed: RawSpeed(order.from_bytes([registers[2], registers[3]])),
+            load: RawLoad(order.from_bytes([registers[4], registers[5]])),
+            voltage: registers[6],
+            temperature: registers[7],
+        }
+    }
+}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<ProtocolHandlerError> {
     ProtocolError(ProtocolHandlerError),
     InvalidArgument,
     NotUpdated,
 }
 
+impl<ProtocolHandlerError> core::fmt::Display for Error<ProtocolHandlerError> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::ProtocolError(_) => f.write_str("servo transaction failed"),
+            Error::InvalidArgument => f.write_str("argument out of range"),
+            Error::NotUpdated => f.write_str("values have not been read from the servo yet"),
+        }
+    }
+}
+
+impl<ProtocolHandlerError: core::error::Error + 'static> core::error::Error for Error<ProtocolHandlerError> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::ProtocolError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 impl<R, W> From<ProtocolHandlerError<R, W>> for Error<ProtocolHandlerError<R, W>> {
     fn from(err: ProtocolHandlerError<R, W>) -> Self {
         Error::ProtocolError(err)
     }
 }
 
-pub mod scs0009;
\ No newline at end of file
+pub mod ax12;
+pub mod raw;
+pub mod scs0009;
+pub mod sts;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::{Deadline, MasterStats, ProtocolMaster, ProtocolMasterConfig, RetryPolicy, StreamReader};
+    use core::time::Duration;
+    extern crate std;
+
+    /// Reader which never receives anything and advances the simulated clock on every read.
+    struct SilentReader {
+        reads: usize,
+    }
+    impl StreamReader for SilentReader {
+        type Error = ();
+        fn read(&mut self, _data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+            self.reads += 1;
+            SimTimer::advance(Duration::from_millis(1));
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    #[test]
+    fn test_status_block_word_order() {
+        let registers = [0x01, 0x02, 0x00, 0x04, 0x00, 0x08, 0x78, 0x28];
+        let block = StatusBlock::from_registers(&registers);
+        assert_eq!((block.position, block.speed, block.load), (0x0102, RawSpeed(0x0004), RawLoad(0x0008)));
+        let block = StatusBlock::from_registers_with_order(&registers, sts::WORD_ORDER);
+        assert_eq!((block.position, block.speed, block.load), (0x0201, RawSpeed(0x0400), RawLoad(0x0800)));
+        assert_eq!((block.voltage, block.temperature), (0x78, 0x28));
+    }
+
+    #[test]
+    fn test_sim_timer() {
+        SimTimer::reset();
+        let start = SimTimer::now();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        SimTimer::advance(Duration::from_millis(5));
+        assert_eq!(start.elapsed(), Duration::from_millis(5));
+        let mut timeout = timeout_after::<SimTimer>(Duration::from_millis(10));
+        assert!(!timeout.expired());
+        SimTimer::advance(Duration::from_millis(9));
+        assert!(!timeout.expired());
+        SimTimer::advance(Duration::from_millis(1));
+        assert!(timeout.expired());
+        // A retry gets the whole timeout again.
+        assert!(timeout.retry(Duration::ZERO));
+        SimTimer::advance(Duration::from_millis(9));
+        assert!(!timeout.expired());
+        // The backoff of a retry is added to the timeout.
+        assert!(timeout.retry(Duration::from_millis(5)));
+        SimTimer::advance(Duration::from_millis(14));
+        assert!(!timeout.expired());
+        SimTimer::advance(Duration::from_millis(1));
+        assert!(timeout.expired());
+    }
+
+    #[test]
+    fn test_master_timeout_with_sim_timer() {
+        SimTimer::reset();
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let mut reader = SilentReader { reads: 0 };
+        let (mut writer, _receiver) = std::sync::mpsc::channel();
+        let mut buffer = [0; 2];
+        let result = master.read_register(&mut reader, &mut writer, 0x01, 0x38, &mut buffer, timeout_after::<SimTimer>(Duration::from_millis(10)));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        assert_eq!(reader.reads, 10);
+        assert_eq!(SimTimer::time(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_master_retry_with_sim_timer() {
+        SimTimer::reset();
+        let retry = RetryPolicy { retries: 2, backoff_ms: 0 };
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().retry(retry).build());
+        let mut reader = SilentReader { reads: 0 };
+        let (mut writer, receiver) = std::sync::mpsc::channel();
+        let mut buffer = [0; 2];
+        let result = master.read_register(&mut reader, &mut writer, 0x01, 0x38, &mut buffer, timeout_after::<SimTimer>(Duration::from_millis(10)));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        // Every attempt waits for the whole timeout.
+        assert_eq!(reader.reads, 30);
+        assert_eq!(SimTimer::time(), Duration::from_millis(30));
+        assert_eq!(receiver.try_iter().count(), 3 * 8);
+    }
+
+    #[test]
+    fn test_master_retry_backoff_with_sim_timer() {
+        SimTimer::reset();
+        let retry = RetryPolicy { retries: 1, backoff_ms: 5 };
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().retry(retry).build());
+        let mut reader = SilentReader { reads: 0 };
+        let (mut writer, receiver) = std::sync::mpsc::channel();
+        let mut buffer = [0; 2];
+        let result = master.read_register(&mut reader, &mut writer, 0x01, 0x38, &mut buffer, timeout_after::<SimTimer>(Duration::from_millis(10)));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        // The bus is polled during the backoff, then the retry gets the whole timeout.
+        assert_eq!(reader.reads, 10 + 5 + 10);
+        assert_eq!(SimTimer::time(), Duration::from_millis(25));
+        assert_eq!(receiver.try_iter().count(), 2 * 8);
+    }
+
+    #[test]
+    fn test_master_inter_byte_timeout_with_sim_timer() {
+        /// Reader which receives one byte of a response truncated after its length field on every read, then
+        /// nothing, and advances the simulated clock on every read.
+        struct StallingReader {
+            bytes: std::vec::IntoIter<u8>,
+        }
+        impl StreamReader for StallingReader {
+            type Error = ();
+            fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+                SimTimer::advance(Duration::from_millis(1));
+                data[0] = self.bytes.next().ok_or(nb::Error::WouldBlock)?;
+                Ok(1)
+            }
+        }
+
+        SimTimer::reset();
+        let config = ProtocolMasterConfig::builder().inter_byte_timeout(Duration::from_millis(3)).build();
+        let mut master = ProtocolMaster::<16>::new(config);
+        let mut reader = StallingReader { bytes: std::vec![0xff, 0xff, 0x01, 0x03].into_iter() };
+        let (mut writer, _receiver) = std::sync::mpsc::channel();
+        let mut buffer = [0; 1];
+        let result = master.read_register(&mut reader, &mut writer, 0x01, 0x2a, &mut buffer, timeout_after::<SimTimer>(Duration::from_millis(100)));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        // The length field arrived after 4 ms and the next read at 5 ms found nothing, then the packet stalled for the
+        // inter-byte timeout.
+        assert_eq!(SimTimer::time(), Duration::from_millis(8));
+        assert!(master.response_status().is_none());
+    }
+
+    #[test]
+    fn test_master_round_trip_with_sim_timer() {
+        /// Reader which receives nothing for `delay` reads, then one byte per read, and advances the simulated clock
+        /// on every read.
+        struct ClockedReader {
+            delay: usize,
+            bytes: std::vec::IntoIter<u8>,
+        }
+        impl StreamReader for ClockedReader {
+            type Error = ();
+            fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+                SimTimer::advance(Duration::from_millis(1));
+                if self.delay > 0 {
+                    self.delay -= 1;
+                    return Err(nb::Error::WouldBlock);
+                }
+                data[0] = self.bytes.next().ok_or(nb::Error::WouldBlock)?;
+                Ok(1)
+            }
+        }
+        let response = || std::vec![0xff, 0xff, 0x01, 0x02, 0x00, 0xfc].into_iter();
+
+        let mut master = ProtocolMaster::<16, MasterStats>::new(ProtocolMasterConfig::default());
+        let (mut writer, _receiver) = std::sync::mpsc::channel();
+        let mut round_trip = |delay| {
+            SimTimer::reset();
+            let mut reader = ClockedReader { delay, bytes: response() };
+            master.ping(&mut reader, &mut writer, 0x01, timeout_after::<SimTimer>(Duration::from_millis(100))).unwrap();
+            SimTimer::time()
+        };
+        let first = round_trip(0);
+        let second = round_trip(4);
+        assert_eq!(second, first + Duration::from_millis(4));
+
+        let stats = master.stats();
+        assert_eq!(stats.round_trips, 2);
+        assert_eq!((stats.round_trip_min(), stats.round_trip_max(), stats.round_trip_last()), (Some(first), Some(second), Some(second)));
+        assert_eq!(stats.round_trip_mean(), Some((first + second) / 2));
+        // Deadlines without a clock measure nothing.
+        let mut reader = ClockedReader { delay: 0, bytes: response() };
+        master.ping(&mut reader, &mut writer, 0x01, || false).unwrap();
+        assert_eq!(master.stats().round_trips, 2);
+    }
+
+    #[test]
+    fn test_master_frame_gap() {
+        let config = ProtocolMasterConfig::builder().frame_gap(Duration::from_millis(2)).build();
+        let mut master = ProtocolMaster::<16>::new(config);
+        let (_sender, mut reader) = std::sync::mpsc::channel::<u8>();
+        let (mut writer, receiver) = std::sync::mpsc::channel();
+        let start = std::time::Instant::now();
+        master.action(&mut reader, &mut writer, timeout_after::<std::time::Instant>(Duration::from_secs(1))).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(2));
+        assert_eq!(receiver.try_iter().count(), 6);
+        // Deadlines without a clock do not wait.
+        master.action(&mut reader, &mut writer, || false).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_register_definition_serde() {
+        let json = serde_json::to_string(&scs0009::REGISTER_ID).unwrap();
+        assert_eq!(json, r#"{"address":5,"storage":"Eeprom","readable":true,"writable":true,"default":0,"description":"ID"}"#);
+        let json: &'static str = std::boxed::Box::leak(json.into_boxed_str());
+        let definition = serde_json::from_str::<RegisterDefinition>(json).unwrap();
+        assert_eq!((definition.address, definition.default, definition.description), (0x05, Some(0x00), "ID"));
+    }
+}
@@ -0,0 +1,202 @@
+//! Raw register values which carry a direction.
+//!
+//! Speed and load registers encode their sign differently depending on the model: the SCS series puts
+//! the direction in a single bit above the magnitude, at bit 15 for the speed and at bit 10 for the load.
+//! [`RawSpeed`] and [`RawLoad`] keep the register value as read, so it cannot be used as a signed value
+//! by accident, and are converted with the [`SignEncoding`] of the model, e.g. [`SPEED_ENCODING`](super::scs0009::SPEED_ENCODING)
+//! and [`LOAD_ENCODING`](super::scs0009::LOAD_ENCODING) of the SCS0009.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignEncoding {
+    /// The direction is `sign_bit` and the magnitude the bits below it. Bits above `sign_bit` are ignored.
+    SignMagnitude { sign_bit: u8 },
+    TwosComplement,
+}
+
+impl SignEncoding {
+    fn magnitude_mask(sign_bit: u8) -> u16 {
+        ((1u32 << sign_bit) - 1) as u16
+    }
+
+    /// Converts a register value into a signed value.
+    pub fn decode(&self, raw: u16) -> i16 {
+        match *self {
+            SignEncoding::SignMagnitude { sign_bit } => {
+                let magnitude = (raw & Self::magnitude_mask(sign_bit)) as i16;
+                if raw & (1 << sign_bit) != 0 { -magnitude } else { magnitude }
+            }
+            SignEncoding::TwosComplement => raw as i16,
+        }
+    }
+    /// Converts a signed value into a register value. Magnitudes which do not fit are saturated.
+    pub fn encode(&self, value: i16) -> u16 {
+        match *self {
+            SignEncoding::SignMagnitude { sign_bit } => {
+                let magnitude = value.unsigned_abs().min(Self::magnitude_mask(sign_bit));
+                if value < 0 { magnitude | (1 << sign_bit) } else { magnitude }
+            }
+            SignEncoding::TwosComplement => value as u16,
+        }
+    }
+}
+
+/// Value of a speed register as read from the servo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RawSpeed(pub u16);
+
+impl RawSpeed {
+    pub fn from_signed(value: i16, encoding: SignEncoding) -> Self {
+        Self(encoding.encode(value))
+    }
+    /// Signed speed. Positive values move toward larger positions.
+    pub fn to_signed(self, encoding: SignEncoding) -> i16 {
+        encoding.decode(self.0)
+    }
+}
+
+/// Value of a load register as read from the servo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RawLoad(pub u16);
+
+impl RawLoad {
+    pub fn from_signed(value: i16, encoding: SignEncoding) -> Self {
+        Self(encoding.encode(value))
+    }
+    /// Signed load. The sign tells the direction the load acts in.
+    pub fn to_signed(self, encoding: SignEncoding) -> i16 {
+        encoding.decode(self.0)
+    }
+    /// Load regardless of its direction.
+    pub fn magnitude(self, encoding: SignEncoding) -> u16 {
+        self.to_signed(encoding).unsigned_abs()
+    }
+}
+
+/// Conversions between raw positions, normalized positions and angles of a model.
+///
+/// Normalized positions run from 0 at the lowest to 1 at the highest raw position, and angles are measured from the
+/// center of the range, so code written in either of them runs on any model, e.g. with 1024 steps over 300 degrees
+/// on the SCS0009 or 4096 steps over 360 degrees on the STS series.
+pub trait PositionSpace {
+    /// Highest raw position.
+    fn max_position(&self) -> u16;
+    /// Raw position at the center of the range, i.e. at 0 degrees.
+    fn center(&self) -> f32;
+    /// Degrees per position step.
+    fn degrees_per_step(&self) -> f32;
+
+    /// Normalized position of `position`. Positions above the range are clamped.
+    fn to_normalized(&self, position: u16) -> f32 {
+        position.min(self.max_position()) as f32 / self.max_position() as f32
+    }
+    /// Nearest raw position of a normalized position. Values outside 0 to 1 are clamped.
+    fn normalized_to_position(&self, value: f32) -> u16 {
+        ((value.clamp(0.0, 1.0) * self.max_position() as f32 + 0.5) as u16).min(self.max_position())
+    }
+    /// Angle of `position` from the center, in degrees.
+    fn to_degrees(&self, position: u16) -> f32 {
+        (position as f32 - self.center()) * self.degrees_per_step()
+    }
+    /// Nearest position of an angle from the center, or `None` if it is out of the range of the servo.
+    fn to_position(&self, degrees: f32) -> Option<u16> {
+        let position = self.center() + degrees / self.degrees_per_step();
+        if (-0.5..self.max_position() as f32 + 0.5).contains(&position) {
+            Some(((position + 0.5) as u16).min(self.max_position()))
+        } else {
+            None
+        }
+    }
+    /// Angle of a normalized position from the center, in degrees.
+    fn normalized_to_degrees(&self, value: f32) -> f32 {
+        (value * self.max_position() as f32 - self.center()) * self.degrees_per_step()
+    }
+    /// Normalized position of an angle from the center. Not clamped.
+    fn degrees_to_normalized(&self, degrees: f32) -> f32 {
+        (self.center() + degrees / self.degrees_per_step()) / self.max_position() as f32
+    }
+    /// Position of the same angle in `other`, or `None` if it is out of the range of `other`.
+    fn convert<S: PositionSpace>(&self, position: u16, other: &S) -> Option<u16> {
+        other.to_position(self.to_degrees(position))
+    }
+}
+
+/// Relation of the position and speed registers of a model to physical units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AngleScale {
+    /// Position at the center of the range.
+    pub center: f32,
+    /// Degrees per position step.
+    pub degrees_per_step: f32,
+    pub max_position: u16,
+    /// Degrees per second per speed step.
+    pub speed_unit: f32,
+}
+
+impl AngleScale {
+    /// Signed speed in degrees per second.
+    pub fn speed_to_degrees(&self, speed: i16) -> f32 {
+        speed as f32 * self.speed_unit
+    }
+}
+
+impl PositionSpace for AngleScale {
+    fn max_position(&self) -> u16 {
+        self.max_position
+    }
+    fn center(&self) -> f32 {
+        self.center
+    }
+    fn degrees_per_step(&self) -> f32 {
+        self.degrees_per_step
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign_encoding() {
+        let speed = SignEncoding::SignMagnitude { sign_bit: 15 };
+        assert_eq!(RawSpeed(0x89ab).to_signed(speed), -0x09ab);
+        assert_eq!(RawSpeed(0x09ab).to_signed(speed), 0x09ab);
+        assert_eq!(RawSpeed(0x8000).to_signed(speed), 0);
+        assert_eq!(RawSpeed::from_signed(-0x09ab, speed), RawSpeed(0x89ab));
+        assert_eq!(RawSpeed::from_signed(i16::MIN, speed), RawSpeed(0xffff));
+
+        let load = SignEncoding::SignMagnitude { sign_bit: 10 };
+        assert_eq!(RawLoad(0x0523).to_signed(load), -0x0123);
+        assert_eq!(RawLoad(0x0123).to_signed(load), 0x0123);
+        // Bits above the sign are not part of the value.
+        assert_eq!(RawLoad(0xcdef).to_signed(load), -0x01ef);
+        assert_eq!(RawLoad(0x07ff).magnitude(load), 0x03ff);
+        assert_eq!(RawLoad::from_signed(-0x0500, load), RawLoad(0x07ff));
+
+        assert_eq!(RawSpeed(0xffff).to_signed(SignEncoding::TwosComplement), -1);
+        assert_eq!(RawSpeed::from_signed(-1, SignEncoding::TwosComplement), RawSpeed(0xffff));
+    }
+
+    #[test]
+    fn test_position_space() {
+        use crate::device::{scs0009, sts};
+        let scs = scs0009::ANGLE_SCALE;
+        assert_eq!(scs.to_normalized(1023), 1.0);
+        assert_eq!(scs.to_normalized(2000), 1.0);
+        assert_eq!(scs.normalized_to_position(0.5), 512);
+        assert_eq!(scs.normalized_to_position(-1.0), 0);
+        assert_eq!(sts::ANGLE_SCALE.normalized_to_position(1.0), 4095);
+        assert!(scs.normalized_to_degrees(0.5).abs() < 1e-3);
+        assert!((scs.normalized_to_degrees(1.0) - 150.0).abs() < 1e-3);
+        assert!((scs.degrees_to_normalized(-150.0)).abs() < 1e-6);
+        assert_eq!(scs.to_position(-150.0), Some(0));
+        assert_eq!(scs.to_position(151.0), None);
+
+        // 90 degrees above the center on both models.
+        let position = scs.to_position(90.0).unwrap();
+        assert_eq!(position, 818);
+        assert_eq!(scs.convert(position, &sts::ANGLE_SCALE), Some(3071));
+        assert_eq!(sts::ANGLE_SCALE.convert(3071, &scs), Some(818));
+        // Beyond the range of the SCS0009.
+        assert_eq!(sts::ANGLE_SCALE.convert(4095, &scs), None);
+    }
+}
@@ -1,11 +1,19 @@
-use core::{borrow::Borrow, marker::PhantomData, time::Duration};
+use core::{fmt, marker::PhantomData, time::Duration};
 
-use crate::protocol::{ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, WriteRegisterCommand};
+use crate::parameters::ParameterMap;
+use crate::policy::{AllowAll, WriteDecision, WritePolicy};
+#[cfg(feature = "std")]
+use crate::eventlog::Outcome;
+#[cfg(feature = "std")]
+use crate::trace::{Access, RegisterTrace, Tracer};
+use crate::protocol::{packet_size, write_command_size, ProtocolHandlerError, ProtocolMasterConfig, SmallMaster, WordOrder, WriteRegisterCommand, SMALL_BUFFER_SIZE};
+#[cfg(feature = "async")]
+use crate::protocol::{StreamReaderAsync, StreamWriterAsync};
 
-use super::{Error, Instant, RegisterDefinition, RegisterStorage};
+use super::{AngleScale, Error, RawLoad, RawSpeed, RegisterDefinition, RegisterStorage, SignEncoding, StatusBlock};
 //                            Register Name,            Address,     R,     W,        Def, Description
 define_register!(EEPROM, REGISTER_VERSION_H,               0x03,  true, false, None      , "Software Version H");
-define_register!(EEPROM, REGISTER_VERSION_L,               0x04,  true, false, None      , "Software Version H");
+define_register!(EEPROM, REGISTER_VERSION_L,               0x04,  true, false, None      , "Software Version L");
 define_register!(EEPROM, REGISTER_ID,                      0x05,  true,  true, Some(0x00), "ID");
 define_register!(EEPROM, REGISTER_BAUD_RATE,               0x06,  true,  true, Some(0x00), "Baud Rate");
 define_register!(EEPROM, REGISTER_RESPONSE_TIME,           0x07,  true,  true, Some(0x00), "Response Time");
@@ -20,7 +28,17 @@ define_register!(EEPROM, REGISTER_MIN_INPUT_VOLTAGE,       0x0f,  true,  true, S
 define_register!(EEPROM, REGISTER_MAX_TORQUE_H,            0x10,  true,  true, Some(0x03), "Max Torque H");
 define_register!(EEPROM, REGISTER_MAX_TORQUE_L,            0x11,  true,  true, Some(0xff), "Max Torque L");
 define_register!(EEPROM, REGISTER_HIGH_VOLTAGE_FLAG,       0x12,  true,  true, Some(0x00), "High Voltage Flag");
-define_register!(EEPROM, REGISTER_ALARM_FLAG,              0x13,  true,  true, Some(0x25), "Alarm Flag");
+define_register!(EEPROM, REGISTER_ALARM_FLAG,              0x13,  true,  true, Some(0x25), "Alarm Flag", bits AlarmFlags {
+    /// Input voltage out of the range.
+    voltage / set_voltage: 0,
+    /// Target position out of the position limits.
+    angle / set_angle: 1,
+    /// Temperature above the upper limit.
+    overheat / set_overheat: 2,
+    /// Load above the max torque.
+    overload / set_overload: 5,
+});
+// The LED Alarm Flag register has the same bits as `AlarmFlags`.
 define_register!(EEPROM, REGISTER_LED_ALARM_FLAG,          0x14,  true,  true, Some(0x25), "LED Alarm Flag");
 define_register!(RAM,    REGISTER_TORQUE_SWITCH,           0x28,  true,  true, Some(0x00), "Torque Switch");
 define_register!(RAM,    REGISTER_TARGET_POSITION_H,       0x2a,  true,  true, None      , "Target Position H");
@@ -76,134 +94,558 @@ pub const REGISTER_LIST: &[RegisterDefinition] = &[
     REGISTER_CURRENT_TEMPERATURE,
 ];
 
-pub struct Scs0009ServoControl<R, W, Timer> {
+/// Registers which change how the servo communicates. Protecting them with
+/// [`ProtectedRegisters`](crate::policy::ProtectedRegisters) during normal operation keeps a bug in the control
+/// logic from making the servo unreachable.
+pub const COMMUNICATION_REGISTERS: &[RegisterDefinition] = &[
+    REGISTER_ID,
+    REGISTER_BAUD_RATE,
+    REGISTER_RESPONSE_ENABLE,
+];
+
+/// Registers for [`write_parameters`](crate::parameters::write_parameters).
+pub const PARAMETER_MAP: ParameterMap<'static> = ParameterMap {
+    definitions: REGISTER_LIST,
+    protected: COMMUNICATION_REGISTERS,
+    eeprom_lock: Some(REGISTER_EEPROM_LOCK),
+};
+
+/// RAM configuration which the servo loses when it restarts, in the order it is restored.
+/// The target position is not included, so a restarted servo holds its position, and the torque switch
+/// comes last, so the servo moves at the restored speed.
+pub const VOLATILE_REGISTERS: &[RegisterDefinition] = &[
+    REGISTER_TARGET_PERIOD_H,
+    REGISTER_TARGET_PERIOD_L,
+    REGISTER_TARGET_SPEED_H,
+    REGISTER_TARGET_SPEED_L,
+    REGISTER_TORQUE_SWITCH,
+];
+
+/// Current Speed and Target Speed: the direction in bit 15, set while moving toward smaller positions.
+pub const SPEED_ENCODING: SignEncoding = SignEncoding::SignMagnitude { sign_bit: 15 };
+/// Current Load: 0 to 1023 of the maximum torque with the direction in bit 10.
+pub const LOAD_ENCODING: SignEncoding = SignEncoding::SignMagnitude { sign_bit: 10 };
+/// 1024 positions over 300 degrees and speed steps of 0.19 deg/s.
+pub const ANGLE_SCALE: AngleScale = AngleScale {
+    center: 511.5,
+    degrees_per_step: 300.0 / 1023.0,
+    max_position: 1023,
+    speed_unit: 0.19,
+};
+/// Words are stored with the H register first.
+pub const WORD_ORDER: WordOrder = WordOrder::BigEndian;
+
+/// Value of a register, or of a pair of H and L registers, decoded by [`decode_registers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterValue {
+    Byte(u8),
+    Word(u16),
+    /// Signed speed or load.
+    Signed(i16),
+    /// Voltage in 0.1 V.
+    Voltage(u8),
+    /// Temperature in degC.
+    Temperature(u8),
+    Alarms(AlarmFlags),
+    /// Software version H and L.
+    Version(u8, u8),
+}
+
+impl fmt::Display for RegisterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RegisterValue::Byte(value) => write!(f, "{} (0x{:02x})", value, value),
+            RegisterValue::Word(value) => write!(f, "{} (0x{:04x})", value, value),
+            RegisterValue::Signed(value) => write!(f, "{}", value),
+            RegisterValue::Voltage(value) => write!(f, "{}.{} V", value / 10, value % 10),
+            RegisterValue::Temperature(value) => write!(f, "{} degC", value),
+            RegisterValue::Alarms(alarms) => {
+                write!(f, "0x{:02x}", alarms.bits())?;
+                let names = [(alarms.voltage(), "voltage"), (alarms.angle(), "angle"), (alarms.overheat(), "overheat"), (alarms.overload(), "overload")];
+                for (index, (_, name)) in names.iter().filter(|(set, _)| *set).enumerate() {
+                    write!(f, "{}{}", if index == 0 { " " } else { ", " }, name)?;
+                }
+                Ok(())
+            }
+            RegisterValue::Version(high, low) => write!(f, "{:02X} {:02X}", high, low),
+        }
+    }
+}
+
+/// A known register decoded by [`decode_registers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedRegister {
+    pub address: u8,
+    /// Description of the register, without the H suffix for pairs.
+    pub name: &'static str,
+    pub value: RegisterValue,
+}
+
+fn is_one_of(register: &RegisterDefinition, registers: &[RegisterDefinition]) -> bool {
+    registers.iter().any(|other| other.address == register.address)
+}
+
+/// Decodes the known registers in `data` read from `address`. Pairs of H and L registers are decoded as one
+/// value if both are in `data`. Addresses without a register are skipped.
+pub fn decode_registers(address: u8, data: &[u8]) -> impl Iterator<Item = DecodedRegister> + '_ {
+    let end = address as usize + data.len();
+    let mut index = 0;
+    core::iter::from_fn(move || {
+        while let Some(register) = REGISTER_LIST.get(index) {
+            index += 1;
+            let Some(offset) = (register.address as usize).checked_sub(address as usize).filter(|offset| *offset < data.len()) else {
+                continue;
+            };
+            let low = REGISTER_LIST.get(index)
+                .filter(|low| low.address == register.address + 1 && (low.address as usize) < end)
+                .filter(|low| register.description.ends_with(" H") && low.description.ends_with(" L"));
+            if low.is_some() {
+                index += 1;
+                let (high, low) = (data[offset], data[offset + 1]);
+                let word = WORD_ORDER.from_bytes([high, low]);
+                let value = if is_one_of(register, &[REGISTER_VERSION_H]) {
+                    RegisterValue::Version(high, low)
+                } else if is_one_of(register, &[REGISTER_TARGET_SPEED_H, REGISTER_CURRENT_SPEED_H]) {
+                    RegisterValue::Signed(RawSpeed(word).to_signed(SPEED_ENCODING))
+                } else if is_one_of(register, &[REGISTER_CURRENT_LOAD_H]) {
+                    RegisterValue::Signed(RawLoad(word).to_signed(LOAD_ENCODING))
+                } else {
+                    RegisterValue::Word(word)
+                };
+                let name = &register.description[..register.description.len() - 2];
+                return Some(DecodedRegister { address: register.address, name, value });
+            }
+            let byte = data[offset];
+            let value = if is_one_of(register, &[REGISTER_MAX_INPUT_VOLTAGE, REGISTER_MIN_INPUT_VOLTAGE, REGISTER_CURRENT_VOLTAGE]) {
+                RegisterValue::Voltage(byte)
+            } else if is_one_of(register, &[REGISTER_UPPER_TEMPERATURE_LIMIT, REGISTER_CURRENT_TEMPERATURE]) {
+                RegisterValue::Temperature(byte)
+            } else if is_one_of(register, &[REGISTER_ALARM_FLAG, REGISTER_LED_ALARM_FLAG]) {
+                RegisterValue::Alarms(AlarmFlags(byte))
+            } else {
+                RegisterValue::Byte(byte)
+            };
+            return Some(DecodedRegister { address: register.address, name: register.description, value });
+        }
+        None
+    })
+}
+
+/// Protection limits stored in the EEPROM.
+///
+/// The factory limits let the servo run up to 80 degC and 25 V, which is far outside the rating of the SCS0009.
+/// The presets keep the servo within its rating and shut the output down on any alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SafeLimits {
+    /// Upper temperature limit in degC.
+    pub max_temperature: u8,
+    /// Input voltage range in 0.1 V.
+    pub max_voltage: u8,
+    pub min_voltage: u8,
+    /// Maximum torque. 0x03ff is the full torque.
+    pub max_torque: u16,
+    /// Alarms which shut down the output.
+    pub alarm_shutdown: AlarmFlags,
+    /// Alarms which blink the LED.
+    pub alarm_led: AlarmFlags,
+}
+
+impl SafeLimits {
+    /// Number of registers from the upper temperature limit to the max torque.
+    const LIMIT_REGISTERS: usize = 5;
+    const ALARMS: AlarmFlags = {
+        let mut alarms = AlarmFlags(0);
+        alarms.set_voltage(true);
+        alarms.set_overheat(true);
+        alarms.set_overload(true);
+        alarms
+    };
+
+    /// Limits for first experiments and unattended operation. The torque is limited to about 70%.
+    pub const fn conservative() -> Self {
+        Self {
+            max_temperature: 65,
+            max_voltage: 65,
+            min_voltage: 45,
+            max_torque: 0x02cc,
+            alarm_shutdown: Self::ALARMS,
+            alarm_led: Self::ALARMS,
+        }
+    }
+    /// Limits at the edge of the rating, with the full torque available.
+    pub const fn standard() -> Self {
+        Self {
+            max_temperature: 70,
+            max_voltage: 70,
+            min_voltage: 40,
+            max_torque: 0x03ff,
+            alarm_shutdown: Self::ALARMS,
+            alarm_led: Self::ALARMS,
+        }
+    }
+    /// The register defaults of a new servo.
+    pub const fn factory() -> Self {
+        Self {
+            max_temperature: 0x50,
+            max_voltage: 0xfa,
+            min_voltage: 0x32,
+            max_torque: 0x03ff,
+            alarm_shutdown: AlarmFlags(0x25),
+            alarm_led: AlarmFlags(0x25),
+        }
+    }
+
+    /// Encodes the registers from the upper temperature limit to the max torque.
+    fn limit_registers(&self) -> [u8; Self::LIMIT_REGISTERS] {
+        let [torque_h, torque_l] = WORD_ORDER.to_bytes(self.max_torque);
+        [self.max_temperature, self.max_voltage, self.min_voltage, torque_h, torque_l]
+    }
+    /// Decodes the limit registers and the two alarm registers.
+    fn from_registers(registers: &[u8; Self::LIMIT_REGISTERS], alarms: &[u8; 2]) -> Self {
+        Self {
+            max_temperature: registers[0],
+            max_voltage: registers[1],
+            min_voltage: registers[2],
+            max_torque: WORD_ORDER.from_bytes([registers[3], registers[4]]),
+            alarm_shutdown: AlarmFlags(alarms[0]),
+            alarm_led: AlarmFlags(alarms[1]),
+        }
+    }
+}
+
+/// Registers from the target position to the target speed, which `set_motion` writes at once.
+const MOTION_REGISTERS: usize = 6;
+/// Largest write of a command sequence: the target registers written by `set_motion`.
+const STEP_DATA_SIZE: usize = if MOTION_REGISTERS > SafeLimits::LIMIT_REGISTERS { MOTION_REGISTERS } else { SafeLimits::LIMIT_REGISTERS };
+
+/// A register write in a command sequence.
+///
+/// The register sequences of the SCS0009 are built once as steps and executed by both
+/// [`Scs0009ServoControl`] and [`Scs0009ServoControlAsync`], so the blocking and the async control
+/// always send the same commands.
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    address: u8,
+    data: [u8; STEP_DATA_SIZE],
+    length: usize,
+    /// Executed even if an earlier step failed, e.g. to lock the EEPROM again.
+    cleanup: bool,
+}
+
+impl Step {
+    fn write(address: u8, data: &[u8]) -> Self {
+        let mut step = Self { address, data: [0; STEP_DATA_SIZE], length: data.len(), cleanup: false };
+        step.data[..data.len()].copy_from_slice(data);
+        step
+    }
+    fn write_u8(address: u8, value: u8) -> Self {
+        Self::write(address, &[value])
+    }
+    fn write_u16(address: u8, value: u16) -> Self {
+        Self::write(address, &WORD_ORDER.to_bytes(value))
+    }
+    fn cleanup(self) -> Self {
+        Self { cleanup: true, ..self }
+    }
+    fn data(&self) -> &[u8] {
+        &self.data[..self.length]
+    }
+    /// Checks the result of the transaction. The write of the ID register is answered from the new ID.
+    fn result<RE, WE>(&self, result: Result<(), ProtocolHandlerError<RE, WE>>) -> Result<(), ProtocolHandlerError<RE, WE>> {
+        match result {
+            Err(ProtocolHandlerError::UnexpectedPacketId(id)) if self.address == REGISTER_ID.address && id == self.data[0] => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Writes the new ID to the EEPROM. The lock is set again through the new ID if the write succeeded.
+fn set_id_sequence(id: u8) -> [Step; 3] {
+    [
+        Step::write_u8(REGISTER_EEPROM_LOCK.address, 0x00),
+        Step::write_u8(REGISTER_ID.address, id),
+        Step::write_u8(REGISTER_EEPROM_LOCK.address, 0x01).cleanup(),
+    ]
+}
+
+/// Writes the protection limits to the EEPROM and sets the lock again.
+fn apply_limits_sequence(limits: &SafeLimits) -> [Step; 4] {
+    [
+        Step::write_u8(REGISTER_EEPROM_LOCK.address, 0x00),
+        Step::write(REGISTER_UPPER_TEMPERATURE_LIMIT.address, &limits.limit_registers()),
+        Step::write(REGISTER_ALARM_FLAG.address, &[limits.alarm_shutdown.bits(), limits.alarm_led.bits()]),
+        Step::write_u8(REGISTER_EEPROM_LOCK.address, 0x01).cleanup(),
+    ]
+}
+
+/// Writes the target position, period and speed, which are adjacent registers, in one transaction.
+fn motion_step(position: u16, period: u16, speed: i16) -> Step {
+    let mut data = [0; MOTION_REGISTERS];
+    data[0..2].copy_from_slice(&WORD_ORDER.to_bytes(position));
+    data[2..4].copy_from_slice(&WORD_ORDER.to_bytes(period));
+    data[4..6].copy_from_slice(&WORD_ORDER.to_bytes(RawSpeed::from_signed(speed, SPEED_ENCODING).0));
+    Step::write(REGISTER_TARGET_POSITION_H.address, &data)
+}
+
+/// State shared by the blocking and the async control.
+struct Core<P> {
     id: u8,
-    reader: R,
-    writer: W,
     master_config: ProtocolMasterConfig,
     timeout: Duration,
-    current_values: Option<CurrentValues>,
-    timer: PhantomData<Timer>,
+    current_values: Option<StatusBlock>,
+    policy: P,
+    #[cfg(feature = "std")]
+    tracer: Option<Tracer>,
 }
 
-struct CurrentValues {
-    buffer: [u8; 8],
-}
-impl CurrentValues {
-    fn new() -> Self {
-        Self {
-            buffer: [0; 8],
+impl<P: WritePolicy> Core<P> {
+    fn with_policy<Q>(self, policy: Q) -> Core<Q> {
+        Core {
+            id: self.id,
+            master_config: self.master_config,
+            timeout: self.timeout,
+            current_values: self.current_values,
+            policy,
+            #[cfg(feature = "std")]
+            tracer: self.tracer,
         }
     }
-    fn position(&self) -> u16 {
-        u16::from_be_bytes([self.buffer[0], self.buffer[1]])
+    /// Checks `step` against the policy. Returns whether it has to be transmitted.
+    fn check<RE, WE>(&mut self, step: &Step) -> Result<bool, ProtocolHandlerError<RE, WE>> {
+        match self.policy.check(self.id, step.address, step.data()) {
+            WriteDecision::Transmit => Ok(true),
+            WriteDecision::Reject(address) => Err(ProtocolHandlerError::WriteProtected(address)),
+            WriteDecision::Drop => Ok(false),
+        }
+    }
+    fn command<RE, WE>(&self, step: &Step) -> Result<WriteRegisterCommand<COMMAND_BUFFER_SIZE>, ProtocolHandlerError<RE, WE>> {
+        WriteRegisterCommand::builder(self.id).address(step.address).data(step.data()).build().map_err(ProtocolHandlerError::PacketError)
     }
-    fn speed(&self) -> i16 {
-        let speed = u16::from_be_bytes([self.buffer[2], self.buffer[3]]);
-        if speed >= 32768 {
-            -((speed - 32768) as i16)
-        } else {
-            speed as i16
+    /// Records a completed step. Once the ID register is written, the servo answers to the new ID.
+    fn complete(&mut self, step: &Step) {
+        if step.address == REGISTER_ID.address {
+            self.id = step.data[0];
         }
     }
-    fn load(&self) -> u16 {
-        u16::from_be_bytes([self.buffer[4], self.buffer[5]])
+    fn current<T, E>(&self, f: impl FnOnce(&StatusBlock) -> T) -> Result<T, Error<E>> {
+        self.current_values.as_ref().map(f).ok_or(Error::NotUpdated)
     }
-    #[allow(dead_code)]
-    fn voltage(&self) -> u8 {
-        self.buffer[6]
+    /// Records a transaction if a trace is running.
+    #[cfg(feature = "std")]
+    fn trace<T, RE, WE>(&mut self, access: Access, address: u8, data: &[u8], result: &Result<T, ProtocolHandlerError<RE, WE>>) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(self.id, access, address, data, Outcome::of(result));
+        }
     }
-    #[allow(dead_code)]
-    fn temperature(&self) -> u8 {
-        self.buffer[7]
+}
+
+/// Fastest target speed in steps of [`ANGLE_SCALE`]. Bit 15 of the register is the direction.
+pub const MAX_SPEED: i16 = 0x7fff;
+/// Longest target period in ms.
+pub const MAX_PERIOD: u16 = 0xffff;
+
+/// Rounds `value` to the nearest integer in `0..=max`, or `None` if it is out of the range or NaN.
+/// Same as `f64::round`, which needs std, with halves rounded away from zero.
+fn round_in_range(value: f64, max: f64) -> Option<f64> {
+    if value.is_nan() || value <= -0.5 || value >= max + 0.5 {
+        return None;
     }
+    if value < 0.0 {
+        return Some(0.0);
+    }
+    let truncated = value as u64 as f64;
+    Some(if value - truncated >= 0.5 { truncated + 1.0 } else { truncated })
+}
+
+/// Converts a speed in deg/s to the nearest target speed.
+fn to_speed<E>(speed: f64) -> Result<i16, Error<E>> {
+    round_in_range(speed / ANGLE_SCALE.speed_unit as f64, MAX_SPEED as f64).map(|speed| speed as i16).ok_or(Error::InvalidArgument)
+}
+
+/// Converts a period in seconds to the nearest target period.
+fn to_period<E>(period: f64) -> Result<u16, Error<E>> {
+    round_in_range(period * 1000.0, MAX_PERIOD as f64).map(|period| period as u16).ok_or(Error::InvalidArgument)
+}
+
+pub struct Scs0009ServoControl<R, W, Timer, P = AllowAll> {
+    core: Core<P>,
+    reader: R,
+    writer: W,
+    timer: PhantomData<Timer>,
 }
 
 impl<R, W, Timer> Scs0009ServoControl<R, W, Timer> {
     pub fn new(id: u8, reader: R, writer: W, master_config: ProtocolMasterConfig, timeout: Duration) -> Self {
         Self {
-            id,
+            core: Core {
+                id,
+                master_config,
+                timeout,
+                current_values: None,
+                policy: AllowAll,
+                #[cfg(feature = "std")]
+                tracer: None,
+            },
             reader,
             writer,
-            master_config,
-            timeout,
-            current_values: None,
             timer: PhantomData,
         }
     }
 }
 
-const COMMAND_BUFFER_SIZE: usize = 16;
+impl<R, W, Timer, P: WritePolicy> Scs0009ServoControl<R, W, Timer, P> {
+    /// Replaces the policy every register write is checked against.
+    pub fn with_policy<Q: WritePolicy>(self, policy: Q) -> Scs0009ServoControl<R, W, Timer, Q> {
+        Scs0009ServoControl {
+            core: self.core.with_policy(policy),
+            reader: self.reader,
+            writer: self.writer,
+            timer: PhantomData,
+        }
+    }
+    pub fn policy(&self) -> &P {
+        &self.core.policy
+    }
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.core.policy
+    }
 
-impl<R, W, Timer> Scs0009ServoControl<R, W, Timer>
+    /// Starts recording every register transaction into a new trace, see [`crate::trace`].
+    #[cfg(feature = "std")]
+    pub fn start_trace(&mut self) where Timer: super::Timer, Timer::Instant: Send + 'static {
+        self.core.tracer = Some(Tracer::new(Timer::now()));
+    }
+    /// The trace recorded so far.
+    #[cfg(feature = "std")]
+    pub fn trace(&self) -> Option<&RegisterTrace> {
+        self.core.tracer.as_ref().map(Tracer::trace)
+    }
+    /// Stops the trace and returns it.
+    #[cfg(feature = "std")]
+    pub fn take_trace(&mut self) -> Option<RegisterTrace> {
+        self.core.tracer.take().map(Tracer::into_trace)
+    }
+}
+
+type ControlError<R, W> = Error<ProtocolHandlerError<<R as crate::protocol::StreamReader>::Error, <W as crate::protocol::StreamWriter>::Error>>;
+
+const COMMAND_BUFFER_SIZE: usize = SMALL_BUFFER_SIZE;
+// `update` reads the 8 bytes from the current position to the temperature in one transaction,
+// and the largest write is the block of target registers written by `set_motion`.
+const _: () = assert!(packet_size(StatusBlock::LENGTH) <= COMMAND_BUFFER_SIZE);
+const _: () = assert!(write_command_size(STEP_DATA_SIZE) <= COMMAND_BUFFER_SIZE);
+
+impl<R, W, Timer, P> Scs0009ServoControl<R, W, Timer, P>
     where R: crate::protocol::StreamReader,
           W: crate::protocol::StreamWriter,
           Timer: super::Timer,
+          P: WritePolicy,
 {
-    fn read_continuous_registers(&mut self, address: u8, data: &mut [u8]) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        let mut master = ProtocolMaster::<COMMAND_BUFFER_SIZE>::new(self.master_config.clone());
-        let start = Timer::now();
-        master.read_register(&mut self.reader, &mut self.writer, self.id, address, data, || start.elapsed() >= self.timeout)?;
-        Ok(())
+    /// Reads the current position, speed, load, voltage and temperature in one transaction.
+    pub fn read_status_block(&mut self) -> Result<StatusBlock, ControlError<R, W>> {
+        let mut registers = [0; StatusBlock::LENGTH];
+        self.read_continuous_registers(REGISTER_CURRENT_POSITION_H.address, &mut registers)?;
+        Ok(StatusBlock::from_registers_with_order(&registers, WORD_ORDER))
     }
-    fn write_continuous_registers(&mut self, address: u8, data: &[u8]) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        let mut master = ProtocolMaster::<COMMAND_BUFFER_SIZE>::new(self.master_config.clone());
-        let mut command = WriteRegisterCommand::<COMMAND_BUFFER_SIZE>::new(self.id, address, data.len());
-        command.writer().data_mut().unwrap()[2..2+data.len()].copy_from_slice(data);
-        command.update_checksum().unwrap();
-        let start = Timer::now();
-        master.write_register(&mut self.reader, &mut self.writer, &command, || start.elapsed() >= self.timeout)?;
-        Ok(())
+    /// Returns the status block read by the last `update`.
+    pub fn status(&self) -> Option<&StatusBlock> {
+        self.core.current_values.as_ref()
     }
-    #[allow(dead_code)]
-    fn read_register_u8(&mut self, address: u8) -> Result<u8, ProtocolHandlerError<R::Error, W::Error>> {
-        let mut data = [0];
-        self.read_continuous_registers(address, &mut data)?;
-        Ok(data[0])
+    pub fn current_voltage(&self) -> Result<u8, ControlError<R, W>> {
+        self.core.current(|values| values.voltage)
+    }
+    pub fn current_temperature(&self) -> Result<u8, ControlError<R, W>> {
+        self.core.current(|values| values.temperature)
+    }
+    /// Sets the target position, period and speed in one transaction instead of the three of
+    /// `set_target_position`, `set_target_period` and `set_target_speed`.
+    pub fn set_motion(&mut self, position: u16, period: u16, speed: i16) -> Result<(), ControlError<R, W>> {
+        Ok(self.write(&motion_step(position, period, speed))?)
+    }
+    /// Writes the protection limits to the EEPROM. The EEPROM lock is released during the write and set again afterwards.
+    pub fn apply_limits(&mut self, limits: &SafeLimits) -> Result<(), ControlError<R, W>> {
+        Ok(self.run(&apply_limits_sequence(limits))?)
+    }
+    /// Reads the protection limits from the EEPROM.
+    pub fn limits(&mut self) -> Result<SafeLimits, ControlError<R, W>> {
+        let mut registers = [0; SafeLimits::LIMIT_REGISTERS];
+        self.read_continuous_registers(REGISTER_UPPER_TEMPERATURE_LIMIT.address, &mut registers)?;
+        let mut alarms = [0; 2];
+        self.read_continuous_registers(REGISTER_ALARM_FLAG.address, &mut alarms)?;
+        Ok(SafeLimits::from_registers(&registers, &alarms))
+    }
+    fn read_continuous_registers(&mut self, address: u8, data: &mut [u8]) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let mut master = SmallMaster::new(self.core.master_config.clone());
+        let result = master.read_register(&mut self.reader, &mut self.writer, self.core.id, address, data, super::timeout_after::<Timer>(self.core.timeout));
+        #[cfg(feature = "std")]
+        self.core.trace(Access::Read, address, data, &result);
+        result?;
+        Ok(())
     }
     fn read_register_u16(&mut self, address: u8) -> Result<u16, ProtocolHandlerError<R::Error, W::Error>> {
         let mut data = [0; 2];
         self.read_continuous_registers(address, &mut data)?;
-        Ok(u16::from_be_bytes(data))
+        Ok(WORD_ORDER.from_bytes(data))
     }
-    fn write_register_u8(&mut self, address: u8, value: u8) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        self.write_continuous_registers(address, &[value])
+    fn write(&mut self, step: &Step) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        if self.core.check(step)? {
+            let mut master = SmallMaster::new(self.core.master_config.clone());
+            let result = step.result(master.write_register(&mut self.reader, &mut self.writer, &self.core.command(step)?, super::timeout_after::<Timer>(self.core.timeout)));
+            #[cfg(feature = "std")]
+            self.core.trace(Access::Write, step.address, step.data(), &result);
+            result?;
+        }
+        self.core.complete(step);
+        Ok(())
     }
-    fn write_register_u16(&mut self, address: u8, value: u16) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        self.write_continuous_registers(address, &value.to_be_bytes())
+    /// Executes `steps` in order. After a failure, only the cleanup steps are executed and the first error is returned.
+    fn run(&mut self, steps: &[Step]) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let mut result = Ok(());
+        for step in steps {
+            if result.is_ok() || step.cleanup {
+                let step_result = self.write(step);
+                if result.is_ok() {
+                    result = step_result;
+                }
+            }
+        }
+        result
     }
 }
 
-impl<R, W, Timer> super::ServoControl for Scs0009ServoControl<R, W, Timer>
+impl<R, W, Timer, P> super::StatusSource for Scs0009ServoControl<R, W, Timer, P> {
+    fn status(&self) -> Option<&StatusBlock> {
+        self.core.current_values.as_ref()
+    }
+}
+
+impl<R, W, Timer, P> super::ServoControl for Scs0009ServoControl<R, W, Timer, P>
     where R: crate::protocol::StreamReader,
           W: crate::protocol::StreamWriter,
           Timer: super::Timer,
+          P: WritePolicy,
 {
     type Error = Error<ProtocolHandlerError<R::Error, W::Error>>;
     type Id = u8;
     type Position = u16;
     type Period = u16;
     type Speed = i16;
-    type Torque = u16;
-    
+    type Torque = i16;
+
     fn id(&self) -> Self::Id {
-        self.id
+        self.core.id
     }
 
     fn set_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
-        self.write_register_u8(REGISTER_ID.address, id)?;
-        self.id = id;
-        Ok(())
+        Ok(self.run(&set_id_sequence(id))?)
     }
 
     fn output_enable(&mut self) -> Result<(), Self::Error> {
-        self.write_register_u8(REGISTER_TORQUE_SWITCH.address, 0x01)?;
-        Ok(())
+        Ok(self.write(&Step::write_u8(REGISTER_TORQUE_SWITCH.address, 0x01))?)
     }
 
     fn output_disable(&mut self) -> Result<(), Self::Error> {
-        self.write_register_u8(REGISTER_TORQUE_SWITCH.address, 0x00)?;
-        Ok(())
+        Ok(self.write(&Step::write_u8(REGISTER_TORQUE_SWITCH.address, 0x00))?)
     }
 
     fn position_lower_limit(&mut self) -> Result<Self::Position, Self::Error> {
@@ -219,7 +661,7 @@ impl<R, W, Timer> super::ServoControl for Scs0009ServoControl<R, W, Timer>
     }
 
     fn set_target_position(&mut self, position: Self::Position) -> Result<(), Self::Error> {
-        Ok(self.write_register_u16(REGISTER_TARGET_POSITION_H.address, position)?)
+        Ok(self.write(&Step::write_u16(REGISTER_TARGET_POSITION_H.address, position))?)
     }
 
     fn target_period(&mut self) -> Result<Self::Period, Self::Error> {
@@ -227,45 +669,31 @@ impl<R, W, Timer> super::ServoControl for Scs0009ServoControl<R, W, Timer>
     }
 
     fn set_target_period(&mut self, period: Self::Period) -> Result<(), Self::Error> {
-        Ok(self.write_register_u16(REGISTER_TARGET_PERIOD_H.address, period)?)
+        Ok(self.write(&Step::write_u16(REGISTER_TARGET_PERIOD_H.address, period))?)
     }
 
     fn target_speed(&mut self) -> Result<Self::Speed, Self::Error> {
-        Ok(self.read_register_u16(REGISTER_TARGET_SPEED_H.address)? as i16)
+        Ok(RawSpeed(self.read_register_u16(REGISTER_TARGET_SPEED_H.address)?).to_signed(SPEED_ENCODING))
     }
 
     fn set_target_speed(&mut self, speed: Self::Speed) -> Result<(), Self::Error> {
-        Ok(self.write_register_u16(REGISTER_TARGET_SPEED_H.address, speed as u16)?)
+        Ok(self.write(&Step::write_u16(REGISTER_TARGET_SPEED_H.address, RawSpeed::from_signed(speed, SPEED_ENCODING).0))?)
     }
 
     fn current_position(&mut self) -> Result<Self::Position, Self::Error> {
-        if let Some(values) = self.current_values.borrow() {
-            Ok(values.position())
-        } else {
-            Err(Error::NotUpdated)
-        }
+        self.core.current(|values| values.position)
     }
 
     fn current_speed(&mut self) -> Result<Self::Speed, Self::Error> {
-        if let Some(values) = self.current_values.borrow() {
-            Ok(values.speed())
-        } else {
-            Err(Error::NotUpdated)
-        }
+        self.core.current(|values| values.speed.to_signed(SPEED_ENCODING))
     }
 
     fn current_load(&mut self) -> Result<Self::Torque, Self::Error> {
-        if let Some(values) = self.current_values.borrow() {
-            Ok(values.load())
-        } else {
-            Err(Error::NotUpdated)
-        }
+        self.core.current(|values| values.load.to_signed(LOAD_ENCODING))
     }
 
     fn update(&mut self) -> Result<(), Self::Error> {
-        let mut values = CurrentValues::new();
-        self.read_continuous_registers(REGISTER_CURRENT_POSITION_H.address, &mut values.buffer)?;
-        self.current_values = Some(values);
+        self.core.current_values = Some(self.read_status_block()?);
         Ok(())
     }
 
@@ -273,41 +701,277 @@ impl<R, W, Timer> super::ServoControl for Scs0009ServoControl<R, W, Timer>
         0
     }
     fn max_speed(&self) -> Self::Speed {
-        0x7fff
+        MAX_SPEED
     }
     fn max_period(&self) -> Self::Period {
-        0xffff
+        MAX_PERIOD
     }
     fn to_speed(&self, speed: f64) -> Result<Self::Speed, Self::Error> {
-        let speed = speed / 0.19;
-        if speed < 0.0 || speed > 65535.0 {
-            Err(Error::InvalidArgument)
-        } else {
-            Ok(speed as Self::Speed)
-        }
+        to_speed(speed)
     }
     fn to_period(&self, period: f64) -> Result<Self::Period, Self::Error> {
-        if period < 0.0 || period > 65.535 {
-            Err(Error::InvalidArgument)
-        } else {
-            Ok((period * 1000.0) as Self::Period)
+        to_period(period)
+    }
+}
+
+/// [`Scs0009ServoControl`] over an async transport, e.g. Web Serial or a tokio serial port.
+#[cfg(feature = "async")]
+pub struct Scs0009ServoControlAsync<R, W, Timer, P = AllowAll> {
+    core: Core<P>,
+    reader: R,
+    writer: W,
+    timer: PhantomData<Timer>,
+}
+
+#[cfg(feature = "async")]
+impl<R, W, Timer> Scs0009ServoControlAsync<R, W, Timer> {
+    pub fn new(id: u8, reader: R, writer: W, master_config: ProtocolMasterConfig, timeout: Duration) -> Self {
+        Self {
+            core: Core {
+                id,
+                master_config,
+                timeout,
+                current_values: None,
+                policy: AllowAll,
+                #[cfg(feature = "std")]
+                tracer: None,
+            },
+            reader,
+            writer,
+            timer: PhantomData,
         }
-        
     }
+}
 
+#[cfg(feature = "async")]
+impl<R, W, Timer, P: WritePolicy> Scs0009ServoControlAsync<R, W, Timer, P> {
+    /// Replaces the policy every register write is checked against.
+    pub fn with_policy<Q: WritePolicy>(self, policy: Q) -> Scs0009ServoControlAsync<R, W, Timer, Q> {
+        Scs0009ServoControlAsync {
+            core: self.core.with_policy(policy),
+            reader: self.reader,
+            writer: self.writer,
+            timer: PhantomData,
+        }
+    }
+    pub fn policy(&self) -> &P {
+        &self.core.policy
+    }
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.core.policy
+    }
+
+    /// Starts recording every register transaction into a new trace, see [`crate::trace`].
+    #[cfg(feature = "std")]
+    pub fn start_trace(&mut self) where Timer: super::Timer, Timer::Instant: Send + 'static {
+        self.core.tracer = Some(Tracer::new(Timer::now()));
+    }
+    /// The trace recorded so far.
+    #[cfg(feature = "std")]
+    pub fn trace(&self) -> Option<&RegisterTrace> {
+        self.core.tracer.as_ref().map(Tracer::trace)
+    }
+    /// Stops the trace and returns it.
+    #[cfg(feature = "std")]
+    pub fn take_trace(&mut self) -> Option<RegisterTrace> {
+        self.core.tracer.take().map(Tracer::into_trace)
+    }
 }
 
+#[cfg(feature = "async")]
+type AsyncControlError<R, W> = Error<ProtocolHandlerError<<R as StreamReaderAsync>::Error, <W as StreamWriterAsync>::Error>>;
+
+#[cfg(feature = "async")]
+impl<R, W, Timer, P> Scs0009ServoControlAsync<R, W, Timer, P>
+    where R: StreamReaderAsync,
+          W: StreamWriterAsync,
+          Timer: super::Timer,
+          P: WritePolicy,
+{
+    /// Reads the current position, speed, load, voltage and temperature in one transaction.
+    pub async fn read_status_block(&mut self) -> Result<StatusBlock, AsyncControlError<R, W>> {
+        let mut registers = [0; StatusBlock::LENGTH];
+        self.read_continuous_registers(REGISTER_CURRENT_POSITION_H.address, &mut registers).await?;
+        Ok(StatusBlock::from_registers_with_order(&registers, WORD_ORDER))
+    }
+    /// Returns the status block read by the last `update`.
+    pub fn status(&self) -> Option<&StatusBlock> {
+        self.core.current_values.as_ref()
+    }
+    pub fn current_voltage(&self) -> Result<u8, AsyncControlError<R, W>> {
+        self.core.current(|values| values.voltage)
+    }
+    pub fn current_temperature(&self) -> Result<u8, AsyncControlError<R, W>> {
+        self.core.current(|values| values.temperature)
+    }
+    /// Async version of [`Scs0009ServoControl::set_motion`].
+    pub async fn set_motion(&mut self, position: u16, period: u16, speed: i16) -> Result<(), AsyncControlError<R, W>> {
+        Ok(self.write(&motion_step(position, period, speed)).await?)
+    }
+    /// Writes the protection limits to the EEPROM. The EEPROM lock is released during the write and set again afterwards.
+    pub async fn apply_limits(&mut self, limits: &SafeLimits) -> Result<(), AsyncControlError<R, W>> {
+        Ok(self.run(&apply_limits_sequence(limits)).await?)
+    }
+    /// Reads the protection limits from the EEPROM.
+    pub async fn limits(&mut self) -> Result<SafeLimits, AsyncControlError<R, W>> {
+        let mut registers = [0; SafeLimits::LIMIT_REGISTERS];
+        self.read_continuous_registers(REGISTER_UPPER_TEMPERATURE_LIMIT.address, &mut registers).await?;
+        let mut alarms = [0; 2];
+        self.read_continuous_registers(REGISTER_ALARM_FLAG.address, &mut alarms).await?;
+        Ok(SafeLimits::from_registers(&registers, &alarms))
+    }
+    async fn read_continuous_registers(&mut self, address: u8, data: &mut [u8]) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let mut master = SmallMaster::new(self.core.master_config.clone());
+        let result = master.read_register_async(&mut self.reader, &mut self.writer, self.core.id, address, data, super::timeout_after::<Timer>(self.core.timeout)).await;
+        #[cfg(feature = "std")]
+        self.core.trace(Access::Read, address, data, &result);
+        result?;
+        Ok(())
+    }
+    async fn read_register_u16(&mut self, address: u8) -> Result<u16, ProtocolHandlerError<R::Error, W::Error>> {
+        let mut data = [0; 2];
+        self.read_continuous_registers(address, &mut data).await?;
+        Ok(WORD_ORDER.from_bytes(data))
+    }
+    async fn write(&mut self, step: &Step) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        if self.core.check(step)? {
+            let mut master = SmallMaster::new(self.core.master_config.clone());
+            let result = step.result(master.write_register_async(&mut self.reader, &mut self.writer, &self.core.command(step)?, super::timeout_after::<Timer>(self.core.timeout)).await);
+            #[cfg(feature = "std")]
+            self.core.trace(Access::Write, step.address, step.data(), &result);
+            result?;
+        }
+        self.core.complete(step);
+        Ok(())
+    }
+    /// Executes `steps` in order. After a failure, only the cleanup steps are executed and the first error is returned.
+    async fn run(&mut self, steps: &[Step]) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let mut result = Ok(());
+        for step in steps {
+            if result.is_ok() || step.cleanup {
+                let step_result = self.write(step).await;
+                if result.is_ok() {
+                    result = step_result;
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, W, Timer, P> super::StatusSource for Scs0009ServoControlAsync<R, W, Timer, P> {
+    fn status(&self) -> Option<&StatusBlock> {
+        self.core.current_values.as_ref()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, W, Timer, P> super::ServoControlAsync for Scs0009ServoControlAsync<R, W, Timer, P>
+    where R: StreamReaderAsync,
+          W: StreamWriterAsync,
+          Timer: super::Timer,
+          P: WritePolicy,
+{
+    type Error = AsyncControlError<R, W>;
+    type Id = u8;
+    type Position = u16;
+    type Period = u16;
+    type Speed = i16;
+    type Torque = i16;
+
+    fn id(&self) -> Self::Id {
+        self.core.id
+    }
+
+    async fn set_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        Ok(self.run(&set_id_sequence(id)).await?)
+    }
+
+    async fn output_enable(&mut self) -> Result<(), Self::Error> {
+        Ok(self.write(&Step::write_u8(REGISTER_TORQUE_SWITCH.address, 0x01)).await?)
+    }
+
+    async fn output_disable(&mut self) -> Result<(), Self::Error> {
+        Ok(self.write(&Step::write_u8(REGISTER_TORQUE_SWITCH.address, 0x00)).await?)
+    }
+
+    async fn position_lower_limit(&mut self) -> Result<Self::Position, Self::Error> {
+        Ok(self.read_register_u16(REGISTER_LOWER_POSITION_LIMIT_H.address).await?)
+    }
+
+    async fn position_upper_limit(&mut self) -> Result<Self::Position, Self::Error> {
+        Ok(self.read_register_u16(REGISTER_UPPER_POSITION_LIMIT_H.address).await?)
+    }
+
+    async fn target_position(&mut self) -> Result<Self::Position, Self::Error> {
+        Ok(self.read_register_u16(REGISTER_TARGET_POSITION_H.address).await?)
+    }
+
+    async fn set_target_position(&mut self, position: Self::Position) -> Result<(), Self::Error> {
+        Ok(self.write(&Step::write_u16(REGISTER_TARGET_POSITION_H.address, position)).await?)
+    }
+
+    async fn target_period(&mut self) -> Result<Self::Period, Self::Error> {
+        Ok(self.read_register_u16(REGISTER_TARGET_PERIOD_H.address).await?)
+    }
+
+    async fn set_target_period(&mut self, period: Self::Period) -> Result<(), Self::Error> {
+        Ok(self.write(&Step::write_u16(REGISTER_TARGET_PERIOD_H.address, period)).await?)
+    }
+
+    async fn target_speed(&mut self) -> Result<Self::Speed, Self::Error> {
+        Ok(RawSpeed(self.read_register_u16(REGISTER_TARGET_SPEED_H.address).await?).to_signed(SPEED_ENCODING))
+    }
+
+    async fn set_target_speed(&mut self, speed: Self::Speed) -> Result<(), Self::Error> {
+        Ok(self.write(&Step::write_u16(REGISTER_TARGET_SPEED_H.address, RawSpeed::from_signed(speed, SPEED_ENCODING).0)).await?)
+    }
+
+    fn current_position(&mut self) -> Result<Self::Position, Self::Error> {
+        self.core.current(|values| values.position)
+    }
+
+    fn current_speed(&mut self) -> Result<Self::Speed, Self::Error> {
+        self.core.current(|values| values.speed.to_signed(SPEED_ENCODING))
+    }
+
+    fn current_load(&mut self) -> Result<Self::Torque, Self::Error> {
+        self.core.current(|values| values.load.to_signed(LOAD_ENCODING))
+    }
+
+    async fn update(&mut self) -> Result<(), Self::Error> {
+        self.core.current_values = Some(self.read_status_block().await?);
+        Ok(())
+    }
+
+    fn min_speed(&self) -> Self::Speed {
+        0
+    }
+    fn max_speed(&self) -> Self::Speed {
+        MAX_SPEED
+    }
+    fn max_period(&self) -> Self::Period {
+        MAX_PERIOD
+    }
+    fn to_speed(&self, speed: f64) -> Result<Self::Speed, Self::Error> {
+        to_speed(speed)
+    }
+    fn to_period(&self, period: f64) -> Result<Self::Period, Self::Error> {
+        to_period(period)
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::device::ServoControl;
+    use crate::device::{RawLoad, ServoControl};
     use crate::{packet::PacketWriter, protocol::{Command, ProtocolMasterConfig, ProtocolSlave, ProtocolSlaveConfig}};
     extern crate std;
     
     #[test]
     fn test_scs0009() {
-        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig {});
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig::default());
         
         let (master_writer, mut slave_reader) = std::sync::mpsc::channel();
         let (mut slave_writer, master_reader) = std::sync::mpsc::channel();
@@ -376,7 +1040,7 @@ mod test {
             }
         });
 
-        let mut control = Scs0009ServoControl::<_, _, std::time::Instant>::new(0x01, master_reader, master_writer, ProtocolMasterConfig { echo_back: false }, Duration::from_secs(2));
+        let mut control = Scs0009ServoControl::<_, _, std::time::Instant>::new(0x01, master_reader, master_writer, ProtocolMasterConfig::default(), Duration::from_secs(2));
         // Check ID
         assert_eq!(control.id(), 0x01);
         // Limit
@@ -407,7 +1071,7 @@ mod test {
         assert_eq!(control.target_period().unwrap(), 0x5678);
 
         // Current status
-        let current_load: Result<u16, Error<ProtocolHandlerError<(), ()>>> = control.current_load();
+        let current_load: Result<i16, Error<ProtocolHandlerError<(), ()>>> = control.current_load();
         assert!(current_load.is_err()); // Must fail because not updated
         control.update().unwrap();
         assert_eq!(control.current_load().unwrap(), 0);
@@ -423,7 +1087,17 @@ mod test {
         control.update().unwrap();
         assert_eq!(control.current_load().unwrap(), 0x0123);
         assert_eq!(control.current_position().unwrap(), 0x4567);
-        assert_eq!(control.current_speed().unwrap(), 0x89ab);
+        assert_eq!(control.current_speed().unwrap(), -0x09ab);
+        assert_eq!(control.current_voltage().unwrap(), 0);
+
+        register_storage.lock().unwrap()[REGISTER_CURRENT_VOLTAGE.address as usize] = 0x46;
+        register_storage.lock().unwrap()[REGISTER_CURRENT_TEMPERATURE.address as usize] = 0x1e;
+        assert_eq!(control.read_status_block().unwrap(), StatusBlock { position: 0x4567, speed: RawSpeed(0x89ab), load: RawLoad(0x0123), voltage: 0x46, temperature: 0x1e });
+        // `read_status_block` does not touch the values cached by `update`.
+        assert_eq!(control.current_temperature().unwrap(), 0);
+        control.update().unwrap();
+        assert_eq!(control.current_voltage().unwrap(), 0x46);
+        assert_eq!(control.current_temperature().unwrap(), 0x1e);
 
         register_storage.lock().unwrap()[REGISTER_CURRENT_LOAD_H.address as usize] = 0xcd;
         register_storage.lock().unwrap()[REGISTER_CURRENT_LOAD_L.address as usize] = 0xef;
@@ -434,11 +1108,12 @@ mod test {
         // Not updated, so the previous values are returned
         assert_eq!(control.current_load().unwrap(), 0x0123);
         assert_eq!(control.current_position().unwrap(), 0x4567);
-        assert_eq!(control.current_speed().unwrap(), 0x89ab);
+        assert_eq!(control.current_speed().unwrap(), -0x09ab);
         control.update().unwrap();
-        assert_eq!(control.current_load().unwrap(), 0xcdef);
+        // Bit 10 is the direction, the bits above it are not part of the load.
+        assert_eq!(control.current_load().unwrap(), -0x01ef);
         assert_eq!(control.current_position().unwrap(), 0xfedc);
-        assert_eq!(control.current_speed().unwrap(), 0xba98);
+        assert_eq!(control.current_speed().unwrap(), -0x3a98);
 
 
         // Change ID
@@ -447,5 +1122,146 @@ mod test {
         assert_eq!(register_storage.lock().unwrap()[REGISTER_ID.address as usize], 0x02);
         control.output_enable().unwrap(); // Check if the new ID is used
         assert_eq!(register_storage.lock().unwrap()[REGISTER_TORQUE_SWITCH.address as usize], 0x01);
+
+        // Protect the ID
+        let mut control = control.with_policy(crate::policy::ProtectedRegisters::from_registers(COMMUNICATION_REGISTERS));
+        assert!(matches!(control.set_id(0x03), Err(Error::ProtocolError(ProtocolHandlerError::WriteProtected(0x05)))));
+        assert_eq!(control.id(), 0x02);
+        assert_eq!(register_storage.lock().unwrap()[REGISTER_ID.address as usize], 0x02);
+        control.output_disable().unwrap();
+        assert_eq!(register_storage.lock().unwrap()[REGISTER_TORQUE_SWITCH.address as usize], 0x00);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_safe_limits() {
+        use crate::emulator::BusEmulator;
+        use crate::testing::emulator_thread::EmulatorThread;
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<1>::new(1, 1));
+
+        let mut control = Scs0009ServoControl::<_, _, std::time::Instant>::new(0x01, master_reader, master_writer, ProtocolMasterConfig::default(), Duration::from_secs(1));
+        assert_eq!(control.limits().unwrap(), SafeLimits::factory());
+        control.apply_limits(&SafeLimits::conservative()).unwrap();
+        let limits = control.limits().unwrap();
+        assert_eq!(limits, SafeLimits::conservative());
+        assert!(limits.alarm_shutdown.overheat() && !limits.alarm_shutdown.angle());
+
+        let emulator = thread.stop();
+        let registers = emulator.servo(0x01).unwrap().registers();
+        assert_eq!(registers[REGISTER_UPPER_TEMPERATURE_LIMIT.address as usize], 65);
+        assert_eq!(registers[REGISTER_MAX_TORQUE_H.address as usize..=REGISTER_MAX_TORQUE_L.address as usize], [0x02, 0xcc]);
+        assert_eq!(registers[REGISTER_HIGH_VOLTAGE_FLAG.address as usize], 0x00);
+        assert_eq!(registers[REGISTER_EEPROM_LOCK.address as usize], 0x01);
+        assert_eq!(registers[REGISTER_ALARM_FLAG.address as usize], 0x25);
+    }
+
+    #[test]
+    fn test_set_motion() {
+        use crate::emulator::BusEmulator;
+        use crate::protocol::IdSet;
+        use crate::testing::loopback::LoopbackBus;
+        let mut emulator = BusEmulator::<1>::new(1, 1);
+        {
+            let mut bus = LoopbackBus::new();
+            bus.add_slave(IdSet::from_ids(&[1]), |packet: &crate::packet::PacketReader, buffer: &mut [u8]| emulator.handle_packet(packet, buffer));
+            let mut control = Scs0009ServoControl::<_, _, std::time::Instant>::new(0x01, bus.reader(), bus.writer(), ProtocolMasterConfig::default(), Duration::from_millis(100));
+            control.start_trace();
+            control.set_motion(0x0123, 0x0456, -0x0078).unwrap();
+            let trace = control.take_trace().unwrap();
+            assert_eq!(trace.entries.len(), 1);
+            assert_eq!((trace.entries[0].address, trace.entries[0].length), (REGISTER_TARGET_POSITION_H.address, 6));
+        }
+        let registers = emulator.servo(0x01).unwrap().registers();
+        assert_eq!(registers[REGISTER_TARGET_POSITION_H.address as usize..=REGISTER_TARGET_SPEED_L.address as usize], [0x01, 0x23, 0x04, 0x56, 0x80, 0x78]);
+    }
+
+    #[test]
+    fn test_speed_and_period() {
+        assert_eq!(to_speed::<()>(19.0).ok(), Some(100));
+        assert_eq!(to_speed::<()>(1.0).ok(), Some(5));
+        assert_eq!(to_speed::<()>(MAX_SPEED as f64 * 0.19).ok(), Some(MAX_SPEED));
+        // Faster speeds used to wrap around into the direction bit.
+        assert!(matches!(to_speed::<()>(6300.0), Err(Error::InvalidArgument)));
+        assert!(matches!(to_speed::<()>(-1.0), Err(Error::InvalidArgument)));
+        assert!(matches!(to_speed::<()>(f64::NAN), Err(Error::InvalidArgument)));
+        assert_eq!(to_period::<()>(0.5).ok(), Some(500));
+        assert_eq!(to_period::<()>(0.0012).ok(), Some(1));
+        assert_eq!(to_period::<()>(65.535).ok(), Some(MAX_PERIOD));
+        assert!(matches!(to_period::<()>(65.6), Err(Error::InvalidArgument)));
+        // Rounded like `f64::round`, also next to the halves.
+        assert_eq!(round_in_range(2.5, 10.0), Some(3.0));
+        assert_eq!(round_in_range(0.49999999999999994, 10.0), Some(0.0));
+        assert_eq!(round_in_range(-0.4, 10.0), Some(0.0));
+        assert_eq!(round_in_range(-0.5, 10.0), None);
+        assert_eq!(round_in_range(10.49, 10.0), Some(10.0));
+        assert_eq!(round_in_range(10.5, 10.0), None);
+    }
+
+    #[test]
+    fn test_alarm_flags() {
+        let mut alarms = AlarmFlags::default();
+        alarms.set_overload(true);
+        alarms.set_voltage(true);
+        assert_eq!(alarms.bits(), 0x21);
+        assert!(alarms.overload() && !alarms.overheat());
+        alarms.set_voltage(false);
+        assert_eq!(alarms, AlarmFlags(0x20));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_scs0009_async() {
+        use crate::device::ServoControlAsync;
+        use crate::emulator::BusEmulator;
+        use crate::testing::block_on;
+        use crate::testing::emulator_thread::EmulatorThread;
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<1>::new(1, 1));
+
+        let mut control = Scs0009ServoControlAsync::<_, _, std::time::Instant>::new(0x01, master_reader, master_writer, ProtocolMasterConfig::default(), Duration::from_secs(1));
+        block_on(async {
+            assert!(matches!(control.current_position(), Err(Error::NotUpdated)));
+            control.set_id(0x05).await.unwrap();
+            assert_eq!(control.id(), 0x05);
+            control.set_target_speed(-0x0123).await.unwrap();
+            assert_eq!(control.target_speed().await.unwrap(), -0x0123);
+            control.update().await.unwrap();
+            assert_eq!(control.current_position().unwrap(), 0x01ff);
+            assert_eq!(control.current_voltage().unwrap(), 70);
+            control.apply_limits(&SafeLimits::conservative()).await.unwrap();
+            assert_eq!(control.limits().await.unwrap(), SafeLimits::conservative());
+        });
+
+        let emulator = thread.stop();
+        assert!(emulator.servo(0x01).is_none());
+        let registers = emulator.servo(0x05).unwrap().registers();
+        assert_eq!(registers[REGISTER_TARGET_SPEED_H.address as usize..=REGISTER_TARGET_SPEED_L.address as usize], [0x81, 0x23]);
+        assert_eq!(registers[REGISTER_EEPROM_LOCK.address as usize], 0x01);
+    }
+
+    #[test]
+    fn test_decode_registers() {
+        use std::string::ToString;
+        let decoded = decode_registers(0x03, &[0x05, 0x04, 0x01]).collect::<std::vec::Vec<_>>();
+        assert_eq!(decoded, [
+            DecodedRegister { address: 0x03, name: "Software Version", value: RegisterValue::Version(0x05, 0x04) },
+            DecodedRegister { address: 0x05, name: "ID", value: RegisterValue::Byte(0x01) },
+        ]);
+        // Half of a pair is decoded as a byte.
+        let decoded = decode_registers(0x0c, &[0xff, 0x50]).collect::<std::vec::Vec<_>>();
+        assert_eq!(decoded[0], DecodedRegister { address: 0x0c, name: "Upper Position Limit L", value: RegisterValue::Byte(0xff) });
+        assert_eq!(decoded[1].value.to_string(), "80 degC");
+
+        // A gap between 0x30 and 0x38 is skipped.
+        let mut data = [0; 16];
+        data[8..].copy_from_slice(&[0x01, 0xff, 0x80, 0x10, 0x04, 0x05, 50, 30]);
+        let decoded = decode_registers(0x30, &data).map(|register| (register.address, register.value.to_string())).collect::<std::vec::Vec<_>>();
+        assert_eq!(decoded, [
+            (0x30, "0 (0x00)".to_string()),
+            (0x38, "511 (0x01ff)".to_string()),
+            (0x3a, "-16".to_string()),
+            (0x3c, "-5".to_string()),
+            (0x3e, "5.0 V".to_string()),
+            (0x3f, "30 degC".to_string()),
+        ]);
+        assert_eq!(RegisterValue::Alarms(AlarmFlags(0x25)).to_string(), "0x25 voltage, overheat, overload");
+    }
+}
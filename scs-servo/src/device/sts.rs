@@ -0,0 +1,17 @@
+//! Feetech STS series, e.g. the STS3215.
+//!
+//! Only the scale of the position and speed registers and the order of the bytes of words are defined so far, so
+//! model independent code can be checked against them. The register map is not supported yet.
+
+use super::AngleScale;
+use crate::protocol::WordOrder;
+
+/// 4096 positions over 360 degrees and speed steps of one position step per second.
+pub const ANGLE_SCALE: AngleScale = AngleScale {
+    center: 2048.0,
+    degrees_per_step: 360.0 / 4096.0,
+    max_position: 4095,
+    speed_unit: 360.0 / 4096.0,
+};
+/// Words are stored with the L register first, unlike the SCS series.
+pub const WORD_ORDER: WordOrder = WordOrder::LittleEndian;
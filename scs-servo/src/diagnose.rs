@@ -0,0 +1,404 @@
+//! Diagnosis of bus wiring and configuration problems.
+//!
+//! [`diagnose`] runs a fixed sequence of checks and collects the findings in a [`DiagnosticReport`]:
+//!
+//! 1. Echo detection: a broadcast PING is sent and the received bytes are compared with it.
+//! 2. Baud rate probing: the ID range is swept at each candidate baud rate until a servo answers.
+//! 3. Transactions: the status block of every servo found is read repeatedly to measure the error rate,
+//!    the supply voltage and whether more than one servo answers.
+//!
+//! The report lists the likely causes of the problems found. They are heuristics, not proofs.
+
+use core::time::Duration;
+
+use crate::device::scs0009::REGISTER_CURRENT_POSITION_H;
+use crate::device::{timeout_after, Instant, StatusBlock, Timer};
+use crate::protocol::{IdSet, PingCommand, ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, ProtocolReaderError, StreamReader, StreamWriter, BROADCAST_ID, SMALL_BUFFER_SIZE};
+use crate::scan::{ScanConfig, Scanner};
+
+/// Baud rates supported by the SCS servos, in the order of the baud rate register values.
+pub const BAUD_RATES: &[u32] = &[1_000_000, 500_000, 250_000, 128_000, 115_200, 76_800, 57_600, 38_400];
+
+const MAX_CAUSES: usize = 6;
+
+type DiagnoseError<R, W> = ProtocolHandlerError<<R as StreamReader>::Error, <W as StreamWriter>::Error>;
+
+#[derive(Debug, Clone)]
+pub struct DiagnoseConfig {
+    /// The baud rate the port is opened with.
+    pub baud_rate: u32,
+    /// Whether the adapter is expected to echo back the transmitted data.
+    pub echo_back: bool,
+    /// Baud rates probed when no servo answers at `baud_rate`.
+    pub baud_rates: &'static [u32],
+    pub scan: ScanConfig,
+    /// How long to wait for the echo of the broadcast PING.
+    pub echo_window: Duration,
+    /// Number of status reads per servo.
+    pub transactions: usize,
+    pub transaction_timeout: Duration,
+    /// How long to listen for a second response after each transaction.
+    pub response_window: Duration,
+    /// Voltages below this, in 0.1 V, are reported as a power sag.
+    pub min_voltage: u8,
+    /// Ratio of failed transactions above which noise is suspected.
+    pub max_error_rate: f32,
+}
+
+impl Default for DiagnoseConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: BAUD_RATES[0],
+            echo_back: false,
+            baud_rates: BAUD_RATES,
+            scan: ScanConfig::default(),
+            echo_window: Duration::from_millis(20),
+            transactions: 20,
+            transaction_timeout: Duration::from_millis(20),
+            response_window: Duration::from_millis(2),
+            min_voltage: 45,
+            max_error_rate: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LikelyCause {
+    /// Nothing was received at all, not even an echo. TX and RX are likely swapped or disconnected.
+    TxRxSwapped,
+    /// The bus is alive but no servo answers at any probed baud rate. The servos are likely unpowered or not connected.
+    NoServo,
+    /// The adapter echoes back the transmitted data (or not) contrary to the configuration.
+    EchoMismatch { detected: bool },
+    /// The servos answer at a different baud rate.
+    WrongBaudRate { baud_rate: u32 },
+    /// More than one servo answers the IDs in `DiagnosticReport::duplicate_ids`.
+    DuplicateId,
+    /// The lowest supply voltage measured, in 0.1 V.
+    PowerSag { id: u8, voltage: u8 },
+    /// Transactions fail on servos with a unique ID. Likely noise, a long bus or a marginal baud rate.
+    Noise { error_rate: f32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticReport {
+    /// Whether the adapter echoes back the transmitted data.
+    pub echo_back: bool,
+    /// The baud rate the servos answered at. The port is left at this baud rate.
+    pub baud_rate: Option<u32>,
+    pub servos: IdSet,
+    pub transactions: usize,
+    pub timeouts: usize,
+    /// Transactions which received a corrupted or unexpected response.
+    pub corrupted: usize,
+    /// IDs answered by more than one servo.
+    pub duplicate_ids: IdSet,
+    /// The servo with the lowest supply voltage and the voltage in 0.1 V.
+    pub lowest_voltage: Option<(u8, u8)>,
+    causes: [Option<LikelyCause>; MAX_CAUSES],
+}
+
+impl DiagnosticReport {
+    fn new(echo_back: bool) -> Self {
+        Self {
+            echo_back,
+            baud_rate: None,
+            servos: IdSet::new(),
+            transactions: 0,
+            timeouts: 0,
+            corrupted: 0,
+            duplicate_ids: IdSet::new(),
+            lowest_voltage: None,
+            causes: [None; MAX_CAUSES],
+        }
+    }
+
+    fn add_cause(&mut self, cause: LikelyCause) {
+        if let Some(slot) = self.causes.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(cause);
+        }
+    }
+
+    pub fn likely_causes(&self) -> impl Iterator<Item = LikelyCause> + '_ {
+        self.causes.iter().flatten().copied()
+    }
+
+    /// Whether no problem was found.
+    pub fn is_healthy(&self) -> bool {
+        self.likely_causes().next().is_none()
+    }
+}
+
+fn write_all<T: Timer, R: StreamReader, W: StreamWriter>(writer: &mut W, data: &[u8], timeout: Duration) -> Result<(), DiagnoseError<R, W>> {
+    let start = T::now();
+    let mut total_bytes_written = 0;
+    while total_bytes_written < data.len() {
+        match writer.write(&data[total_bytes_written..]) {
+            Ok(bytes_written) => total_bytes_written += bytes_written,
+            Err(nb::Error::WouldBlock) => {}
+            Err(nb::Error::Other(err)) => return Err(ProtocolHandlerError::WriterError(err)),
+        }
+        if start.elapsed() >= timeout {
+            return Err(ProtocolHandlerError::TimedOut);
+        }
+    }
+    Ok(())
+}
+
+/// Receives bytes until `window` elapses. The first bytes are stored in `head`. Returns the number of bytes received.
+fn listen<T: Timer, R: StreamReader, W: StreamWriter>(reader: &mut R, window: Duration, head: &mut [u8]) -> Result<usize, DiagnoseError<R, W>> {
+    let start = T::now();
+    let mut buffer = [0; 16];
+    let mut total_bytes_read = 0;
+    while start.elapsed() < window {
+        match reader.read(&mut buffer) {
+            Ok(bytes_read) => {
+                if total_bytes_read < head.len() {
+                    let length = bytes_read.min(head.len() - total_bytes_read);
+                    head[total_bytes_read..total_bytes_read + length].copy_from_slice(&buffer[..length]);
+                }
+                total_bytes_read += bytes_read;
+            }
+            Err(nb::Error::WouldBlock) => {}
+            Err(nb::Error::Other(err)) => return Err(ProtocolHandlerError::ReaderError(err)),
+        }
+    }
+    Ok(total_bytes_read)
+}
+
+/// Sends a broadcast PING and returns whether it is received back. Returns `None` if nothing is received.
+fn detect_echo<T: Timer, R: StreamReader, W: StreamWriter>(reader: &mut R, writer: &mut W, window: Duration) -> Result<Option<bool>, DiagnoseError<R, W>> {
+    let command = PingCommand::new(BROADCAST_ID);
+    write_all::<T, R, W>(writer, &command.raw, window)?;
+    let mut head = [0; 6];
+    let received = listen::<T, R, W>(reader, window, &mut head)?;
+    if received == 0 {
+        return Ok(None);
+    }
+    Ok(Some(received >= head.len() && head == command.raw))
+}
+
+/// Runs the checks described in the module documentation.
+/// `set_baud_rate` is called to switch the port to each probed baud rate.
+pub fn diagnose<T, R, W, SetBaudRate>(reader: &mut R, writer: &mut W, mut set_baud_rate: SetBaudRate, config: &DiagnoseConfig) -> Result<DiagnosticReport, DiagnoseError<R, W>>
+    where T: Timer,
+          R: StreamReader,
+          W: StreamWriter,
+          SetBaudRate: FnMut(u32),
+{
+    let echo = detect_echo::<T, R, W>(reader, writer, config.echo_window)?;
+    let mut report = DiagnosticReport::new(echo.unwrap_or(false));
+    if report.echo_back != config.echo_back {
+        report.add_cause(LikelyCause::EchoMismatch { detected: report.echo_back });
+    }
+    // Retries would hide the errors counted below.
+    let master_config = ProtocolMasterConfig::builder().echo_back(report.echo_back).build();
+
+    let candidates = core::iter::once(config.baud_rate).chain(config.baud_rates.iter().copied().filter(|baud_rate| *baud_rate != config.baud_rate));
+    for baud_rate in candidates {
+        if baud_rate != config.baud_rate {
+            set_baud_rate(baud_rate);
+        }
+        let mut scanner = Scanner::<SMALL_BUFFER_SIZE, T>::new(master_config.clone(), config.scan.clone());
+        let found = scanner.scan(reader, writer, |_, _| {})?;
+        if !found.is_empty() {
+            report.baud_rate = Some(baud_rate);
+            report.servos = found;
+            break;
+        }
+    }
+    let baud_rate = match report.baud_rate {
+        Some(baud_rate) => baud_rate,
+        None => {
+            set_baud_rate(config.baud_rate);
+            report.add_cause(if echo.is_none() { LikelyCause::TxRxSwapped } else { LikelyCause::NoServo });
+            return Ok(report);
+        }
+    };
+    if baud_rate != config.baud_rate {
+        report.add_cause(LikelyCause::WrongBaudRate { baud_rate });
+    }
+
+    let mut master = ProtocolMaster::<SMALL_BUFFER_SIZE>::new(master_config);
+    // Responses of duplicate IDs to the sweep may still be in flight.
+    listen::<T, R, W>(reader, config.response_window, &mut [])?;
+    let mut failed = 0;
+    let mut unique_transactions = 0;
+    for id in report.servos.iter() {
+        let mut servo_failed = 0;
+        let mut extra_responses = 0;
+        for _ in 0..config.transactions {
+            let mut registers = [0; StatusBlock::LENGTH];
+            let result = master.read_register(reader, writer, id, REGISTER_CURRENT_POSITION_H.address, &mut registers, timeout_after::<T>(config.transaction_timeout));
+            master.reset();
+            let trailing = listen::<T, R, W>(reader, config.response_window, &mut [])?;
+            match result {
+                Ok(_) => {
+                    let voltage = StatusBlock::from_registers(&registers).voltage;
+                    if report.lowest_voltage.is_none_or(|(_, lowest)| voltage < lowest) {
+                        report.lowest_voltage = Some((id, voltage));
+                    }
+                    if trailing > 0 {
+                        extra_responses += 1;
+                    }
+                }
+                Err(ProtocolHandlerError::TimedOut) => {
+                    report.timeouts += 1;
+                    servo_failed += 1;
+                }
+                Err(ProtocolHandlerError::ReaderError(err)) | Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(err))) => return Err(ProtocolHandlerError::ReaderError(err)),
+                Err(ProtocolHandlerError::WriterError(err)) => return Err(ProtocolHandlerError::WriterError(err)),
+                Err(_) => {
+                    report.corrupted += 1;
+                    servo_failed += 1;
+                }
+            }
+        }
+        report.transactions += config.transactions;
+        if (servo_failed + extra_responses) * 2 > config.transactions {
+            report.duplicate_ids.insert(id);
+        } else {
+            failed += servo_failed;
+            unique_transactions += config.transactions;
+        }
+    }
+
+    if !report.duplicate_ids.is_empty() {
+        report.add_cause(LikelyCause::DuplicateId);
+    }
+    if let Some((id, voltage)) = report.lowest_voltage {
+        if voltage < config.min_voltage {
+            report.add_cause(LikelyCause::PowerSag { id, voltage });
+        }
+    }
+    if unique_transactions > 0 {
+        let error_rate = failed as f32 / unique_transactions as f32;
+        if error_rate > config.max_error_rate {
+            report.add_cause(LikelyCause::Noise { error_rate });
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::REGISTER_CURRENT_VOLTAGE;
+    use crate::device::SimTimer;
+    use crate::emulator::BusEmulator;
+    extern crate std;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::vec::Vec;
+
+    /// Emulators sharing one bus. Requests only reach them when the port is connected and at their baud rate.
+    struct TestBus {
+        emulators: Vec<(BusEmulator<4>, Sender<u8>, Receiver<u8>)>,
+        responses: Sender<u8>,
+        received: Receiver<u8>,
+        echo: bool,
+        connected: bool,
+        baud_rate: u32,
+        servo_baud_rate: u32,
+    }
+    struct TestReader<'a>(&'a core::cell::RefCell<TestBus>);
+    struct TestWriter<'a>(&'a core::cell::RefCell<TestBus>);
+
+    impl StreamReader for TestReader<'_> {
+        type Error = ();
+        fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+            let bus = &mut *self.0.borrow_mut();
+            for (emulator, _, requests) in bus.emulators.iter_mut() {
+                for _ in 0..4 {
+                    emulator.process(requests, &mut bus.responses).unwrap();
+                }
+            }
+            SimTimer::advance(Duration::from_micros(100));
+            bus.received.read(data)
+        }
+    }
+    impl StreamWriter for TestWriter<'_> {
+        type Error = ();
+        fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+            let bus = &mut *self.0.borrow_mut();
+            for byte in data {
+                if bus.echo {
+                    bus.responses.send(*byte).unwrap();
+                }
+                if bus.connected && bus.baud_rate == bus.servo_baud_rate {
+                    for (_, requests, _) in bus.emulators.iter() {
+                        requests.send(*byte).unwrap();
+                    }
+                }
+            }
+            Ok(data.len())
+        }
+    }
+
+    fn test_bus(emulators: Vec<BusEmulator<4>>) -> core::cell::RefCell<TestBus> {
+        let (responses, received) = channel();
+        let emulators = emulators.into_iter().map(|emulator| {
+            let (sender, requests) = channel();
+            (emulator, sender, requests)
+        }).collect();
+        core::cell::RefCell::new(TestBus { emulators, responses, received, echo: false, connected: true, baud_rate: BAUD_RATES[0], servo_baud_rate: BAUD_RATES[0] })
+    }
+
+    fn test_config() -> DiagnoseConfig {
+        let scan = ScanConfig { ids: IdSet::from_ids(&[0, 1, 2, 3, 4, 5, 6, 7]), ..Default::default() };
+        DiagnoseConfig { scan, ..Default::default() }
+    }
+
+    fn run(bus: &core::cell::RefCell<TestBus>) -> DiagnosticReport {
+        SimTimer::reset();
+        diagnose::<SimTimer, _, _, _>(&mut TestReader(bus), &mut TestWriter(bus), |baud_rate| bus.borrow_mut().baud_rate = baud_rate, &test_config()).unwrap()
+    }
+
+    #[test]
+    fn test_diagnose_healthy() {
+        let bus = test_bus(std::vec![BusEmulator::new(1, 3)]);
+        let report = run(&bus);
+        assert!(report.is_healthy(), "{:?}", report);
+        assert_eq!(report.baud_rate, Some(BAUD_RATES[0]));
+        assert_eq!(report.servos, IdSet::from_ids(&[1, 2, 3]));
+        assert_eq!(report.transactions, 3 * 20);
+        assert_eq!(report.lowest_voltage.map(|(_, voltage)| voltage), Some(70));
+    }
+
+    #[test]
+    fn test_diagnose_echo_and_baud_rate() {
+        let bus = test_bus(std::vec![BusEmulator::new(1, 2)]);
+        bus.borrow_mut().echo = true;
+        bus.borrow_mut().servo_baud_rate = 115_200;
+        let report = run(&bus);
+        assert!(report.echo_back);
+        assert_eq!(report.baud_rate, Some(115_200));
+        assert_eq!(bus.borrow().baud_rate, 115_200);
+        assert_eq!(report.servos, IdSet::from_ids(&[1, 2]));
+        assert_eq!(report.likely_causes().collect::<Vec<_>>(), [LikelyCause::EchoMismatch { detected: true }, LikelyCause::WrongBaudRate { baud_rate: 115_200 }]);
+    }
+
+    #[test]
+    fn test_diagnose_duplicate_id_and_power_sag() {
+        let mut emulator = BusEmulator::new(1, 2);
+        emulator.servo_mut(1).unwrap().registers_mut()[REGISTER_CURRENT_VOLTAGE.address as usize] = 40;
+        let bus = test_bus(std::vec![emulator, BusEmulator::new(2, 1)]);
+        let report = run(&bus);
+        assert_eq!(report.servos, IdSet::from_ids(&[1, 2]));
+        assert_eq!(report.duplicate_ids, IdSet::from_ids(&[2]));
+        assert_eq!(report.likely_causes().collect::<Vec<_>>(), [LikelyCause::DuplicateId, LikelyCause::PowerSag { id: 1, voltage: 40 }]);
+    }
+
+    #[test]
+    fn test_diagnose_silent_bus() {
+        let bus = test_bus(std::vec![BusEmulator::new(1, 2)]);
+        bus.borrow_mut().connected = false;
+        let report = run(&bus);
+        assert_eq!(report.baud_rate, None);
+        assert_eq!(bus.borrow().baud_rate, BAUD_RATES[0]);
+        assert_eq!(report.likely_causes().collect::<Vec<_>>(), [LikelyCause::TxRxSwapped]);
+
+        bus.borrow_mut().echo = true;
+        let report = run(&bus);
+        assert_eq!(report.likely_causes().collect::<Vec<_>>(), [LikelyCause::EchoMismatch { detected: true }, LikelyCause::NoServo]);
+    }
+}
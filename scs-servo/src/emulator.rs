@@ -0,0 +1,427 @@
+//! SCS0009 servo emulator.
+//!
+//! [`EmulatedServo`] holds the register map of a single servo and a simple motion model.
+//! [`BusEmulator`] puts several of them behind one [`ProtocolSlave`] so a whole bus can be
+//! simulated on a single port (e.g. one end of a pty pair) or in-process.
+
+use core::time::Duration;
+
+use crate::device::scs0009::*;
+use crate::device::{RawSpeed, RegisterDefinition, RegisterStorage};
+use crate::packet::{PacketReader, PacketWriter};
+use crate::protocol::{IdSet, ParsedInstruction, ProtocolHandlerError, ProtocolSlave, ProtocolSlaveConfig, StreamReader, StreamWriter, BROADCAST_ID};
+
+const REGISTER_SIZE: usize = 256;
+
+const INITIAL_VOLTAGE: u8 = 70; // 7.0V
+const INITIAL_TEMPERATURE: u8 = 30; // 30 degC
+const MAX_SPEED: u32 = 2048; // counts/s
+
+/// Faults injected into an [`EmulatedServo`], e.g. by a [`Scenario`](crate::scenario::Scenario).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ServoFaults {
+    /// The servo ignores every packet, e.g. while its supply is browned out.
+    pub silent: bool,
+    /// Fraction of the responses sent with a wrong checksum, from 0.0 to 1.0.
+    pub checksum_noise: f32,
+}
+
+pub struct EmulatedServo {
+    registers: [u8; REGISTER_SIZE],
+    // Position in 1/1_000_000 counts to accumulate sub-count movements.
+    position_micro: u64,
+    // Movement speed for the current target in counts/s.
+    move_speed: u32,
+    // Data of the last REG WRITE, written on ACTION: start address, length and data.
+    staged: Option<(usize, usize)>,
+    staged_data: [u8; REGISTER_SIZE],
+    faults: ServoFaults,
+    // State of the xorshift generator of the checksum noise, seeded with the ID so that runs are reproducible.
+    noise_state: u32,
+}
+
+impl EmulatedServo {
+    pub fn new(id: u8) -> Self {
+        let mut registers = [0u8; REGISTER_SIZE];
+        for definition in REGISTER_LIST {
+            if let Some(default) = definition.default {
+                registers[definition.address as usize] = default;
+            }
+        }
+        registers[REGISTER_VERSION_H.address as usize] = 0x05;
+        registers[REGISTER_VERSION_L.address as usize] = 0x04;
+        registers[REGISTER_ID.address as usize] = id;
+        registers[REGISTER_CURRENT_VOLTAGE.address as usize] = INITIAL_VOLTAGE;
+        registers[REGISTER_CURRENT_TEMPERATURE.address as usize] = INITIAL_TEMPERATURE;
+        let mut servo = Self {
+            registers,
+            position_micro: 0,
+            move_speed: 0,
+            staged: None,
+            staged_data: [0; REGISTER_SIZE],
+            faults: ServoFaults::default(),
+            noise_state: 0x9e37_79b9 ^ id as u32,
+        };
+        let center = (servo.register_u16(REGISTER_LOWER_POSITION_LIMIT_H) + servo.register_u16(REGISTER_UPPER_POSITION_LIMIT_H)) / 2;
+        servo.set_register_u16(REGISTER_CURRENT_POSITION_H, center);
+        servo.set_register_u16(REGISTER_TARGET_POSITION_H, center);
+        servo.position_micro = center as u64 * 1_000_000;
+        servo
+    }
+
+    pub fn id(&self) -> u8 {
+        self.registers[REGISTER_ID.address as usize]
+    }
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+    pub fn registers_mut(&mut self) -> &mut [u8] {
+        &mut self.registers
+    }
+    pub fn position(&self) -> u16 {
+        self.register_u16(REGISTER_CURRENT_POSITION_H)
+    }
+    pub fn faults(&self) -> &ServoFaults {
+        &self.faults
+    }
+    pub fn faults_mut(&mut self) -> &mut ServoFaults {
+        &mut self.faults
+    }
+
+    fn register_u16(&self, register: RegisterDefinition) -> u16 {
+        let address = register.address as usize;
+        WORD_ORDER.from_bytes([self.registers[address], self.registers[address + 1]])
+    }
+    fn set_register_u16(&mut self, register: RegisterDefinition, value: u16) {
+        let address = register.address as usize;
+        self.registers[address..address + 2].copy_from_slice(&WORD_ORDER.to_bytes(value));
+    }
+
+    fn target_position(&self) -> u16 {
+        let lower = self.register_u16(REGISTER_LOWER_POSITION_LIMIT_H);
+        let upper = self.register_u16(REGISTER_UPPER_POSITION_LIMIT_H);
+        self.register_u16(REGISTER_TARGET_POSITION_H).clamp(lower, upper.max(lower))
+    }
+
+    /// Recalculates the movement speed after the target registers were written.
+    fn start_motion(&mut self) {
+        let period = self.register_u16(REGISTER_TARGET_PERIOD_H) as u32;
+        let speed = RawSpeed(self.register_u16(REGISTER_TARGET_SPEED_H)).to_signed(SPEED_ENCODING).unsigned_abs() as u32;
+        let distance = (self.target_position() as i32 - self.position() as i32).unsigned_abs();
+        self.move_speed = match (distance * 1000).checked_div(period) {
+            Some(speed) => speed.max(1),
+            // Speed unit is 0.19 deg/s, position unit is 300/1023 deg.
+            None if speed > 0 => (speed * 19 * 1023 / (100 * 300)).max(1),
+            None => MAX_SPEED,
+        };
+    }
+
+    /// Advances the motion model by `elapsed`.
+    pub fn update(&mut self, elapsed: Duration) {
+        let torque_enabled = self.registers[REGISTER_TORQUE_SWITCH.address as usize] != 0;
+        let target = self.target_position() as u64 * 1_000_000;
+        let step = if torque_enabled { self.move_speed as u64 * elapsed.as_micros() as u64 } else { 0 };
+        let (position, direction) = if self.position_micro < target {
+            ((self.position_micro + step).min(target), 1)
+        } else {
+            (self.position_micro.saturating_sub(step).max(target), -1)
+        };
+        let moved = self.position_micro != position;
+        self.position_micro = position;
+        self.set_register_u16(REGISTER_CURRENT_POSITION_H, (position / 1_000_000) as u16);
+        let speed = if moved { (self.move_speed * 100 * 300 / (19 * 1023)).min(0x7fff) as i16 * direction } else { 0 };
+        self.set_register_u16(REGISTER_CURRENT_SPEED_H, RawSpeed::from_signed(speed, SPEED_ENCODING).0);
+    }
+
+    /// Restarts the servo like after a dip of the supply voltage: the RAM registers return to their power-on
+    /// values and the servo holds its current position.
+    pub fn restart(&mut self) {
+        for definition in REGISTER_LIST.iter().filter(|definition| matches!(definition.storage, RegisterStorage::Ram)) {
+            if let Some(default) = definition.default {
+                self.registers[definition.address as usize] = default;
+            }
+        }
+        self.set_register_u16(REGISTER_TARGET_POSITION_H, self.position());
+        self.move_speed = 0;
+    }
+
+    /// Alarm conditions reported in the error byte of the responses.
+    pub fn alarms(&self) -> AlarmFlags {
+        let voltage = self.registers[REGISTER_CURRENT_VOLTAGE.address as usize];
+        let min_voltage = self.registers[REGISTER_MIN_INPUT_VOLTAGE.address as usize];
+        let max_voltage = self.registers[REGISTER_MAX_INPUT_VOLTAGE.address as usize];
+        let temperature = self.registers[REGISTER_CURRENT_TEMPERATURE.address as usize];
+        let mut alarms = AlarmFlags(0);
+        alarms.set_voltage(!(min_voltage..=max_voltage).contains(&voltage));
+        alarms.set_overheat(temperature > self.registers[REGISTER_UPPER_TEMPERATURE_LIMIT.address as usize]);
+        alarms
+    }
+
+    fn write_response(&self, buffer: &mut [u8], data: &[u8]) -> Option<usize> {
+        let length = data.len() + 6;
+        if buffer.len() < length {
+            return None;
+        }
+        buffer[0] = 0xff;
+        buffer[1] = 0xff;
+        let mut writer = PacketWriter::new(&mut buffer[2..length]);
+        writer.set_length(data.len() as u8 + 2).ok()?;
+        writer.set_id(self.id()).ok()?;
+        let body = writer.data_mut().ok()?;
+        body[0] = self.alarms().bits();
+        body[1..].copy_from_slice(data);
+        writer.update_checksum().ok()?;
+        Some(length)
+    }
+
+    /// Data staged by REG WRITE as the start address and the data.
+    pub fn staged(&self) -> Option<(u8, &[u8])> {
+        self.staged.map(|(start, length)| (start as u8, &self.staged_data[..length]))
+    }
+
+    fn write_registers(&mut self, start: usize, body: &[u8]) {
+        let end = (start + body.len()).min(REGISTER_SIZE);
+        for (address, value) in (start..end).zip(body) {
+            let writable = REGISTER_LIST.iter().any(|definition| definition.address as usize == address && definition.writable);
+            if writable {
+                self.registers[address] = *value;
+            }
+        }
+        let target_range = REGISTER_TARGET_POSITION_H.address as usize..=REGISTER_TARGET_SPEED_L.address as usize;
+        if (start..end).any(|address| target_range.contains(&address)) {
+            self.start_motion();
+        }
+    }
+
+    /// Whether the next response gets a wrong checksum.
+    fn roll_checksum_noise(&mut self) -> bool {
+        if self.faults.checksum_noise <= 0.0 {
+            return false;
+        }
+        let mut state = self.noise_state;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.noise_state = state;
+        (state as f32 / u32::MAX as f32) < self.faults.checksum_noise
+    }
+
+    /// Handles a request packet and writes the response to `buffer`.
+    /// Returns the length of the response, or `None` if no response must be sent.
+    pub fn handle_packet(&mut self, packet: &PacketReader, buffer: &mut [u8]) -> Option<usize> {
+        if self.faults.silent {
+            return None;
+        }
+        let length = self.handle_request(packet, buffer)?;
+        if self.roll_checksum_noise() {
+            buffer[length - 1] = !buffer[length - 1];
+        }
+        Some(length)
+    }
+
+    fn handle_request(&mut self, packet: &PacketReader, buffer: &mut [u8]) -> Option<usize> {
+        let id = packet.id().ok()?;
+        if id != self.id() && id != BROADCAST_ID {
+            return None;
+        }
+        let respond = id != BROADCAST_ID;
+        let response_enabled = self.registers[REGISTER_RESPONSE_ENABLE.address as usize] != 0;
+        match ParsedInstruction::parse(packet).ok()? {
+            ParsedInstruction::Ping => {
+                if respond { self.write_response(buffer, &[]) } else { None }
+            },
+            ParsedInstruction::ReadRegister { address, length } => {
+                if !respond {
+                    return None;
+                }
+                let start = address as usize;
+                let end = start + length as usize;
+                if end > REGISTER_SIZE {
+                    return None;
+                }
+                self.write_response(buffer, &self.registers[start..end])
+            },
+            ParsedInstruction::WriteRegister { address, data } => {
+                self.write_registers(address as usize, data);
+                if respond && response_enabled { self.write_response(buffer, &[]) } else { None }
+            },
+            ParsedInstruction::RegWrite { address, data } => {
+                self.staged_data[..data.len()].copy_from_slice(data);
+                self.staged = Some((address as usize, data.len()));
+                if respond && response_enabled { self.write_response(buffer, &[]) } else { None }
+            },
+            ParsedInstruction::Action => {
+                if let Some((start, length)) = self.staged.take() {
+                    let staged_data = self.staged_data;
+                    self.write_registers(start, &staged_data[..length]);
+                }
+                if respond && response_enabled { self.write_response(buffer, &[]) } else { None }
+            },
+            _ => None,
+        }
+    }
+}
+
+pub const EMULATOR_BUFFER_SIZE: usize = 1024;
+
+/// Emulates up to `MAX_SERVOS` servos sharing one bus.
+pub struct BusEmulator<const MAX_SERVOS: usize> {
+    servos: [EmulatedServo; MAX_SERVOS],
+    count: usize,
+    slave: ProtocolSlave<EMULATOR_BUFFER_SIZE>,
+}
+
+impl<const MAX_SERVOS: usize> BusEmulator<MAX_SERVOS> {
+    /// Creates `count` servos with consecutive IDs starting from `base_id`.
+    pub fn new(base_id: u8, count: usize) -> Self {
+        let count = count.min(MAX_SERVOS);
+        let servos = core::array::from_fn(|index| EmulatedServo::new(base_id.wrapping_add(index as u8)));
+        let mut emulator = Self {
+            servos,
+            count,
+            slave: ProtocolSlave::new(ProtocolSlaveConfig { ids: IdSet::new() }),
+        };
+        emulator.update_ids();
+        emulator
+    }
+
+    pub fn servos(&self) -> &[EmulatedServo] {
+        &self.servos[..self.count]
+    }
+    pub fn servos_mut(&mut self) -> &mut [EmulatedServo] {
+        &mut self.servos[..self.count]
+    }
+    pub fn servo(&self, id: u8) -> Option<&EmulatedServo> {
+        self.servos().iter().find(|servo| servo.id() == id)
+    }
+    pub fn servo_mut(&mut self, id: u8) -> Option<&mut EmulatedServo> {
+        self.servos_mut().iter_mut().find(|servo| servo.id() == id)
+    }
+
+    fn update_ids(&mut self) {
+        let mut ids = IdSet::new();
+        for servo in self.servos() {
+            ids.insert(servo.id());
+        }
+        *self.slave.ids_mut() = ids;
+    }
+
+    /// Discards a partially received or sent packet.
+    pub fn reset(&mut self) {
+        self.slave.reset();
+    }
+
+    /// Advances the motion model of all servos by `elapsed`.
+    pub fn update(&mut self, elapsed: Duration) {
+        for servo in self.servos_mut() {
+            servo.update(elapsed);
+        }
+    }
+
+    /// Processes the bus traffic. Must be called repeatedly.
+    pub fn process<R: StreamReader, W: StreamWriter>(&mut self, reader: &mut R, writer: &mut W) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let servos = &mut self.servos[..self.count];
+        self.slave.process(reader, writer, |packet, buffer| handle_packet(servos, packet, buffer))?;
+        self.update_ids();
+        Ok(())
+    }
+
+    /// Handles a request without going through a stream. `packet` starts after the markers. Returns the length of the
+    /// response with the markers written to `response`, or `None` if no servo answers.
+    pub fn handle_packet(&mut self, packet: &PacketReader, response: &mut [u8]) -> Option<usize> {
+        let length = handle_packet(&mut self.servos[..self.count], packet, response);
+        self.update_ids();
+        length
+    }
+}
+
+/// Passes `packet` to the servo it is addressed to, or to all servos if it is broadcast.
+fn handle_packet(servos: &mut [EmulatedServo], packet: &PacketReader, buffer: &mut [u8]) -> Option<usize> {
+    let id = packet.id().ok()?;
+    if id == BROADCAST_ID {
+        for servo in servos.iter_mut() {
+            servo.handle_packet(packet, buffer);
+        }
+        None
+    } else {
+        servos.iter_mut().find(|servo| servo.id() == id)?.handle_packet(packet, buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::ServoControl;
+    use crate::protocol::{ActionCommand, Command, ProtocolMasterConfig};
+    use crate::testing::emulator_thread::EmulatorThread;
+    extern crate std;
+
+    #[test]
+    fn test_bus_emulator() {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn_moving(BusEmulator::<6>::new(1, 6));
+
+        let mut control = crate::device::scs0009::Scs0009ServoControl::<_, _, std::time::Instant>::new(0x06, master_reader, master_writer, ProtocolMasterConfig::default(), Duration::from_secs(1));
+        assert_eq!(control.position_lower_limit().unwrap(), 0x0000);
+        assert_eq!(control.position_upper_limit().unwrap(), 0x03ff);
+        control.output_enable().unwrap();
+        control.set_target_period(50).unwrap();
+        control.set_target_position(0x0100).unwrap();
+        let start = std::time::Instant::now();
+        loop {
+            control.update().unwrap();
+            if control.current_position().unwrap() == 0x0100 {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(1), "servo did not reach the target");
+        }
+
+        let emulator = thread.stop();
+        assert_eq!(emulator.servo(0x06).unwrap().position(), 0x0100);
+        assert_eq!(emulator.servo(0x01).unwrap().position(), 0x01ff);
+    }
+
+    #[test]
+    fn test_emulated_servo_update() {
+        let mut servo = EmulatedServo::new(1);
+        servo.registers_mut()[REGISTER_TORQUE_SWITCH.address as usize] = 1;
+        let mut request = [0u8; 9];
+        {
+            let mut writer = PacketWriter::new(&mut request);
+            writer.set_id(1).unwrap();
+            writer.set_length(7).unwrap();
+            writer.data_mut().unwrap().copy_from_slice(&[Command::WriteRegister as u8, REGISTER_TARGET_POSITION_H.address, 0x02, 0x00, 0x03, 0xe8]);
+            writer.update_checksum().unwrap();
+        }
+        let mut response = [0u8; 16];
+        assert_eq!(servo.handle_packet(&PacketReader::new(&request), &mut response), Some(6));
+        // Move from 0x1ff to 0x200 within 1000ms
+        servo.update(Duration::from_millis(500));
+        assert_ne!(servo.position(), 0x200);
+        assert_ne!(servo.registers()[REGISTER_CURRENT_SPEED_L.address as usize], 0);
+        servo.update(Duration::from_millis(600));
+        assert_eq!(servo.position(), 0x200);
+    }
+
+    #[test]
+    fn test_emulated_servo_reg_write() {
+        let mut servo = EmulatedServo::new(1);
+        let mut request = [0u8; 7];
+        {
+            let mut writer = PacketWriter::new(&mut request);
+            writer.set_id(1).unwrap();
+            writer.set_length(5).unwrap();
+            writer.data_mut().unwrap().copy_from_slice(&[Command::RegWriteRegister as u8, REGISTER_TARGET_POSITION_H.address, 0x01, 0x00]);
+            writer.update_checksum().unwrap();
+        }
+        let mut response = [0u8; 16];
+        assert_eq!(servo.handle_packet(&PacketReader::new(&request), &mut response), Some(6));
+        // The write is staged until ACTION.
+        assert_eq!(servo.staged(), Some((REGISTER_TARGET_POSITION_H.address, &[0x01, 0x00][..])));
+        assert_eq!(&servo.registers()[REGISTER_TARGET_POSITION_H.address as usize..][..2], &[0x01, 0xff]);
+
+        let action = ActionCommand::new(BROADCAST_ID);
+        assert_eq!(servo.handle_packet(&PacketReader::new(&action.raw[2..]), &mut response), None);
+        assert_eq!(servo.staged(), None);
+        assert_eq!(&servo.registers()[REGISTER_TARGET_POSITION_H.address as usize..][..2], &[0x01, 0x00]);
+    }
+}
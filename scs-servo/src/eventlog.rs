@@ -0,0 +1,218 @@
+//! Bounded record of the recent bus transactions.
+//!
+//! [`Bus`](crate::bus::Bus) records every transaction into an [`EventLog`], which keeps only the last `N`
+//! events, so it can stay enabled in the field without growing. When an operation fails, the application
+//! can take the recent history from the bus and [`dump`](EventLog::dump) it, e.g. into a file, to see what
+//! happened on the bus before the failure.
+
+use core::fmt;
+use core::time::Duration;
+
+use crate::protocol::{ProtocolHandlerError, ProtocolReaderError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Ping,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    Ok,
+    /// The write policy dropped the write without transmitting it.
+    Dropped,
+    /// The bus refused the operation without transmitting it, e.g. a write to a protected register.
+    Rejected,
+    TimedOut,
+    UnexpectedPacketId(u8),
+    UnexpectedLength(usize),
+    /// The response was malformed.
+    InvalidPacket,
+    /// The response was discarded while the link recovered from a burst of corrupted frames.
+    LinkUnstable,
+    /// The reader or the writer failed.
+    TransportError,
+    /// The registers read back differ from the data written.
+    VerificationFailed,
+    /// The adapter echoed back other bytes than the command written.
+    EchoMismatch,
+    /// More than one servo answered the ID.
+    IdConflict,
+}
+
+impl Outcome {
+    pub fn of<T, ReaderError, WriterError>(result: &Result<T, ProtocolHandlerError<ReaderError, WriterError>>) -> Self {
+        match result {
+            Ok(_) => Outcome::Ok,
+            Err(ProtocolHandlerError::TimedOut) => Outcome::TimedOut,
+            Err(ProtocolHandlerError::UnexpectedPacketId(id)) => Outcome::UnexpectedPacketId(*id),
+            Err(ProtocolHandlerError::UnexpectedLength(length)) => Outcome::UnexpectedLength(*length),
+            Err(ProtocolHandlerError::LinkUnstable) => Outcome::LinkUnstable,
+            Err(ProtocolHandlerError::VerificationFailed { .. }) => Outcome::VerificationFailed,
+            Err(ProtocolHandlerError::EchoMismatch) => Outcome::EchoMismatch,
+            Err(ProtocolHandlerError::IdConflict(_)) => Outcome::IdConflict,
+            Err(ProtocolHandlerError::ResponsesDisabled) | Err(ProtocolHandlerError::WriteProtected(_)) | Err(ProtocolHandlerError::Rejected(_)) |
+            Err(ProtocolHandlerError::BroadcastDenied) => Outcome::Rejected,
+            Err(ProtocolHandlerError::PacketError(_)) |
+            Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::PacketError(_))) |
+            Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer)) => Outcome::InvalidPacket,
+            Err(ProtocolHandlerError::ReaderError(_)) |
+            Err(ProtocolHandlerError::WriterError(_)) |
+            Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(_))) => Outcome::TransportError,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusEvent {
+    /// Start of the transaction since the creation of the bus.
+    pub timestamp: Duration,
+    /// Time the transaction took.
+    pub elapsed: Duration,
+    pub operation: Operation,
+    pub id: u8,
+    /// First register read or written. 0 for pings.
+    pub address: u8,
+    /// Number of registers read or written. 0 for pings.
+    pub length: u8,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for BusEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:12.6} ID {:3} ", self.timestamp.as_secs_f64(), self.id)?;
+        match self.operation {
+            Operation::Ping => write!(f, "ping          ")?,
+            Operation::Read => write!(f, "read  0x{:02x}+{:<3}", self.address, self.length)?,
+            Operation::Write => write!(f, "write 0x{:02x}+{:<3}", self.address, self.length)?,
+        }
+        write!(f, " {:?} in {}us", self.outcome, self.elapsed.as_micros())
+    }
+}
+
+/// Ring buffer of the last `N` bus events.
+pub struct EventLog<const N: usize> {
+    events: [Option<BusEvent>; N],
+    next: usize,
+    len: usize,
+    recorded: u32,
+}
+
+impl<const N: usize> Default for EventLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EventLog<N> {
+    pub const fn new() -> Self {
+        Self {
+            events: [None; N],
+            next: 0,
+            len: 0,
+            recorded: 0,
+        }
+    }
+
+    /// Appends `event`, overwriting the oldest one if the log is full.
+    pub fn push(&mut self, event: BusEvent) {
+        self.recorded = self.recorded.wrapping_add(1);
+        if N == 0 {
+            return;
+        }
+        self.events[self.next] = Some(event);
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+    pub fn clear(&mut self) {
+        self.events = [None; N];
+        self.next = 0;
+        self.len = 0;
+        self.recorded = 0;
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+    /// Number of events held.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Number of events recorded since the creation or the last clear, including the overwritten ones.
+    pub fn recorded(&self) -> u32 {
+        self.recorded
+    }
+
+    /// Events held, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &BusEvent> + '_ {
+        let start = if self.len < N { 0 } else { self.next };
+        (0..self.len).filter_map(move |i| self.events[(start + i) % N].as_ref())
+    }
+    pub fn last(&self) -> Option<&BusEvent> {
+        self.iter().last()
+    }
+
+    /// Writes the events held, oldest first, one per line.
+    pub fn dump<Out: fmt::Write>(&self, out: &mut Out) -> fmt::Result {
+        writeln!(out, "# last {} of {} bus events", self.len(), self.recorded)?;
+        for event in self.iter() {
+            writeln!(out, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+    use std::string::String;
+
+    fn event(id: u8, outcome: Outcome) -> BusEvent {
+        BusEvent {
+            timestamp: Duration::from_millis(id as u64),
+            elapsed: Duration::from_micros(320),
+            operation: Operation::Read,
+            id,
+            address: 0x38,
+            length: 6,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_event_log() {
+        let mut log = EventLog::<3>::new();
+        assert!(log.is_empty());
+        assert_eq!(log.last(), None);
+        log.push(event(1, Outcome::Ok));
+        log.push(event(2, Outcome::TimedOut));
+        assert_eq!(log.iter().map(|event| event.id).collect::<std::vec::Vec<_>>(), [1, 2]);
+        for id in 3..=5 {
+            log.push(event(id, Outcome::Ok));
+        }
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.recorded(), 5);
+        assert_eq!(log.iter().map(|event| event.id).collect::<std::vec::Vec<_>>(), [3, 4, 5]);
+        assert_eq!(log.last().unwrap().id, 5);
+
+        let mut dump = String::new();
+        log.dump(&mut dump).unwrap();
+        let mut lines = dump.lines();
+        assert_eq!(lines.next(), Some("# last 3 of 5 bus events"));
+        assert_eq!(lines.next(), Some("    0.003000 ID   3 read  0x38+6   Ok in 320us"));
+        assert_eq!(lines.count(), 2);
+
+        log.clear();
+        assert!(log.is_empty());
+        let mut empty = EventLog::<0>::new();
+        empty.push(event(1, Outcome::Ok));
+        assert_eq!(empty.iter().count(), 0);
+        assert_eq!(empty.recorded(), 1);
+    }
+}
@@ -0,0 +1,140 @@
+//! Entry points for fuzzing the frame parser.
+//!
+//! Each function takes arbitrary bytes and runs them through the parser, calling every checked
+//! accessor on the result. They must never panic, whatever the input is. A cargo-fuzz target
+//! only needs to forward its input:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     scs_servo::fuzz::parse_stream(data);
+//! });
+//! ```
+
+use crate::packet::{PacketReader, PacketWriter};
+use crate::protocol::{ProtocolReader, StreamReader};
+
+fn exercise_packet(packet: &PacketReader) -> bool {
+    packet.id().ok();
+    packet.length().ok();
+    packet.checksum().ok();
+    packet.data().ok();
+    packet.calculate_checksum().ok();
+    packet.verify_checksum().is_ok()
+}
+
+/// Parses `data` as a single packet without markers (ID, length, data and checksum).
+/// Returns whether the packet is valid.
+pub fn parse_packet(data: &[u8]) -> bool {
+    let mut buffer = [0u8; 260];
+    let length = data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&data[..length]);
+    {
+        let mut writer = PacketWriter::new(&mut buffer[..length]);
+        writer.id().ok();
+        writer.length().ok();
+        writer.data().ok();
+        writer.checksum().ok();
+        writer.calculate_checksum().ok();
+        if let Ok(data) = writer.data_mut() {
+            data.iter_mut().for_each(|byte| *byte = byte.wrapping_add(1));
+        }
+        writer.update_checksum().ok();
+    }
+    exercise_packet(&PacketReader::new(&data[..length]))
+}
+
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    chunk_size: usize,
+}
+
+impl StreamReader for ChunkedReader<'_> {
+    type Error = ();
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let remaining = self.data.len() - self.position;
+        if remaining == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        let length = data.len().min(remaining).min(self.chunk_size);
+        data[..length].copy_from_slice(&self.data[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Feeds `data` to a `ProtocolReader` as a byte stream. The first byte selects the maximum number of bytes
+/// returned by each read, so the fuzzer also explores how frames are split across reads.
+/// Returns the number of valid packets found in the stream.
+pub fn parse_stream(data: &[u8]) -> usize {
+    let (chunk_size, stream) = match data.split_first() {
+        Some((chunk_size, stream)) => ((*chunk_size as usize % 16) + 1, stream),
+        None => return 0,
+    };
+    let mut reader = ChunkedReader { data: stream, position: 0, chunk_size };
+    let mut protocol_reader = ProtocolReader::<64>::new();
+    let mut valid_packets = 0;
+    while reader.position < stream.len() {
+        match protocol_reader.read(&mut reader) {
+            Ok(true) => {
+                if exercise_packet(&protocol_reader.packet().unwrap()) {
+                    valid_packets += 1;
+                }
+            },
+            Ok(false) => {},
+            Err(_) => {
+                protocol_reader = ProtocolReader::new();
+            },
+        }
+    }
+    valid_packets
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct XorShift(u32);
+    impl XorShift {
+        fn next(&mut self) -> u8 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as u8
+        }
+    }
+
+    #[test]
+    fn test_parse_random_input() {
+        let mut random = XorShift(0x12345678);
+        let mut data = [0u8; 128];
+        for _ in 0..10000 {
+            let length = random.next() as usize % data.len();
+            for byte in data[..length].iter_mut() {
+                // Bias towards markers and small length values.
+                *byte = match random.next() % 4 {
+                    0 => 0xff,
+                    1 => random.next() % 8,
+                    _ => random.next(),
+                };
+            }
+            parse_packet(&data[..length]);
+            parse_stream(&data[..length]);
+        }
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let frame = [0xff, 0xff, 0x01, 0x05, 0x03, 0x2a, 0x00, 0x14, 0xb8];
+        for chunk_size in 0..16u8 {
+            let mut data = [0u8; 1 + 3 + 9 * 2];
+            data[0] = chunk_size;
+            data[1..4].copy_from_slice(&[0xff, 0x00, 0xff]);
+            data[4..13].copy_from_slice(&frame);
+            data[13..].copy_from_slice(&frame);
+            assert_eq!(parse_stream(&data), 2);
+        }
+        assert!(parse_packet(&frame[2..]));
+        assert!(!parse_packet(&[0x01, 0x00, 0x00]));
+    }
+}
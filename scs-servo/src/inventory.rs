@@ -0,0 +1,118 @@
+//! Cache of the servos found on a bus.
+//!
+//! Scanning the whole ID range takes a while, so tools can keep the result of a scan as an [`Inventory`]
+//! (serializable with the `serde` feature) and restore it on the next start. A restored inventory may be
+//! stale: [`Inventory::verify`] pings every servo in it, and the tools fall back to a scan if one is missing.
+
+extern crate std;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::bus::{Bus, BusError};
+use crate::device::Timer;
+use crate::policy::WritePolicy;
+use crate::protocol::{IdSet, ProtocolHandlerError, ProtocolReaderError, StreamReader, StreamWriter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InventoryEntry {
+    pub id: u8,
+    /// Model name if known. The servos do not report their model, so a scan leaves it empty.
+    pub model: Option<String>,
+    /// Software version registers (0x03-0x04), if they could be read.
+    pub firmware_version: Option<[u8; 2]>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Inventory {
+    pub port: String,
+    pub baud_rate: u32,
+    pub servos: Vec<InventoryEntry>,
+}
+
+impl Inventory {
+    pub fn new(port: &str, baud_rate: u32) -> Self {
+        Self {
+            port: port.into(),
+            baud_rate,
+            servos: Vec::new(),
+        }
+    }
+
+    pub fn ids(&self) -> IdSet {
+        let mut ids = IdSet::new();
+        for servo in &self.servos {
+            ids.insert(servo.id);
+        }
+        ids
+    }
+
+    /// Whether the inventory was taken on `port` at `baud_rate`.
+    pub fn is_for(&self, port: &str, baud_rate: u32) -> bool {
+        self.port == port && self.baud_rate == baud_rate
+    }
+
+    /// Pings every servo in the inventory and returns the IDs which did not answer.
+    /// Transport errors are returned, since they say nothing about the servos.
+    pub fn verify<R, W, T, const BUFFER_SIZE: usize, P>(&self, bus: &mut Bus<R, W, T, BUFFER_SIZE, P>) -> Result<Vec<u8>, BusError<R, W>>
+        where R: StreamReader,
+              W: StreamWriter,
+              T: Timer,
+              P: WritePolicy,
+    {
+        let mut missing = Vec::new();
+        for servo in &self.servos {
+            match bus.ping(servo.id) {
+                Ok(_) => {}
+                Err(ProtocolHandlerError::ReaderError(err)) | Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(err))) => return Err(ProtocolHandlerError::ReaderError(err)),
+                Err(ProtocolHandlerError::WriterError(err)) => return Err(ProtocolHandlerError::WriterError(err)),
+                Err(_) => missing.push(servo.id),
+            }
+        }
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    use core::time::Duration;
+
+    fn inventory(ids: &[u8]) -> Inventory {
+        let mut inventory = Inventory::new("/dev/ttyUSB0", 1_000_000);
+        inventory.servos = ids.iter().map(|&id| InventoryEntry { id, model: None, firmware_version: Some([0x05, 0x04]) }).collect();
+        inventory
+    }
+
+    #[test]
+    fn test_inventory_verify() {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<2>::new(1, 2));
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+
+        assert!(inventory(&[1, 2]).verify(&mut bus).unwrap().is_empty());
+        assert_eq!(inventory(&[1, 3, 2]).verify(&mut bus).unwrap(), [3]);
+        assert!(inventory(&[1]).is_for("/dev/ttyUSB0", 1_000_000));
+        assert!(!inventory(&[1]).is_for("/dev/ttyUSB0", 115_200));
+        assert_eq!(inventory(&[2, 1]).ids().iter().collect::<Vec<_>>(), [1, 2]);
+
+        thread.stop();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_inventory_serde() {
+        let inventory = inventory(&[1, 2]);
+        let json = serde_json::to_string(&inventory).unwrap();
+        assert_eq!(serde_json::from_str::<Inventory>(&json).unwrap(), inventory);
+    }
+}
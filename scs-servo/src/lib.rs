@@ -2,4 +2,48 @@
 
 pub mod packet;
 pub mod protocol;
-pub mod device;
\ No newline at end of file
+pub mod protocol2;
+pub mod device;
+pub mod storage;
+pub mod emulator;
+pub mod bank;
+pub mod scan;
+pub mod bus;
+pub mod policy;
+pub mod diagnose;
+pub mod parameters;
+pub mod telemetry;
+pub mod collision;
+pub mod thermal;
+pub mod budget;
+pub mod queue;
+pub mod scheduler;
+pub mod transaction;
+pub mod coalesce;
+pub mod buffered;
+pub mod streaming;
+pub mod eventlog;
+pub mod robot;
+pub mod selftest;
+pub mod recovery;
+pub mod link;
+pub mod monitor;
+pub mod odometry;
+#[cfg(feature = "alloc")]
+pub mod command_vec;
+#[cfg(feature = "async")]
+pub mod cancel;
+#[cfg(feature = "std")]
+pub mod multibus;
+#[cfg(feature = "std")]
+pub mod inventory;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "simulate")]
+pub mod simulate;
+#[cfg(feature = "simulate")]
+pub mod scenario;
+#[cfg(any(feature = "fuzz", test))]
+pub mod fuzz;
+pub mod testing;
+pub mod transport;
\ No newline at end of file
@@ -0,0 +1,97 @@
+//! Link stability on noisy lines.
+//!
+//! On long unshielded daisy chains a burst of noise corrupts several frames in a row, and the first frames which
+//! pass the checksum after the burst are not trustworthy yet. A [`LinkGuard`] counts consecutive corrupted frames:
+//! after [`LinkGuardConfig::error_burst`] of them the link is unstable, and frames are distrusted until
+//! [`LinkGuardConfig::recovery_frames`] consecutive valid frames have been received.
+//!
+//! [`Bus`](crate::bus::Bus) fails transactions whose response was distrusted with
+//! [`ProtocolHandlerError::LinkUnstable`](crate::protocol::ProtocolHandlerError::LinkUnstable), see
+//! [`Bus::set_link_guard`](crate::bus::Bus::set_link_guard).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkGuardConfig {
+    /// Number of consecutive corrupted frames which make the link unstable. At least 1.
+    pub error_burst: u8,
+    /// Number of consecutive valid frames discarded before the link is trusted again.
+    pub recovery_frames: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Stable,
+    /// A burst of corrupted frames was received. `valid_frames` valid frames have been received since.
+    Unstable { valid_frames: u8 },
+}
+
+pub struct LinkGuard {
+    config: LinkGuardConfig,
+    errors: u8,
+    state: LinkState,
+}
+
+impl LinkGuard {
+    pub const fn new(config: LinkGuardConfig) -> Self {
+        Self { config, errors: 0, state: LinkState::Stable }
+    }
+
+    pub fn config(&self) -> &LinkGuardConfig {
+        &self.config
+    }
+    pub fn state(&self) -> LinkState {
+        self.state
+    }
+    pub fn is_stable(&self) -> bool {
+        self.state == LinkState::Stable
+    }
+
+    /// Updates the state with a received frame, `valid` if it passed the checksum.
+    /// Returns whether the frame can be trusted.
+    pub fn frame(&mut self, valid: bool) -> bool {
+        if !valid {
+            self.errors = self.errors.saturating_add(1);
+            if self.errors >= self.config.error_burst || !self.is_stable() {
+                self.state = LinkState::Unstable { valid_frames: 0 };
+            }
+            return false;
+        }
+        self.errors = 0;
+        match &mut self.state {
+            LinkState::Stable => true,
+            LinkState::Unstable { valid_frames } if *valid_frames >= self.config.recovery_frames => {
+                self.state = LinkState::Stable;
+                true
+            }
+            LinkState::Unstable { valid_frames } => {
+                *valid_frames += 1;
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_link_guard() {
+        let mut guard = LinkGuard::new(LinkGuardConfig { error_burst: 2, recovery_frames: 2 });
+        // A single corrupted frame is not a burst.
+        assert!(!guard.frame(false));
+        assert!(guard.frame(true));
+        assert!(!guard.frame(false));
+        assert!(!guard.frame(false));
+        assert_eq!(guard.state(), LinkState::Unstable { valid_frames: 0 });
+        assert!(!guard.frame(true));
+        // Any corrupted frame restarts the recovery.
+        assert!(!guard.frame(false));
+        assert_eq!(guard.state(), LinkState::Unstable { valid_frames: 0 });
+        assert!(!guard.frame(true));
+        assert!(!guard.frame(true));
+        assert_eq!(guard.state(), LinkState::Unstable { valid_frames: 2 });
+        assert!(guard.frame(true));
+        assert!(guard.is_stable());
+    }
+}
@@ -0,0 +1,236 @@
+//! Passive bus monitor.
+//!
+//! [`ProtocolMonitor`] listens to a bus driven by another controller, e.g. through a second adapter which only
+//! receives, and pairs the requests with the responses into timestamped [`Transaction`]s. Requests and responses
+//! cannot be told apart by their bytes, so a frame is taken as the response if it comes from the servo the last
+//! request expects an answer from, within the response timeout. The timeout should be shorter than the gap between
+//! two requests of the controller to the same servo.
+
+use core::time::Duration;
+
+use crate::device::{Instant, Timer};
+use crate::packet::PacketReader;
+use crate::protocol::{ParsedInstruction, ProtocolReader, ProtocolReaderError, StreamReader, BROADCAST_ID, BULK_BUFFER_SIZE};
+
+/// A request and one of its responses.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    /// Time the request was received since the monitor was created.
+    pub timestamp: Duration,
+    pub request: PacketReader<'a>,
+    /// The response, or `None` if none arrived within the timeout or none is expected, e.g. for broadcasts.
+    pub response: Option<PacketReader<'a>>,
+    /// Time from the request to the response.
+    pub latency: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum MonitorEvent<'a> {
+    /// A request with a response, with no response, or with one of the responses to a SYNC READ or a broadcast PING.
+    Transaction(Transaction<'a>),
+    /// A frame which failed the checksum, from the ID to the checksum.
+    Corrupted { timestamp: Duration, frame: &'a [u8] },
+}
+
+/// Responses expected for the last request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expected {
+    Nothing,
+    One(u8),
+    /// The next of the IDs listed in a SYNC READ.
+    Sync(usize),
+    /// Any servo, after a broadcast PING.
+    Any,
+}
+
+pub struct ProtocolMonitor<T: Timer, const BUFFER_SIZE: usize = BULK_BUFFER_SIZE> {
+    reader: ProtocolReader<BUFFER_SIZE>,
+    start: T::Instant,
+    response_timeout: Duration,
+    request: [u8; BUFFER_SIZE],
+    request_length: usize,
+    request_timestamp: Duration,
+    expected: Expected,
+    answered: bool,
+}
+
+impl<T: Timer, const BUFFER_SIZE: usize> ProtocolMonitor<T, BUFFER_SIZE> {
+    pub fn new(response_timeout: Duration) -> Self {
+        Self {
+            reader: ProtocolReader::new(),
+            start: T::now(),
+            response_timeout,
+            request: [0; BUFFER_SIZE],
+            request_length: 0,
+            request_timestamp: Duration::ZERO,
+            expected: Expected::Nothing,
+            answered: false,
+        }
+    }
+
+    pub fn response_timeout(&self) -> Duration {
+        self.response_timeout
+    }
+
+    /// Reads the bytes available from `reader` and reports the transactions completed to `on_event`. A request
+    /// without a response is reported once the timeout has passed, so call this periodically even if the bus is idle.
+    pub fn poll<R: StreamReader, OnEvent: FnMut(MonitorEvent)>(&mut self, reader: &mut R, mut on_event: OnEvent) -> Result<(), R::Error> {
+        loop {
+            match self.reader.read(reader) {
+                Ok(true) => self.receive(&mut on_event),
+                Ok(false) => break,
+                Err(ProtocolReaderError::ReaderError(err)) => return Err(err),
+                // The reader has skipped a frame too long for the buffer.
+                Err(_) => {}
+            }
+        }
+        if self.expected != Expected::Nothing && self.start.elapsed().saturating_sub(self.request_timestamp) > self.response_timeout {
+            self.finish(&mut on_event);
+        }
+        Ok(())
+    }
+
+    fn receive<OnEvent: FnMut(MonitorEvent)>(&mut self, on_event: &mut OnEvent) {
+        let timestamp = self.start.elapsed();
+        loop {
+            let frame = self.reader.packet().unwrap();
+            if frame.verify_checksum().is_ok() {
+                break;
+            }
+            on_event(MonitorEvent::Corrupted { timestamp, frame: frame.raw });
+            // The corrupted frame may have swallowed the start of the next one.
+            if !self.reader.resync() {
+                return;
+            }
+        }
+
+        let frame = self.reader.packet().unwrap();
+        let id = frame.id_unchecked();
+        let latency = timestamp.saturating_sub(self.request_timestamp);
+        let request = &self.request[..self.request_length];
+        let expected = match self.expected {
+            Expected::One(expected) => expected == id,
+            Expected::Sync(index) => sync_read_ids(request).get(index) == Some(&id),
+            Expected::Any => true,
+            Expected::Nothing => false,
+        };
+        if expected && latency <= self.response_timeout {
+            self.answered = true;
+            self.expected = match self.expected {
+                Expected::Sync(index) if index + 1 < sync_read_ids(request).len() => Expected::Sync(index + 1),
+                Expected::Any => Expected::Any,
+                _ => Expected::Nothing,
+            };
+            on_event(MonitorEvent::Transaction(Transaction { timestamp: self.request_timestamp, request: PacketReader::new(request), response: Some(frame), latency: Some(latency) }));
+            return;
+        }
+
+        self.finish(on_event);
+        let frame = self.reader.packet().unwrap();
+        self.request_length = frame.raw.len();
+        self.request[..self.request_length].copy_from_slice(frame.raw);
+        self.request_timestamp = timestamp;
+        self.answered = false;
+        let request = PacketReader::new(&self.request[..self.request_length]);
+        self.expected = match ParsedInstruction::parse(&request) {
+            Ok(ParsedInstruction::SyncRead { ids, .. }) if id == BROADCAST_ID && !ids.is_empty() => Expected::Sync(0),
+            Ok(ParsedInstruction::Ping) if id == BROADCAST_ID => Expected::Any,
+            _ if id == BROADCAST_ID => Expected::Nothing,
+            _ => Expected::One(id),
+        };
+        if self.expected == Expected::Nothing {
+            on_event(MonitorEvent::Transaction(Transaction { timestamp, request, response: None, latency: None }));
+        }
+    }
+
+    /// Reports the last request if it has not been answered, and stops waiting for its responses.
+    fn finish<OnEvent: FnMut(MonitorEvent)>(&mut self, on_event: &mut OnEvent) {
+        if self.expected != Expected::Nothing && !self.answered {
+            let request = PacketReader::new(&self.request[..self.request_length]);
+            on_event(MonitorEvent::Transaction(Transaction { timestamp: self.request_timestamp, request, response: None, latency: None }));
+        }
+        self.expected = Expected::Nothing;
+    }
+}
+
+/// IDs a SYNC READ request asks to respond, in order.
+fn sync_read_ids(request: &[u8]) -> &[u8] {
+    match ParsedInstruction::parse(&PacketReader::new(request)) {
+        Ok(ParsedInstruction::SyncRead { ids, .. }) => ids,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::SimTimer;
+    use crate::protocol::{Command, PingCommand, ReadRegisterCommand, SyncWriteCommand};
+    extern crate std;
+    use std::vec::Vec;
+
+    /// Summary of an event: the request ID and instruction, the response ID and the latency in ms.
+    type Summary = (u8, u8, Option<u8>, Option<u128>);
+
+    fn poll(monitor: &mut ProtocolMonitor<SimTimer>, reader: &mut std::sync::mpsc::Receiver<u8>, corrupted: &mut usize) -> Vec<Summary> {
+        let mut events = Vec::new();
+        monitor.poll(reader, |event| match event {
+            MonitorEvent::Transaction(transaction) => events.push((
+                transaction.request.id_unchecked(),
+                transaction.request.data().unwrap()[0],
+                transaction.response.map(|response| response.id_unchecked()),
+                transaction.latency.map(|latency| latency.as_millis()),
+            )),
+            MonitorEvent::Corrupted { .. } => *corrupted += 1,
+        }).unwrap();
+        events
+    }
+
+    #[test]
+    fn test_protocol_monitor() {
+        SimTimer::reset();
+        let (writer, mut reader) = std::sync::mpsc::channel();
+        let send = |bytes: &[u8]| bytes.iter().for_each(|byte| writer.send(*byte).unwrap());
+        let mut monitor = ProtocolMonitor::<SimTimer>::new(Duration::from_millis(10));
+        let mut corrupted = 0;
+        let read = Command::ReadRegister as u8;
+
+        send(&ReadRegisterCommand::new(0x01, 0x38, 1).raw);
+        SimTimer::advance(Duration::from_millis(1));
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), []);
+        send(&[0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9]);
+        SimTimer::advance(Duration::from_millis(2));
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), [(0x01, read, Some(0x01), Some(2))]);
+
+        // Broadcasts are not answered.
+        let command = SyncWriteCommand::<32>::builder(0x2a, 1).servo(0x01, &[0x01]).servo(0x02, &[0x02]).build().unwrap();
+        send(command.packet());
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), [(BROADCAST_ID, Command::SyncWrite as u8, None, None)]);
+
+        // A request without response is reported after the timeout, or when the next request arrives.
+        send(&PingCommand::new(0x02).raw);
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), []);
+        SimTimer::advance(Duration::from_millis(11));
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), [(0x02, Command::Ping as u8, None, None)]);
+        send(&PingCommand::new(0x02).raw);
+        send(&ReadRegisterCommand::new(0x01, 0x38, 1).raw);
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), [(0x02, Command::Ping as u8, None, None)]);
+
+        // A corrupted response is reported, and the request is left unanswered.
+        send(&[0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0x00]);
+        SimTimer::advance(Duration::from_millis(11));
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), [(0x01, read, None, None)]);
+        assert_eq!(corrupted, 1);
+
+        // Each response to a SYNC READ is paired with the request.
+        let mut request = [0xff, 0xff, BROADCAST_ID, 0x06, Command::SyncRead as u8, 0x38, 0x01, 0x03, 0x01, 0x00];
+        request[9] = crate::packet::checksum(&request[2..9]);
+        send(&request);
+        send(&[0xff, 0xff, 0x03, 0x03, 0x00, 0x12, 0xe7]);
+        send(&[0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9]);
+        assert_eq!(poll(&mut monitor, &mut reader, &mut corrupted), [
+            (BROADCAST_ID, Command::SyncRead as u8, Some(0x03), Some(0)),
+            (BROADCAST_ID, Command::SyncRead as u8, Some(0x01), Some(0)),
+        ]);
+    }
+}
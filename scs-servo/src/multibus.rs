@@ -0,0 +1,225 @@
+//! Coordination of several serial chains.
+//!
+//! Larger robots spread their servos over two or three UARTs. [`MultiBus`] owns one [`Bus`] per port and
+//! maps joint names to a bus index and a servo ID. Writes and telemetry polls are grouped by bus and each
+//! bus is driven from its own thread, so the chains are served in parallel.
+
+extern crate std;
+use std::collections::HashMap;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::bus::{Bus, BusError};
+use crate::device::{StatusBlock, Timer};
+use crate::protocol::{StreamReader, StreamWriter, STANDARD_BUFFER_SIZE};
+
+/// Location of a joint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Joint {
+    /// Index of the bus returned by `MultiBus::add_bus`.
+    pub bus: usize,
+    pub id: u8,
+}
+
+#[derive(Debug)]
+pub enum MultiBusError<E> {
+    UnknownBus(usize),
+    UnknownJoint(String),
+    DuplicateJoint(String),
+    /// A transaction failed on a bus. The other buses may have completed their transactions.
+    BusError { joint: Joint, error: E },
+}
+
+pub struct MultiBus<R, W, T: Timer, const BUFFER_SIZE: usize = STANDARD_BUFFER_SIZE> {
+    buses: Vec<Bus<R, W, T, BUFFER_SIZE>>,
+    joints: HashMap<String, Joint>,
+}
+
+type Error<R, W> = MultiBusError<BusError<R, W>>;
+/// Joints grouped by bus, each with the index of the joint in the request.
+type Groups = Vec<Vec<(usize, Joint)>>;
+
+impl<R, W, T, const BUFFER_SIZE: usize> MultiBus<R, W, T, BUFFER_SIZE>
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          Bus<R, W, T, BUFFER_SIZE>: Send,
+          BusError<R, W>: Send,
+{
+    pub fn new() -> Self {
+        Self {
+            buses: Vec::new(),
+            joints: HashMap::new(),
+        }
+    }
+
+    /// Adds a bus and returns its index.
+    pub fn add_bus(&mut self, bus: Bus<R, W, T, BUFFER_SIZE>) -> usize {
+        self.buses.push(bus);
+        self.buses.len() - 1
+    }
+    pub fn bus_mut(&mut self, index: usize) -> Option<&mut Bus<R, W, T, BUFFER_SIZE>> {
+        self.buses.get_mut(index)
+    }
+    pub fn into_buses(self) -> Vec<Bus<R, W, T, BUFFER_SIZE>> {
+        self.buses
+    }
+
+    pub fn add_joint(&mut self, name: &str, bus: usize, id: u8) -> Result<(), Error<R, W>> {
+        if bus >= self.buses.len() {
+            return Err(MultiBusError::UnknownBus(bus));
+        }
+        if self.joints.contains_key(name) {
+            return Err(MultiBusError::DuplicateJoint(name.into()));
+        }
+        self.joints.insert(name.into(), Joint { bus, id });
+        Ok(())
+    }
+    pub fn joint(&self, name: &str) -> Option<Joint> {
+        self.joints.get(name).copied()
+    }
+    pub fn joints(&self) -> impl Iterator<Item = (&str, Joint)> + '_ {
+        self.joints.iter().map(|(name, joint)| (name.as_str(), *joint))
+    }
+
+    /// Resolves `names` and groups their indices by bus.
+    fn group_by_bus(&self, names: impl Iterator<Item = impl AsRef<str>>) -> Result<Groups, Error<R, W>> {
+        let mut groups = std::vec![Vec::new(); self.buses.len()];
+        for (index, name) in names.enumerate() {
+            let name = name.as_ref();
+            let joint = self.joint(name).ok_or_else(|| MultiBusError::UnknownJoint(name.into()))?;
+            groups[joint.bus].push((index, joint));
+        }
+        Ok(groups)
+    }
+
+    /// Runs `f` with each joint in `groups`, one thread per bus. The results are returned in the order of the joint indices.
+    fn fan_out<Output, F>(&mut self, groups: Groups, count: usize, f: F) -> Result<Vec<Output>, Error<R, W>>
+        where Output: Send + Default + Clone,
+              F: Fn(&mut Bus<R, W, T, BUFFER_SIZE>, usize, Joint) -> Result<Output, BusError<R, W>> + Sync,
+    {
+        let f = &f;
+        let results = std::thread::scope(|scope| {
+            let handles = self.buses.iter_mut().zip(groups)
+                .filter(|(_, group)| !group.is_empty())
+                .map(|(bus, group)| scope.spawn(move || {
+                    group.into_iter()
+                        .map(|(index, joint)| f(bus, index, joint)
+                            .map(|output| (index, output))
+                            .map_err(|error| MultiBusError::BusError { joint, error }))
+                        .collect::<Result<Vec<_>, _>>()
+                }))
+                .collect::<Vec<_>>();
+            handles.into_iter()
+                .map(|handle| handle.join().expect("bus thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        let mut outputs = std::vec![Output::default(); count];
+        for result in results {
+            for (index, output) in result? {
+                outputs[index] = output;
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Writes the data of each joint in `values` to its registers starting at `address`, one acknowledged WRITE per
+    /// joint, so the write policy and the shadow cache of the bus apply. The buses are written in parallel.
+    pub fn write_all(&mut self, address: u8, values: &[(&str, &[u8])]) -> Result<(), Error<R, W>> {
+        let groups = self.group_by_bus(values.iter().map(|(name, _)| name))?;
+        self.fan_out(groups, values.len(), |bus, index, joint| bus.write_register(joint.id, address, values[index].1))?;
+        Ok(())
+    }
+
+    /// Reads the status block of each joint in `names`. The buses are polled in parallel.
+    pub fn read_status(&mut self, names: &[&str]) -> Result<Vec<StatusBlock>, Error<R, W>> {
+        let groups = self.group_by_bus(names.iter())?;
+        self.fan_out(groups, names.len(), |bus, _, joint| bus.read_status_block(joint.id))
+    }
+}
+
+impl<R, W, T, const BUFFER_SIZE: usize> Default for MultiBus<R, W, T, BUFFER_SIZE>
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          Bus<R, W, T, BUFFER_SIZE>: Send,
+          BusError<R, W>: Send,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::device::scs0009::{REGISTER_CURRENT_VOLTAGE, REGISTER_TARGET_POSITION_H};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    use core::time::Duration;
+    use std::sync::mpsc::{Receiver, Sender};
+
+    type TestBus = Bus<Receiver<u8>, Sender<u8>, std::time::Instant>;
+
+    fn spawn_bus(emulator: BusEmulator<2>) -> (TestBus, EmulatorThread<2>) {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(emulator);
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_secs(1),
+            mode: BusMode::Normal,
+        };
+        (Bus::new(master_reader, master_writer, config), thread)
+    }
+
+    #[test]
+    fn test_multibus() {
+        let mut right = BusEmulator::new(1, 2);
+        right.servo_mut(2).unwrap().registers_mut()[REGISTER_CURRENT_VOLTAGE.address as usize] = 60;
+        let (left_bus, left_thread) = spawn_bus(BusEmulator::new(1, 2));
+        let (right_bus, right_thread) = spawn_bus(right);
+
+        let mut multibus = MultiBus::new();
+        let left = multibus.add_bus(left_bus);
+        let right = multibus.add_bus(right_bus);
+        multibus.add_joint("left_hip", left, 1).unwrap();
+        multibus.add_joint("left_knee", left, 2).unwrap();
+        multibus.add_joint("right_hip", right, 1).unwrap();
+        multibus.add_joint("right_knee", right, 2).unwrap();
+        assert!(matches!(multibus.add_joint("left_hip", right, 3), Err(MultiBusError::DuplicateJoint(_))));
+        assert!(matches!(multibus.add_joint("neck", 2, 1), Err(MultiBusError::UnknownBus(2))));
+        assert_eq!(multibus.joint("right_knee"), Some(Joint { bus: right, id: 2 }));
+
+        multibus.write_all(REGISTER_TARGET_POSITION_H.address, &[
+            ("left_hip", &[0x01, 0x00]),
+            ("right_hip", &[0x02, 0x00]),
+            ("right_knee", &[0x03, 0x00]),
+        ]).unwrap();
+        let status = multibus.read_status(&["right_knee", "left_hip"]).unwrap();
+        assert_eq!(status[0].voltage, 60);
+        assert_eq!(status[1].voltage, 70);
+        assert!(matches!(multibus.read_status(&["tail"]), Err(MultiBusError::UnknownJoint(_))));
+
+        let left = left_thread.stop();
+        let right = right_thread.stop();
+        let target = |emulator: &BusEmulator<2>, id| emulator.servo(id).unwrap().registers()[REGISTER_TARGET_POSITION_H.address as usize];
+        assert_eq!(target(&left, 1), 0x01);
+        assert_eq!(target(&right, 1), 0x02);
+        assert_eq!(target(&right, 2), 0x03);
+    }
+
+    #[test]
+    fn test_multibus_error() {
+        let (bus, thread) = spawn_bus(BusEmulator::new(1, 1));
+        let mut multibus = MultiBus::new();
+        let index = multibus.add_bus(bus);
+        multibus.bus_mut(index).unwrap().set_mode(BusMode::FireAndForget { interval: Duration::ZERO });
+        multibus.add_joint("wrist", index, 1).unwrap();
+        match multibus.read_status(&["wrist"]) {
+            Err(MultiBusError::BusError { joint, error: crate::protocol::ProtocolHandlerError::ResponsesDisabled }) => assert_eq!(joint, Joint { bus: index, id: 1 }),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        thread.stop();
+    }
+}
@@ -0,0 +1,194 @@
+//! Usage odometry for maintenance.
+//!
+//! The gears and the potentiometer of a servo wear with the distance travelled, and the motor with the time spent
+//! under load and at high temperatures. [`Odometer`] accumulates these per servo from the commanded targets and the
+//! telemetry samples, so servos can be replaced based on their actual usage. The totals are [`ServoUsage`] records,
+//! serializable with the `serde` feature, which are saved with [`Odometer::usage`] and loaded again with
+//! [`Odometer::restore`].
+
+use core::time::Duration;
+
+use crate::device::scs0009::LOAD_ENCODING;
+use crate::telemetry::{Sample, TelemetryFrame};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServoUsage {
+    pub id: u8,
+    /// Sum of the distances between consecutive target positions, in position steps.
+    pub commanded_travel: u64,
+    /// Number of targets which moved the servo.
+    pub moves: u32,
+    /// Time with a load at or above [`OdometryConfig::load_threshold`].
+    pub time_under_load: Duration,
+    /// Time at or above [`OdometryConfig::temperature_limit`].
+    pub over_temperature: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OdometryConfig {
+    /// Load magnitude which counts as under load, out of 1023.
+    pub load_threshold: u16,
+    /// Temperature in degC which counts as over temperature.
+    pub temperature_limit: u8,
+    /// Samples further apart than this are not integrated, e.g. after the servo did not answer for a while.
+    pub max_gap: Duration,
+}
+
+impl Default for OdometryConfig {
+    fn default() -> Self {
+        Self {
+            load_threshold: 500,
+            temperature_limit: 60,
+            max_gap: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LastSample {
+    timestamp: Duration,
+    loaded: bool,
+    hot: bool,
+}
+
+pub struct Odometer<const N: usize> {
+    config: OdometryConfig,
+    usage: [ServoUsage; N],
+    targets: [Option<u16>; N],
+    samples: [Option<LastSample>; N],
+}
+
+impl<const N: usize> Odometer<N> {
+    pub fn new(ids: [u8; N], config: OdometryConfig) -> Self {
+        Self {
+            config,
+            usage: ids.map(|id| ServoUsage { id, ..Default::default() }),
+            targets: [None; N],
+            samples: [None; N],
+        }
+    }
+
+    pub fn config(&self) -> &OdometryConfig {
+        &self.config
+    }
+    /// Totals of all servos, to be saved.
+    pub fn usage(&self) -> &[ServoUsage] {
+        &self.usage
+    }
+    pub fn servo(&self, id: u8) -> Option<&ServoUsage> {
+        self.usage.iter().find(|usage| usage.id == id)
+    }
+
+    /// Continues from saved totals. Totals of IDs which are not tracked by this odometer are ignored.
+    pub fn restore(&mut self, saved: &[ServoUsage]) {
+        for saved in saved {
+            if let Some(usage) = self.usage.iter_mut().find(|usage| usage.id == saved.id) {
+                *usage = *saved;
+            }
+        }
+    }
+
+    fn index(&self, id: u8) -> Option<usize> {
+        self.usage.iter().position(|usage| usage.id == id)
+    }
+
+    /// Records a target position written to servo `id`. The first target of a servo only sets the starting point.
+    pub fn record_target(&mut self, id: u8, position: u16) {
+        let Some(index) = self.index(id) else { return };
+        if let Some(previous) = self.targets[index].replace(position) {
+            if previous != position {
+                let usage = &mut self.usage[index];
+                usage.commanded_travel += previous.abs_diff(position) as u64;
+                usage.moves = usage.moves.saturating_add(1);
+            }
+        }
+    }
+
+    /// Integrates the time since the previous sample of the servo with the load and temperature of the previous one.
+    pub fn record_sample(&mut self, sample: &Sample) {
+        let Some(index) = self.index(sample.id) else { return };
+        let Some(status) = sample.status else {
+            self.samples[index] = None;
+            return;
+        };
+        let current = LastSample {
+            timestamp: sample.timestamp,
+            loaded: status.load.magnitude(LOAD_ENCODING) >= self.config.load_threshold,
+            hot: status.temperature >= self.config.temperature_limit,
+        };
+        if let Some(previous) = self.samples[index].replace(current) {
+            let elapsed = sample.timestamp.saturating_sub(previous.timestamp);
+            if elapsed <= self.config.max_gap {
+                let usage = &mut self.usage[index];
+                if previous.loaded {
+                    usage.time_under_load += elapsed;
+                }
+                if previous.hot {
+                    usage.over_temperature += elapsed;
+                }
+            }
+        }
+    }
+
+    pub fn record_frame<const M: usize>(&mut self, frame: &TelemetryFrame<M>) {
+        for sample in &frame.samples {
+            self.record_sample(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::AlarmFlags;
+    use crate::device::{RawLoad, RawSpeed, StatusBlock};
+
+    fn sample(id: u8, millis: u64, load: i16, temperature: u8) -> Sample {
+        let status = StatusBlock { position: 0, speed: RawSpeed(0), load: RawLoad::from_signed(load, LOAD_ENCODING), voltage: 70, temperature };
+        Sample { id, timestamp: Duration::from_millis(millis), status: Some(status), alarms: AlarmFlags(0) }
+    }
+
+    #[test]
+    fn test_odometer() {
+        let mut odometer = Odometer::new([1, 2], OdometryConfig::default());
+        odometer.record_target(1, 500);
+        odometer.record_target(1, 600);
+        odometer.record_target(1, 600);
+        odometer.record_target(1, 400);
+        odometer.record_target(3, 100);
+        assert_eq!(odometer.servo(1).unwrap().commanded_travel, 300);
+        assert_eq!(odometer.servo(1).unwrap().moves, 2);
+
+        // Loaded in both directions, hot from the second sample.
+        odometer.record_sample(&sample(2, 0, -600, 50));
+        odometer.record_sample(&sample(2, 100, 600, 60));
+        odometer.record_sample(&sample(2, 300, 100, 60));
+        odometer.record_sample(&sample(2, 400, 100, 40));
+        let usage = *odometer.servo(2).unwrap();
+        assert_eq!(usage.time_under_load, Duration::from_millis(300));
+        assert_eq!(usage.over_temperature, Duration::from_millis(300));
+
+        // A missing answer or a long gap is not counted.
+        odometer.record_sample(&sample(2, 500, 600, 70));
+        odometer.record_sample(&Sample { status: None, ..sample(2, 600, 0, 0) });
+        odometer.record_sample(&sample(2, 700, 600, 70));
+        odometer.record_sample(&sample(2, 2000, 600, 70));
+        let usage = *odometer.servo(2).unwrap();
+        assert_eq!(usage.time_under_load, Duration::from_millis(300));
+        assert_eq!(usage.over_temperature, Duration::from_millis(300));
+
+        let mut restored = Odometer::new([2, 4], OdometryConfig::default());
+        restored.restore(odometer.usage());
+        assert_eq!(restored.servo(2), Some(&usage));
+        assert_eq!(restored.servo(4).unwrap().commanded_travel, 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_usage_serde() {
+        let usage = ServoUsage { id: 1, commanded_travel: 1234, moves: 5, time_under_load: Duration::from_millis(1500), over_temperature: Duration::ZERO };
+        let json = serde_json::to_string(&[usage]).unwrap();
+        assert_eq!(serde_json::from_str::<[ServoUsage; 1]>(&json).unwrap(), [usage]);
+    }
+}
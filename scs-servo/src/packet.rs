@@ -1,5 +1,14 @@
+use core::fmt;
+
+use crate::protocol::Command;
+
+/// Calculates the checksum of the ID, length and data fields of a packet.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
 pub struct PacketReader<'a> {
-    raw: &'a [u8],
+    pub(crate) raw: &'a [u8],
 }
 impl<'a> PacketReader<'a> {
     pub fn new(data: &'a [u8]) -> Self {
@@ -23,6 +32,15 @@ impl<'a> PacketReader<'a> {
         }
         Ok(())
     }
+    // The length field counts the instruction (or error) byte, the parameters and the checksum.
+    fn check_packet_length(&self) -> Result<(), PacketError> {
+        self.check_header_length()?;
+        let length = self.length_unchecked() as usize;
+        if length < 2 || length + 2 > self.raw.len() {
+            return Err(PacketError::InvalidLength);
+        }
+        Ok(())
+    }
 
     pub fn id(&self) -> Result<u8, PacketError> {
         self.check_header_length()?;
@@ -33,25 +51,18 @@ impl<'a> PacketReader<'a> {
         Ok(self.length_unchecked())
     }
     pub fn checksum(&self) -> Result<u8, PacketError> {
-        self.check_header_length()?;
+        self.check_packet_length()?;
         Ok(self.checksum_unchecked())
     }
-    pub fn data(&self) -> Result<&[u8], PacketError> {
-        self.check_header_length()?;
+    pub fn data(&self) -> Result<&'a [u8], PacketError> {
+        self.check_packet_length()?;
         let length = self.length_unchecked() as usize;
-        if length + 2 > self.raw.len() {
-            return Err(PacketError::InvalidLength);
-        }
         Ok(&self.raw[2..length + 2 - 1])
     }
 
     pub fn calculate_checksum(&self) -> Result<u8, PacketError> {
-        self.check_header_length()?;
-        let mut checksum = 0u8;
-        for i in 0..self.length_unchecked() as usize + 2 - 1 {
-            checksum = checksum.wrapping_add(self.raw[i as usize]);
-        }
-        Ok(!checksum)
+        self.check_packet_length()?;
+        Ok(checksum(&self.raw[..self.length_unchecked() as usize + 2 - 1]))
     }
 
     pub fn verify_checksum(&self) -> Result<(), PacketError> {
@@ -92,6 +103,13 @@ impl<'a> PacketWriter<'a> {
         }
         Ok(())
     }
+    fn check_packet_length(&self) -> Result<(), PacketError> {
+        self.check_header_length()?;
+        if self.length_unchecked() < 2 {
+            return Err(PacketError::InvalidLength);
+        }
+        Ok(())
+    }
 
     pub fn id(&self) -> Result<u8, PacketError> {
         self.check_header_length()?;
@@ -102,21 +120,20 @@ impl<'a> PacketWriter<'a> {
         Ok(self.length_unchecked())
     }
     pub fn data(&self) -> Result<&[u8], PacketError> {
-        self.check_header_length()?;
+        self.check_packet_length()?;
         let length = self.length_unchecked() as usize;
-        if length + 2 > self.data.len() {
-            return Err(PacketError::InvalidLength);
-        }
         Ok(&self.data[2..length + 2 - 1])
     }
 
     pub fn checksum(&self) -> Result<u8, PacketError> {
-        self.check_header_length()?;
+        self.check_packet_length()?;
         Ok(self.checksum_unchecked())
     }
 
     pub fn set_id(&mut self, id: u8) -> Result<(), PacketError> {
-        self.check_header_length()?;
+        if self.data.len() < 3 {
+            return Err(PacketError::InvalidHeader);
+        }
         self.data[0] = id;
         Ok(())
     }
@@ -128,37 +145,121 @@ impl<'a> PacketWriter<'a> {
         Ok(())
     }
     pub fn data_mut(&mut self) -> Result<&mut [u8], PacketError> {
-        self.check_header_length()?;
+        self.check_packet_length()?;
         let length = self.length_unchecked() as usize;
-        if length + 2 > self.data.len() {
-            return Err(PacketError::InvalidLength);
-        }
         Ok(&mut self.data[2..length + 2 - 1])
     }
 
     pub fn calculate_checksum(&self) -> Result<u8, PacketError> {
-        self.check_header_length()?;
-        let mut checksum = 0u8;
-        for i in 0..self.length_unchecked() as usize + 2 - 1 {
-            checksum = checksum.wrapping_add(self.data[i as usize]);
-        }
-        Ok(!checksum)
+        self.check_packet_length()?;
+        Ok(checksum(&self.data[..self.length_unchecked() as usize + 2 - 1]))
     }
 
     pub fn update_checksum(&mut self) -> Result<(), PacketError> {
-        self.check_header_length()?;
+        self.check_packet_length()?;
         self.data[self.length_unchecked() as usize + 1] = self.calculate_checksum()?;
         Ok(())
     }
 }
 
+impl fmt::Debug for PacketReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.data(), self.checksum()) {
+            (Ok(data), Ok(checksum)) => f.debug_struct("PacketReader")
+                .field("id", &self.id_unchecked())
+                .field("length", &self.length_unchecked())
+                .field("data", &HexDump(data))
+                .field("checksum", &format_args!("0x{:02x}", checksum))
+                .finish(),
+            _ => f.debug_struct("PacketReader").field("raw", &HexDump(self.raw)).finish(),
+        }
+    }
+}
+
+/// Shows the packet as an instruction, e.g. `ID 1 READ 0x38: 02` for a READ of two registers from 0x38.
+/// The status byte of a response is shown as an unknown instruction, e.g. `ID 1 0x00: 01 ff`.
+impl fmt::Display for PacketReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Ok(data) = self.data() else {
+            return write!(f, "invalid packet: {}", HexDump(self.raw));
+        };
+        write!(f, "ID {}", self.id_unchecked())?;
+        let parameters = match data.split_first() {
+            Some((instruction, parameters)) => match Command::from_instruction(*instruction) {
+                Some(command @ (Command::Ping | Command::Action | Command::Reset)) => {
+                    write!(f, " {}", command)?;
+                    parameters
+                }
+                Some(command) if !parameters.is_empty() => {
+                    write!(f, " {} 0x{:02x}", command, parameters[0])?;
+                    &parameters[1..]
+                }
+                Some(command) => {
+                    write!(f, " {}", command)?;
+                    parameters
+                }
+                None => {
+                    write!(f, " 0x{:02x}", instruction)?;
+                    parameters
+                }
+            },
+            None => data,
+        };
+        if !parameters.is_empty() {
+            write!(f, ": {}", HexDump(parameters))?;
+        }
+        if self.verify_checksum().is_err() {
+            write!(f, " (checksum error)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats bytes as space-separated hex, e.g. `ff ff 01`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self)
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum PacketError {
     InvalidHeader,
     InvalidChecksum,
     InvalidLength,
+    /// The instruction byte is not a defined instruction.
+    UnknownInstruction(u8),
 }
 
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader => f.write_str("packet shorter than its header"),
+            Self::InvalidChecksum => f.write_str("packet checksum mismatch"),
+            Self::InvalidLength => f.write_str("packet length field out of range"),
+            Self::UnknownInstruction(instruction) => write!(f, "undefined instruction 0x{:02x}", instruction),
+        }
+    }
+}
+
+impl core::error::Error for PacketError {}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -175,25 +276,25 @@ mod test {
         assert_eq!(reader.checksum().unwrap(), 0xb8);
         assert_eq!(reader.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
         assert_eq!(reader.calculate_checksum().unwrap(), 0xb8);
-        assert_eq!(reader.verify_checksum().is_ok(), true);
+        assert!(reader.verify_checksum().is_ok());
     }
     #[test]
     fn test_packet_reader_checksum_error() {
         let data = [0x01, 0x05, 0x03, 0x2a, 0x00, 0x14, 0xb7];
         let reader = PacketReader::new(&data);
         assert_eq!(reader.calculate_checksum().unwrap(), 0xb8);
-        assert_eq!(reader.verify_checksum().is_ok(), false);
+        assert!(reader.verify_checksum().is_err());
     }
 
     #[test]
     fn test_packet_reader_length_error() {
         let data = [0x01, 0x00];
         let reader = PacketReader::new(&data);
-        assert_eq!(reader.id().is_err(), true);
-        assert_eq!(reader.length().is_err(), true);
-        assert_eq!(reader.checksum().is_err(), true);
-        assert_eq!(reader.data().is_err(), true);
-        assert_eq!(reader.verify_checksum().is_err(), true);
+        assert!(reader.id().is_err());
+        assert!(reader.length().is_err());
+        assert!(reader.checksum().is_err());
+        assert!(reader.data().is_err());
+        assert!(reader.verify_checksum().is_err());
     }
 
     #[test]
@@ -222,6 +323,57 @@ mod test {
         assert_eq!(reader.id_unchecked(), 0x01);
         assert_eq!(reader.length_unchecked(), 0x05);
         assert_eq!(reader.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
-        assert_eq!(reader.verify_checksum().is_ok(), true);
+        assert!(reader.verify_checksum().is_ok());
+    }
+
+    #[test]
+    fn test_packet_reader_invalid_length_field() {
+        // Length field shorter than the minimum (instruction + checksum)
+        for data in [[0x01, 0x00, 0x00], [0x01, 0x01, 0x00]] {
+            let reader = PacketReader::new(&data);
+            assert!(reader.data().is_err());
+            assert!(reader.checksum().is_err());
+            assert!(reader.calculate_checksum().is_err());
+            assert!(reader.verify_checksum().is_err());
+        }
+        // Length field pointing past the end of the packet
+        let data = [0x01, 0xff, 0x02, 0x00];
+        let reader = PacketReader::new(&data);
+        assert_eq!(reader.length().unwrap(), 0xff);
+        assert!(reader.data().is_err());
+        assert!(reader.checksum().is_err());
+        assert!(reader.calculate_checksum().is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_packet_writer_invalid_length_field() {
+        let mut data = [0xff; 4];
+        let mut writer = PacketWriter::new(&mut data);
+        // The ID can be set before the length field is initialized.
+        writer.set_id(0x01).unwrap();
+        assert!(writer.data().is_err());
+        writer.set_length(0x00).unwrap();
+        assert!(writer.data_mut().is_err());
+        assert!(writer.update_checksum().is_err());
+        writer.set_length(0x02).unwrap();
+        assert_eq!(writer.data().unwrap().len(), 1);
+        writer.update_checksum().unwrap();
+    }
+
+    #[test]
+    fn test_packet_reader_format() {
+        extern crate std;
+        use std::format;
+        let data = [0x01, 0x04, 0x02, 0x38, 0x02, 0xbe];
+        let reader = PacketReader::new(&data);
+        assert_eq!(format!("{}", reader), "ID 1 READ 0x38: 02");
+        assert_eq!(format!("{:?}", reader), "PacketReader { id: 1, length: 4, data: [02 38 02], checksum: 0xbe }");
+        let data = [0x01, 0x02, 0x01, 0x00];
+        assert_eq!(format!("{}", PacketReader::new(&data)), "ID 1 PING (checksum error)");
+        let data = [0x01, 0x04, 0x00, 0x01, 0xff, 0xfa];
+        assert_eq!(format!("{}", PacketReader::new(&data)), "ID 1 0x00: 01 ff");
+        let data = [0x01, 0xff, 0x02];
+        assert_eq!(format!("{}", PacketReader::new(&data)), "invalid packet: 01 ff 02");
+        assert_eq!(format!("{:?}", PacketReader::new(&data)), "PacketReader { raw: [01 ff 02] }");
+    }
+}
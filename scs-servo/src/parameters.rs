@@ -0,0 +1,202 @@
+//! Writing of the EEPROM parameter area.
+//!
+//! A parameter image is written through the regular WRITE instruction while the EEPROM lock is released,
+//! then read back for verification. The registers which change how the servo communicates are never written,
+//! because changing them in the middle of the sequence would break the communication with the servo.
+
+use crate::bus::{Bus, BusError};
+use crate::device::{RegisterDefinition, RegisterStorage, Timer};
+use crate::protocol::{StreamReader, StreamWriter};
+
+/// Number of registers written or read by one transaction.
+const CHUNK_LENGTH: usize = 8;
+
+/// Registers of a servo family which [`write_parameters`] needs, e.g.
+/// [`scs0009::PARAMETER_MAP`](crate::device::scs0009::PARAMETER_MAP).
+#[derive(Debug, Clone, Copy)]
+pub struct ParameterMap<'a> {
+    pub definitions: &'a [RegisterDefinition],
+    /// Registers which are never written, such as the ID and the baud rate.
+    pub protected: &'a [RegisterDefinition],
+    /// Register which releases the EEPROM with 0 and locks it with 1, if the family has one.
+    pub eeprom_lock: Option<RegisterDefinition>,
+}
+
+/// Register values to write, starting at `address`.
+#[derive(Debug, Clone, Copy)]
+pub struct ParameterImage<'a> {
+    pub address: u8,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterStage {
+    Unlock,
+    Write,
+    Verify,
+    Lock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterProgress {
+    pub stage: ParameterStage,
+    /// Number of registers processed in this stage.
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug)]
+pub enum ParameterError<E> {
+    ProtocolError(E),
+    /// The image does not fit in the register space.
+    InvalidImage,
+    VerifyFailed { address: u8, expected: u8, actual: u8 },
+}
+
+impl<E> From<E> for ParameterError<E> {
+    fn from(error: E) -> Self {
+        Self::ProtocolError(error)
+    }
+}
+
+fn is_writable(map: &ParameterMap, address: usize) -> bool {
+    !map.protected.iter().any(|definition| definition.address as usize == address)
+        && map.definitions.iter().any(|definition| {
+            definition.address as usize == address && definition.writable && matches!(definition.storage, RegisterStorage::Eeprom)
+        })
+}
+
+/// Calls `f` with each run of consecutive writable registers in `image`, split into chunks of `CHUNK_LENGTH`.
+fn for_each_chunk<E, F: FnMut(u8, &[u8]) -> Result<(), E>>(map: &ParameterMap, image: &ParameterImage, mut f: F) -> Result<(), E> {
+    let start = image.address as usize;
+    let mut offset = 0;
+    while offset < image.data.len() {
+        if !is_writable(map, start + offset) {
+            offset += 1;
+            continue;
+        }
+        let mut end = offset + 1;
+        while end < image.data.len() && end - offset < CHUNK_LENGTH && is_writable(map, start + end) {
+            end += 1;
+        }
+        f((start + offset) as u8, &image.data[offset..end])?;
+        offset = end;
+    }
+    Ok(())
+}
+
+/// Writes the EEPROM registers of `image` to servo `id` and verifies them.
+/// Registers which are not writable EEPROM registers of `map`, or are protected by it, are skipped.
+/// The EEPROM is locked again even if writing fails.
+pub fn write_parameters<R, W, T, const BUFFER_SIZE: usize, Progress>(bus: &mut Bus<R, W, T, BUFFER_SIZE>, id: u8, map: &ParameterMap, image: &ParameterImage, mut progress: Progress) -> Result<(), ParameterError<BusError<R, W>>>
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          Progress: FnMut(ParameterProgress),
+{
+    if image.address as usize + image.data.len() > 0x100 {
+        return Err(ParameterError::InvalidImage);
+    }
+    let mut total = 0;
+    for_each_chunk::<(), _>(map, image, |_, data| {
+        total += data.len();
+        Ok(())
+    }).ok();
+
+    if let Some(lock) = map.eeprom_lock {
+        progress(ParameterProgress { stage: ParameterStage::Unlock, done: 0, total: 1 });
+        bus.write_register(id, lock.address, &[0])?;
+    }
+
+    let mut done = 0;
+    progress(ParameterProgress { stage: ParameterStage::Write, done, total });
+    let written = for_each_chunk::<BusError<R, W>, _>(map, image, |address, data| {
+        bus.write_register(id, address, data)?;
+        done += data.len();
+        progress(ParameterProgress { stage: ParameterStage::Write, done, total });
+        Ok(())
+    });
+
+    let locked = match map.eeprom_lock {
+        Some(lock) => {
+            progress(ParameterProgress { stage: ParameterStage::Lock, done: 0, total: 1 });
+            bus.write_register(id, lock.address, &[1])
+        },
+        None => Ok(()),
+    };
+    written?;
+    locked?;
+
+    let mut done = 0;
+    progress(ParameterProgress { stage: ParameterStage::Verify, done, total });
+    for_each_chunk(map, image, |address, data| {
+        let mut actual = [0; CHUNK_LENGTH];
+        let actual = &mut actual[..data.len()];
+        bus.read_register(id, address, actual)?;
+        if let Some(index) = (0..data.len()).find(|index| actual[*index] != data[*index]) {
+            return Err(ParameterError::VerifyFailed { address: address + index as u8, expected: data[index], actual: actual[index] });
+        }
+        done += data.len();
+        progress(ParameterProgress { stage: ParameterStage::Verify, done, total });
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::device::scs0009::*;
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    use core::time::Duration;
+    extern crate std;
+
+    #[test]
+    fn test_write_parameters() {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<1>::new(3, 1));
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_secs(1),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        // Image from Software Version H to LED Alarm Flag. The version, ID and baud rate must be left untouched.
+        let image = [
+            0x01, 0x02, 0x09, 0x07, 0x00, 0x01, 0x00, 0x20, 0x03, 0x00,
+            0x46, 0x8c, 0x40, 0x02, 0x00, 0x00, 0x25, 0x25,
+        ];
+        let mut stages = std::vec::Vec::new();
+        write_parameters(&mut bus, 3, &PARAMETER_MAP, &ParameterImage { address: REGISTER_VERSION_H.address, data: &image }, |progress| {
+            if stages.last() != Some(&progress.stage) {
+                stages.push(progress.stage);
+            }
+            assert!(progress.done <= progress.total);
+        }).unwrap();
+        assert_eq!(stages, [ParameterStage::Unlock, ParameterStage::Write, ParameterStage::Lock, ParameterStage::Verify]);
+
+        let emulator = thread.stop();
+        let registers = emulator.servo(3).unwrap().registers();
+        assert_eq!(registers[REGISTER_ID.address as usize], 0x03);
+        assert_eq!(registers[REGISTER_VERSION_H.address as usize], 0x05);
+        assert_eq!(registers[REGISTER_LOWER_POSITION_LIMIT_L.address as usize], 0x20);
+        assert_eq!(registers[REGISTER_MAX_TORQUE_H.address as usize], 0x02);
+        assert_eq!(registers[REGISTER_EEPROM_LOCK.address as usize], 0x01);
+    }
+
+    #[test]
+    fn test_parameter_map() {
+        use crate::device::ax12;
+        // The ID and the baud rate of the AX-12 are at other addresses than those of the SCS0009.
+        let image = [0; 0x12];
+        let mut chunks = std::vec::Vec::new();
+        for_each_chunk::<(), _>(&ax12::PARAMETER_MAP, &ParameterImage { address: 0, data: &image }, |address, data| {
+            chunks.push((address, data.len()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(chunks, [(ax12::REGISTER_RETURN_DELAY_TIME.address, 5), (ax12::REGISTER_TEMPERATURE_LIMIT.address, 5), (ax12::REGISTER_ALARM_LED.address, 1)]);
+        assert!(ax12::PARAMETER_MAP.eeprom_lock.is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! Runtime access control of register writes.
+//!
+//! A [`WritePolicy`] is consulted by [`Bus`](crate::bus::Bus) and the device controls before every write.
+//! It can let the write through, reject it with [`ProtocolHandlerError::WriteProtected`](crate::protocol::ProtocolHandlerError::WriteProtected),
+//! or drop it silently. [`ProtectedRegisters`] marks registers read-only, e.g. the ID and the baud rate
+//! during normal operation, and [`DryRun`] records the writes instead of transmitting them, so higher-level
+//! logic can be tested without moving anything.
+
+use crate::device::RegisterDefinition;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteDecision {
+    Transmit,
+    /// Fail the write. The address is the first protected register in the write.
+    Reject(u8),
+    /// Report success without transmitting.
+    Drop,
+}
+
+pub trait WritePolicy {
+    /// Decides what to do with a write of `data` to the registers starting at `address` of servo `id`.
+    fn check(&mut self, id: u8, address: u8, data: &[u8]) -> WriteDecision;
+}
+
+/// Transmits every write.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+impl WritePolicy for AllowAll {
+    fn check(&mut self, _id: u8, _address: u8, _data: &[u8]) -> WriteDecision {
+        WriteDecision::Transmit
+    }
+}
+
+/// Rejects writes which touch any of the protected registers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtectedRegisters {
+    bits: [u32; 8],
+}
+
+impl ProtectedRegisters {
+    pub const fn new() -> Self {
+        Self { bits: [0; 8] }
+    }
+    pub fn from_registers(registers: &[RegisterDefinition]) -> Self {
+        let mut protected = Self::new();
+        for register in registers {
+            protected.protect(register.address);
+        }
+        protected
+    }
+    pub fn protect(&mut self, address: u8) {
+        self.bits[address as usize / 32] |= 1 << (address % 32);
+    }
+    pub fn unprotect(&mut self, address: u8) {
+        self.bits[address as usize / 32] &= !(1 << (address % 32));
+    }
+    pub fn is_protected(&self, address: u8) -> bool {
+        self.bits[address as usize / 32] & (1 << (address % 32)) != 0
+    }
+}
+
+impl WritePolicy for ProtectedRegisters {
+    fn check(&mut self, _id: u8, address: u8, data: &[u8]) -> WriteDecision {
+        let end = (address as usize + data.len()).min(0x100);
+        match (address as usize..end).map(|address| address as u8).find(|address| self.is_protected(*address)) {
+            Some(address) => WriteDecision::Reject(address),
+            None => WriteDecision::Transmit,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+extern crate std;
+
+/// A write recorded by [`DryRun`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedWrite {
+    pub id: u8,
+    pub address: u8,
+    pub data: std::vec::Vec<u8>,
+}
+
+/// Records the writes allowed by the inner policy instead of transmitting them.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct DryRun<P = AllowAll> {
+    inner: P,
+    writes: std::vec::Vec<RecordedWrite>,
+}
+
+#[cfg(feature = "std")]
+impl<P: WritePolicy> DryRun<P> {
+    pub fn new(inner: P) -> Self {
+        Self { inner, writes: std::vec::Vec::new() }
+    }
+    pub fn writes(&self) -> &[RecordedWrite] {
+        &self.writes
+    }
+    pub fn take_writes(&mut self) -> std::vec::Vec<RecordedWrite> {
+        core::mem::take(&mut self.writes)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: WritePolicy> WritePolicy for DryRun<P> {
+    fn check(&mut self, id: u8, address: u8, data: &[u8]) -> WriteDecision {
+        match self.inner.check(id, address, data) {
+            WriteDecision::Transmit => {
+                self.writes.push(RecordedWrite { id, address, data: data.into() });
+                WriteDecision::Drop
+            }
+            decision => decision,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{REGISTER_BAUD_RATE, REGISTER_ID};
+    extern crate std;
+
+    #[test]
+    fn test_protected_registers() {
+        let mut policy = ProtectedRegisters::from_registers(&[REGISTER_ID, REGISTER_BAUD_RATE]);
+        assert_eq!(policy.check(1, 0x2a, &[0x01, 0x00]), WriteDecision::Transmit);
+        assert_eq!(policy.check(1, 0x05, &[0x02]), WriteDecision::Reject(0x05));
+        assert_eq!(policy.check(1, 0x03, &[0x00; 4]), WriteDecision::Reject(0x05));
+        assert_eq!(policy.check(1, 0xff, &[0x00; 2]), WriteDecision::Transmit);
+        policy.unprotect(0x05);
+        assert_eq!(policy.check(1, 0x05, &[0x02, 0x00]), WriteDecision::Reject(0x06));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut policy = DryRun::new(ProtectedRegisters::from_registers(&[REGISTER_ID]));
+        assert_eq!(policy.check(1, 0x2a, &[0x01, 0x00]), WriteDecision::Drop);
+        assert_eq!(policy.check(1, 0x05, &[0x02]), WriteDecision::Reject(0x05));
+        assert_eq!(policy.take_writes(), [RecordedWrite { id: 1, address: 0x2a, data: std::vec![0x01, 0x00] }]);
+        assert!(policy.writes().is_empty());
+    }
+}
@@ -1,14 +1,28 @@
-use crate::packet::{PacketError, PacketReader, PacketWriter};
+use core::fmt;
+use core::ops::Range;
+use core::time::Duration;
+
+use crate::device::{timeout_after, Timer};
+use crate::packet::{HexDump, PacketError, PacketReader, PacketWriter};
 
 pub trait StreamReader {
     type Error;
     fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error>;
+    /// Discards the bytes received but not read yet, e.g. the rest of a response which arrived after its transaction
+    /// timed out. [`ProtocolMaster`] calls it before each command it writes. Does nothing by default.
+    fn purge(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "async")]
 pub trait StreamReaderAsync {
     type Error;
     fn read(&mut self, data: &mut [u8]) -> impl core::future::Future<Output = Result<usize, Self::Error>>;
+    /// Same as [`StreamReader::purge`].
+    fn purge(&mut self) -> impl core::future::Future<Output = Result<(), Self::Error>> {
+        async { Ok(()) }
+    }
 }
 
 pub trait StreamWriter {
@@ -22,10 +36,136 @@ pub trait StreamWriterAsync {
     fn write(&mut self, data: &[u8]) -> impl core::future::Future<Output = Result<usize, Self::Error>>;
 }
 
+/// Deadline of a transaction, polled by [`ProtocolMaster`] while it waits for the bus.
+/// Any `FnMut() -> bool` which returns whether the deadline has passed is a deadline.
+/// [`timeout_after`] returns one which can be restarted for retries, which the `_timed` transactions such as
+/// [`ProtocolMaster::read_register_timed`] create from a [`Duration`].
+pub trait Deadline {
+    /// Whether the deadline has passed.
+    fn expired(&mut self) -> bool;
+    /// Starts a new window for another attempt of the transaction, which the master begins by keeping the bus idle
+    /// for `backoff`. Returns false if the deadline cannot be restarted, then the attempt has to complete in the time
+    /// left without a backoff.
+    fn retry(&mut self, backoff: Duration) -> bool {
+        let _ = backoff;
+        false
+    }
+    /// Time elapsed since the deadline was started or restarted, which measures the inter-byte timeout and the frame
+    /// gap of [`ProtocolMasterConfig`]. None if the deadline has no clock, then those options are ignored.
+    fn elapsed(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<F: FnMut() -> bool> Deadline for F {
+    fn expired(&mut self) -> bool {
+        self()
+    }
+}
+
+/// Deadline shared by the transactions of one call, e.g. the write and the read back of a verified write.
+/// It keeps the clock of the deadline but does not restart it, so that a retry cannot extend the call.
+struct SharedDeadline<'a, T: Deadline>(&'a mut T);
+
+impl<T: Deadline> Deadline for SharedDeadline<'_, T> {
+    fn expired(&mut self) -> bool {
+        self.0.expired()
+    }
+    fn elapsed(&self) -> Option<Duration> {
+        self.0.elapsed()
+    }
+}
+
+/// Number of bytes read at once while searching for the packet markers: two markers, ID and length.
+const MARKER_SCAN_LENGTH: usize = 4;
+
+/// Framing of the packets on the bus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameFormat {
+    /// Two markers, 8-bit length and checksum of the SCS protocol, which is Dynamixel Protocol 1.0.
+    #[default]
+    Scs,
+    /// Four byte header, 16-bit length and CRC16. See [`protocol2`](crate::protocol2).
+    Protocol2,
+}
+
+/// Conventions of the servos on the bus, which share the packet format but differ in what the status byte and the
+/// register layout mean.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dialect {
+    /// Feetech SCS servos, which report alarms in the status byte and store words with the high byte first.
+    #[default]
+    Scs,
+    /// Dynamixel Protocol 1.0 servos, e.g. the AX-12, which set the range, checksum and instruction bits of the
+    /// status byte when they reject a command, and store words with the low byte first.
+    /// See [`ax12`](crate::device::ax12).
+    Dynamixel1,
+}
+
+impl Dialect {
+    /// Whether the servo rejected the command it responds to with `status`.
+    pub const fn rejects(&self, status: ServoStatusFlags) -> bool {
+        match self {
+            Self::Scs => false,
+            Self::Dynamixel1 => status.range() || status.checksum() || status.instruction(),
+        }
+    }
+    /// Order of the bytes of the words in the registers. Feetech STS servos share the SCS dialect but store words
+    /// with the low byte first, see [`sts::WORD_ORDER`](crate::device::sts::WORD_ORDER).
+    pub const fn word_order(&self) -> WordOrder {
+        match self {
+            Self::Scs => WordOrder::BigEndian,
+            Self::Dynamixel1 => WordOrder::LittleEndian,
+        }
+    }
+    /// Word stored in two consecutive registers.
+    pub const fn word_from_bytes(&self, bytes: [u8; 2]) -> u16 {
+        self.word_order().from_bytes(bytes)
+    }
+    /// Bytes of two consecutive registers to store `word` in.
+    pub const fn word_to_bytes(&self, word: u16) -> [u8; 2] {
+        self.word_order().to_bytes(word)
+    }
+}
+
+/// Order of the bytes of a 16-bit value stored in two consecutive registers. Each device module defines the order of
+/// its servos as `WORD_ORDER`, e.g. [`scs0009::WORD_ORDER`](crate::device::scs0009::WORD_ORDER).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WordOrder {
+    /// The high byte at the lower address, e.g. Feetech SCS servos.
+    #[default]
+    BigEndian,
+    /// The low byte at the lower address, e.g. Feetech STS and Dynamixel servos.
+    LittleEndian,
+}
+
+impl WordOrder {
+    pub const fn from_bytes(&self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Self::BigEndian => u16::from_be_bytes(bytes),
+            Self::LittleEndian => u16::from_le_bytes(bytes),
+        }
+    }
+    pub const fn to_bytes(&self, word: u16) -> [u8; 2] {
+        match self {
+            Self::BigEndian => word.to_be_bytes(),
+            Self::LittleEndian => word.to_le_bytes(),
+        }
+    }
+}
+
 pub struct ProtocolReader<const BUFFER_SIZE: usize> {
     buffer: [u8; BUFFER_SIZE],
-    position: usize,
+    position: u16,
     state: ReaderState,
+    format: FrameFormat,
+    max_length: u8,
 }
 
 #[derive(PartialEq)]
@@ -38,180 +178,284 @@ enum ReaderState {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ProtocolReaderError<ReaderError> {
     ReaderError(ReaderError),
     PacketError(PacketError),
     InsufficientBuffer,
 }
 
+impl<ReaderError> fmt::Display for ProtocolReaderError<ReaderError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReaderError(_) => f.write_str("failed to read from the stream"),
+            Self::PacketError(_) => f.write_str("invalid packet received"),
+            Self::InsufficientBuffer => f.write_str("packet larger than the receive buffer"),
+        }
+    }
+}
+
+impl<ReaderError: core::error::Error + 'static> core::error::Error for ProtocolReaderError<ReaderError> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::ReaderError(error) => Some(error),
+            Self::PacketError(error) => Some(error),
+            Self::InsufficientBuffer => None,
+        }
+    }
+}
+
 impl From<PacketError> for ProtocolReaderError<()> {
     fn from(error: PacketError) -> Self {
         Self::PacketError(error)
     }
 }
 
+impl<const BUFFER_SIZE: usize> Default for ProtocolReader<BUFFER_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const BUFFER_SIZE: usize> ProtocolReader<BUFFER_SIZE> {
     pub fn new() -> Self {
         Self {
             buffer: [0; BUFFER_SIZE],
             position: 0,
             state: ReaderState::Marker1,
+            format: FrameFormat::Scs,
+            max_length: u8::MAX,
         }
     }
 
-    #[cfg(feature = "async")]
-    async fn read_inner_async<R: StreamReaderAsync>(&mut self, reader: &mut R) -> Result<(bool, bool), ProtocolReaderError<R::Error>> {
-        let (new_state, position, fully_read) = match self.state {
-            ReaderState::Marker1 | ReaderState::Completed => {
-                let bytes_read = reader.read(&mut self.buffer[0..2]).await
-                    .map_err(|err| ProtocolReaderError::ReaderError(err))?;
-                let new_state = if bytes_read == 1 && self.buffer[0] == 0xff {
-                    ReaderState::Marker2
-                } else if bytes_read == 2 {
-                    if self.buffer[0] == 0xff {
-                        if self.buffer[1] == 0xff {
-                            ReaderState::Header
-                        } else {
-                            ReaderState::Marker2
-                        }
-                    } else if self.buffer[1] == 0xff {
-                        ReaderState::Marker2
+    /// Creates a reader which receives frames in `format`. Protocol 2.0 frames are converted into SCS packets, see
+    /// [`protocol2::decode`](crate::protocol2::decode), and need a buffer which holds the whole frame from the ID.
+    pub fn with_format(format: FrameFormat) -> Self {
+        Self { format, ..Self::new() }
+    }
+
+    pub fn format(&self) -> FrameFormat {
+        self.format
+    }
+    /// Changes the framing. A partially received packet is discarded.
+    pub fn set_format(&mut self, format: FrameFormat) {
+        self.format = format;
+        self.reset();
+    }
+
+    pub fn max_length(&self) -> u8 {
+        self.max_length
+    }
+    /// Rejects packets whose length field exceeds `max_length` with [`PacketError::InvalidLength`] as soon as the
+    /// field is received, and searches for the next markers, instead of waiting for the bytes of a length which
+    /// garbage on the bus happened to decode to. E.g. the longest response expected on the bus. Only applies to
+    /// SCS frames, Protocol 2.0 frames are limited by the buffer. `u8::MAX`, the default, accepts every length
+    /// which fits in the buffer.
+    pub fn set_max_length(&mut self, max_length: u8) {
+        self.max_length = max_length;
+    }
+
+    /// Converts the SCS `packet` of a command, markers included, into a frame of the format of the reader, using
+    /// the receive buffer. A partially received packet is discarded.
+    pub(crate) fn encode<'a>(&'a mut self, packet: &'a [u8]) -> Result<&'a [u8], PacketError> {
+        match self.format {
+            FrameFormat::Scs => Ok(packet),
+            FrameFormat::Protocol2 => {
+                self.reset();
+                let length = crate::protocol2::encode(&PacketReader::new(packet.get(2..).unwrap_or_default()), &mut self.buffer)?;
+                Ok(&self.buffer[..length])
+            }
+        }
+    }
+
+    /// Discards a partially received packet.
+    pub fn reset(&mut self) {
+        self.position = 0;
+        self.state = ReaderState::Marker1;
+    }
+
+    /// Number of bytes of the packet being received after its markers. Zero while searching for the markers.
+    pub(crate) fn received(&self) -> usize {
+        match self.state {
+            ReaderState::Header | ReaderState::Data => self.position as usize,
+            _ => 0,
+        }
+    }
+
+    /// Returns the region of the buffer the next read from the stream goes into.
+    fn read_range(&self) -> core::ops::Range<usize> {
+        if self.format == FrameFormat::Protocol2 {
+            return match self.state {
+                // The header is searched for a byte at a time, counting the bytes matched in the position.
+                ReaderState::Marker1 | ReaderState::Marker2 | ReaderState::Completed => 0..1,
+                ReaderState::Header => self.position as usize..3,
+                ReaderState::Data => self.position as usize..3 + u16::from_le_bytes([self.buffer[1], self.buffer[2]]) as usize,
+            };
+        }
+        match self.state {
+            // Read the markers together with the ID and length fields. At most the header of the packet is consumed,
+            // so no bytes after the packet are taken from the stream.
+            ReaderState::Marker1 | ReaderState::Completed => 0..MARKER_SCAN_LENGTH.min(BUFFER_SIZE),
+            ReaderState::Marker2 => 0..(MARKER_SCAN_LENGTH - 1).min(BUFFER_SIZE),
+            ReaderState::Header => self.position as usize..2,
+            ReaderState::Data => self.position as usize..self.buffer[1] as usize + 2,
+        }
+    }
+
+    /// Updates the state with `bytes_read` bytes stored in the region returned by `read_range`.
+    /// Returns whether a packet has been completed and whether the read has filled the requested region.
+    fn consume<E>(&mut self, bytes_read: usize) -> Result<(bool, bool), ProtocolReaderError<E>> {
+        if self.format == FrameFormat::Protocol2 {
+            return self.consume_protocol2(bytes_read);
+        }
+        let range = self.read_range();
+        let fully_read = bytes_read == range.len();
+        let end = range.start + bytes_read;
+        match self.state {
+            ReaderState::Marker1 | ReaderState::Completed | ReaderState::Marker2 => {
+                let mut markers = if self.state == ReaderState::Marker2 { 1 } else { 0 };
+                let mut header_start = None;
+                for (index, byte) in self.buffer[..end].iter().enumerate() {
+                    if *byte != 0xff {
+                        markers = 0;
                     } else {
-                        ReaderState::Marker1
+                        markers += 1;
+                        if markers == 2 {
+                            header_start = Some(index + 1);
+                            break;
+                        }
                     }
-                } else {
-                    ReaderState::Marker1
-                };
-                (new_state, 0, bytes_read == 2)
-            }
-            ReaderState::Marker2 => {
-                let bytes_read = reader.read(&mut self.buffer[0..1]).await
-                    .map_err(|err| ProtocolReaderError::ReaderError(err))?;
-                let new_state = if bytes_read == 1 && self.buffer[0] == 0xff {
-                    ReaderState::Header
-                } else {
-                    ReaderState::Marker1
-                };
-                (new_state, 0, bytes_read == 1)
+                }
+                match header_start {
+                    Some(header_start) => {
+                        self.buffer.copy_within(header_start..end, 0);
+                        self.position = (end - header_start) as u16;
+                        self.state = ReaderState::Header;
+                        self.skip_extra_markers();
+                        self.complete_header()?;
+                    }
+                    None => {
+                        self.position = 0;
+                        self.state = if markers == 1 { ReaderState::Marker2 } else { ReaderState::Marker1 };
+                    }
+                }
             }
             ReaderState::Header => {
-                let bytes_read = reader.read(&mut self.buffer[self.position..2]).await
-                    .map_err(|err| ProtocolReaderError::ReaderError(err))?;
-                let new_position = self.position + bytes_read;
-                let new_state = if new_position == 2 {
-                    let length = self.buffer[1] as usize;
-                    if length + 2 > BUFFER_SIZE {
-                        return Err(ProtocolReaderError::InsufficientBuffer);
-                    } else {
-                        ReaderState::Data
-                    }
-                } else {
-                    ReaderState::Header
-                };
-                (new_state, new_position, bytes_read == 2)
+                self.position = end as u16;
+                if range.start == 0 {
+                    self.skip_extra_markers();
+                }
+                self.complete_header()?;
             }
             ReaderState::Data => {
-                let length = self.buffer[1] as usize;
-                let end = length + 2;
-                let bytes_to_read = end - self.position;
-                let bytes_read = reader.read(&mut self.buffer[self.position..end]).await
-                    .map_err(|err| ProtocolReaderError::ReaderError(err))?;
-                let new_position = self.position + bytes_read;
-                let new_state = if new_position == end {
-                    ReaderState::Completed
-                } else {
-                    ReaderState::Data
-                };
-                (new_state, new_position, bytes_read == bytes_to_read)
+                self.position = end as u16;
+                if end == self.buffer[1] as usize + 2 {
+                    self.state = ReaderState::Completed;
+                }
             }
-        };
-        self.state = new_state;
-        self.position = position;
+        }
         Ok((self.state == ReaderState::Completed, fully_read))
     }
 
-    fn read_inner<R: StreamReader>(&mut self, reader: &mut R) -> Result<(bool, bool), ProtocolReaderError<R::Error>> {
-        let (new_state, position, fully_read) = match self.state {
-            ReaderState::Marker1 | ReaderState::Completed => {
-                let bytes_read = match reader.read(&mut self.buffer[0..2]) {
-                    Ok(bytes_read) => bytes_read,
-                    Err(nb::Error::WouldBlock) => 0,
-                    Err(nb::Error::Other(err)) => return Err(ProtocolReaderError::ReaderError(err)),
-                };
-                let new_state = if bytes_read == 1 && self.buffer[0] == 0xff {
-                    ReaderState::Marker2
-                } else if bytes_read == 2 {
-                    if self.buffer[0] == 0xff {
-                        if self.buffer[1] == 0xff {
-                            ReaderState::Header
-                        } else {
-                            ReaderState::Marker2
-                        }
-                    } else if self.buffer[1] == 0xff {
-                        ReaderState::Marker2
-                    } else {
-                        ReaderState::Marker1
-                    }
-                } else {
-                    ReaderState::Marker1
-                };
-                (new_state, 0, bytes_read == 2)
-            }
-            ReaderState::Marker2 => {
-                let bytes_read = match reader.read(&mut self.buffer[0..1]) {
-                    Ok(bytes_read) => bytes_read,
-                    Err(nb::Error::WouldBlock) => 0,
-                    Err(nb::Error::Other(err)) => return Err(ProtocolReaderError::ReaderError(err)),
-                };
-                let new_state = if bytes_read == 1 && self.buffer[0] == 0xff {
-                    ReaderState::Header
+    /// Same as [`consume`](Self::consume) for Protocol 2.0 frames. A completed frame is converted into an SCS packet.
+    fn consume_protocol2<E>(&mut self, bytes_read: usize) -> Result<(bool, bool), ProtocolReaderError<E>> {
+        let range = self.read_range();
+        let fully_read = bytes_read == range.len();
+        let end = range.start + bytes_read;
+        match self.state {
+            ReaderState::Marker1 | ReaderState::Marker2 | ReaderState::Completed if bytes_read == 1 => {
+                let matched = if self.state == ReaderState::Completed { 0 } else { self.position as usize };
+                let byte = self.buffer[0];
+                let matched = if byte == crate::protocol2::HEADER[matched] {
+                    matched + 1
+                } else if byte == 0xff {
+                    // `ff ff ff` still ends with two markers.
+                    if matched == 2 { 2 } else { 1 }
                 } else {
-                    ReaderState::Marker1
+                    0
                 };
-                (new_state, 0, bytes_read == 1)
+                (self.state, self.position) = if matched == crate::protocol2::HEADER.len() { (ReaderState::Header, 0) } else { (ReaderState::Marker1, matched as u16) };
             }
+            ReaderState::Marker1 | ReaderState::Marker2 | ReaderState::Completed => {}
             ReaderState::Header => {
-                let bytes_read = match reader.read(&mut self.buffer[self.position..2]) {
-                    Ok(bytes_read) => bytes_read,
-                    Err(nb::Error::WouldBlock) => 0,
-                    Err(nb::Error::Other(err)) => return Err(ProtocolReaderError::ReaderError(err)),
-                };
-                let new_position = self.position + bytes_read;
-                let new_state = if new_position == 2 {
-                    let length = self.buffer[1] as usize;
-                    if length + 2 > BUFFER_SIZE {
+                self.position = end as u16;
+                if end == 3 {
+                    let length = u16::from_le_bytes([self.buffer[1], self.buffer[2]]) as usize;
+                    if length < 3 {
+                        self.reset();
+                        return Err(ProtocolReaderError::PacketError(PacketError::InvalidLength));
+                    }
+                    if length + 3 > BUFFER_SIZE {
+                        self.reset();
                         return Err(ProtocolReaderError::InsufficientBuffer);
-                    } else {
-                        ReaderState::Data
                     }
-                } else {
-                    ReaderState::Header
-                };
-                (new_state, new_position, bytes_read == 2)
+                    self.state = ReaderState::Data;
+                }
             }
             ReaderState::Data => {
-                let length = self.buffer[1] as usize;
-                let end = length + 2;
-                let bytes_to_read = end - self.position;
-                let bytes_read = match reader.read(&mut self.buffer[self.position..end]) {
-                    Ok(bytes_read) => bytes_read,
-                    Err(nb::Error::WouldBlock) => 0,
-                    Err(nb::Error::Other(err)) => return Err(ProtocolReaderError::ReaderError(err)),
-                };
-                let new_position = self.position + bytes_read;
-                let new_state = if new_position == end {
-                    ReaderState::Completed
-                } else {
-                    ReaderState::Data
-                };
-                (new_state, new_position, bytes_read == bytes_to_read)
+                self.position = end as u16;
+                if end == range.end {
+                    match crate::protocol2::decode(&mut self.buffer[..end]) {
+                        Ok(length) => {
+                            self.position = length as u16;
+                            self.state = ReaderState::Completed;
+                        }
+                        Err(err) => {
+                            self.reset();
+                            return Err(ProtocolReaderError::PacketError(err));
+                        }
+                    }
+                }
             }
-        };
-        self.state = new_state;
-        self.position = position;
+        }
         Ok((self.state == ReaderState::Completed, fully_read))
     }
 
+    /// Skips 0xff bytes following the markers. 0xff is not a valid ID, so they are part of a longer run of markers.
+    fn skip_extra_markers(&mut self) {
+        let position = self.position as usize;
+        let markers = self.buffer[..position].iter().take_while(|byte| **byte == 0xff).count();
+        self.buffer.copy_within(markers..position, 0);
+        self.position -= markers as u16;
+    }
+
+    fn complete_header<E>(&mut self) -> Result<(), ProtocolReaderError<E>> {
+        if self.position == 2 {
+            let length = self.buffer[1] as usize;
+            if length > self.max_length as usize {
+                // A length of 0xff may be the first marker of the next packet.
+                self.state = if length == 0xff { ReaderState::Marker2 } else { ReaderState::Marker1 };
+                self.position = 0;
+                return Err(ProtocolReaderError::PacketError(PacketError::InvalidLength));
+            }
+            if length + 2 > BUFFER_SIZE {
+                self.state = ReaderState::Marker1;
+                self.position = 0;
+                return Err(ProtocolReaderError::InsufficientBuffer);
+            }
+            self.state = ReaderState::Data;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    async fn read_inner_async<R: StreamReaderAsync>(&mut self, reader: &mut R) -> Result<(bool, bool), ProtocolReaderError<R::Error>> {
+        let range = self.read_range();
+        let bytes_read = reader.read(&mut self.buffer[range]).await
+            .map_err(ProtocolReaderError::ReaderError)?;
+        self.consume(bytes_read)
+    }
+
+    fn read_inner<R: StreamReader>(&mut self, reader: &mut R) -> Result<(bool, bool), ProtocolReaderError<R::Error>> {
+        let range = self.read_range();
+        let bytes_read = match reader.read(&mut self.buffer[range]) {
+            Ok(bytes_read) => bytes_read,
+            Err(nb::Error::WouldBlock) => 0,
+            Err(nb::Error::Other(err)) => return Err(ProtocolReaderError::ReaderError(err)),
+        };
+        self.consume(bytes_read)
+    }
+
     pub fn read<R: StreamReader>(&mut self, reader: &mut R) -> Result<bool, ProtocolReaderError<R::Error>> {
         loop {
             let (completed, fully_read) = self.read_inner(reader)?;
@@ -235,311 +479,2182 @@ impl<const BUFFER_SIZE: usize> ProtocolReader<BUFFER_SIZE> {
         }
     }
 
-    pub fn packet(&self) -> Option<PacketReader> {
+    pub fn packet(&self) -> Option<PacketReader<'_>> {
         if self.state == ReaderState::Completed {
-            Some(PacketReader::new(&self.buffer[0..self.position]))
+            Some(PacketReader::new(&self.buffer[0..self.position as usize]))
         } else {
             None
         }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct ProtocolMasterConfig {
-    // The underlying reader receives command from this master.
-    pub echo_back: bool,
+    /// Discards the markers of the packet received, e.g. after it failed the checksum verification, and searches
+    /// the bytes taken for it for the markers of the next packet. A corrupted length field may have made the packet
+    /// swallow the start of the next one, which is lost if the reader is reset instead. Returns whether a packet has
+    /// been completed from those bytes, then [`packet`](Self::packet) returns it. Otherwise the next read continues
+    /// the packet found, if any. Bytes after a completed packet are discarded.
+    /// A Protocol 2.0 frame has been converted when it is completed, so the reader is only reset.
+    pub fn resync(&mut self) -> bool {
+        if self.format == FrameFormat::Protocol2 {
+            self.reset();
+            return false;
+        }
+        let pending = self.buffer;
+        let mut rescan = Rescan(&pending[..self.position as usize]);
+        self.reset();
+        while !rescan.0.is_empty() {
+            // On an error, the reader has started over after a length which does not fit in the buffer.
+            if let Ok(completed) = self.read(&mut rescan) {
+                return completed;
+            }
+        }
+        false
+    }
 }
 
-pub struct ProtocolMaster<const BUFFER_SIZE: usize> {
-    config: ProtocolMasterConfig,
-    reader: ProtocolReader<BUFFER_SIZE>,
-}
+/// Bytes of a discarded packet read again by [`ProtocolReader::resync`].
+struct Rescan<'a>(&'a [u8]);
 
-#[repr(u8)]
-pub enum Command {
-    ReadRegister = 0x02,
-    WriteRegister = 0x03,
+impl StreamReader for Rescan<'_> {
+    type Error = core::convert::Infallible;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let length = data.len().min(self.0.len());
+        data[..length].copy_from_slice(&self.0[..length]);
+        self.0 = &self.0[length..];
+        Ok(length)
+    }
 }
 
-#[derive(Debug)]
-pub enum ProtocolHandlerError<ReaderError, WriterError> {
-    PacketError(PacketError),
-    ReaderError(ReaderError),
-    WriterError(WriterError),
-    ProtocolReaderError(ProtocolReaderError<ReaderError>),
-    UnexpectedPacketId(u8),
-    UnexpectedLength(usize),
-    TimedOut,
+/// Configuration of a [`ProtocolMaster`], created with [`ProtocolMasterConfig::builder`]. The default echoes nothing
+/// back, sends every transaction once and fails on responses of other servos.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Options missing from a serialized config take their default.
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ProtocolMasterConfig {
+    // The underlying reader receives command from this master. The echo is compared with the command written.
+    echo_back: bool,
+    retry: RetryPolicy,
+    mismatch: MismatchPolicy,
+    // Zero disables the inter-byte timeout and the frame gap.
+    inter_byte_timeout_us: u32,
+    frame_gap_us: u32,
+    broadcast: BroadcastPolicy,
 }
-impl<ReaderError, WriterError> From<ProtocolReaderError<ReaderError>> for ProtocolHandlerError<ReaderError, WriterError> {
-    fn from(error: ProtocolReaderError<ReaderError>) -> Self {
-        Self::ProtocolReaderError(error)
+
+impl ProtocolMasterConfig {
+    pub fn builder() -> ProtocolMasterConfigBuilder {
+        ProtocolMasterConfigBuilder { config: Self::default() }
+    }
+    /// Builder which starts from this config, e.g. to derive a config without retries.
+    pub fn to_builder(&self) -> ProtocolMasterConfigBuilder {
+        ProtocolMasterConfigBuilder { config: self.clone() }
     }
-}
 
-pub struct ReadRegisterCommand {
-    pub raw: [u8; 8],
-}
-impl ReadRegisterCommand {
-    pub fn new(id: u8, address: u8, length: u8) -> Self {
-        let mut raw = [0; 8];
-        {
-            raw[0] = 0xff;  // Marker1
-            raw[1] = 0xff;  // Marker2
-            let mut writer = PacketWriter::new(&mut raw[2..]);
-            writer.set_id(id).unwrap();
-            writer.set_length(4).unwrap();
-            let data = writer.data_mut().unwrap();
-            data[0] = Command::ReadRegister as u8;
-            data[1] = address;
-            data[2] = length;
-            writer.update_checksum().unwrap();
-        }
-        Self { raw }
+    pub fn echo_back(&self) -> bool {
+        self.echo_back
+    }
+    pub fn retry(&self) -> RetryPolicy {
+        self.retry
+    }
+    pub fn mismatch(&self) -> MismatchPolicy {
+        self.mismatch
+    }
+    pub fn inter_byte_timeout(&self) -> Duration {
+        Duration::from_micros(self.inter_byte_timeout_us as u64)
+    }
+    pub fn frame_gap(&self) -> Duration {
+        Duration::from_micros(self.frame_gap_us as u64)
+    }
+    pub fn broadcast(&self) -> BroadcastPolicy {
+        self.broadcast
     }
 }
 
-pub struct WriteRegisterCommand<const SIZE: usize> {
-    pub raw: [u8; SIZE],
+fn micros(duration: Duration) -> u32 {
+    duration.as_micros().min(u32::MAX as u128) as u32
 }
 
-impl<const SIZE: usize> WriteRegisterCommand<SIZE> {
-    pub fn new(id: u8, address: u8, length: usize) -> Self {
-        let mut raw = [0; SIZE];
-        {
-            raw[0] = 0xff;  // Marker1
-            raw[1] = 0xff;  // Marker2
-            let mut writer = PacketWriter::new(&mut raw[2..]);
-            writer.set_id(id).unwrap();
-            writer.set_length(3 + length as u8).unwrap();
-            let data = writer.data_mut().unwrap();
-            data[0] = Command::WriteRegister as u8;
-            data[1] = address;
-            //data[2..].copy_from_slice(data);
-            //writer.update_checksum().unwrap();
-        }
-        Self { raw }
+/// Builder of a [`ProtocolMasterConfig`]. Options which are not set keep their default.
+#[derive(Debug, Clone)]
+pub struct ProtocolMasterConfigBuilder {
+    config: ProtocolMasterConfig,
+}
+
+impl ProtocolMasterConfigBuilder {
+    /// The adapter echoes back every command written, e.g. a single-wire adapter without a direction switch.
+    /// The echo is received and compared with the command before the response.
+    pub fn echo_back(mut self, echo_back: bool) -> Self {
+        self.config.echo_back = echo_back;
+        self
     }
-    pub fn len(&self) -> usize {
-        self.reader().length_unchecked() as usize + 4
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
     }
-    pub fn packet(&self) -> &[u8] {
-        &self.raw[..self.len()]
+    pub fn mismatch(mut self, mismatch: MismatchPolicy) -> Self {
+        self.config.mismatch = mismatch;
+        self
     }
-    pub fn reader(&self) -> PacketReader {
-        PacketReader::new(&self.raw[2..])
+    /// Discards a packet whose next byte does not arrive within `timeout`, and fails the attempt with
+    /// [`ProtocolHandlerError::TimedOut`], instead of waiting for the rest until the deadline. Only measured with
+    /// deadlines which report the time elapsed, see [`Deadline::elapsed`]. Rounded down to whole microseconds. Zero,
+    /// the default, disables it.
+    pub fn inter_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.config.inter_byte_timeout_us = micros(timeout);
+        self
     }
-    pub fn writer(&mut self) -> PacketWriter {
-        PacketWriter::new(&mut self.raw[2..])
+    /// Keeps the bus idle for at least `gap` before each command is written, e.g. for servos which need time to
+    /// switch their transceiver back after a response. Only measured with deadlines which report the time elapsed,
+    /// see [`Deadline::elapsed`]. Rounded down to whole microseconds. Zero, the default, disables it.
+    pub fn frame_gap(mut self, gap: Duration) -> Self {
+        self.config.frame_gap_us = micros(gap);
+        self
     }
-    pub fn body_mut(&mut self) -> &mut [u8] {
-        let len = self.len();
-        &mut self.raw[6..len - 1]
+    pub fn broadcast(mut self, broadcast: BroadcastPolicy) -> Self {
+        self.config.broadcast = broadcast;
+        self
     }
-    pub fn update_checksum(&mut self) -> Result<(), PacketError> {
-        self.writer().update_checksum()
+    pub fn build(self) -> ProtocolMasterConfig {
+        self.config
     }
 }
 
-impl<const BUFFER_SIZE: usize> ProtocolMaster<BUFFER_SIZE> {
-    pub fn new(config: ProtocolMasterConfig) -> Self {
-        Self {
-            config,
-            reader: ProtocolReader::new(),
-        }
-    }
+/// Handling of WRITE and REG WRITE commands addressed to [`BROADCAST_ID`]. SYNC WRITE and ACTION are always
+/// broadcast.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BroadcastPolicy {
+    /// The command is sent to every servo and completes once it is sent, as no servo answers it.
+    #[default]
+    Send,
+    /// The command fails with [`ProtocolHandlerError::BroadcastDenied`] without being sent, e.g. so that a tool
+    /// cannot change the ID of every servo on the bus by mistake.
+    Deny,
+}
 
-    pub fn read_register<R: StreamReader, W: StreamWriter, Timeout: FnMut() -> bool>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffer: &mut [u8], mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        let command = ReadRegisterCommand::new(id, address, buffer.len() as u8);
-        let mut total_bytes_written = 0;
-        while total_bytes_written < command.raw.len() {
-            match writer.write(&command.raw[total_bytes_written..]) {
-                Ok(bytes_written) => {
-                    total_bytes_written += bytes_written;
-                }
-                Err(nb::Error::WouldBlock) => {
-                    // TODO: wait for writer to be ready
-                }
-                Err(nb::Error::Other(err)) => {
-                    return Err(ProtocolHandlerError::WriterError(err));
-                }
-            }
-            if timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
-            }
-        }
+/// Instructions the servos answer with a status packet, e.g. as set in their response-enable register. See
+/// [`ProtocolMaster::set_response_level`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResponseLevel {
+    /// Only PING and READ are answered, e.g. SCS servos with the response disabled. WRITE and REG WRITE commands
+    /// complete once they are sent.
+    ReadsOnly,
+    /// Every command addressed to a single servo is answered.
+    #[default]
+    All,
+}
 
-        if self.config.echo_back {
-            // Discard echo backed packet.
-            while !self.reader.read(reader)? {
-                if timeout() {
-                    return Err(ProtocolHandlerError::TimedOut);
-                }
-            }
-        }
+/// Retries of READ and WRITE transactions which failed with a corrupted or unexpected response, or without a
+/// response in time, e.g. on a noisy half-duplex bus. See [`ProtocolHandlerError::is_transient`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// Number of times a transaction is sent again after the first attempt.
+    pub retries: u8,
+    /// Time in ms waited before each retry, so that the rest of a corrupted response has passed.
+    pub backoff_ms: u16,
+}
 
-        while !self.reader.read(reader)? {
-            if timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
-            }
-        }
+impl RetryPolicy {
+    /// Every transaction is sent once.
+    pub const NONE: Self = Self { retries: 0, backoff_ms: 0 };
 
-        let packet = self.reader.packet().unwrap();
-        packet.verify_checksum().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        let response_id = packet.id().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        if response_id != id {
-            return Err(ProtocolHandlerError::UnexpectedPacketId(response_id));
-        }
-        let data = packet.data().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        if data.len() != buffer.len() + 1 {
-            return Err(ProtocolHandlerError::UnexpectedLength(data.len()));
-        }
-        buffer.copy_from_slice(&data[1..]);
-        Ok(())
+    pub fn backoff(&self) -> Duration {
+        Duration::from_millis(self.backoff_ms as u64)
     }
+}
 
-    #[cfg(feature = "async")]
-    pub async fn read_register_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: FnMut() -> bool>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffer: &mut [u8], mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        let command = ReadRegisterCommand::new(id, address, buffer.len() as u8);
-        let mut total_bytes_written = 0;
-        while total_bytes_written < command.raw.len() {
-            let bytes_written = writer.write(&command.raw[total_bytes_written..]).await
-                .map_err(|err| ProtocolHandlerError::WriterError(err))?;
-            total_bytes_written += bytes_written;
-            if bytes_written == 0 && timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
+/// Handling of a valid response from another servo than the addressed one, e.g. a late response to a previous
+/// transaction which timed out.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MismatchPolicy {
+    /// Discards the response and keeps waiting for the addressed servo until the deadline, instead of failing with
+    /// [`ProtocolHandlerError::UnexpectedPacketId`]. Keep this off to detect responses of a servo whose ID was just
+    /// changed or IDs shared by several servos.
+    pub skip: bool,
+    /// Called with the addressed ID and the response of the other servo, e.g. to count late responses.
+    /// Not serialized, so a deserialized policy has no hook.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub hook: Option<fn(u8, &PacketReader)>,
+}
+
+impl MismatchPolicy {
+    /// The transaction fails.
+    pub const FAIL: Self = Self { skip: false, hook: None };
+    /// The response is discarded.
+    pub const SKIP: Self = Self { skip: true, hook: None };
+}
+
+// The hook is shown as whether it is set.
+#[cfg(feature = "defmt")]
+impl defmt::Format for MismatchPolicy {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "MismatchPolicy {{ skip: {}, hook: {} }}", self.skip, self.hook.is_some())
+    }
+}
+
+/// Size of a packet without the markers: ID, length, instruction (or error), `parameters` bytes and checksum.
+pub const fn packet_size(parameters: usize) -> usize {
+    parameters + 4
+}
+
+/// Size of a WRITE command with `length` bytes of data, including the markers.
+pub const fn write_command_size(length: usize) -> usize {
+    2 + packet_size(1 + length)
+}
+
+/// Size of a SYNC WRITE command writing `length` bytes to each of `count` servos, including the markers.
+pub const fn sync_write_command_size(length: usize, count: usize) -> usize {
+    2 + packet_size(2 + (1 + length) * count)
+}
+
+/// Largest packet without the markers. The length field is at most 255.
+pub const MAX_PACKET_SIZE: usize = 255 + 2;
+
+/// Maximum number of bytes [`ProtocolMaster::write_register_verified`] writes and reads back.
+pub const MAX_VERIFY_LENGTH: usize = 16;
+
+/// Receive buffer size of [`SmallMaster`].
+pub const SMALL_BUFFER_SIZE: usize = 16;
+/// Receive buffer size of [`StandardMaster`].
+pub const STANDARD_BUFFER_SIZE: usize = 64;
+/// Receive buffer size of [`BulkMaster`].
+pub const BULK_BUFFER_SIZE: usize = MAX_PACKET_SIZE;
+
+/// Master for single servo control loops: reads up to 12 bytes (e.g. the 8 byte status block) and,
+/// with an echoing adapter, writes up to 11 bytes.
+pub type SmallMaster = ProtocolMaster<SMALL_BUFFER_SIZE>;
+/// Master for reads up to 60 bytes and, with an echoing adapter, writes up to 59 bytes, e.g. a whole EEPROM area.
+pub type StandardMaster = ProtocolMaster<STANDARD_BUFFER_SIZE>;
+/// Master which can receive any packet, e.g. for tools which read or write arbitrary register ranges.
+pub type BulkMaster = ProtocolMaster<BULK_BUFFER_SIZE>;
+
+/// Master of the bus. `Stats` counts the transactions, see [`StatsCounter`]. `Direction` switches a half-duplex
+/// transceiver, see [`DirectionControl`]. `Hooks` observes the packets, see [`PacketHooks`].
+pub struct ProtocolMaster<const BUFFER_SIZE: usize, Stats: StatsCounter = (), Direction: DirectionControl = (), Hooks: PacketHooks = ()> {
+    config: ProtocolMasterConfig,
+    reader: ProtocolReader<BUFFER_SIZE>,
+    dialect: Dialect,
+    response_level: ResponseLevel,
+    // Whether the next command waits for the retry backoff.
+    backoff: bool,
+    stats: Stats,
+    direction: Direction,
+    hooks: Hooks,
+}
+
+/// Direction of a half-duplex transceiver, e.g. the DE and RE pins of an RS-485 transceiver, which a
+/// [`ProtocolMaster`] switches around every command it writes. `()` does nothing, for adapters which switch by
+/// themselves.
+pub trait DirectionControl {
+    /// Called before the first byte of a command is written.
+    fn transmit(&mut self);
+    /// Called after the last byte of a command is written or the write failed. Must not return before the bytes
+    /// have left the transmitter, e.g. by flushing the UART, or the end of the command is cut off.
+    fn receive(&mut self);
+}
+
+impl DirectionControl for () {
+    fn transmit(&mut self) {}
+    fn receive(&mut self) {}
+}
+
+/// Modem control line of a serial adapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlLine {
+    /// Data Terminal Ready.
+    Dtr,
+    /// Request To Send.
+    Rts,
+}
+
+/// Modem control lines of an adapter, e.g. of the programming boards which switch modes or reset the servo on a pulse
+/// of DTR or RTS. See [`ProtocolMaster::pulse_control_line`].
+pub trait ControlLines {
+    type Error;
+    /// Asserts `line` if `asserted`, or deasserts it. The level on the pin depends on the adapter, e.g. an asserted
+    /// DTR of an FTDI adapter is low.
+    fn set_line(&mut self, line: ControlLine, asserted: bool) -> Result<(), Self::Error>;
+}
+
+/// Transaction counters of a [`ProtocolMaster`], e.g. to monitor the health of the bus in a long-running application.
+/// The counters wrap around on overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MasterStats {
+    /// Commands sent completely.
+    pub packets_sent: u32,
+    /// Responses received with a valid checksum, including responses of other servos than the addressed one.
+    pub responses_received: u32,
+    /// Responses received with an invalid checksum.
+    pub checksum_errors: u32,
+    /// Transaction attempts which timed out.
+    pub timeouts: u32,
+    /// Transactions sent again by the [`RetryPolicy`].
+    pub retries: u32,
+    /// Bytes written to the bus.
+    pub bytes_out: u32,
+    /// Bytes of the responses received, including the markers. Echoed commands are not counted.
+    pub bytes_in: u32,
+    /// Responses whose round-trip time was measured, i.e. received within a deadline with a clock such as the
+    /// `_timed` transactions use. The round trip runs from the start of the command to the end of the response.
+    pub round_trips: u32,
+    /// Sum of the round-trip times in microseconds.
+    pub round_trip_total_us: u64,
+    /// Shortest round-trip time in microseconds.
+    pub round_trip_min_us: u32,
+    /// Longest round-trip time in microseconds.
+    pub round_trip_max_us: u32,
+    /// Round-trip time of the last response measured in microseconds.
+    pub round_trip_last_us: u32,
+}
+
+impl MasterStats {
+    /// Counts a response received `round_trip` after the start of its command.
+    pub fn record_round_trip(&mut self, round_trip: Duration) {
+        let us = u32::try_from(round_trip.as_micros()).unwrap_or(u32::MAX);
+        if self.round_trips == 0 || us < self.round_trip_min_us {
+            self.round_trip_min_us = us;
+        }
+        self.round_trip_max_us = self.round_trip_max_us.max(us);
+        self.round_trip_last_us = us;
+        self.round_trip_total_us = self.round_trip_total_us.wrapping_add(us as u64);
+        self.round_trips = self.round_trips.wrapping_add(1);
+    }
+    /// Mean round-trip time, e.g. to tune the return delay of the servos. None if no round trip was measured.
+    pub fn round_trip_mean(&self) -> Option<Duration> {
+        (self.round_trips > 0).then(|| Duration::from_micros(self.round_trip_total_us / self.round_trips as u64))
+    }
+    pub fn round_trip_min(&self) -> Option<Duration> {
+        (self.round_trips > 0).then(|| Duration::from_micros(self.round_trip_min_us as u64))
+    }
+    /// Longest round-trip time. A maximum creeping up from the mean hints at a degrading bus.
+    pub fn round_trip_max(&self) -> Option<Duration> {
+        (self.round_trips > 0).then(|| Duration::from_micros(self.round_trip_max_us as u64))
+    }
+    pub fn round_trip_last(&self) -> Option<Duration> {
+        (self.round_trips > 0).then(|| Duration::from_micros(self.round_trip_last_us as u64))
+    }
+}
+
+/// Storage of the counters of a [`ProtocolMaster`]. `()` counts nothing, which keeps masters such as [`SmallMaster`]
+/// within a few words of their receive buffer. [`MasterStats`] counts every transaction.
+pub trait StatsCounter: Default {
+    fn stats(&self) -> MasterStats;
+    fn update<F: FnOnce(&mut MasterStats)>(&mut self, update: F);
+}
+
+impl StatsCounter for () {
+    fn stats(&self) -> MasterStats {
+        MasterStats::default()
+    }
+    fn update<F: FnOnce(&mut MasterStats)>(&mut self, _update: F) {}
+}
+
+impl StatsCounter for MasterStats {
+    fn stats(&self) -> MasterStats {
+        *self
+    }
+    fn update<F: FnOnce(&mut MasterStats)>(&mut self, update: F) {
+        update(self)
+    }
+}
+
+/// Observer of the packets a [`ProtocolMaster`] or [`ProtocolSlave`] writes and receives, e.g. to log or mirror the
+/// traffic of the bus while debugging without wrapping the streams. `()` observes nothing. [`PacketHookFns`] calls a
+/// function for each packet.
+pub trait PacketHooks: Default {
+    /// Called with the bytes of each frame once it is written completely, markers included. The responses of a slave
+    /// to a SYNC READ are passed at once.
+    fn on_transmit(&mut self, _frame: &[u8]) {}
+    /// Called with each packet received, whether its checksum is valid or not. The echo of a command written by the
+    /// master is not passed.
+    fn on_receive(&mut self, _packet: &PacketReader) {}
+}
+
+impl PacketHooks for () {}
+
+/// Functions called with the packets, see [`PacketHooks`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PacketHookFns {
+    pub on_transmit: Option<fn(&[u8])>,
+    pub on_receive: Option<fn(&PacketReader)>,
+}
+
+impl PacketHooks for PacketHookFns {
+    fn on_transmit(&mut self, frame: &[u8]) {
+        if let Some(hook) = self.on_transmit {
+            hook(frame);
+        }
+    }
+    fn on_receive(&mut self, packet: &PacketReader) {
+        if let Some(hook) = self.on_receive {
+            hook(packet);
+        }
+    }
+}
+
+pub const BROADCAST_ID: u8 = 0xfe;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Ping = 0x01,
+    ReadRegister = 0x02,
+    WriteRegister = 0x03,
+    RegWriteRegister = 0x04,
+    Action = 0x05,
+    /// Restores the factory defaults of the registers.
+    Reset = 0x06,
+    SyncRead = 0x82,
+    SyncWrite = 0x83,
+}
+
+impl Command {
+    /// Returns the command of an instruction byte, or `None` if the instruction is not defined.
+    pub fn from_instruction(instruction: u8) -> Option<Self> {
+        [Self::Ping, Self::ReadRegister, Self::WriteRegister, Self::RegWriteRegister, Self::Action, Self::Reset, Self::SyncRead, Self::SyncWrite]
+            .into_iter()
+            .find(|command| *command as u8 == instruction)
+    }
+    /// Name of the instruction in the datasheet, e.g. `REG WRITE`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ping => "PING",
+            Self::ReadRegister => "READ",
+            Self::WriteRegister => "WRITE",
+            Self::RegWriteRegister => "REG WRITE",
+            Self::Action => "ACTION",
+            Self::Reset => "RESET",
+            Self::SyncRead => "SYNC READ",
+            Self::SyncWrite => "SYNC WRITE",
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Instruction of a command packet with its parameters, e.g. for slave handlers and monitors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsedInstruction<'a> {
+    Ping,
+    ReadRegister { address: u8, length: u8 },
+    WriteRegister { address: u8, data: &'a [u8] },
+    RegWrite { address: u8, data: &'a [u8] },
+    Action,
+    /// `ids` are the servos asked to respond, in the order of their responses.
+    SyncRead { address: u8, length: u8, ids: &'a [u8] },
+    /// `entries` holds the ID followed by the `length` bytes written for each servo. See
+    /// [`sync_write_entries`](Self::sync_write_entries).
+    SyncWrite { address: u8, length: u8, entries: &'a [u8] },
+    Reset,
+}
+
+impl<'a> ParsedInstruction<'a> {
+    /// Decodes the instruction of `packet`. Fails with [`PacketError::UnknownInstruction`] if the instruction is
+    /// not defined, and with [`PacketError::InvalidLength`] if the parameters do not fit the instruction, e.g. a
+    /// SYNC WRITE with a truncated entry. The checksum is not verified.
+    pub fn parse(packet: &PacketReader<'a>) -> Result<Self, PacketError> {
+        let (instruction, parameters) = packet.data()?.split_first().ok_or(PacketError::InvalidLength)?;
+        let command = Command::from_instruction(*instruction).ok_or(PacketError::UnknownInstruction(*instruction))?;
+        let parsed = match (command, parameters) {
+            (Command::Ping, []) => Self::Ping,
+            (Command::ReadRegister, &[address, length]) => Self::ReadRegister { address, length },
+            (Command::WriteRegister, [address, data @ ..]) => Self::WriteRegister { address: *address, data },
+            (Command::RegWriteRegister, [address, data @ ..]) => Self::RegWrite { address: *address, data },
+            (Command::Action, []) => Self::Action,
+            (Command::Reset, []) => Self::Reset,
+            (Command::SyncRead, [address, length, ids @ ..]) => Self::SyncRead { address: *address, length: *length, ids },
+            (Command::SyncWrite, [address, length, entries @ ..]) if entries.len() % (1 + *length as usize) == 0 => {
+                Self::SyncWrite { address: *address, length: *length, entries }
+            }
+            _ => return Err(PacketError::InvalidLength),
+        };
+        Ok(parsed)
+    }
+
+    pub fn command(&self) -> Command {
+        match self {
+            Self::Ping => Command::Ping,
+            Self::ReadRegister { .. } => Command::ReadRegister,
+            Self::WriteRegister { .. } => Command::WriteRegister,
+            Self::RegWrite { .. } => Command::RegWriteRegister,
+            Self::Action => Command::Action,
+            Self::SyncRead { .. } => Command::SyncRead,
+            Self::SyncWrite { .. } => Command::SyncWrite,
+            Self::Reset => Command::Reset,
+        }
+    }
+
+    /// The servos of a SYNC WRITE and the data written to each of them. Empty for other instructions.
+    pub fn sync_write_entries(&self) -> impl Iterator<Item = (u8, &'a [u8])> {
+        let (length, entries) = match *self {
+            Self::SyncWrite { length, entries, .. } => (length as usize, entries),
+            _ => (0, &[][..]),
+        };
+        entries.chunks_exact(1 + length).map(|entry| (entry[0], &entry[1..]))
+    }
+}
+
+/// Error byte of a response, which carries the alarm flags of the servo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ServoStatusFlags(pub u8);
+
+impl ServoStatusFlags {
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+    /// Whether no flag is set.
+    pub const fn is_ok(&self) -> bool {
+        self.0 == 0
+    }
+    /// Input voltage out of the range.
+    pub const fn voltage(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+    /// Target position out of the position limits.
+    pub const fn angle_limit(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+    /// Temperature above the upper limit.
+    pub const fn overheat(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+    /// A value out of the range of its register was written.
+    pub const fn range(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+    /// The servo received a command with a wrong checksum.
+    pub const fn checksum(&self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+    /// Load above the max torque.
+    pub const fn overload(&self) -> bool {
+        self.0 & (1 << 5) != 0
+    }
+    /// The servo received an undefined instruction.
+    pub const fn instruction(&self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolHandlerError<ReaderError, WriterError> {
+    PacketError(PacketError),
+    ReaderError(ReaderError),
+    WriterError(WriterError),
+    ProtocolReaderError(ProtocolReaderError<ReaderError>),
+    UnexpectedPacketId(u8),
+    UnexpectedLength(usize),
+    TimedOut,
+    /// The operation needs a response, but the bus is operated with responses disabled.
+    ResponsesDisabled,
+    /// The write policy rejected a write to the register at the address.
+    WriteProtected(u8),
+    /// A valid response was discarded because the link has not recovered from a burst of corrupted frames yet.
+    LinkUnstable,
+    /// The registers read back after a verified write differ from the data written. The first `length` bytes of
+    /// `observed` are the bytes read back.
+    VerificationFailed { observed: [u8; MAX_VERIFY_LENGTH], length: usize },
+    /// The adapter echoed back other bytes than the command written, e.g. because another device transmitted at the
+    /// same time.
+    EchoMismatch,
+    /// The servo rejected the command with the status in its response. Only reported in [`Dialect::Dynamixel1`].
+    Rejected(ServoStatusFlags),
+    /// A WRITE to the broadcast ID was not sent, as the master is configured with [`BroadcastPolicy::Deny`].
+    BroadcastDenied,
+    /// More than one servo answers the ID, so that their responses follow each other or collide.
+    IdConflict(u8),
+}
+impl<ReaderError, WriterError> ProtocolHandlerError<ReaderError, WriterError> {
+    /// Whether repeating the transaction may succeed: the command or the response was corrupted, the response came
+    /// from another servo or did not arrive in time.
+    pub fn is_transient(&self) -> bool {
+        matches!(self,
+            Self::PacketError(_) | Self::ProtocolReaderError(ProtocolReaderError::PacketError(_)) |
+            Self::UnexpectedPacketId(_) | Self::UnexpectedLength(_) | Self::TimedOut | Self::EchoMismatch)
+            || matches!(self, Self::Rejected(status) if status.checksum())
+    }
+}
+
+// Shows only the bytes read back of a failed verification, instead of the whole buffer.
+impl<ReaderError: fmt::Debug, WriterError: fmt::Debug> fmt::Debug for ProtocolHandlerError<ReaderError, WriterError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PacketError(error) => f.debug_tuple("PacketError").field(error).finish(),
+            Self::ReaderError(error) => f.debug_tuple("ReaderError").field(error).finish(),
+            Self::WriterError(error) => f.debug_tuple("WriterError").field(error).finish(),
+            Self::ProtocolReaderError(error) => f.debug_tuple("ProtocolReaderError").field(error).finish(),
+            Self::UnexpectedPacketId(id) => f.debug_tuple("UnexpectedPacketId").field(id).finish(),
+            Self::UnexpectedLength(length) => f.debug_tuple("UnexpectedLength").field(length).finish(),
+            Self::TimedOut => f.write_str("TimedOut"),
+            Self::ResponsesDisabled => f.write_str("ResponsesDisabled"),
+            Self::WriteProtected(address) => f.debug_tuple("WriteProtected").field(&format_args!("0x{:02x}", address)).finish(),
+            Self::LinkUnstable => f.write_str("LinkUnstable"),
+            Self::VerificationFailed { observed, length } => f.debug_struct("VerificationFailed")
+                .field("observed", &HexDump(&observed[..(*length).min(MAX_VERIFY_LENGTH)]))
+                .finish(),
+            Self::EchoMismatch => f.write_str("EchoMismatch"),
+            Self::Rejected(status) => f.debug_tuple("Rejected").field(&format_args!("0x{:02x}", status.bits())).finish(),
+            Self::BroadcastDenied => f.write_str("BroadcastDenied"),
+            Self::IdConflict(id) => f.debug_tuple("IdConflict").field(id).finish(),
+        }
+    }
+}
+
+// The messages do not repeat the underlying error, which is the source.
+impl<ReaderError, WriterError> fmt::Display for ProtocolHandlerError<ReaderError, WriterError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PacketError(_) => f.write_str("invalid response"),
+            Self::ReaderError(_) => f.write_str("failed to read from the bus"),
+            Self::WriterError(_) => f.write_str("failed to write to the bus"),
+            Self::ProtocolReaderError(_) => f.write_str("failed to receive a response"),
+            Self::UnexpectedPacketId(id) => write!(f, "unexpected response from ID {}", id),
+            Self::UnexpectedLength(length) => write!(f, "unexpected length {}", length),
+            Self::TimedOut => f.write_str("timed out"),
+            Self::ResponsesDisabled => f.write_str("responses are disabled on the bus"),
+            Self::WriteProtected(address) => write!(f, "register 0x{:02x} is write-protected", address),
+            Self::LinkUnstable => f.write_str("link has not recovered from corrupted frames"),
+            Self::VerificationFailed { observed, length } => {
+                write!(f, "registers read back as {}", HexDump(&observed[..(*length).min(MAX_VERIFY_LENGTH)]))
+            }
+            Self::EchoMismatch => f.write_str("echo differs from the command written"),
+            Self::Rejected(status) => write!(f, "servo rejected the command with status 0x{:02x}", status.bits()),
+            Self::BroadcastDenied => f.write_str("writes to the broadcast ID are denied"),
+            Self::IdConflict(id) => write!(f, "more than one servo answers ID {}", id),
+        }
+    }
+}
+
+impl<ReaderError: core::error::Error + 'static, WriterError: core::error::Error + 'static> core::error::Error for ProtocolHandlerError<ReaderError, WriterError> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::PacketError(error) => Some(error),
+            Self::ReaderError(error) => Some(error),
+            Self::WriterError(error) => Some(error),
+            Self::ProtocolReaderError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<ReaderError, WriterError> From<ProtocolReaderError<ReaderError>> for ProtocolHandlerError<ReaderError, WriterError> {
+    fn from(error: ProtocolReaderError<ReaderError>) -> Self {
+        Self::ProtocolReaderError(error)
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadRegisterCommand {
+    pub raw: [u8; 8],
+}
+impl ReadRegisterCommand {
+    pub fn new(id: u8, address: u8, length: u8) -> Self {
+        let mut raw = [0; 8];
+        {
+            raw[0] = 0xff;  // Marker1
+            raw[1] = 0xff;  // Marker2
+            let mut writer = PacketWriter::new(&mut raw[2..]);
+            writer.set_id(id).unwrap();
+            writer.set_length(4).unwrap();
+            let data = writer.data_mut().unwrap();
+            data[0] = Command::ReadRegister as u8;
+            data[1] = address;
+            data[2] = length;
+            writer.update_checksum().unwrap();
+        }
+        Self { raw }
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PingCommand {
+    pub raw: [u8; 6],
+}
+impl PingCommand {
+    pub fn new(id: u8) -> Self {
+        let mut raw = [0; 6];
+        {
+            raw[0] = 0xff;  // Marker1
+            raw[1] = 0xff;  // Marker2
+            let mut writer = PacketWriter::new(&mut raw[2..]);
+            writer.set_id(id).unwrap();
+            writer.set_length(2).unwrap();
+            writer.data_mut().unwrap()[0] = Command::Ping as u8;
+            writer.update_checksum().unwrap();
+        }
+        Self { raw }
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ActionCommand {
+    pub raw: [u8; 6],
+}
+impl ActionCommand {
+    pub fn new(id: u8) -> Self {
+        let mut raw = [0; 6];
+        {
+            raw[0] = 0xff;  // Marker1
+            raw[1] = 0xff;  // Marker2
+            let mut writer = PacketWriter::new(&mut raw[2..]);
+            writer.set_id(id).unwrap();
+            writer.set_length(2).unwrap();
+            writer.data_mut().unwrap()[0] = Command::Action as u8;
+            writer.update_checksum().unwrap();
+        }
+        Self { raw }
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WriteRegisterCommand<const SIZE: usize> {
+    pub raw: [u8; SIZE],
+}
+
+impl<const SIZE: usize> WriteRegisterCommand<SIZE> {
+    /// Maximum number of data bytes the command can hold, at most the 252 bytes the length field can describe.
+    pub const MAX_LENGTH: usize = if SIZE - write_command_size(0) < 252 { SIZE - write_command_size(0) } else { 252 };
+
+    pub fn new(id: u8, address: u8, length: usize) -> Self {
+        assert!(length <= Self::MAX_LENGTH, "the data does not fit in the command");
+        let mut raw = [0; SIZE];
+        {
+            raw[0] = 0xff;  // Marker1
+            raw[1] = 0xff;  // Marker2
+            let mut writer = PacketWriter::new(&mut raw[2..]);
+            writer.set_id(id).unwrap();
+            writer.set_length(3 + length as u8).unwrap();
+            let data = writer.data_mut().unwrap();
+            data[0] = Command::WriteRegister as u8;
+            data[1] = address;
+            //data[2..].copy_from_slice(data);
+            //writer.update_checksum().unwrap();
+        }
+        Self { raw }
+    }
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.reader().length_unchecked() as usize + 4
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw[..self.len()]
+    }
+    pub fn reader(&self) -> PacketReader<'_> {
+        PacketReader::new(&self.raw[2..])
+    }
+    pub fn writer(&mut self) -> PacketWriter<'_> {
+        PacketWriter::new(&mut self.raw[2..])
+    }
+    pub fn id(&self) -> u8 {
+        self.reader().id_unchecked()
+    }
+    /// The first register address written.
+    pub fn address(&self) -> u8 {
+        self.raw[5]
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.raw[6..self.len() - 1]
+    }
+    pub fn body_mut(&mut self) -> &mut [u8] {
+        let len = self.len();
+        &mut self.raw[6..len - 1]
+    }
+    pub fn update_checksum(&mut self) -> Result<(), PacketError> {
+        self.writer().update_checksum()
+    }
+
+    /// Starts a command to servo `id`, e.g.
+    /// `WriteRegisterCommand::<16>::builder(id).address(0x2a).data(&[hi, lo]).build()?`.
+    pub fn builder(id: u8) -> WriteRegisterBuilder<'static, SIZE> {
+        WriteRegisterBuilder { id, address: 0, data: &[] }
+    }
+    /// Command storing `word` in register `address` and the following one in `order`, e.g.
+    /// `WriteRegisterCommand::<{ write_command_size(2) }>::word(id, 0x2a, position, scs0009::WORD_ORDER)?`.
+    /// Fails with [`PacketError::InvalidLength`] if the command cannot hold two bytes.
+    pub fn word(id: u8, address: u8, word: u16, order: WordOrder) -> Result<Self, PacketError> {
+        Self::builder(id).address(address).data(&order.to_bytes(word)).build()
+    }
+}
+
+/// Builder of a [`WriteRegisterCommand`], which fills in the data and the checksum.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteRegisterBuilder<'a, const SIZE: usize> {
+    id: u8,
+    address: u8,
+    data: &'a [u8],
+}
+
+impl<'a, const SIZE: usize> WriteRegisterBuilder<'a, SIZE> {
+    /// The first register address written.
+    pub fn address(self, address: u8) -> Self {
+        Self { address, ..self }
+    }
+    pub fn data<'b>(self, data: &'b [u8]) -> WriteRegisterBuilder<'b, SIZE> {
+        WriteRegisterBuilder { id: self.id, address: self.address, data }
+    }
+    /// Fails with [`PacketError::InvalidLength`] if the data does not fit in the command or in a packet.
+    pub fn build(self) -> Result<WriteRegisterCommand<SIZE>, PacketError> {
+        if self.data.len() > WriteRegisterCommand::<SIZE>::MAX_LENGTH {
+            return Err(PacketError::InvalidLength);
+        }
+        let mut command = WriteRegisterCommand::new(self.id, self.address, self.data.len());
+        command.body_mut().copy_from_slice(self.data);
+        command.update_checksum()?;
+        Ok(command)
+    }
+}
+
+/// WRITE command built in a buffer owned by the caller, e.g. the DMA buffer of the UART, so that the packet is sent
+/// from where it was built and its size is not a const generic.
+pub struct WriteRegisterCommandRef<'a> {
+    raw: &'a mut [u8],
+}
+
+impl<'a> WriteRegisterCommandRef<'a> {
+    /// Builds the command writing `data` to the registers of servo `id` from `address` at the start of `buffer`, with
+    /// the checksum updated. The command takes [`write_command_size`] of the data bytes of the buffer. Fails with
+    /// [`PacketError::InvalidLength`] if it does not fit in the buffer or in a packet.
+    pub fn new(buffer: &'a mut [u8], id: u8, address: u8, data: &[u8]) -> Result<Self, PacketError> {
+        let size = write_command_size(data.len());
+        if size > buffer.len() || size > MAX_PACKET_SIZE + 2 {
+            return Err(PacketError::InvalidLength);
+        }
+        let raw = &mut buffer[..size];
+        raw[0] = 0xff;  // Marker1
+        raw[1] = 0xff;  // Marker2
+        let mut writer = PacketWriter::new(&mut raw[2..]);
+        writer.set_id(id)?;
+        writer.set_length(3 + data.len() as u8)?;
+        let body = writer.data_mut()?;
+        body[0] = Command::WriteRegister as u8;
+        body[1] = address;
+        body[2..].copy_from_slice(data);
+        writer.update_checksum()?;
+        Ok(Self { raw })
+    }
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+    pub fn packet(&self) -> &[u8] {
+        self.raw
+    }
+    pub fn reader(&self) -> PacketReader<'_> {
+        PacketReader::new(&self.raw[2..])
+    }
+    pub fn writer(&mut self) -> PacketWriter<'_> {
+        PacketWriter::new(&mut self.raw[2..])
+    }
+    pub fn id(&self) -> u8 {
+        self.reader().id_unchecked()
+    }
+    /// The first register address written.
+    pub fn address(&self) -> u8 {
+        self.raw[5]
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.raw[6..self.len() - 1]
+    }
+    /// The data, to be followed by [`Self::update_checksum`] when changed.
+    pub fn body_mut(&mut self) -> &mut [u8] {
+        let len = self.len();
+        &mut self.raw[6..len - 1]
+    }
+    pub fn update_checksum(&mut self) -> Result<(), PacketError> {
+        self.writer().update_checksum()
+    }
+    /// The part of the buffer holding the packet, e.g. to start the DMA transfer with.
+    pub fn into_packet(self) -> &'a mut [u8] {
+        self.raw
+    }
+}
+
+/// REG WRITE command. The servo stores the data and responds like to a WRITE, but writes the registers only
+/// when it receives ACTION, so writes to several servos can take effect at the same time.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegWriteRegisterCommand<const SIZE: usize> {
+    command: WriteRegisterCommand<SIZE>,
+}
+
+impl<const SIZE: usize> RegWriteRegisterCommand<SIZE> {
+    /// Maximum number of data bytes the command can hold.
+    pub const MAX_LENGTH: usize = WriteRegisterCommand::<SIZE>::MAX_LENGTH;
+
+    pub fn new(id: u8, address: u8, length: usize) -> Self {
+        let mut command = WriteRegisterCommand::new(id, address, length);
+        command.raw[4] = Command::RegWriteRegister as u8;
+        Self { command }
+    }
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.command.len()
+    }
+    pub fn packet(&self) -> &[u8] {
+        self.command.packet()
+    }
+    pub fn id(&self) -> u8 {
+        self.command.id()
+    }
+    /// The first register address written.
+    pub fn address(&self) -> u8 {
+        self.command.address()
+    }
+    pub fn body(&self) -> &[u8] {
+        self.command.body()
+    }
+    pub fn body_mut(&mut self) -> &mut [u8] {
+        self.command.body_mut()
+    }
+    pub fn update_checksum(&mut self) -> Result<(), PacketError> {
+        self.command.update_checksum()
+    }
+
+    /// Starts a command to servo `id`. See [`WriteRegisterCommand::builder`].
+    pub fn builder(id: u8) -> RegWriteRegisterBuilder<'static, SIZE> {
+        RegWriteRegisterBuilder { write: WriteRegisterCommand::builder(id) }
+    }
+}
+
+/// Builder of a [`RegWriteRegisterCommand`], which fills in the data and the checksum.
+#[derive(Debug, Clone, Copy)]
+pub struct RegWriteRegisterBuilder<'a, const SIZE: usize> {
+    write: WriteRegisterBuilder<'a, SIZE>,
+}
+
+impl<'a, const SIZE: usize> RegWriteRegisterBuilder<'a, SIZE> {
+    /// The first register address written.
+    pub fn address(self, address: u8) -> Self {
+        Self { write: self.write.address(address) }
+    }
+    pub fn data<'b>(self, data: &'b [u8]) -> RegWriteRegisterBuilder<'b, SIZE> {
+        RegWriteRegisterBuilder { write: self.write.data(data) }
+    }
+    /// Fails with [`PacketError::InvalidLength`] if the data does not fit in the command or in a packet.
+    pub fn build(self) -> Result<RegWriteRegisterCommand<SIZE>, PacketError> {
+        let mut command = RegWriteRegisterCommand { command: self.write.build()? };
+        command.command.raw[4] = Command::RegWriteRegister as u8;
+        command.update_checksum()?;
+        Ok(command)
+    }
+}
+
+/// SYNC WRITE command, which writes the same registers of several servos in one broadcast packet.
+/// The servos do not respond to it.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SyncWriteCommand<const SIZE: usize> {
+    pub raw: [u8; SIZE],
+}
+
+impl<const SIZE: usize> SyncWriteCommand<SIZE> {
+    /// Creates a command which writes `length` bytes starting at `address` and has no servo yet.
+    /// Panics if not even one servo fits in the command or in a packet.
+    pub fn new(address: u8, length: usize) -> Self {
+        assert!(sync_write_command_size(length, 1) <= SIZE, "the data does not fit in the command");
+        assert!(sync_write_command_size(length, 1) <= MAX_PACKET_SIZE + 2, "the data does not fit in a packet");
+        let mut raw = [0; SIZE];
+        {
+            raw[0] = 0xff;  // Marker1
+            raw[1] = 0xff;  // Marker2
+            let mut writer = PacketWriter::new(&mut raw[2..]);
+            writer.set_id(BROADCAST_ID).unwrap();
+            writer.set_length(4).unwrap();
+            let data = writer.data_mut().unwrap();
+            data[0] = Command::SyncWrite as u8;
+            data[1] = address;
+            data[2] = length as u8;
+        }
+        Self { raw }
+    }
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.reader().length_unchecked() as usize + 4
+    }
+    pub fn packet(&self) -> &[u8] {
+        &self.raw[..self.len()]
+    }
+    pub fn reader(&self) -> PacketReader<'_> {
+        PacketReader::new(&self.raw[2..])
+    }
+    pub fn writer(&mut self) -> PacketWriter<'_> {
+        PacketWriter::new(&mut self.raw[2..])
+    }
+    /// The first register address written.
+    pub fn address(&self) -> u8 {
+        self.raw[5]
+    }
+    /// Number of bytes written to each servo.
+    pub fn length(&self) -> usize {
+        self.raw[6] as usize
+    }
+    /// Number of servos in the command.
+    pub fn count(&self) -> usize {
+        (self.len() - sync_write_command_size(0, 0)) / (1 + self.length())
+    }
+    /// Whether another servo fits in the command.
+    pub fn is_full(&self) -> bool {
+        self.len() + 1 + self.length() > SIZE || self.len() + 1 + self.length() > MAX_PACKET_SIZE + 2
+    }
+
+    /// Adds the data for servo `id`. Returns false if the command is full.
+    /// Panics if the length of `data` differs from the length of the command.
+    pub fn push(&mut self, id: u8, data: &[u8]) -> bool {
+        assert_eq!(data.len(), self.length(), "the data length differs from the command");
+        if self.is_full() {
+            return false;
+        }
+        let position = self.len() - 1;
+        self.raw[position] = id;
+        self.raw[position + 1..position + 1 + data.len()].copy_from_slice(data);
+        let length = self.reader().length_unchecked() + 1 + data.len() as u8;
+        self.writer().set_length(length).unwrap();
+        true
+    }
+    /// The servos in the command and the data written to each of them.
+    pub fn entries(&self) -> impl Iterator<Item = (u8, &[u8])> + '_ {
+        self.raw[7..self.len() - 1].chunks_exact(1 + self.length()).map(|entry| (entry[0], &entry[1..]))
+    }
+    pub fn update_checksum(&mut self) -> Result<(), PacketError> {
+        self.writer().update_checksum()
+    }
+
+    /// Splits `entries`, each `(id, data)`, into commands which write `length` bytes starting at `address` to as many
+    /// servos as fit in a command, with the checksums updated, e.g. to move all the joints of a robot:
+    /// `for command in SyncWriteCommand::<{ MAX_PACKET_SIZE + 2 }>::split(0x2a, 2, targets)? { ... }`.
+    /// Fails with [`PacketError::InvalidLength`] if not even one servo fits in a command.
+    pub fn split<I: IntoIterator<Item = (u8, D)>, D: AsRef<[u8]>>(address: u8, length: usize, entries: I) -> Result<SyncWriteChunks<SIZE, I::IntoIter>, PacketError> {
+        if sync_write_command_size(length, 1) > SIZE.min(MAX_PACKET_SIZE + 2) {
+            return Err(PacketError::InvalidLength);
+        }
+        Ok(SyncWriteChunks { address, length, entries: entries.into_iter().peekable(), failed: false })
+    }
+    /// Starts a command which writes `length` bytes starting at `address` to each servo, e.g.
+    /// `SyncWriteCommand::<64>::builder(0x2a, 2).servo(1, &[hi, lo]).servo(2, &[hi, lo]).build()?`.
+    pub fn builder(address: u8, length: usize) -> SyncWriteBuilder<SIZE> {
+        let command = if sync_write_command_size(length, 1) <= SIZE.min(MAX_PACKET_SIZE + 2) {
+            Ok(Self::new(address, length))
+        } else {
+            Err(PacketError::InvalidLength)
+        };
+        SyncWriteBuilder { command }
+    }
+}
+
+/// Builder of a [`SyncWriteCommand`], which fills in the length and the checksum.
+pub struct SyncWriteBuilder<const SIZE: usize> {
+    command: Result<SyncWriteCommand<SIZE>, PacketError>,
+}
+
+impl<const SIZE: usize> SyncWriteBuilder<SIZE> {
+    /// Adds the data for servo `id`.
+    pub fn servo(mut self, id: u8, data: &[u8]) -> Self {
+        if let Ok(command) = &mut self.command {
+            if data.len() != command.length() || !command.push(id, data) {
+                self.command = Err(PacketError::InvalidLength);
+            }
+        }
+        self
+    }
+    /// Fails with [`PacketError::InvalidLength`] if the length of the data of a servo differs from the length of the
+    /// command, or the servos do not fit in the command.
+    pub fn build(self) -> Result<SyncWriteCommand<SIZE>, PacketError> {
+        let mut command = self.command?;
+        command.update_checksum()?;
+        Ok(command)
+    }
+}
+
+/// Iterator over the commands of [`SyncWriteCommand::split`]. Yields [`PacketError::InvalidLength`] and ends if the
+/// data of a servo is not as long as the commands.
+pub struct SyncWriteChunks<const SIZE: usize, I: Iterator> {
+    address: u8,
+    length: usize,
+    entries: core::iter::Peekable<I>,
+    failed: bool,
+}
+
+impl<const SIZE: usize, I: Iterator<Item = (u8, D)>, D: AsRef<[u8]>> Iterator for SyncWriteChunks<SIZE, I> {
+    type Item = Result<SyncWriteCommand<SIZE>, PacketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        self.entries.peek()?;
+        let mut command = SyncWriteCommand::<SIZE>::new(self.address, self.length);
+        while let Some((id, data)) = self.entries.next_if(|_| !command.is_full()) {
+            if data.as_ref().len() != self.length {
+                self.failed = true;
+                return Some(Err(PacketError::InvalidLength));
+            }
+            command.push(id, data.as_ref());
+        }
+        Some(command.update_checksum().map(|_| command))
+    }
+}
+
+/// A WRITE command whatever holds its bytes, which the master methods taking a WRITE accept, e.g.
+/// [`WriteRegisterCommand`], [`WriteRegisterCommandRef`] or, with the `alloc` feature,
+/// [`WriteRegisterCommandVec`](crate::command_vec::WriteRegisterCommandVec).
+pub trait WritePacket {
+    /// The packet including the markers, with the checksum updated.
+    fn packet(&self) -> &[u8];
+
+    fn id(&self) -> u8 {
+        self.packet()[2]
+    }
+    /// The first register address written.
+    fn address(&self) -> u8 {
+        self.packet()[5]
+    }
+    /// The data written.
+    fn body(&self) -> &[u8] {
+        let packet = self.packet();
+        &packet[6..packet.len() - 1]
+    }
+}
+
+/// A SYNC WRITE command whatever holds its bytes, e.g. [`SyncWriteCommand`] or, with the `alloc` feature,
+/// [`SyncWriteCommandVec`](crate::command_vec::SyncWriteCommandVec).
+pub trait SyncWritePacket {
+    /// The packet including the markers, with the checksum updated.
+    fn packet(&self) -> &[u8];
+}
+
+impl<const SIZE: usize> WritePacket for WriteRegisterCommand<SIZE> {
+    fn packet(&self) -> &[u8] {
+        WriteRegisterCommand::packet(self)
+    }
+}
+
+impl WritePacket for WriteRegisterCommandRef<'_> {
+    fn packet(&self) -> &[u8] {
+        self.raw
+    }
+}
+
+impl<const SIZE: usize> SyncWritePacket for SyncWriteCommand<SIZE> {
+    fn packet(&self) -> &[u8] {
+        SyncWriteCommand::packet(self)
+    }
+}
+
+// Commands show as their packet, e.g. `ID 1 WRITE 0x2a: 08 00` and `WriteRegisterCommand(ID 1 WRITE 0x2a: 08 00)`.
+macro_rules! impl_command_fmt {
+    ($($command:ident $(<$size:ident>)?),*) => {$(
+        impl$(<const $size: usize>)? fmt::Display for $command$(<$size>)? {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&PacketReader::new(&self.packet()[2..]), f)
+            }
+        }
+        impl$(<const $size: usize>)? fmt::Debug for $command$(<$size>)? {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($command)).field(&format_args!("{}", self)).finish()
+            }
+        }
+    )*};
+}
+
+impl_command_fmt!(ReadRegisterCommand, PingCommand, ActionCommand, WriteRegisterCommand<SIZE>, RegWriteRegisterCommand<SIZE>, SyncWriteCommand<SIZE>);
+
+impl fmt::Display for WriteRegisterCommandRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.reader(), f)
+    }
+}
+impl fmt::Debug for WriteRegisterCommandRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WriteRegisterCommandRef").field(&format_args!("{}", self)).finish()
+    }
+}
+
+fn scatter_length(buffers: &[&mut [u8]]) -> usize {
+    buffers.iter().map(|buffer| buffer.len()).sum()
+}
+
+fn scatter(mut data: &[u8], buffers: &mut [&mut [u8]]) {
+    for buffer in buffers.iter_mut() {
+        let (head, tail) = data.split_at(buffer.len());
+        buffer.copy_from_slice(head);
+        data = tail;
+    }
+}
+
+/// Number of the `ranges` from the first one which one READ of at most `max_length` bytes covers, and the span of
+/// registers it reads.
+fn range_batch(ranges: &[(u8, &mut [u8])], max_length: usize) -> (usize, Range<usize>) {
+    let mut span = 0..0;
+    for (count, (address, buffer)) in ranges.iter().enumerate() {
+        let (start, end) = (*address as usize, *address as usize + buffer.len());
+        let merged = if count == 0 { start..end } else { span.start.min(start)..span.end.max(end) };
+        if count > 0 && merged.len() > max_length {
+            return (count, span);
+        }
+        span = merged;
+    }
+    (ranges.len(), span)
+}
+
+fn timed_out<Stats: StatsCounter, RE, WE>(stats: &mut Stats) -> ProtocolHandlerError<RE, WE> {
+    stats.update(|stats| stats.timeouts = stats.timeouts.wrapping_add(1));
+    ProtocolHandlerError::TimedOut
+}
+
+fn written<Stats: StatsCounter>(stats: &mut Stats, bytes_written: usize, completed: bool) {
+    stats.update(|stats| {
+        stats.bytes_out = stats.bytes_out.wrapping_add(bytes_written as u32);
+        stats.packets_sent = stats.packets_sent.wrapping_add(completed as u32);
+    });
+}
+
+// Writing takes the counters instead of the master, as the frame of a Protocol 2.0 command is in the receive buffer.
+fn write_packet<Stats: StatsCounter, RE, W: StreamWriter, Timeout: Deadline>(stats: &mut Stats, writer: &mut W, packet: &[u8], timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<RE, W::Error>> {
+    let mut total_bytes_written = 0;
+    while total_bytes_written < packet.len() {
+        match writer.write(&packet[total_bytes_written..]) {
+            Ok(bytes_written) => {
+                total_bytes_written += bytes_written;
+                written(stats, bytes_written, total_bytes_written == packet.len());
+            }
+            Err(nb::Error::WouldBlock) => {}
+            Err(nb::Error::Other(err)) => {
+                return Err(ProtocolHandlerError::WriterError(err));
             }
         }
+        if timeout.expired() {
+            return Err(timed_out(stats));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+async fn write_packet_async<Stats: StatsCounter, RE, W: StreamWriterAsync, Timeout: Deadline>(stats: &mut Stats, writer: &mut W, packet: &[u8], timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<RE, W::Error>> {
+    let mut total_bytes_written = 0;
+    while total_bytes_written < packet.len() {
+        let bytes_written = writer.write(&packet[total_bytes_written..]).await
+            .map_err(ProtocolHandlerError::WriterError)?;
+        total_bytes_written += bytes_written;
+        written(stats, bytes_written, total_bytes_written == packet.len());
+        if bytes_written == 0 && timeout.expired() {
+            return Err(timed_out(stats));
+        }
+    }
+    Ok(())
+}
+
+/// Compares the data written with the registers read back by a verified write.
+fn verify<RE, WE>(written: &[u8], observed: [u8; MAX_VERIFY_LENGTH]) -> Result<(), ProtocolHandlerError<RE, WE>> {
+    let length = written.len();
+    if observed[..length] == *written {
+        Ok(())
+    } else {
+        Err(ProtocolHandlerError::VerificationFailed { observed, length })
+    }
+}
+
+impl<const BUFFER_SIZE: usize, Stats: StatsCounter, Hooks: PacketHooks> ProtocolMaster<BUFFER_SIZE, Stats, (), Hooks> {
+    pub fn new(config: ProtocolMasterConfig) -> Self {
+        Self::with_direction(config, ())
+    }
+}
+
+impl<const BUFFER_SIZE: usize, Stats: StatsCounter, Direction: DirectionControl, Hooks: PacketHooks> ProtocolMaster<BUFFER_SIZE, Stats, Direction, Hooks> {
+    /// Maximum number of bytes a single READ can return.
+    pub const MAX_READ_LENGTH: usize = if BUFFER_SIZE - packet_size(0) < 253 { BUFFER_SIZE - packet_size(0) } else { 253 };
+    /// Maximum number of bytes a single WRITE can send when the adapter echoes back the command.
+    pub const MAX_ECHO_WRITE_LENGTH: usize = BUFFER_SIZE - packet_size(1);
+    const BUFFER_SIZE_CHECK: () = assert!(BUFFER_SIZE >= packet_size(2), "the buffer must hold an echoed READ command");
+
+    /// Creates a master with a transceiver which is switched by `direction`.
+    pub fn with_direction(config: ProtocolMasterConfig, direction: Direction) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::BUFFER_SIZE_CHECK;
+        Self {
+            config,
+            reader: ProtocolReader::new(),
+            dialect: Dialect::Scs,
+            response_level: ResponseLevel::All,
+            backoff: false,
+            stats: Stats::default(),
+            direction,
+            hooks: Hooks::default(),
+        }
+    }
+
+    pub fn direction(&self) -> &Direction {
+        &self.direction
+    }
+
+    pub fn direction_mut(&mut self) -> &mut Direction {
+        &mut self.direction
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+    /// The hooks, e.g. to set the functions of [`PacketHookFns`].
+    pub fn hooks_mut(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+
+    /// Discards a partially received response, e.g. after a timeout.
+    pub fn reset(&mut self) {
+        self.reader.reset();
+    }
+
+    /// Asserts `line` of the adapter until `width` expires, then deasserts it, e.g. to switch a programming board to
+    /// the bus or to reset the servo. A partially received response is discarded, as the adapter may have sent
+    /// garbage while switching. If asserting the line fails, it is deasserted without waiting.
+    pub fn pulse_control_line<L: ControlLines, Width: Deadline>(&mut self, lines: &mut L, line: ControlLine, mut width: Width) -> Result<(), L::Error> {
+        let asserted = lines.set_line(line, true);
+        if asserted.is_ok() {
+            while !width.expired() {}
+        }
+        let deasserted = lines.set_line(line, false);
+        self.reader.reset();
+        asserted.and(deasserted)
+    }
+
+    /// Same as [`Self::pulse_control_line`] with a pulse of `width` measured by `T`.
+    pub fn pulse_control_line_timed<T: Timer, L: ControlLines>(&mut self, lines: &mut L, line: ControlLine, width: Duration) -> Result<(), L::Error> {
+        self.pulse_control_line(lines, line, timeout_after::<T>(width))
+    }
+
+    /// Transaction counters since the master was created or the counters were reset. All zero unless `Stats` is
+    /// [`MasterStats`]. The round-trip time of the last transaction is [`MasterStats::round_trip_last`].
+    pub fn stats(&self) -> MasterStats {
+        self.stats.stats()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    fn timed_out<RE, WE>(&mut self) -> ProtocolHandlerError<RE, WE> {
+        timed_out(&mut self.stats)
+    }
+
+    /// Counts a response received and verifies its checksum.
+    fn verify_response(&mut self) -> Result<(), PacketError> {
+        let packet = self.reader.packet().unwrap();
+        // The markers, the ID and the length field precede the bytes counted by the length field.
+        let length = packet.length_unchecked() as usize + 4;
+        let result = packet.verify_checksum();
+        self.hooks.on_receive(&packet);
+        self.stats.update(|stats| {
+            stats.bytes_in = stats.bytes_in.wrapping_add(length as u32);
+            match result {
+                Ok(()) => stats.responses_received = stats.responses_received.wrapping_add(1),
+                Err(_) => stats.checksum_errors = stats.checksum_errors.wrapping_add(1),
+            }
+        });
+        result
+    }
+
+    /// Counts the round trip of a response completed now to a command started at `start`, if the deadline has a clock.
+    fn record_round_trip<Timeout: Deadline>(&mut self, start: Option<Duration>, timeout: &Timeout) {
+        if let (Some(start), Some(now)) = (start, timeout.elapsed()) {
+            self.stats.update(|stats| stats.record_round_trip(now.saturating_sub(start)));
+        }
+    }
+
+    /// Prepares another attempt of a failed transaction. Returns false if no time is left for it.
+    fn prepare_retry<Timeout: Deadline>(&mut self, timeout: &mut Timeout) -> bool {
+        // Do not take the rest of the failed response for the next one.
+        self.reset();
+        self.backoff = timeout.retry(self.config.retry.backoff());
+        let retry = self.backoff || !timeout.expired();
+        if retry {
+            self.stats.update(|stats| stats.retries = stats.retries.wrapping_add(1));
+        }
+        retry
+    }
+
+    /// Error byte of the last response received, which carries the alarm flags of the servo.
+    pub fn response_status(&self) -> Option<ServoStatusFlags> {
+        self.reader.packet()?.data().ok()?.first().copied().map(ServoStatusFlags)
+    }
+
+    /// Verifies the received response. Returns whether it comes from another servo than `id` and is skipped.
+    fn skip_mismatched<RE, WE>(&mut self, id: u8) -> Result<bool, ProtocolHandlerError<RE, WE>> {
+        self.verify_response().map_err(ProtocolHandlerError::PacketError)?;
+        let packet = self.reader.packet().unwrap();
+        let response_id = packet.id().map_err(ProtocolHandlerError::PacketError)?;
+        if response_id == id {
+            return Ok(false);
+        }
+        if let Some(hook) = self.config.mismatch.hook {
+            hook(id, &packet);
+        }
+        if self.config.mismatch.skip {
+            Ok(true)
+        } else {
+            Err(ProtocolHandlerError::UnexpectedPacketId(response_id))
+        }
+    }
+
+    /// Fails if the packet being received has not grown within the inter-byte timeout. `last` holds the number of
+    /// bytes received and when it was seen first.
+    fn check_inter_byte<RE, WE, Timeout: Deadline>(&mut self, timeout: &Timeout, last: &mut (usize, Duration)) -> Result<(), ProtocolHandlerError<RE, WE>> {
+        let limit = self.config.inter_byte_timeout();
+        let Some(now) = timeout.elapsed().filter(|_| !limit.is_zero()) else {
+            return Ok(());
+        };
+        let received = self.reader.received();
+        if received != last.0 {
+            *last = (received, now);
+        } else if received > 0 && now.saturating_sub(last.1) >= limit {
+            // Do not take the rest of the stalled packet for the start of the next one.
+            self.reset();
+            return Err(self.timed_out());
+        }
+        Ok(())
+    }
+
+    /// Time the bus is kept idle before a command is written: the frame gap, and the retry backoff before a retry.
+    fn idle_time(&mut self) -> Duration {
+        let backoff = if core::mem::take(&mut self.backoff) { self.config.retry.backoff() } else { Duration::ZERO };
+        self.config.frame_gap().max(backoff)
+    }
+
+    /// Keeps the bus idle for [`Self::idle_time`], or until the deadline. Whatever is received meanwhile, e.g. the
+    /// rest of a corrupted response, is discarded.
+    fn wait_idle<R: StreamReader, WE, Timeout: Deadline>(&mut self, reader: &mut R, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, WE>> {
+        let idle = self.idle_time();
+        if let Some(start) = timeout.elapsed().filter(|_| !idle.is_zero()) {
+            let mut discarded = [0; MARKER_SCAN_LENGTH];
+            while !timeout.expired() && timeout.elapsed().is_some_and(|now| now.saturating_sub(start) < idle) {
+                match reader.read(&mut discarded) {
+                    Ok(_) | Err(nb::Error::WouldBlock) => {}
+                    Err(nb::Error::Other(err)) => return Err(ProtocolHandlerError::ReaderError(err)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    async fn wait_idle_async<R: StreamReaderAsync, WE, Timeout: Deadline>(&mut self, reader: &mut R, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, WE>> {
+        let idle = self.idle_time();
+        if let Some(start) = timeout.elapsed().filter(|_| !idle.is_zero()) {
+            let mut discarded = [0; MARKER_SCAN_LENGTH];
+            while !timeout.expired() && timeout.elapsed().is_some_and(|now| now.saturating_sub(start) < idle) {
+                reader.read(&mut discarded).await.map_err(ProtocolHandlerError::ReaderError)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails a WRITE to `id` which the [`BroadcastPolicy`] does not allow.
+    fn check_broadcast<RE, WE>(&self, id: u8) -> Result<(), ProtocolHandlerError<RE, WE>> {
+        if id == BROADCAST_ID && self.config.broadcast == BroadcastPolicy::Deny {
+            return Err(ProtocolHandlerError::BroadcastDenied);
+        }
+        Ok(())
+    }
+
+    /// Waits for the next packet, i.e. the echo of a command or a response.
+    fn receive_packet<R: StreamReader, WE, Timeout: Deadline>(&mut self, reader: &mut R, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, WE>> {
+        let mut last = (0, Duration::ZERO);
+        while !self.reader.read(reader)? {
+            if timeout.expired() {
+                return Err(self.timed_out());
+            }
+            self.check_inter_byte(timeout, &mut last)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    async fn receive_packet_async<R: StreamReaderAsync, WE, Timeout: Deadline>(&mut self, reader: &mut R, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, WE>> {
+        let mut last = (0, Duration::ZERO);
+        while !self.reader.read_async(reader).await
+            .map_err(ProtocolHandlerError::ProtocolReaderError)? {
+            if timeout.expired() {
+                return Err(self.timed_out());
+            }
+            self.check_inter_byte(timeout, &mut last)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the response of servo `id`. Responses of other servos are handled by the [`MismatchPolicy`].
+    fn receive_response<R: StreamReader, WE, Timeout: Deadline>(&mut self, reader: &mut R, id: u8, start: Option<Duration>, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, WE>> {
+        loop {
+            self.receive_packet(reader, timeout)?;
+            if !self.skip_mismatched(id)? {
+                self.record_round_trip(start, timeout);
+                return Ok(());
+            }
+            if timeout.expired() {
+                return Err(self.timed_out());
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn receive_response_async<R: StreamReaderAsync, WE, Timeout: Deadline>(&mut self, reader: &mut R, id: u8, start: Option<Duration>, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, WE>> {
+        loop {
+            self.receive_packet_async(reader, timeout).await?;
+            if !self.skip_mismatched(id)? {
+                self.record_round_trip(start, timeout);
+                return Ok(());
+            }
+            if timeout.expired() {
+                return Err(self.timed_out());
+            }
+        }
+    }
+
+    pub fn read_register<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffer: &mut [u8], timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        self.read_register_scatter(reader, writer, id, address, &mut [buffer], timeout)
+    }
+
+    /// Same as [`Self::read_register`] with a deadline `timeout` after the start, measured by `T`, e.g.
+    /// `master.read_register_timed::<std::time::Instant, _, _>(..., Duration::from_millis(10))`.
+    /// Every retry gets the whole timeout.
+    pub fn read_register_timed<T: Timer, R: StreamReader, W: StreamWriter>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffer: &mut [u8], timeout: Duration) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        self.read_register(reader, writer, id, address, buffer, timeout_after::<T>(timeout))
+    }
+
+    /// Reads the word stored at `address` and the following register in `order`, e.g. the position of a servo.
+    /// [`WriteRegisterCommand::word`] writes one.
+    pub fn read_register_u16<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, order: WordOrder, timeout: Timeout) -> Result<u16, ProtocolHandlerError<R::Error, W::Error>> {
+        let mut data = [0; 2];
+        self.read_register(reader, writer, id, address, &mut data, timeout)?;
+        Ok(order.from_bytes(data))
+    }
+
+    /// Reads consecutive registers into multiple buffers.
+    /// The response payload is split across `buffers` in order, e.g. directly into the position, speed and load fields of a struct.
+    pub fn read_register_scatter<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffers: &mut [&mut [u8]], mut timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let mut retries = self.config.retry.retries;
+        loop {
+            match self.read_register_once(reader, writer, id, address, buffers, &mut timeout) {
+                Err(err) if err.is_transient() && retries > 0 && self.prepare_retry(&mut timeout) => retries -= 1,
+                result => return result,
+            }
+        }
+    }
+
+    fn read_register_once<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffers: &mut [&mut [u8]], timeout: &mut Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let length = scatter_length(buffers);
+        if length > Self::MAX_READ_LENGTH {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        let command = ReadRegisterCommand::new(id, address, length as u8);
+        let start = self.send(reader, writer, &command.raw, timeout)?;
+
+        self.receive_response(reader, id, start, timeout)?;
+        let packet = self.reader.packet().unwrap();
+        let data = packet.data().map_err(ProtocolHandlerError::PacketError)?;
+        let status = self.check_status(data)?;
+        if data.len() != length + 1 {
+            return Err(ProtocolHandlerError::UnexpectedLength(data.len()));
+        }
+        scatter(&data[1..], buffers);
+        Ok(status)
+    }
 
-        if self.config.echo_back {
-            // Discard echo backed packet.
-            while !self.reader.read_async(reader).await
-                .map_err(|err| ProtocolHandlerError::ProtocolReaderError(err))? {
-                if timeout() {
-                    return Err(ProtocolHandlerError::TimedOut);
+    /// Reads several ranges of registers of servo `id`, each `(address, buffer)`, e.g. the position, the voltage and
+    /// the temperature of a telemetry snapshot. Neighbouring ranges are read together as long as the registers
+    /// between them fit in one READ, so the ranges take as few transactions as possible when sorted by address.
+    /// Each transaction is retried on its own, all within `timeout`. Returns the flags of all responses combined.
+    /// Fails with [`ProtocolHandlerError::UnexpectedLength`] before anything is sent if a range is empty or runs past
+    /// the last register.
+    pub fn read_register_ranges<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, ranges: &mut [(u8, &mut [u8])], mut timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        Self::check_ranges(ranges)?;
+        let mut status = ServoStatusFlags::default();
+        let mut rest = ranges;
+        while !rest.is_empty() {
+            let (count, span) = range_batch(rest, Self::MAX_READ_LENGTH);
+            let (batch, tail) = rest.split_at_mut(count);
+            rest = tail;
+            let mut retries = self.config.retry.retries;
+            let flags = loop {
+                match self.read_range_batch_once(reader, writer, id, span.clone(), batch, &mut timeout) {
+                    Err(err) if err.is_transient() && retries > 0 && self.prepare_retry(&mut timeout) => retries -= 1,
+                    result => break result?,
                 }
-            }
+            };
+            status = ServoStatusFlags(status.0 | flags.0);
         }
+        Ok(status)
+    }
 
-        while !self.reader.read_async(reader).await
-            .map_err(|err| ProtocolHandlerError::ProtocolReaderError(err))? {
-            if timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
+    /// Fails if one of `ranges` is empty, runs past the last register or does not fit in one READ.
+    fn check_ranges<RE, WE>(ranges: &[(u8, &mut [u8])]) -> Result<(), ProtocolHandlerError<RE, WE>> {
+        for (address, buffer) in ranges {
+            if buffer.is_empty() || *address as usize + buffer.len() > 256 {
+                return Err(ProtocolHandlerError::UnexpectedLength(buffer.len()));
+            }
+            if buffer.len() > Self::MAX_READ_LENGTH {
+                return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
             }
         }
+        Ok(())
+    }
+
+    /// Reads the registers of `span` and copies the ranges of `batch` out of the response.
+    fn read_range_batch_once<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, span: Range<usize>, batch: &mut [(u8, &mut [u8])], timeout: &mut Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let command = ReadRegisterCommand::new(id, span.start as u8, span.len() as u8);
+        let start = self.send(reader, writer, &command.raw, timeout)?;
+
+        self.receive_response(reader, id, start, timeout)?;
+        self.copy_ranges(span, batch)
+    }
 
+    /// Checks the response to the READ of `span` and copies the ranges of `batch` out of it.
+    fn copy_ranges<RE, WE>(&self, span: Range<usize>, batch: &mut [(u8, &mut [u8])]) -> Result<ServoStatusFlags, ProtocolHandlerError<RE, WE>> {
         let packet = self.reader.packet().unwrap();
-        packet.verify_checksum().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        let response_id = packet.id().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        if response_id != id {
-            return Err(ProtocolHandlerError::UnexpectedPacketId(response_id));
-        }
-        let data = packet.data().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        if data.len() != buffer.len() + 1 {
+        let data = packet.data().map_err(ProtocolHandlerError::PacketError)?;
+        let status = self.check_status(data)?;
+        if data.len() != span.len() + 1 {
             return Err(ProtocolHandlerError::UnexpectedLength(data.len()));
         }
-        buffer.copy_from_slice(&data[1..]);
-        Ok(())
+        for (address, buffer) in batch.iter_mut() {
+            let offset = 1 + *address as usize - span.start;
+            buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+        }
+        Ok(status)
     }
 
-    pub fn write_register<R: StreamReader, W: StreamWriter, Timeout: FnMut() -> bool, const SIZE: usize>(&mut self, reader: &mut R, writer: &mut W, command: &WriteRegisterCommand<SIZE>, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
-        let buffer = command.packet();
-        let mut total_bytes_written = 0;
-        while total_bytes_written < buffer.len() {
-            match writer.write(&buffer[total_bytes_written..]) {
-                Ok(bytes_written) => {
-                    total_bytes_written += bytes_written;
-                }
-                Err(nb::Error::WouldBlock) => {
-                    // TODO: wait for writer to be ready
-                }
-                Err(nb::Error::Other(err)) => {
-                    return Err(ProtocolHandlerError::WriterError(err));
-                }
+    /// Reads registers from several servos in turn with one deadline for all of them, e.g. to sample the joints
+    /// within a control period. Each request is `(id, address, buffer)`.
+    /// `on_result` receives the index and the result of every request. The requests left when `timeout` expires
+    /// fail with `TimedOut` without being sent. Returns the number of requests which succeeded.
+    pub fn read_register_many<R: StreamReader, W: StreamWriter, Timeout: Deadline, OnResult: FnMut(usize, Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>>)>(&mut self, reader: &mut R, writer: &mut W, requests: &mut [(u8, u8, &mut [u8])], mut timeout: Timeout, mut on_result: OnResult) -> usize {
+        let mut succeeded = 0;
+        for (index, (id, address, buffer)) in requests.iter_mut().enumerate() {
+            let result = if timeout.expired() {
+                Err(ProtocolHandlerError::TimedOut)
+            } else {
+                self.read_register(reader, writer, *id, *address, buffer, SharedDeadline(&mut timeout))
+            };
+            if result.is_ok() {
+                succeeded += 1;
             }
-            if timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
+            on_result(index, result);
+        }
+        succeeded
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn read_register_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffer: &mut [u8], timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        self.read_register_scatter_async(reader, writer, id, address, &mut [buffer], timeout).await
+    }
+
+    /// Async version of [`Self::read_register_timed`].
+    #[cfg(feature = "async")]
+    pub async fn read_register_timed_async<T: Timer, R: StreamReaderAsync, W: StreamWriterAsync>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffer: &mut [u8], timeout: Duration) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        self.read_register_async(reader, writer, id, address, buffer, timeout_after::<T>(timeout)).await
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn read_register_scatter_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffers: &mut [&mut [u8]], mut timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let mut retries = self.config.retry.retries;
+        loop {
+            match self.read_register_once_async(reader, writer, id, address, buffers, &mut timeout).await {
+                Err(err) if err.is_transient() && retries > 0 && self.prepare_retry(&mut timeout) => retries -= 1,
+                result => return result,
             }
         }
+    }
+
+    #[cfg(feature = "async")]
+    async fn read_register_once_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, buffers: &mut [&mut [u8]], timeout: &mut Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let length = scatter_length(buffers);
+        if length > Self::MAX_READ_LENGTH {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        let command = ReadRegisterCommand::new(id, address, length as u8);
+        let start = self.send_async(reader, writer, &command.raw, timeout).await?;
 
-        if self.config.echo_back {
-            // Discard echo backed packet.
-            while !self.reader.read(reader)? {
-                if timeout() {
-                    return Err(ProtocolHandlerError::TimedOut);
+        self.receive_response_async(reader, id, start, timeout).await?;
+        let packet = self.reader.packet().unwrap();
+        let data = packet.data().map_err(ProtocolHandlerError::PacketError)?;
+        let status = self.check_status(data)?;
+        if data.len() != length + 1 {
+            return Err(ProtocolHandlerError::UnexpectedLength(data.len()));
+        }
+        scatter(&data[1..], buffers);
+        Ok(status)
+    }
+
+    /// Async version of [`Self::read_register_ranges`].
+    #[cfg(feature = "async")]
+    pub async fn read_register_ranges_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, ranges: &mut [(u8, &mut [u8])], mut timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        Self::check_ranges(ranges)?;
+        let mut status = ServoStatusFlags::default();
+        let mut rest = ranges;
+        while !rest.is_empty() {
+            let (count, span) = range_batch(rest, Self::MAX_READ_LENGTH);
+            let (batch, tail) = rest.split_at_mut(count);
+            rest = tail;
+            let mut retries = self.config.retry.retries;
+            let flags = loop {
+                match self.read_range_batch_once_async(reader, writer, id, span.clone(), batch, &mut timeout).await {
+                    Err(err) if err.is_transient() && retries > 0 && self.prepare_retry(&mut timeout) => retries -= 1,
+                    result => break result?,
                 }
-            }
+            };
+            status = ServoStatusFlags(status.0 | flags.0);
         }
+        Ok(status)
+    }
 
-        while !self.reader.read(reader)? {
-            if timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
+    #[cfg(feature = "async")]
+    async fn read_range_batch_once_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, span: Range<usize>, batch: &mut [(u8, &mut [u8])], timeout: &mut Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let command = ReadRegisterCommand::new(id, span.start as u8, span.len() as u8);
+        let start = self.send_async(reader, writer, &command.raw, timeout).await?;
+
+        self.receive_response_async(reader, id, start, timeout).await?;
+        self.copy_ranges(span, batch)
+    }
+
+    /// Sends a WRITE command and waits for the response. Writes to the broadcast ID are not answered, so they
+    /// complete once they are sent, as do all writes with [`ResponseLevel::ReadsOnly`] configured. See
+    /// [`write_register_no_response`](Self::write_register_no_response) for single servos with their responses
+    /// disabled.
+    pub fn write_register<R: StreamReader, W: StreamWriter, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let mut retries = self.config.retry.retries;
+        loop {
+            match self.write_register_once(reader, writer, command, &mut timeout) {
+                Err(err) if err.is_transient() && retries > 0 && self.prepare_retry(&mut timeout) => retries -= 1,
+                result => return result,
             }
         }
+    }
 
-        let packet = self.reader.packet().unwrap();
-        packet.verify_checksum().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        let response_id = packet.id().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        if response_id != command.reader().id().unwrap() {
-            return Err(ProtocolHandlerError::UnexpectedPacketId(response_id));
+    /// Same as [`Self::write_register`] with a deadline `timeout` after the start, measured by `T`.
+    /// Every retry gets the whole timeout.
+    pub fn write_register_timed<T: Timer, R: StreamReader, W: StreamWriter, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, timeout: Duration) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        self.write_register(reader, writer, command, timeout_after::<T>(timeout))
+    }
+
+    /// Writes `command`, then reads the registers back and compares them with the data written, e.g. for EEPROM writes
+    /// which a locked servo ignores silently. The deadline covers both transactions. Fails with
+    /// [`ProtocolHandlerError::VerificationFailed`] if the registers differ, and with
+    /// [`ProtocolHandlerError::UnexpectedLength`] without writing if the command writes more than
+    /// [`MAX_VERIFY_LENGTH`] bytes. Broadcast writes cannot be read back, so they fail with
+    /// [`ProtocolHandlerError::BroadcastDenied`] without writing.
+    pub fn write_register_verified<R: StreamReader, W: StreamWriter, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        if command.id() == BROADCAST_ID {
+            return Err(ProtocolHandlerError::BroadcastDenied);
+        }
+        let length = command.body().len();
+        if length > MAX_VERIFY_LENGTH {
+            return Err(ProtocolHandlerError::UnexpectedLength(length));
+        }
+        self.write_register(reader, writer, command, SharedDeadline(&mut timeout))?;
+        let mut observed = [0; MAX_VERIFY_LENGTH];
+        self.read_register(reader, writer, command.id(), command.address(), &mut observed[..length], SharedDeadline(&mut timeout))?;
+        verify(command.body(), observed)
+    }
+
+    fn write_register_once<R: StreamReader, W: StreamWriter, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        self.check_broadcast(command.id())?;
+        let buffer = command.packet();
+        if self.config.echo_back && buffer.len() - 2 > BUFFER_SIZE {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        let start = self.send(reader, writer, buffer, timeout)?;
+        if command.id() == BROADCAST_ID || self.response_level == ResponseLevel::ReadsOnly {
+            return Ok(());
         }
-        // TODO: Check the write response.
+
+        self.receive_response(reader, command.id(), start, timeout)?;
+        let packet = self.reader.packet().unwrap();
+        self.check_status(packet.data().map_err(ProtocolHandlerError::PacketError)?)?;
         Ok(())
     }
 
-    #[cfg(feature = "async")]
-    pub async fn write_register_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: FnMut() -> bool, const SIZE: usize>(&mut self, reader: &mut R, writer: &mut W, command: &WriteRegisterCommand<SIZE>, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+    /// Sends a REG WRITE command and waits for the response, unless it is a broadcast.
+    /// The data takes effect on the next ACTION.
+    pub fn reg_write_register<R: StreamReader, W: StreamWriter, Timeout: Deadline, const SIZE: usize>(&mut self, reader: &mut R, writer: &mut W, command: &RegWriteRegisterCommand<SIZE>, timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        // The packet only differs from WRITE in the instruction, so is the response.
+        self.write_register(reader, writer, &command.command, timeout)
+    }
+
+    /// Writes `packet` in the frame format with the transceiver switched to transmit, then receives the echo if the
+    /// adapter echoes back. Returns the time the transmission started as measured by the deadline, if it has a clock.
+    fn send<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, packet: &[u8], timeout: &mut Timeout) -> Result<Option<Duration>, ProtocolHandlerError<R::Error, W::Error>> {
+        // Do not take the leftovers of an earlier transaction, received or not, for the response.
+        self.reset();
+        reader.purge().map_err(ProtocolHandlerError::ReaderError)?;
+        self.wait_idle(reader, timeout)?;
+        let start = timeout.elapsed();
+        let frame = self.reader.encode(packet)
+            .map_err(|_| ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer))?;
+        self.direction.transmit();
+        let result = write_packet(&mut self.stats, writer, frame, timeout);
+        self.direction.receive();
+        result?;
+        self.hooks.on_transmit(frame);
+        if self.config.echo_back {
+            self.receive_packet(reader, timeout)?;
+            self.verify_echo(packet)?;
+        }
+        Ok(start)
+    }
+
+    /// Compares the echo received with `packet` written, markers included.
+    fn verify_echo<RE, WE>(&self, packet: &[u8]) -> Result<(), ProtocolHandlerError<RE, WE>> {
+        match self.reader.packet() {
+            Some(echo) if packet.get(2..) == Some(echo.raw) => Ok(()),
+            _ => Err(ProtocolHandlerError::EchoMismatch),
+        }
+    }
+
+
+    /// Status flags in the first byte of the response `data`. Fails if the servo rejected the command in the dialect.
+    fn check_status<RE, WE>(&self, data: &[u8]) -> Result<ServoStatusFlags, ProtocolHandlerError<RE, WE>> {
+        let status = data.first().copied().map(ServoStatusFlags).ok_or(ProtocolHandlerError::UnexpectedLength(data.len()))?;
+        if self.dialect.rejects(status) {
+            return Err(ProtocolHandlerError::Rejected(status));
+        }
+        Ok(status)
+    }
+
+    pub fn frame_format(&self) -> FrameFormat {
+        self.reader.format()
+    }
+    /// Changes the framing of the commands written and the responses received, e.g. to [`FrameFormat::Protocol2`]
+    /// for servos which use Protocol 2.0. With Protocol 2.0, the whole frame of a command has to fit in `BUFFER_SIZE`.
+    pub fn set_frame_format(&mut self, format: FrameFormat) {
+        self.reader.set_format(format);
+    }
+
+    pub fn max_packet_length(&self) -> u8 {
+        self.reader.max_length()
+    }
+    /// Limits the length field of the responses, see [`ProtocolReader::set_max_length`]. A response above it fails
+    /// the attempt with [`ProtocolReaderError::PacketError`], which the [`RetryPolicy`] retries.
+    pub fn set_max_packet_length(&mut self, max_length: u8) {
+        self.reader.set_max_length(max_length);
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+    /// Changes how the status of the responses is interpreted, e.g. to [`Dialect::Dynamixel1`] to drive AX-12-class
+    /// servos, whose rejected commands then fail with [`ProtocolHandlerError::Rejected`].
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn response_level(&self) -> ResponseLevel {
+        self.response_level
+    }
+    /// Sets the instructions the servos on the bus answer. With [`ResponseLevel::ReadsOnly`], writes complete once
+    /// they are sent instead of timing out without a response.
+    pub fn set_response_level(&mut self, response_level: ResponseLevel) {
+        self.response_level = response_level;
+    }
+
+    /// Sends a WRITE command without waiting for a response, for servos with responses disabled.
+    /// If the adapter echoes back, the echo is still consumed.
+    pub fn write_register_no_response<R: StreamReader, W: StreamWriter, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        self.check_broadcast(command.id())?;
         let buffer = command.packet();
-        let mut total_bytes_written = 0;
-        while total_bytes_written < buffer.len() {
-            let bytes_written = writer.write(&buffer[total_bytes_written..]).await
-                .map_err(|err| ProtocolHandlerError::WriterError(err))?;
-            total_bytes_written += bytes_written;
-            if bytes_written == 0 && timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
+        if self.config.echo_back && buffer.len() - 2 > BUFFER_SIZE {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        self.send(reader, writer, buffer, &mut timeout).map(|_| ())
+    }
+
+    /// Sends a SYNC WRITE command. The servos do not respond, so the command is complete once it is sent.
+    /// If the adapter echoes back, the echo is consumed.
+    pub fn sync_write<R: StreamReader, W: StreamWriter, Timeout: Deadline, C: SyncWritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let buffer = command.packet();
+        if self.config.echo_back && buffer.len() - 2 > BUFFER_SIZE {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        self.send(reader, writer, buffer, &mut timeout).map(|_| ())
+    }
+
+    /// Broadcasts an ACTION command so that all servos apply the data staged by REG WRITE at the same time.
+    /// Broadcasts are not answered, so the command is complete once it is sent.
+    pub fn action<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let command = ActionCommand::new(BROADCAST_ID);
+        self.send(reader, writer, &command.raw, &mut timeout).map(|_| ())
+    }
+
+    /// Sends a PING to `id` and waits for the status response.
+    /// Returns the status flags of the servo from the response.
+    pub fn ping<R: StreamReader, W: StreamWriter, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, mut timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let command = PingCommand::new(id);
+        let start = self.send(reader, writer, &command.raw, &mut timeout)?;
+        self.receive_response(reader, id, start, &mut timeout)?;
+        let packet = self.reader.packet().unwrap();
+        let data = packet.data().map_err(ProtocolHandlerError::PacketError)?;
+        self.check_status(data)
+    }
+
+    /// Sends a PING to the broadcast ID and reports the ID of every valid response received until `timeout` expires.
+    /// Only firmware which answers broadcast pings (with staggered response delays) responds to it.
+    pub fn broadcast_ping<R: StreamReader, W: StreamWriter, Timeout: Deadline, Found: FnMut(u8)>(&mut self, reader: &mut R, writer: &mut W, mut timeout: Timeout, mut found: Found) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let command = PingCommand::new(BROADCAST_ID);
+        self.send(reader, writer, &command.raw, &mut timeout)?;
+        while !timeout.expired() {
+            match self.reader.read(reader) {
+                Ok(true) => loop {
+                    if self.verify_response().is_ok() {
+                        found(self.reader.packet().unwrap().id_unchecked());
+                        break;
+                    }
+                    // A collision may have corrupted the length field, so that the packet swallowed the next response.
+                    if !self.reader.resync() {
+                        break;
+                    }
+                },
+                Ok(false) => {}
+                // Collided or corrupted responses are skipped.
+                Err(ProtocolReaderError::InsufficientBuffer | ProtocolReaderError::PacketError(_)) => {}
+                Err(ProtocolReaderError::ReaderError(err)) => return Err(ProtocolHandlerError::ReaderError(err)),
             }
         }
+        Ok(())
+    }
 
+    #[cfg(feature = "async")]
+    async fn send_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, packet: &[u8], timeout: &mut Timeout) -> Result<Option<Duration>, ProtocolHandlerError<R::Error, W::Error>> {
+        // A transaction which was cancelled or dropped at an await point may have left a partial response.
+        self.reset();
+        reader.purge().await.map_err(ProtocolHandlerError::ReaderError)?;
+        self.wait_idle_async(reader, timeout).await?;
+        let start = timeout.elapsed();
+        let frame = self.reader.encode(packet)
+            .map_err(|_| ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer))?;
+        self.direction.transmit();
+        let result = write_packet_async(&mut self.stats, writer, frame, timeout).await;
+        self.direction.receive();
+        result?;
+        self.hooks.on_transmit(frame);
         if self.config.echo_back {
-            // Discard echo backed packet.
-            while !self.reader.read_async(reader).await
-                .map_err(|err| ProtocolHandlerError::ProtocolReaderError(err))? {
-                if timeout() {
-                    return Err(ProtocolHandlerError::TimedOut);
-                }
+            self.receive_packet_async(reader, timeout).await?;
+            self.verify_echo(packet)?;
+        }
+        Ok(start)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn ping_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, mut timeout: Timeout) -> Result<ServoStatusFlags, ProtocolHandlerError<R::Error, W::Error>> {
+        let command = PingCommand::new(id);
+        let start = self.send_async(reader, writer, &command.raw, &mut timeout).await?;
+        self.receive_response_async(reader, id, start, &mut timeout).await?;
+        let packet = self.reader.packet().unwrap();
+        let data = packet.data().map_err(ProtocolHandlerError::PacketError)?;
+        self.check_status(data)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn broadcast_ping_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline, Found: FnMut(u8)>(&mut self, reader: &mut R, writer: &mut W, mut timeout: Timeout, mut found: Found) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let command = PingCommand::new(BROADCAST_ID);
+        self.send_async(reader, writer, &command.raw, &mut timeout).await?;
+        while !timeout.expired() {
+            match self.reader.read_async(reader).await {
+                Ok(true) => loop {
+                    if self.verify_response().is_ok() {
+                        found(self.reader.packet().unwrap().id_unchecked());
+                        break;
+                    }
+                    // A collision may have corrupted the length field, so that the packet swallowed the next response.
+                    if !self.reader.resync() {
+                        break;
+                    }
+                },
+                Ok(false) => {}
+                // Collided or corrupted responses are skipped.
+                Err(ProtocolReaderError::InsufficientBuffer | ProtocolReaderError::PacketError(_)) => {}
+                Err(ProtocolReaderError::ReaderError(err)) => return Err(ProtocolHandlerError::ReaderError(err)),
             }
         }
+        Ok(())
+    }
 
-        while !self.reader.read_async(reader).await
-            .map_err(|err| ProtocolHandlerError::ProtocolReaderError(err))? {
-            if timeout() {
-                return Err(ProtocolHandlerError::TimedOut);
+    #[cfg(feature = "async")]
+    pub async fn sync_write_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline, C: SyncWritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let buffer = command.packet();
+        if self.config.echo_back && buffer.len() - 2 > BUFFER_SIZE {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        self.send_async(reader, writer, buffer, &mut timeout).await.map(|_| ())
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn write_register_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let mut retries = self.config.retry.retries;
+        loop {
+            match self.write_register_once_async(reader, writer, command, &mut timeout).await {
+                Err(err) if err.is_transient() && retries > 0 && self.prepare_retry(&mut timeout) => retries -= 1,
+                result => return result,
             }
         }
+    }
 
-        let packet = self.reader.packet().unwrap();
-        packet.verify_checksum().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        let response_id = packet.id().map_err(|err| ProtocolHandlerError::PacketError(err))?;
-        if response_id != command.reader().id().unwrap() {
-            return Err(ProtocolHandlerError::UnexpectedPacketId(response_id));
+    /// Async version of [`Self::write_register_timed`].
+    #[cfg(feature = "async")]
+    pub async fn write_register_timed_async<T: Timer, R: StreamReaderAsync, W: StreamWriterAsync, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, timeout: Duration) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        self.write_register_async(reader, writer, command, timeout_after::<T>(timeout)).await
+    }
+
+    /// Async version of [`Self::read_register_u16`].
+    #[cfg(feature = "async")]
+    pub async fn read_register_u16_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, id: u8, address: u8, order: WordOrder, timeout: Timeout) -> Result<u16, ProtocolHandlerError<R::Error, W::Error>> {
+        let mut data = [0; 2];
+        self.read_register_async(reader, writer, id, address, &mut data, timeout).await?;
+        Ok(order.from_bytes(data))
+    }
+
+    /// Async version of [`Self::write_register_verified`].
+    #[cfg(feature = "async")]
+    pub async fn write_register_verified_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        if command.id() == BROADCAST_ID {
+            return Err(ProtocolHandlerError::BroadcastDenied);
+        }
+        let length = command.body().len();
+        if length > MAX_VERIFY_LENGTH {
+            return Err(ProtocolHandlerError::UnexpectedLength(length));
+        }
+        self.write_register_async(reader, writer, command, SharedDeadline(&mut timeout)).await?;
+        let mut observed = [0; MAX_VERIFY_LENGTH];
+        self.read_register_async(reader, writer, command.id(), command.address(), &mut observed[..length], SharedDeadline(&mut timeout)).await?;
+        verify(command.body(), observed)
+    }
+
+    #[cfg(feature = "async")]
+    async fn write_register_once_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline, C: WritePacket + ?Sized>(&mut self, reader: &mut R, writer: &mut W, command: &C, timeout: &mut Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        self.check_broadcast(command.id())?;
+        let buffer = command.packet();
+        if self.config.echo_back && buffer.len() - 2 > BUFFER_SIZE {
+            return Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer));
+        }
+        let start = self.send_async(reader, writer, buffer, timeout).await?;
+        if command.id() == BROADCAST_ID || self.response_level == ResponseLevel::ReadsOnly {
+            return Ok(());
         }
-        // TODO: Check the write response.
+
+        self.receive_response_async(reader, command.id(), start, timeout).await?;
+        let packet = self.reader.packet().unwrap();
+        self.check_status(packet.data().map_err(ProtocolHandlerError::PacketError)?)?;
         Ok(())
     }
+
+    #[cfg(feature = "async")]
+    pub async fn reg_write_register_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline, const SIZE: usize>(&mut self, reader: &mut R, writer: &mut W, command: &RegWriteRegisterCommand<SIZE>, timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        self.write_register_async(reader, writer, &command.command, timeout).await
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn action_async<R: StreamReaderAsync, W: StreamWriterAsync, Timeout: Deadline>(&mut self, reader: &mut R, writer: &mut W, mut timeout: Timeout) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
+        let command = ActionCommand::new(BROADCAST_ID);
+        self.send_async(reader, writer, &command.raw, &mut timeout).await.map(|_| ())
+    }
+}
+
+
+/// Set of servo IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IdSet {
+    bits: [u32; 8],
+}
+
+impl IdSet {
+    pub const fn new() -> Self {
+        Self { bits: [0; 8] }
+    }
+    pub const fn all() -> Self {
+        Self { bits: [0xffffffff; 8] }
+    }
+    pub fn from_ids(ids: &[u8]) -> Self {
+        let mut set = Self::new();
+        for id in ids {
+            set.insert(*id);
+        }
+        set
+    }
+    pub fn insert(&mut self, id: u8) {
+        self.bits[id as usize / 32] |= 1 << (id % 32);
+    }
+    pub fn remove(&mut self, id: u8) {
+        self.bits[id as usize / 32] &= !(1 << (id % 32));
+    }
+    pub fn contains(&self, id: u8) -> bool {
+        self.bits[id as usize / 32] & (1 << (id % 32)) != 0
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|bits| *bits == 0)
+    }
+    pub fn len(&self) -> usize {
+        self.bits.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+    /// Returns the smallest ID in the set.
+    pub fn first(&self) -> Option<u8> {
+        self.bits.iter().enumerate()
+            .find(|(_, bits)| **bits != 0)
+            .map(|(index, bits)| (index * 32) as u8 + bits.trailing_zeros() as u8)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=255u8).filter(move |id| self.contains(*id))
+    }
 }
 
+impl Default for IdSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProtocolSlaveConfig {
+    /// IDs this slave responds to. Packets addressed to other IDs (except the broadcast ID) are ignored.
+    pub ids: IdSet,
 }
 
-pub struct ProtocolSlave<const BUFFER_SIZE: usize> {
-    #[allow(dead_code)]
+impl Default for ProtocolSlaveConfig {
+    fn default() -> Self {
+        Self { ids: IdSet::all() }
+    }
+}
+
+/// Slave of the bus, which answers the commands to its IDs. `Hooks` observes the packets, see [`PacketHooks`].
+pub struct ProtocolSlave<const BUFFER_SIZE: usize, Hooks: PacketHooks = ()> {
     config: ProtocolSlaveConfig,
     reader: ProtocolReader<BUFFER_SIZE>,
     response_buffer: [u8; BUFFER_SIZE],
     response_position: usize,
     response_length: usize,
     state: ProtocolSlaveState,
+    hooks: Hooks,
 }
 
 enum ProtocolSlaveState {
@@ -548,7 +2663,7 @@ enum ProtocolSlaveState {
     SendResponse,
 }
 
-impl<const BUFFER_SIZE: usize> ProtocolSlave<BUFFER_SIZE> {
+impl<const BUFFER_SIZE: usize, Hooks: PacketHooks> ProtocolSlave<BUFFER_SIZE, Hooks> {
     pub fn new(config: ProtocolSlaveConfig) -> Self {
         Self {
             config,
@@ -557,6 +2672,7 @@ impl<const BUFFER_SIZE: usize> ProtocolSlave<BUFFER_SIZE> {
             response_position: 0,
             response_length: 0,
             state: ProtocolSlaveState::Idle,
+            hooks: Hooks::default(),
         }
     }
 
@@ -564,27 +2680,131 @@ impl<const BUFFER_SIZE: usize> ProtocolSlave<BUFFER_SIZE> {
         self.state = ProtocolSlaveState::Idle;
     }
 
+    pub fn ids(&self) -> &IdSet {
+        &self.config.ids
+    }
+    pub fn ids_mut(&mut self) -> &mut IdSet {
+        &mut self.config.ids
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+    /// The hooks, e.g. to set the functions of [`PacketHookFns`].
+    pub fn hooks_mut(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+
+    /// Passes the packet just received to the hooks.
+    fn received(&mut self) -> ProtocolSlaveState {
+        self.hooks.on_receive(&self.reader.packet().unwrap());
+        ProtocolSlaveState::ProcessCommand
+    }
+
+    /// Dispatches a SYNC READ request to the handler as individual READ requests.
+    /// The responses of the owned IDs are queued in the order of the ID list, so they are sent back to back
+    /// in the order the master expects them.
+    fn process_sync_read<PacketHandler: FnMut(&PacketReader, &mut [u8]) -> Option<usize>>(&mut self, handler: &mut PacketHandler) -> usize {
+        let Ok(ParsedInstruction::SyncRead { address, length, ids }) = ParsedInstruction::parse(&self.reader.packet().unwrap()) else {
+            return 0;
+        };
+        let mut response_length = 0;
+        for &id in ids {
+            if !self.config.ids.contains(id) {
+                continue;
+            }
+            let mut request = [0u8; 6];
+            {
+                let mut writer = PacketWriter::new(&mut request);
+                writer.set_id(id).unwrap();
+                writer.set_length(4).unwrap();
+                let data = writer.data_mut().unwrap();
+                data[0] = Command::ReadRegister as u8;
+                data[1] = address;
+                data[2] = length;
+                writer.update_checksum().unwrap();
+            }
+            if let Some(length) = handler(&PacketReader::new(&request), &mut self.response_buffer[response_length..]) {
+                response_length += length;
+            }
+        }
+        response_length
+    }
+
+    /// Dispatches a SYNC WRITE request to the handler as individual WRITE requests to the owned IDs.
+    /// The responses of the handler are discarded, since the servos do not respond to SYNC WRITE.
+    fn process_sync_write<PacketHandler: FnMut(&PacketReader, &mut [u8]) -> Option<usize>>(&mut self, handler: &mut PacketHandler) {
+        // A command with a truncated entry fails to parse, as the entry would be written to the wrong servo.
+        let Ok(instruction @ ParsedInstruction::SyncWrite { address, length, .. }) = ParsedInstruction::parse(&self.reader.packet().unwrap()) else {
+            return;
+        };
+        for (id, data) in instruction.sync_write_entries() {
+            if !self.config.ids.contains(id) {
+                continue;
+            }
+            let mut request = [0u8; MAX_PACKET_SIZE];
+            {
+                let mut writer = PacketWriter::new(&mut request);
+                writer.set_id(id).unwrap();
+                writer.set_length(3 + length).unwrap();
+                let body = writer.data_mut().unwrap();
+                body[0] = Command::WriteRegister as u8;
+                body[1] = address;
+                body[2..].copy_from_slice(data);
+                writer.update_checksum().unwrap();
+            }
+            handler(&PacketReader::new(&request), &mut self.response_buffer);
+        }
+    }
+
+    /// Receives a command and passes it to `handler`, which writes the response to the buffer and returns its
+    /// length, then sends the response over the following calls. SYNC READ and SYNC WRITE are passed as READ and
+    /// WRITE packets to each owned ID in the command. Other broadcast commands are passed as they are, and are not
+    /// answered whatever the handler returns.
     pub fn process<R: StreamReader, W: StreamWriter, PacketHandler: FnMut(&PacketReader, &mut [u8]) -> Option<usize>>(&mut self, reader: &mut R, writer: &mut W, mut handler: PacketHandler) -> Result<(), ProtocolHandlerError<R::Error, W::Error>> {
         self.state = match self.state {
             ProtocolSlaveState::Idle => {
                 match self.reader.read(reader) {
-                    Ok(true) => ProtocolSlaveState::ProcessCommand,
+                    Ok(true) => self.received(),
                     Ok(false) => ProtocolSlaveState::Idle,
                     Err(err) => return Err(ProtocolHandlerError::ProtocolReaderError(err)),
                 }
             },
+            ProtocolSlaveState::ProcessCommand if self.reader.packet().is_some_and(|packet| packet.verify_checksum().is_err()) => {
+                // Look for the next command in the bytes taken for the corrupted one.
+                if self.reader.resync() {
+                    self.received()
+                } else {
+                    ProtocolSlaveState::Idle
+                }
+            },
             ProtocolSlaveState::ProcessCommand => {
                 let packet = self.reader.packet().unwrap();
-                if packet.verify_checksum().is_err() {
+                let id = packet.id().unwrap_or(0);
+                let instruction = ParsedInstruction::parse(&packet).ok().map(|instruction| instruction.command());
+                if id == BROADCAST_ID && instruction == Some(Command::SyncRead) {
+                    let length = self.process_sync_read(&mut handler);
+                    if length > 0 {
+                        self.response_position = 0;
+                        self.response_length = length;
+                        ProtocolSlaveState::SendResponse
+                    } else {
+                        ProtocolSlaveState::Idle
+                    }
+                } else if id == BROADCAST_ID && instruction == Some(Command::SyncWrite) {
+                    self.process_sync_write(&mut handler);
+                    ProtocolSlaveState::Idle
+                } else if id != BROADCAST_ID && !self.config.ids.contains(id) {
                     ProtocolSlaveState::Idle
                 } else {
                     match handler(&packet, &mut self.response_buffer) {
-                        Some(length) => {
+                        // Broadcast instructions are not answered, even if the handler made a response.
+                        Some(length) if id != BROADCAST_ID => {
                             self.response_position = 0;
                             self.response_length = length;
                             ProtocolSlaveState::SendResponse
                         },
-                        None => ProtocolSlaveState::Idle,
+                        _ => ProtocolSlaveState::Idle,
                     }
                 }
             },
@@ -608,6 +2828,7 @@ impl<const BUFFER_SIZE: usize> ProtocolSlave<BUFFER_SIZE> {
                     }
                 }
                 if self.response_position == self.response_length {
+                    self.hooks.on_transmit(&self.response_buffer[..self.response_length]);
                     ProtocolSlaveState::Idle
                 } else {
                     ProtocolSlaveState::SendResponse
@@ -635,14 +2856,14 @@ extern crate std;
 impl<'a, T: std::io::Read> StreamReader for StreamWrapper<'a, T> {
     type Error = std::io::Error;
     fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
-        std::io::Read::read(self.inner, data).map_err(|err| nb::Error::Other(err))
+        std::io::Read::read(self.inner, data).map_err(nb::Error::Other)
     }
 }
 #[cfg(feature = "std")]
 impl<'a, T: std::io::Write> StreamWriter for StreamWrapper<'a, T> {
     type Error = std::io::Error;
     fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
-        std::io::Write::write(self.inner, data).map_err(|err| nb::Error::Other(err))
+        std::io::Write::write(self.inner, data).map_err(nb::Error::Other)
     }
 }
 
@@ -651,10 +2872,10 @@ impl StreamReader for std::sync::mpsc::Receiver<u8> {
     type Error = ();
     fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
         let mut bytes_read = 0;
-        for i in 0..data.len() {
+        for slot in data.iter_mut() {
             match self.try_recv() {
                 Ok(byte) => {
-                    data[i] = byte;
+                    *slot = byte;
                     bytes_read += 1;
                 },
                 Err(std::sync::mpsc::TryRecvError::Empty) => { 
@@ -687,6 +2908,27 @@ impl StreamWriter for std::sync::mpsc::Sender<u8> {
     }
 }
 
+/// Never pends: an empty channel reads 0 bytes, and the master polls again until its deadline.
+#[cfg(all(feature = "std", feature = "async"))]
+impl StreamReaderAsync for std::sync::mpsc::Receiver<u8> {
+    type Error = ();
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+        match StreamReader::read(self, data) {
+            Ok(bytes_read) => Ok(bytes_read),
+            Err(nb::Error::WouldBlock) => Ok(0),
+            Err(nb::Error::Other(err)) => Err(err),
+        }
+    }
+}
+
+#[cfg(all(feature = "std", feature = "async"))]
+impl StreamWriterAsync for std::sync::mpsc::Sender<u8> {
+    type Error = ();
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        StreamWriter::write(self, data).map_err(|_| ())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -761,6 +3003,24 @@ mod test {
         assert_eq!(packet.length().unwrap(), 0x05);
         assert_eq!(packet.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
     }
+    #[test]
+    fn test_protocol_reader_stray_marker() {
+        let raw = [0xff, 0x00, 0xff, 0xff, 0x01, 0x05, 0x03, 0x2a, 0x00, 0x14, 0xb8];
+        for chunk_size in 1..raw.len() {
+            let mut reader = ProtocolReader::<8>::new();
+            let mut completed = false;
+            for chunk in raw.chunks(chunk_size) {
+                let mut stream = Cursor::new(chunk);
+                let mut stream = StreamWrapper::new(&mut stream);
+                completed = reader.read(&mut stream).unwrap();
+            }
+            assert!(completed, "chunk size {}", chunk_size);
+            let packet = reader.packet().unwrap();
+            assert!(packet.verify_checksum().is_ok());
+            assert_eq!(packet.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
+        }
+    }
+
     #[test]
     fn test_protocol_reader_two_packets() {
         let mut reader = ProtocolReader::<8>::new();
@@ -794,37 +3054,96 @@ mod test {
         let mut stream = Cursor::new(&raw);
         let mut stream = StreamWrapper::new(&mut stream);
 
-        let result = reader.read(&mut stream);
-        assert!(result.unwrap());
-        
-        let packet = reader.packet().unwrap();
-        assert!(packet.verify_checksum().is_ok());
-        assert_eq!(packet.id().unwrap(), 0x01);
-        assert_eq!(packet.length().unwrap(), 0x05);
-        assert_eq!(packet.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
-        
-        let result = reader.read(&mut stream);
-        assert!(result.unwrap());
-        
-        let packet = reader.packet().unwrap();
-        assert!(packet.verify_checksum().is_ok());
-        assert_eq!(packet.id().unwrap(), 0x01);
-        assert_eq!(packet.length().unwrap(), 0x05);
-        assert_eq!(packet.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
+        let result = reader.read(&mut stream);
+        assert!(result.unwrap());
+        
+        let packet = reader.packet().unwrap();
+        assert!(packet.verify_checksum().is_ok());
+        assert_eq!(packet.id().unwrap(), 0x01);
+        assert_eq!(packet.length().unwrap(), 0x05);
+        assert_eq!(packet.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
+        
+        let result = reader.read(&mut stream);
+        assert!(result.unwrap());
+        
+        let packet = reader.packet().unwrap();
+        assert!(packet.verify_checksum().is_ok());
+        assert_eq!(packet.id().unwrap(), 0x01);
+        assert_eq!(packet.length().unwrap(), 0x05);
+        assert_eq!(packet.data().unwrap(), &[0x03, 0x2a, 0x00, 0x14]);
+    }
+
+    #[test]
+    fn test_protocol_reader_resync() {
+        // The length field of the first packet is corrupted, so that it swallows the whole next packet.
+        let mut reader = ProtocolReader::<16>::new();
+        let raw = [0xff, 0xff, 0x01, 0x0a, 0x00, 0x12, 0x00, 0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9, 0x00];
+        let mut stream = Cursor::new(&raw);
+        let mut stream = StreamWrapper::new(&mut stream);
+        assert!(reader.read(&mut stream).unwrap());
+        assert!(reader.packet().unwrap().verify_checksum().is_err());
+        assert!(reader.resync());
+        let packet = reader.packet().unwrap();
+        assert!(packet.verify_checksum().is_ok());
+        assert_eq!(packet.data().unwrap(), &[0x00, 0x12]);
+
+        // Only the start of the next packet is swallowed. The next read completes it.
+        let mut reader = ProtocolReader::<16>::new();
+        let raw = [0xff, 0xff, 0x01, 0x07, 0x00, 0x12, 0x00, 0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut stream = Cursor::new(&raw);
+        let mut stream = StreamWrapper::new(&mut stream);
+        assert!(reader.read(&mut stream).unwrap());
+        assert!(reader.packet().unwrap().verify_checksum().is_err());
+        assert!(!reader.resync());
+        assert!(reader.packet().is_none());
+        assert!(reader.read(&mut stream).unwrap());
+        assert_eq!(reader.packet().unwrap().data().unwrap(), &[0x00, 0x12]);
+
+        // Without markers in the packet, the reader starts over.
+        let mut reader = ProtocolReader::<16>::new();
+        let raw = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0x00, 0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut stream = Cursor::new(&raw);
+        let mut stream = StreamWrapper::new(&mut stream);
+        assert!(reader.read(&mut stream).unwrap());
+        assert!(!reader.resync());
+        assert!(reader.read(&mut stream).unwrap());
+        assert!(reader.packet().unwrap().verify_checksum().is_ok());
+    }
+
+    #[test]
+    fn test_protocol_reader_max_length() {
+        // Garbage decodes as a packet of 0xf0 bytes, which is rejected as soon as its length field is received.
+        let mut reader = ProtocolReader::<64>::new();
+        reader.set_max_length(0x10);
+        let raw = [0xff, 0xff, 0x12, 0xf0, 0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut stream = Cursor::new(&raw);
+        let mut stream = StreamWrapper::new(&mut stream);
+        assert!(matches!(reader.read(&mut stream), Err(ProtocolReaderError::PacketError(PacketError::InvalidLength))));
+        assert!(reader.read(&mut stream).unwrap());
+        assert_eq!(reader.packet().unwrap().data().unwrap(), &[0x00, 0x12]);
+
+        // A length of 0xff is the first marker of the next packet.
+        let raw = [0xff, 0xff, 0x12, 0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut stream = Cursor::new(&raw);
+        let mut stream = StreamWrapper::new(&mut stream);
+        assert!(reader.read(&mut stream).is_err());
+        assert!(reader.read(&mut stream).unwrap());
+        assert_eq!(reader.packet().unwrap().id().unwrap(), 0x01);
+        assert_eq!(reader.max_length(), 0x10);
     }
 
     #[test]
     fn test_protocol_master() {
-        let mut master = ProtocolMaster::<256>::new(ProtocolMasterConfig { echo_back: false });
-        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig {});
+        let mut master = ProtocolMaster::<256>::new(ProtocolMasterConfig::default());
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig::default());
         
         let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
         let (mut slave_writer, mut master_reader) = std::sync::mpsc::channel();
 
         std::thread::spawn(move || {
             let mut register_storage = [0u8; 256];
-            for i in 0..256 {
-                register_storage[i] = i as u8;
+            for (i, value) in register_storage.iter_mut().enumerate() {
+                *value = i as u8;
             }
             loop {
                 match slave.process(&mut slave_reader, &mut slave_writer, |packet, buffer| {
@@ -891,4 +3210,1018 @@ mod test {
         assert_eq!(buffer, [0x30, 0x31, 0x32, 0x33]);
 
     }
+
+    #[test]
+    fn test_protocol_master_presets() {
+        assert_eq!(SmallMaster::MAX_READ_LENGTH, 12);
+        assert_eq!(SmallMaster::MAX_ECHO_WRITE_LENGTH, 11);
+        assert_eq!(StandardMaster::MAX_READ_LENGTH, 60);
+        assert_eq!(BulkMaster::MAX_READ_LENGTH, 253);
+        assert_eq!(WriteRegisterCommand::<{ write_command_size(2) }>::MAX_LENGTH, 2);
+        assert_eq!(WriteRegisterCommand::<{ write_command_size(260) }>::MAX_LENGTH, 252);
+        // The master holds nothing but the receive buffer and a few words of state.
+        assert!(core::mem::size_of::<SmallMaster>() <= SMALL_BUFFER_SIZE + 48);
+        assert!(core::mem::size_of::<BulkMaster>() <= BULK_BUFFER_SIZE + 48);
+
+        // A read which does not fit in the buffer fails before anything is sent.
+        let mut master = SmallMaster::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel::<u8>();
+        let mut buffer = [0; 13];
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x00, &mut buffer, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer))));
+        assert!(slave_reader.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_protocol_master_read_scatter() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        for byte in [0xff, 0xff, 0x01, 0x0a, 0x00, 0x01, 0xff, 0x00, 0x10, 0x00, 0x20, 0x46, 0x1e, 0x60] {
+            slave_writer.send(byte).unwrap();
+        }
+
+        let mut position = [0; 2];
+        let mut speed = [0; 2];
+        let mut load = [0; 2];
+        let mut rest = [0; 2];
+        master.read_register_scatter(&mut master_reader, &mut master_writer, 0x01, 0x38, &mut [&mut position, &mut speed, &mut load, &mut rest], || false).unwrap();
+        assert_eq!(position, [0x01, 0xff]);
+        assert_eq!(speed, [0x00, 0x10]);
+        assert_eq!(load, [0x00, 0x20]);
+        assert_eq!(rest, [0x46, 0x1e]);
+    }
+
+    #[test]
+    fn test_protocol_master_read_ranges() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        for byte in [0xff, 0xff, 0x01, 0x0a, 0x00, 0x01, 0xff, 0x00, 0x00, 0x00, 0x00, 0x46, 0x1e, 0x90, 0xff, 0xff, 0x01, 0x03, 0x00, 0x07, 0xf4] {
+            slave_writer.send(byte).unwrap();
+        }
+
+        // Position, voltage and temperature are read together, the ID too far from them on its own.
+        let mut position = [0; 2];
+        let mut voltage = [0; 1];
+        let mut temperature = [0; 1];
+        let mut id = [0; 1];
+        let mut ranges: [(u8, &mut [u8]); 4] = [(0x38, &mut position), (0x3e, &mut voltage), (0x3f, &mut temperature), (0x05, &mut id)];
+        master.read_register_ranges(&mut master_reader, &mut master_writer, 0x01, &mut ranges, || false).unwrap();
+        assert_eq!((position, voltage, temperature, id), ([0x01, 0xff], [0x46], [0x1e], [0x07]));
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [
+            0xff, 0xff, 0x01, 0x04, 0x02, 0x38, 0x08, 0xb8,
+            0xff, 0xff, 0x01, 0x04, 0x02, 0x05, 0x01, 0xf2,
+        ]);
+
+        let mut long = [0; 13];
+        let result = master.read_register_ranges(&mut master_reader, &mut master_writer, 0x01, &mut [(0x00, &mut long[..])], || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::InsufficientBuffer))));
+        // Empty ranges and ranges past the last register fail before anything is sent.
+        let mut empty = [0; 0];
+        let result = master.read_register_ranges(&mut master_reader, &mut master_writer, 0x01, &mut [(0x38, &mut position[..]), (0x3e, &mut empty[..])], || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::UnexpectedLength(0))));
+        let result = master.read_register_ranges(&mut master_reader, &mut master_writer, 0x01, &mut [(0xff, &mut position[..])], || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::UnexpectedLength(2))));
+        assert_eq!(slave_reader.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_protocol_master_read_many() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        // Only servo 1 answers.
+        for byte in [0xff, 0xff, 0x01, 0x04, 0x00, 0x01, 0xff, 0xfa] {
+            slave_writer.send(byte).unwrap();
+        }
+
+        let mut first = [0; 2];
+        let mut second = [0; 2];
+        let mut third = [0; 2];
+        let mut ticks = 0;
+        let mut results = std::vec::Vec::new();
+        let succeeded = master.read_register_many(&mut master_reader, &mut master_writer, &mut [(1, 0x38, &mut first), (2, 0x38, &mut second), (3, 0x38, &mut third)], || {
+            ticks += 1;
+            ticks > 100
+        }, |index, result| results.push((index, matches!(result, Err(ProtocolHandlerError::TimedOut)))));
+        assert_eq!(succeeded, 1);
+        assert_eq!(first, [0x01, 0xff]);
+        // Servo 2 used up the deadline, so no request was sent to servo 3.
+        assert_eq!(results, [(0, false), (1, true), (2, true)]);
+        assert_eq!(slave_reader.try_iter().count(), 2 * 8);
+
+        // The reads keep the clock of the deadline, so a stalled response ends at the inter-byte timeout.
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().inter_byte_timeout(Duration::from_millis(1)).build());
+        for byte in [0xff, 0xff, 0x01] {
+            slave_writer.send(byte).unwrap();
+        }
+        let start = std::time::Instant::now();
+        let succeeded = master.read_register_many(&mut master_reader, &mut master_writer, &mut [(1, 0x38, &mut first)], timeout_after::<std::time::Instant>(Duration::from_secs(1)), |_, result| {
+            assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        });
+        assert_eq!(succeeded, 0);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_protocol_slave_sync_read() {
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig { ids: IdSet::from_ids(&[0x01, 0x03]) });
+        let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
+        let (mut slave_writer, mut master_reader) = std::sync::mpsc::channel();
+
+        // SYNC READ 2 bytes from 0x38 of ID 3, 2 and 1.
+        let mut request = [0u8; 11];
+        request[0] = 0xff;
+        request[1] = 0xff;
+        {
+            let mut writer = PacketWriter::new(&mut request[2..]);
+            writer.set_id(BROADCAST_ID).unwrap();
+            writer.set_length(7).unwrap();
+            writer.data_mut().unwrap().copy_from_slice(&[Command::SyncRead as u8, 0x38, 0x02, 0x03, 0x02, 0x01]);
+            writer.update_checksum().unwrap();
+        }
+        StreamWriter::write(&mut master_writer, &request).unwrap();
+
+        let mut handled_ids = std::vec::Vec::new();
+        for _ in 0..3 {
+            slave.process(&mut slave_reader, &mut slave_writer, |packet, buffer| {
+                let id = packet.id().unwrap();
+                let data = packet.data().unwrap();
+                assert_eq!(data, &[Command::ReadRegister as u8, 0x38, 0x02]);
+                handled_ids.push(id);
+                buffer[0] = 0xff;
+                buffer[1] = 0xff;
+                let mut writer = PacketWriter::new(&mut buffer[2..]);
+                writer.set_id(id).unwrap();
+                writer.set_length(4).unwrap();
+                writer.data_mut().unwrap().copy_from_slice(&[0x00, id, 0x10 + id]);
+                writer.update_checksum().unwrap();
+                Some(8)
+            }).unwrap();
+        }
+        assert_eq!(handled_ids, [0x03, 0x01]);
+
+        let mut reader = ProtocolReader::<16>::new();
+        for id in [0x03, 0x01] {
+            assert!(reader.read(&mut master_reader).unwrap());
+            let packet = reader.packet().unwrap();
+            assert!(packet.verify_checksum().is_ok());
+            assert_eq!(packet.id().unwrap(), id);
+            assert_eq!(packet.data().unwrap(), &[0x00, id, 0x10 + id]);
+        }
+        assert!(matches!(StreamReader::read(&mut master_reader, &mut [0]), Err(nb::Error::WouldBlock)));
+    }
+
+    #[test]
+    fn test_protocol_master_ping() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        // The servo answers with the overload alarm set.
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x20, 0xdc] {
+            slave_writer.send(byte).unwrap();
+        }
+        let status = master.ping(&mut master_reader, &mut master_writer, 0x01, || false).unwrap();
+        assert_eq!(status.bits(), 0x20);
+        assert!(status.overload() && !status.voltage() && !status.is_ok());
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0x01, 0x02, 0x01, 0xfb]);
+    }
+
+    #[test]
+    fn test_protocol_master_read_status() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        // The data is returned along with the voltage and overheat alarms.
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x05, 0x12, 0xe4] {
+            slave_writer.send(byte).unwrap();
+        }
+        let mut data = [0; 1];
+        let status = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(data, [0x12]);
+        assert!(status.voltage() && status.overheat());
+        assert!(!status.angle_limit() && !status.range() && !status.checksum() && !status.overload() && !status.instruction());
+        assert_eq!(master.response_status(), Some(status));
+    }
+
+    #[test]
+    fn test_protocol_master_retry() {
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let corrupted = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0x00];
+        let valid = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut data = [0; 1];
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        for byte in corrupted.iter().chain(valid.iter()) {
+            slave_writer.send(*byte).unwrap();
+        }
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::PacketError(PacketError::InvalidChecksum))));
+        while master_reader.try_recv().is_ok() {}
+
+        // The command is sent again after the corrupted response, within the time left.
+        master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().retry(RetryPolicy { retries: 1, backoff_ms: 0 }).build());
+        slave_reader.try_iter().count();
+        for byte in corrupted.iter().chain(valid.iter()) {
+            slave_writer.send(*byte).unwrap();
+        }
+        master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(data, [0x12]);
+        assert_eq!(slave_reader.try_iter().count(), 2 * 8);
+
+        // Errors of the transport are not retried.
+        assert!(!ProtocolHandlerError::<(), ()>::ReaderError(()).is_transient());
+        assert!(ProtocolHandlerError::<(), ()>::TimedOut.is_transient());
+    }
+
+    #[test]
+    fn test_protocol_master_stats() {
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let corrupted = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0x00];
+        let valid = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut data = [0; 1];
+
+        let mut master = ProtocolMaster::<16, MasterStats>::new(ProtocolMasterConfig::builder().retry(RetryPolicy { retries: 1, backoff_ms: 0 }).build());
+        for byte in corrupted.iter().chain(valid.iter()) {
+            slave_writer.send(*byte).unwrap();
+        }
+        master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(master.stats(), MasterStats { packets_sent: 2, responses_received: 1, checksum_errors: 1, timeouts: 0, retries: 1, bytes_out: 16, bytes_in: 14, ..MasterStats::default() });
+
+        // No time is left for a retry.
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || true);
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        assert_eq!((master.stats().packets_sent, master.stats().timeouts, master.stats().retries), (3, 1, 1));
+        assert_eq!(slave_reader.try_iter().count(), master.stats().bytes_out as usize);
+        master.reset_stats();
+        assert_eq!(master.stats(), MasterStats::default());
+
+        // Masters without counters report none.
+        let mut master = SmallMaster::new(ProtocolMasterConfig::default());
+        for byte in valid {
+            slave_writer.send(byte).unwrap();
+        }
+        master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(master.stats(), MasterStats::default());
+    }
+
+    #[test]
+    fn test_protocol_master_direction() {
+        static TRANSMITTING: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+        #[derive(Default)]
+        struct Transceiver {
+            switches: usize,
+        }
+        impl DirectionControl for Transceiver {
+            fn transmit(&mut self) {
+                TRANSMITTING.store(true, core::sync::atomic::Ordering::Relaxed);
+                self.switches += 1;
+            }
+            fn receive(&mut self) {
+                TRANSMITTING.store(false, core::sync::atomic::Ordering::Relaxed);
+            }
+        }
+        struct TransmitWriter {
+            fail: bool,
+        }
+        impl StreamWriter for TransmitWriter {
+            type Error = ();
+            fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+                assert!(TRANSMITTING.load(core::sync::atomic::Ordering::Relaxed));
+                if self.fail { Err(nb::Error::Other(())) } else { Ok(data.len()) }
+            }
+        }
+
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let mut master = ProtocolMaster::<16, (), Transceiver>::with_direction(ProtocolMasterConfig::default(), Transceiver::default());
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9] {
+            slave_writer.send(byte).unwrap();
+        }
+        let mut data = [0; 1];
+        master.read_register(&mut master_reader, &mut TransmitWriter { fail: false }, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(data, [0x12]);
+        assert!(!TRANSMITTING.load(core::sync::atomic::Ordering::Relaxed));
+
+        // The transceiver is switched back after a failed write too.
+        let result = master.read_register(&mut master_reader, &mut TransmitWriter { fail: true }, 0x01, 0x2a, &mut data, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::WriterError(()))));
+        assert!(!TRANSMITTING.load(core::sync::atomic::Ordering::Relaxed));
+        assert_eq!(master.direction().switches, 2);
+    }
+
+    #[test]
+    fn test_protocol_master_control_lines() {
+        #[derive(Default)]
+        struct Adapter {
+            levels: std::vec::Vec<(ControlLine, bool)>,
+            fail: bool,
+        }
+        impl ControlLines for Adapter {
+            type Error = ();
+            fn set_line(&mut self, line: ControlLine, asserted: bool) -> Result<(), Self::Error> {
+                self.levels.push((line, asserted));
+                if self.fail && asserted { Err(()) } else { Ok(()) }
+            }
+        }
+
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let mut adapter = Adapter::default();
+        // Garbage received before the pulse is discarded with it.
+        for byte in [0xff, 0xff, 0x01] {
+            slave_writer.send(byte).unwrap();
+        }
+        let mut polls = 0;
+        let result = master.ping(&mut master_reader, &mut master_writer, 0x01, || { polls += 1; polls > 10 });
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        let mut polls = 0;
+        master.pulse_control_line(&mut adapter, ControlLine::Dtr, || { polls += 1; polls > 3 }).unwrap();
+        assert_eq!(polls, 4);
+        assert_eq!(adapter.levels, [(ControlLine::Dtr, true), (ControlLine::Dtr, false)]);
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc] {
+            slave_writer.send(byte).unwrap();
+        }
+        master.ping(&mut master_reader, &mut master_writer, 0x01, || false).unwrap();
+
+        // The line is deasserted after asserting it failed, without waiting.
+        let mut adapter = Adapter { fail: true, ..Adapter::default() };
+        assert_eq!(master.pulse_control_line(&mut adapter, ControlLine::Rts, || false), Err(()));
+        assert_eq!(adapter.levels, [(ControlLine::Rts, true), (ControlLine::Rts, false)]);
+    }
+
+    #[test]
+    fn test_packet_hooks() {
+        #[derive(Default)]
+        struct Recorder {
+            transmitted: std::vec::Vec<std::vec::Vec<u8>>,
+            received: std::vec::Vec<std::vec::Vec<u8>>,
+        }
+        impl PacketHooks for Recorder {
+            fn on_transmit(&mut self, frame: &[u8]) {
+                self.transmitted.push(frame.to_vec());
+            }
+            fn on_receive(&mut self, packet: &PacketReader) {
+                self.received.push(packet.raw.to_vec());
+            }
+        }
+        let ping = [0xff, 0xff, 0x01, 0x02, 0x01, 0xfb];
+        let response = [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc];
+
+        // The master passes its command and the response, but not the echo.
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let config = ProtocolMasterConfig::builder().echo_back(true).build();
+        let mut master = ProtocolMaster::<16, (), (), Recorder>::new(config);
+        for byte in ping.into_iter().chain(response) {
+            slave_writer.send(byte).unwrap();
+        }
+        master.ping(&mut master_reader, &mut master_writer, 0x01, || false).unwrap();
+        assert_eq!(master.hooks().transmitted, [ping]);
+        assert_eq!(master.hooks().received, [&response[2..]]);
+
+        // The slave passes the command and its response.
+        let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
+        let (mut slave_writer, _master_reader) = std::sync::mpsc::channel();
+        let mut slave = ProtocolSlave::<16, Recorder>::new(ProtocolSlaveConfig { ids: IdSet::from_ids(&[0x01]) });
+        StreamWriter::write(&mut master_writer, &ping).unwrap();
+        for _ in 0..3 {
+            slave.process(&mut slave_reader, &mut slave_writer, |_, buffer| {
+                buffer[..response.len()].copy_from_slice(&response);
+                Some(response.len())
+            }).unwrap();
+        }
+        assert_eq!(slave.hooks().received, [&ping[2..]]);
+        assert_eq!(slave.hooks().transmitted, [response]);
+
+        // Functions are called the same way.
+        static TRANSMITTED: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+        let mut master = ProtocolMaster::<16, (), (), PacketHookFns>::new(ProtocolMasterConfig::default());
+        master.hooks_mut().on_transmit = Some(|frame| {
+            TRANSMITTED.fetch_add(frame.len(), core::sync::atomic::Ordering::Relaxed);
+        });
+        master.action(&mut master_reader, &mut master_writer, || false).unwrap();
+        assert_eq!(TRANSMITTED.load(core::sync::atomic::Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn test_protocol_master_echo() {
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().echo_back(true).build());
+        let command = ReadRegisterCommand::new(0x01, 0x2a, 1);
+        for byte in command.raw.iter().chain(&[0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9]) {
+            slave_writer.send(*byte).unwrap();
+        }
+        let mut data = [0; 1];
+        master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(data, [0x12]);
+
+        // Another device transmitted at the same time, so the echo differs from the command.
+        let mut collided = command.raw;
+        collided[2] = 0x02;
+        collided[7] = collided[7].wrapping_sub(1);
+        for byte in collided {
+            slave_writer.send(byte).unwrap();
+        }
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::EchoMismatch)));
+        assert!(result.unwrap_err().is_transient());
+    }
+
+    #[test]
+    fn test_protocol_master_protocol2() {
+        let mut master = ProtocolMaster::<32>::new(ProtocolMasterConfig::builder().echo_back(true).build());
+        master.set_frame_format(FrameFormat::Protocol2);
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let send = |bytes: &[u8]| bytes.iter().for_each(|byte| slave_writer.send(*byte).unwrap());
+        let status = |id: u8, body: &[u8]| {
+            let mut frame = std::vec![0xff, 0xff, 0xfd, 0x00, id, body.len() as u8 + 2, 0x00];
+            frame.extend_from_slice(body);
+            let crc = crate::protocol2::crc16(0, &frame);
+            frame.extend_from_slice(&crc.to_le_bytes());
+            frame
+        };
+
+        // The echo of the command, then the status after stray bytes.
+        let command = [0xff, 0xff, 0xfd, 0x00, 0x01, 0x07, 0x00, 0x02, 0x38, 0x00, 0x02, 0x00];
+        let crc = crate::protocol2::crc16(0, &command);
+        send(&command);
+        send(&crc.to_le_bytes());
+        send(&[0xff, 0x00, 0xff, 0xff]);
+        send(&status(0x01, &[0x55, 0x20, 0x12, 0x34]));
+        let mut data = [0; 2];
+        let flags = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x38, &mut data, || false).unwrap();
+        assert_eq!((data, flags), ([0x12, 0x34], ServoStatusFlags(0x20)));
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>()[..12], command);
+
+        // A corrupted CRC fails like a corrupted checksum.
+        let command = WriteRegisterCommand::<16>::builder(0x01).address(0x2a).data(&[0x00]).build().unwrap();
+        let mut echo = [0; 16];
+        let length = crate::protocol2::encode(&command.reader(), &mut echo).unwrap();
+        send(&echo[..length]);
+        let mut response = status(0x01, &[0x55, 0x00]);
+        *response.last_mut().unwrap() ^= 0x01;
+        send(&response);
+        let result = master.write_register(&mut master_reader, &mut master_writer, &command, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::PacketError(PacketError::InvalidChecksum))));
+    }
+
+    #[test]
+    fn test_protocol_master_dynamixel1() {
+        let mut master = ProtocolMaster::<32>::new(ProtocolMasterConfig::builder().retry(RetryPolicy { retries: 1, backoff_ms: 0 }).build());
+        master.set_dialect(Dialect::Dynamixel1);
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let send = |id: u8, body: &[u8]| {
+            let mut response = std::vec![0xff, 0xff, id, body.len() as u8 + 1];
+            response.extend_from_slice(body);
+            response.push(crate::packet::checksum(&response[2..]));
+            response.iter().for_each(|byte| slave_writer.send(*byte).unwrap());
+        };
+
+        // Alarms are reported like in the SCS dialect, and words are stored with the low byte first.
+        send(0x01, &[0x20, 0x00, 0x02]);
+        let mut data = [0; 2];
+        let flags = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x24, &mut data, || false).unwrap();
+        assert_eq!((Dialect::Dynamixel1.word_from_bytes(data), flags), (0x0200, ServoStatusFlags(0x20)));
+        assert_eq!(Dialect::Dynamixel1.word_to_bytes(0x0200), [0x00, 0x02]);
+        send(0x01, &[0x00, 0x00, 0x02]);
+        let word = master.read_register_u16(&mut master_reader, &mut master_writer, 0x01, 0x24, Dialect::Dynamixel1.word_order(), || false).unwrap();
+        assert_eq!(word, 0x0200);
+
+        // A command rejected for its checksum is sent again.
+        send(0x01, &[0x10]);
+        send(0x01, &[0x00, 0x34, 0x12]);
+        let flags = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x24, &mut data, || false).unwrap();
+        assert_eq!((data, flags), ([0x34, 0x12], ServoStatusFlags(0x00)));
+
+        // A value out of the range is not.
+        send(0x01, &[0x08]);
+        let command = WriteRegisterCommand::<16>::builder(0x01).address(0x1e).data(&[0xff, 0x7f]).build().unwrap();
+        let result = master.write_register(&mut master_reader, &mut master_writer, &command, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::Rejected(ServoStatusFlags(0x08)))));
+        assert!(!result.unwrap_err().is_transient());
+
+        // SCS servos use the bits for alarms.
+        master.set_dialect(Dialect::Scs);
+        send(0x01, &[0x08]);
+        master.write_register(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(master.response_status(), Some(ServoStatusFlags(0x08)));
+
+        // Words are written in the order of the servos, e.g. the low byte first for STS servos.
+        while slave_reader.try_recv().is_ok() {}
+        send(0x01, &[0x00]);
+        let command = WriteRegisterCommand::<{ write_command_size(2) }>::word(0x01, 0x2a, 0x0800, WordOrder::LittleEndian).unwrap();
+        master.write_register(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().skip(5).take(3).collect::<std::vec::Vec<_>>(), [0x2a, 0x00, 0x08]);
+    }
+
+    #[test]
+    fn test_protocol_master_mismatch() {
+        static MISMATCHED: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let late = [0xff, 0xff, 0x02, 0x03, 0x00, 0x34, 0xc6];
+        let valid = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        let mut data = [0; 1];
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        for byte in late.iter().chain(valid.iter()) {
+            slave_writer.send(*byte).unwrap();
+        }
+        let result = master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::UnexpectedPacketId(0x02))));
+        while master_reader.try_recv().is_ok() {}
+
+        // The late response is reported and skipped, and the response of the addressed servo is taken.
+        let mismatch = MismatchPolicy {
+            skip: true,
+            hook: Some(|id, packet| {
+                assert_eq!((id, packet.id_unchecked()), (0x01, 0x02));
+                MISMATCHED.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            }),
+        };
+        master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().mismatch(mismatch).build());
+        for byte in late.iter().chain(valid.iter()) {
+            slave_writer.send(*byte).unwrap();
+        }
+        master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).unwrap();
+        assert_eq!(data, [0x12]);
+        assert_eq!(MISMATCHED.load(core::sync::atomic::Ordering::Relaxed), 1);
+
+        // Skipping does not extend the deadline.
+        for byte in late {
+            slave_writer.send(byte).unwrap();
+        }
+        let mut polls = 0;
+        let result = master.ping(&mut master_reader, &mut master_writer, 0x01, || { polls += 1; polls > 4 });
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        assert_eq!(MISMATCHED.load(core::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_protocol_master_config_builder() {
+        let config = ProtocolMasterConfig::builder()
+            .echo_back(true)
+            .inter_byte_timeout(Duration::from_nanos(1_550_900))
+            .frame_gap(Duration::from_secs(1))
+            .build();
+        assert!(config.echo_back());
+        assert_eq!(config.inter_byte_timeout(), Duration::from_micros(1550));
+        assert_eq!(config.frame_gap(), Duration::from_secs(1));
+        assert_eq!(ProtocolMasterConfig::builder().frame_gap(Duration::from_micros(50)).build().frame_gap(), Duration::from_micros(50));
+        // Options which are not set keep those of the config the builder starts from.
+        let config = config.to_builder().retry(RetryPolicy { retries: 2, backoff_ms: 0 }).build();
+        assert!(config.echo_back() && config.retry().retries == 2);
+        assert_eq!(config.broadcast(), BroadcastPolicy::Send);
+
+        // Writes to the broadcast ID are not sent.
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel::<u8>();
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().broadcast(BroadcastPolicy::Deny).build());
+        let command = WriteRegisterCommand::<16>::builder(BROADCAST_ID).address(0x05).data(&[0x02]).build().unwrap();
+        let result = master.write_register(&mut master_reader, &mut master_writer, &command, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::BroadcastDenied)));
+        let result = master.write_register_no_response(&mut master_reader, &mut master_writer, &command, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::BroadcastDenied)));
+        assert_eq!(slave_reader.try_iter().count(), 0);
+        // ACTION is always a broadcast.
+        master.action(&mut master_reader, &mut master_writer, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().count(), 6);
+    }
+
+    #[test]
+    fn test_protocol_master_response_level() {
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel::<u8>();
+        // Servos which only answer reads complete writes once they are sent, but pings still wait for the response.
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        assert_eq!(master.response_level(), ResponseLevel::All);
+        master.set_response_level(ResponseLevel::ReadsOnly);
+        assert_eq!(master.response_level(), ResponseLevel::ReadsOnly);
+        let command = WriteRegisterCommand::<16>::builder(0x01).address(0x28).data(&[0x01]).build().unwrap();
+        master.write_register(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), command.packet());
+        let mut polls = 0;
+        let result = master.ping(&mut master_reader, &mut master_writer, 0x01, || { polls += 1; polls > 10 });
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+    }
+
+    #[test]
+    fn test_protocol_master_purge() {
+        /// Reader which has the rest of a response which timed out in its buffer before the next response arrives.
+        struct StaleReader {
+            stale: std::collections::VecDeque<u8>,
+            response: std::collections::VecDeque<u8>,
+            purges: usize,
+        }
+        impl StreamReader for StaleReader {
+            type Error = ();
+            fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+                let source = if self.stale.is_empty() { &mut self.response } else { &mut self.stale };
+                let length = data.len().min(source.len());
+                for (slot, byte) in data.iter_mut().zip(source.drain(..length)) {
+                    *slot = byte;
+                }
+                if length == 0 { Err(nb::Error::WouldBlock) } else { Ok(length) }
+            }
+            fn purge(&mut self) -> Result<(), Self::Error> {
+                self.purges += 1;
+                self.stale.clear();
+                Ok(())
+            }
+        }
+
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let mut reader = StaleReader {
+            stale: [0xff, 0xff, 0x02, 0x02, 0x00, 0xfb].into(),
+            response: [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc].into(),
+            purges: 0,
+        };
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        master.ping(&mut reader, &mut master_writer, 0x01, || false).unwrap();
+        assert_eq!(reader.purges, 1);
+    }
+
+    #[test]
+    fn test_protocol_master_partial_response() {
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        // The response is cut off when the transaction times out.
+        for byte in [0xff, 0xff, 0x01, 0x02] {
+            slave_writer.send(byte).unwrap();
+        }
+        let mut polls = 0;
+        let result = master.ping(&mut master_reader, &mut master_writer, 0x01, || { polls += 1; polls > 10 });
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        // The next transaction starts over instead of completing the partial response with its own.
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc] {
+            slave_writer.send(byte).unwrap();
+        }
+        master.ping(&mut master_reader, &mut master_writer, 0x01, || false).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_protocol_master_config_serde() {
+        // Options added later take their default in configs saved before.
+        let config = serde_json::from_str::<ProtocolMasterConfig>(r#"{"echo_back":true,"retry":{"retries":1,"backoff_ms":5}}"#).unwrap();
+        assert!(config.echo_back());
+        assert_eq!(config.retry(), RetryPolicy { retries: 1, backoff_ms: 5 });
+        assert_eq!((config.inter_byte_timeout(), config.frame_gap(), config.broadcast()), (Duration::ZERO, Duration::ZERO, BroadcastPolicy::Send));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_protocol_master_async() {
+        use crate::testing::block_on;
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let ack = [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc];
+        let corrupted = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0x00];
+        let valid = [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9];
+        for byte in ack.iter().chain(ack.iter()).chain(corrupted.iter()).chain(valid.iter()) {
+            slave_writer.send(*byte).unwrap();
+        }
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().retry(RetryPolicy { retries: 1, backoff_ms: 0 }).build());
+        block_on(async {
+            let status = master.ping_async(&mut master_reader, &mut master_writer, 0x01, || false).await.unwrap();
+            assert!(status.is_ok());
+            assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0x01, 0x02, 0x01, 0xfb]);
+
+            let mut command = WriteRegisterCommand::<16>::new(0x01, 0x2a, 1);
+            command.body_mut().copy_from_slice(&[0x12]);
+            command.update_checksum().unwrap();
+            master.write_register_async(&mut master_reader, &mut master_writer, &command, || false).await.unwrap();
+            assert_eq!(slave_reader.try_iter().count(), 8);
+
+            // The corrupted response is retried as by the blocking master.
+            let mut data = [0; 1];
+            master.read_register_async(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || false).await.unwrap();
+            assert_eq!(data, [0x12]);
+            assert_eq!(slave_reader.try_iter().count(), 2 * 8);
+
+            let result = master.read_register_async(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, || true).await;
+            assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+            slave_reader.try_iter().count();
+
+            let mut command = SyncWriteCommand::<{ sync_write_command_size(2, 2) }>::new(0x2a, 2);
+            command.push(0x01, &[0x01, 0x00]);
+            command.push(0x02, &[0x02, 0x00]);
+            command.update_checksum().unwrap();
+            master.sync_write_async(&mut master_reader, &mut master_writer, &command, || false).await.unwrap();
+            assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0xfe, 0x0a, 0x83, 0x2a, 0x02, 0x01, 0x01, 0x00, 0x02, 0x02, 0x00, 0x42]);
+
+            // Ranges are checked before anything is sent as by the blocking master.
+            let (mut empty, mut word) = ([0; 0], [0; 2]);
+            let result = master.read_register_ranges_async(&mut master_reader, &mut master_writer, 0x01, &mut [(0x2a, &mut empty[..])], || false).await;
+            assert!(matches!(result, Err(ProtocolHandlerError::UnexpectedLength(0))));
+            let result = master.read_register_ranges_async(&mut master_reader, &mut master_writer, 0x01, &mut [(0x2a, &mut data[..]), (0xff, &mut word[..])], || false).await;
+            assert!(matches!(result, Err(ProtocolHandlerError::UnexpectedLength(2))));
+            assert_eq!(slave_reader.try_iter().count(), 0);
+
+            // A broadcast write cannot be read back, so it is not sent.
+            let command = WriteRegisterCommand::<{ write_command_size(1) }>::builder(BROADCAST_ID).address(0x2a).data(&[0x12]).build().unwrap();
+            let result = master.write_register_verified_async(&mut master_reader, &mut master_writer, &command, || false).await;
+            assert!(matches!(result, Err(ProtocolHandlerError::BroadcastDenied)));
+            assert_eq!(slave_reader.try_iter().count(), 0);
+        });
+    }
+
+    #[test]
+    fn test_protocol_master_broadcast_write() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel::<u8>();
+        let mut command = WriteRegisterCommand::<{ write_command_size(1) }>::new(BROADCAST_ID, 0x28, 1);
+        command.body_mut()[0] = 0x01;
+        command.update_checksum().unwrap();
+        // The timeout never expires, so waiting for a response would hang.
+        master.write_register(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().count(), command.len());
+    }
+
+    #[test]
+    fn test_command_builders() {
+        let command = WriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        assert_eq!(command.packet(), [0xff, 0xff, 0x01, 0x05, 0x03, 0x2a, 0x01, 0x00, 0xcb]);
+        assert!(matches!(WriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).data(&[0; 3]).build(), Err(PacketError::InvalidLength)));
+        assert!(matches!(RegWriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).data(&[0; 3]).build(), Err(PacketError::InvalidLength)));
+        // The length field describes at most 252 data bytes, however large the command is.
+        assert!(WriteRegisterCommand::<{ write_command_size(253) }>::builder(0x01).data(&[0; 252]).build().is_ok());
+        assert!(matches!(WriteRegisterCommand::<{ write_command_size(253) }>::builder(0x01).data(&[0; 253]).build(), Err(PacketError::InvalidLength)));
+
+        type SyncWrite = SyncWriteCommand<{ sync_write_command_size(2, 2) }>;
+        let command = SyncWrite::builder(0x2a, 2).servo(0x01, &[0x01, 0x00]).servo(0x02, &[0x02, 0x00]).build().unwrap();
+        assert_eq!(command.packet(), [0xff, 0xff, 0xfe, 0x0a, 0x83, 0x2a, 0x02, 0x01, 0x01, 0x00, 0x02, 0x02, 0x00, 0x42]);
+        assert!(matches!(SyncWrite::builder(0x2a, 2).servo(0x01, &[0x01]).build(), Err(PacketError::InvalidLength)));
+        assert!(matches!(SyncWrite::builder(0x2a, 2).servo(1, &[0; 2]).servo(2, &[0; 2]).servo(3, &[0; 2]).build(), Err(PacketError::InvalidLength)));
+        assert!(matches!(SyncWrite::builder(0x2a, 8).build(), Err(PacketError::InvalidLength)));
+        // Servos are split into as many commands as needed.
+        let targets = (1..=5).map(|id| (id, [id, 0x00]));
+        let commands = SyncWrite::split(0x2a, 2, targets).unwrap().collect::<std::vec::Vec<_>>();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[2].as_ref().unwrap().entries().collect::<std::vec::Vec<_>>(), [(5, &[5, 0x00][..])]);
+        assert_eq!(commands[0].as_ref().unwrap().packet(), SyncWrite::builder(0x2a, 2).servo(1, &[1, 0]).servo(2, &[2, 0]).build().unwrap().packet());
+        let mut commands = SyncWrite::split(0x2a, 2, [(1, &[0x01, 0x00][..]), (2, &[0x02][..])]).unwrap();
+        assert!(matches!(commands.next(), Some(Err(PacketError::InvalidLength))));
+        assert!(commands.next().is_none());
+        assert!(matches!(SyncWrite::split(0x2a, 8, [(1, [0; 8])]), Err(PacketError::InvalidLength)));
+        // The length field limits a servo to 250 bytes, however large the command is.
+        assert!(SyncWriteCommand::<512>::builder(0x00, 250).servo(0x01, &[0; 250]).build().is_ok());
+        assert!(matches!(SyncWriteCommand::<512>::builder(0x00, 251).build(), Err(PacketError::InvalidLength)));
+        assert!(matches!(SyncWriteCommand::<512>::builder(0x00, 300).build(), Err(PacketError::InvalidLength)));
+    }
+
+    #[test]
+    fn test_write_register_command_ref() {
+        let mut buffer = [0; 16];
+        let command = WriteRegisterCommandRef::new(&mut buffer, 0x01, 0x2a, &[0x01, 0x00]).unwrap();
+        assert_eq!(command.packet(), [0xff, 0xff, 0x01, 0x05, 0x03, 0x2a, 0x01, 0x00, 0xcb]);
+        assert_eq!((command.id(), command.address(), command.body()), (0x01, 0x2a, &[0x01, 0x00][..]));
+        assert_eq!(std::format!("{:?}", command), "WriteRegisterCommandRef(ID 1 WRITE 0x2a: 01 00)");
+        assert!(matches!(WriteRegisterCommandRef::new(&mut buffer[..8], 0x01, 0x2a, &[0x01, 0x00]), Err(PacketError::InvalidLength)));
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let mut command = WriteRegisterCommandRef::new(&mut buffer, BROADCAST_ID, 0x2a, &[0x01, 0x00]).unwrap();
+        command.body_mut()[0] = 0x02;
+        command.update_checksum().unwrap();
+        master.write_register(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), command.packet());
+        // The packet is built in place.
+        assert_eq!(command.into_packet().as_ptr(), buffer.as_ptr());
+    }
+
+    #[test]
+    fn test_command_format() {
+        use std::format;
+        assert_eq!(format!("{}", ReadRegisterCommand::new(0x01, 0x38, 2)), "ID 1 READ 0x38: 02");
+        assert_eq!(format!("{:?}", PingCommand::new(0x02)), "PingCommand(ID 2 PING)");
+        let command = RegWriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        assert_eq!(format!("{}", command), "ID 1 REG WRITE 0x2a: 01 00");
+        let command = SyncWriteCommand::<{ sync_write_command_size(1, 2) }>::builder(0x2a, 1).servo(0x01, &[0x01]).servo(0x02, &[0x02]).build().unwrap();
+        assert_eq!(format!("{}", command), "ID 254 SYNC WRITE 0x2a: 01 01 01 02 02");
+
+        let error = ProtocolHandlerError::<(), ()>::VerificationFailed { observed: [0x01; MAX_VERIFY_LENGTH], length: 2 };
+        assert_eq!(format!("{:?}", error), "VerificationFailed { observed: [01 01] }");
+        assert_eq!(format!("{:?}", ProtocolHandlerError::<(), ()>::WriteProtected(0x05)), "WriteProtected(0x05)");
+    }
+
+    #[test]
+    fn test_parsed_instruction() {
+        assert_eq!(ParsedInstruction::parse(&PacketReader::new(&PingCommand::new(0x01).packet()[2..])).unwrap(), ParsedInstruction::Ping);
+        let command = ReadRegisterCommand::new(0x01, 0x38, 2);
+        assert_eq!(ParsedInstruction::parse(&PacketReader::new(&command.packet()[2..])).unwrap(), ParsedInstruction::ReadRegister { address: 0x38, length: 2 });
+        let command = RegWriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        assert_eq!(ParsedInstruction::parse(&PacketReader::new(&command.packet()[2..])).unwrap(), ParsedInstruction::RegWrite { address: 0x2a, data: &[0x01, 0x00] });
+
+        let command = SyncWriteCommand::<{ sync_write_command_size(1, 2) }>::builder(0x2a, 1).servo(0x01, &[0x10]).servo(0x02, &[0x20]).build().unwrap();
+        let instruction = ParsedInstruction::parse(&command.reader()).unwrap();
+        assert_eq!(instruction.command(), Command::SyncWrite);
+        assert!(instruction.sync_write_entries().eq([(0x01, &[0x10][..]), (0x02, &[0x20][..])]));
+
+        // A truncated SYNC WRITE entry, parameters missing from a READ and an undefined instruction.
+        assert!(matches!(ParsedInstruction::parse(&PacketReader::new(&[0xfe, 0x06, 0x83, 0x2a, 0x02, 0x01, 0x10, 0x00])), Err(PacketError::InvalidLength)));
+        assert!(matches!(ParsedInstruction::parse(&PacketReader::new(&[0x01, 0x03, 0x02, 0x38, 0x00])), Err(PacketError::InvalidLength)));
+        assert!(matches!(ParsedInstruction::parse(&PacketReader::new(&[0x01, 0x02, 0x07, 0x00])), Err(PacketError::UnknownInstruction(0x07))));
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::format;
+        use std::string::ToString;
+        use core::error::Error;
+        let error = ProtocolHandlerError::<std::io::Error, std::io::Error>::ProtocolReaderError(ProtocolReaderError::PacketError(PacketError::InvalidChecksum));
+        assert_eq!(error.to_string(), "failed to receive a response");
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "invalid packet received");
+        assert_eq!(source.source().unwrap().to_string(), "packet checksum mismatch");
+
+        let error = crate::device::Error::ProtocolError(ProtocolHandlerError::<std::io::Error, std::io::Error>::ReaderError(std::io::ErrorKind::BrokenPipe.into()));
+        let chain = std::iter::successors(Some(&error as &(dyn Error + 'static)), |&error| error.source()).map(|error| error.to_string()).collect::<std::vec::Vec<_>>();
+        assert_eq!(chain, ["servo transaction failed", "failed to read from the bus", "broken pipe"]);
+        assert_eq!(format!("{}", ProtocolHandlerError::<(), ()>::UnexpectedPacketId(3)), "unexpected response from ID 3");
+    }
+
+    #[test]
+    fn test_protocol_master_write_verified() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let command = WriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        // The status of the write and the registers read back.
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc, 0xff, 0xff, 0x01, 0x04, 0x00, 0x01, 0x00, 0xf9] {
+            slave_writer.send(byte).unwrap();
+        }
+        master.write_register_verified(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().skip(command.len()).collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0x01, 0x04, 0x02, 0x2a, 0x02, 0xcc]);
+
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc, 0xff, 0xff, 0x01, 0x04, 0x00, 0x01, 0xff, 0xfa] {
+            slave_writer.send(byte).unwrap();
+        }
+        match master.write_register_verified(&mut master_reader, &mut master_writer, &command, || false) {
+            Err(ProtocolHandlerError::VerificationFailed { observed, length }) => assert_eq!(observed[..length], [0x01, 0xff]),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let command = WriteRegisterCommand::<{ write_command_size(MAX_VERIFY_LENGTH + 1) }>::builder(0x01).data(&[0; MAX_VERIFY_LENGTH + 1]).build().unwrap();
+        slave_reader.try_iter().count();
+        let result = master.write_register_verified(&mut master_reader, &mut master_writer, &command, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::UnexpectedLength(17))));
+        assert_eq!(slave_reader.try_iter().count(), 0);
+        let command = WriteRegisterCommand::<{ write_command_size(2) }>::builder(BROADCAST_ID).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        let result = master.write_register_verified(&mut master_reader, &mut master_writer, &command, || false);
+        assert!(matches!(result, Err(ProtocolHandlerError::BroadcastDenied)));
+        assert_eq!(slave_reader.try_iter().count(), 0);
+
+        // Both transactions keep the clock of the deadline, so a stalled read back ends at the inter-byte timeout.
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().inter_byte_timeout(Duration::from_millis(1)).build());
+        let command = WriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc, 0xff, 0xff, 0x01] {
+            slave_writer.send(byte).unwrap();
+        }
+        let start = std::time::Instant::now();
+        let result = master.write_register_verified(&mut master_reader, &mut master_writer, &command, timeout_after::<std::time::Instant>(Duration::from_secs(1)));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_protocol_master_timed() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let timeout = Duration::from_millis(10);
+        let mut data = [0; 1];
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9, 0xff, 0xff, 0x01, 0x02, 0x00, 0xfc] {
+            slave_writer.send(byte).unwrap();
+        }
+        master.read_register_timed::<std::time::Instant, _, _>(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, timeout).unwrap();
+        assert_eq!(data, [0x12]);
+        let command = WriteRegisterCommand::<{ write_command_size(1) }>::builder(0x01).address(0x28).data(&[0x01]).build().unwrap();
+        master.write_register_timed::<std::time::Instant, _, _, _>(&mut master_reader, &mut master_writer, &command, timeout).unwrap();
+
+        let start = std::time::Instant::now();
+        let result = master.read_register_timed::<std::time::Instant, _, _>(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, timeout);
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn test_protocol_master_frame_gap_deadline() {
+        // A frame gap longer than the deadline ends with it instead of holding the bus past it.
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::builder().frame_gap(Duration::from_secs(1)).build());
+        let (mut master_writer, _slave_reader) = std::sync::mpsc::channel();
+        let (_slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        let start = std::time::Instant::now();
+        let mut data = [0; 1];
+        let result = master.read_register_timed::<std::time::Instant, _, _>(&mut master_reader, &mut master_writer, 0x01, 0x2a, &mut data, Duration::from_millis(10));
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_protocol_master_reg_write() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        for byte in [0xff, 0xff, 0x01, 0x02, 0x00, 0xfc] {
+            slave_writer.send(byte).unwrap();
+        }
+
+        let command = RegWriteRegisterCommand::<{ write_command_size(2) }>::builder(0x01).address(0x2a).data(&[0x01, 0x00]).build().unwrap();
+        assert_eq!((command.id(), command.address(), command.body()), (0x01, 0x2a, &[0x01, 0x00][..]));
+        master.reg_write_register(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0x01, 0x05, 0x04, 0x2a, 0x01, 0x00, 0xca]);
+
+        // ACTION is broadcast and not answered.
+        master.action(&mut master_reader, &mut master_writer, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0xfe, 0x02, 0x05, 0xfa]);
+    }
+
+    #[test]
+    fn test_protocol_sync_write() {
+        let mut command = SyncWriteCommand::<{ sync_write_command_size(2, 2) }>::new(0x2a, 2);
+        assert!(command.push(0x01, &[0x01, 0x00]));
+        assert!(command.push(0x02, &[0x02, 0x00]));
+        assert!(command.is_full());
+        assert!(!command.push(0x03, &[0x03, 0x00]));
+        command.update_checksum().unwrap();
+        assert_eq!(command.count(), 2);
+        assert_eq!(command.entries().collect::<std::vec::Vec<_>>(), [(0x01, &[0x01, 0x00][..]), (0x02, &[0x02, 0x00][..])]);
+
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
+        let (mut slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        master.sync_write(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        assert_eq!(slave_reader.try_iter().collect::<std::vec::Vec<_>>(), [0xff, 0xff, 0xfe, 0x0a, 0x83, 0x2a, 0x02, 0x01, 0x01, 0x00, 0x02, 0x02, 0x00, 0x42]);
+
+        // The slave owning ID 2 and 3 handles the write to ID 2 and does not respond.
+        master.sync_write(&mut master_reader, &mut master_writer, &command, || false).unwrap();
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig { ids: IdSet::from_ids(&[0x02, 0x03]) });
+        let mut writes = std::vec::Vec::new();
+        for _ in 0..2 {
+            slave.process(&mut slave_reader, &mut slave_writer, |packet, buffer| {
+                assert!(packet.verify_checksum().is_ok());
+                writes.push((packet.id().unwrap(), packet.data().unwrap().to_vec()));
+                buffer[..6].copy_from_slice(&[0xff, 0xff, 0x02, 0x02, 0x00, 0xfb]);
+                Some(6)
+            }).unwrap();
+        }
+        assert_eq!(writes, [(0x02, std::vec![Command::WriteRegister as u8, 0x2a, 0x02, 0x00])]);
+        assert!(matches!(StreamReader::read(&mut master_reader, &mut [0]), Err(nb::Error::WouldBlock)));
+    }
+
+    #[test]
+    fn test_protocol_slave_sync_write() {
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig { ids: IdSet::from_ids(&[0x01, 0x03]) });
+        let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
+        let (mut slave_writer, master_reader) = std::sync::mpsc::channel();
+        let command = SyncWriteCommand::<32>::builder(0x2a, 2).servo(0x01, &[0x01, 0x00]).servo(0x02, &[0x02, 0x00]).servo(0x03, &[0x03, 0x00]).build().unwrap();
+        StreamWriter::write(&mut master_writer, command.packet()).unwrap();
+        let command = WriteRegisterCommand::<16>::builder(BROADCAST_ID).address(0x28).data(&[0x01]).build().unwrap();
+        StreamWriter::write(&mut master_writer, command.packet()).unwrap();
+
+        // The handler answers every packet, as for commands addressed to a single servo.
+        let mut handled = std::vec::Vec::new();
+        for _ in 0..6 {
+            slave.process(&mut slave_reader, &mut slave_writer, |packet, buffer| {
+                handled.push((packet.id().unwrap(), packet.data().unwrap().to_vec()));
+                buffer[..6].copy_from_slice(&[0xff, 0xff, packet.id().unwrap(), 0x02, 0x00, 0x00]);
+                Some(6)
+            }).unwrap();
+        }
+        assert_eq!(handled, [
+            (0x01, std::vec![Command::WriteRegister as u8, 0x2a, 0x01, 0x00]),
+            (0x03, std::vec![Command::WriteRegister as u8, 0x2a, 0x03, 0x00]),
+            (BROADCAST_ID, std::vec![Command::WriteRegister as u8, 0x28, 0x01]),
+        ]);
+        assert_eq!(master_reader.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_protocol_slave_ignores_other_ids() {
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig { ids: IdSet::from_ids(&[0x02]) });
+        let (mut master_writer, mut slave_reader) = std::sync::mpsc::channel();
+        let (mut slave_writer, _master_reader) = std::sync::mpsc::channel();
+        StreamWriter::write(&mut master_writer, &ReadRegisterCommand::new(0x01, 0x38, 2).raw).unwrap();
+        let mut called = false;
+        for _ in 0..3 {
+            slave.process(&mut slave_reader, &mut slave_writer, |_, _| {
+                called = true;
+                None
+            }).unwrap();
+        }
+        assert!(!called);
+    }
 }
\ No newline at end of file
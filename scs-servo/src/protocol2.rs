@@ -0,0 +1,181 @@
+//! Protocol 2.0 frames.
+//!
+//! Newer Feetech and Dynamixel-style servos frame packets with a 4-byte header, a 16-bit length and a CRC16, and
+//! widen register addresses and lengths to 16 bits. Commands and responses are kept in the SCS packet layout inside
+//! the crate: [`encode`] converts a command into a frame when it is written, and [`decode`] converts a received frame
+//! back, so [`ProtocolMaster`](crate::protocol::ProtocolMaster) and [`ParsedInstruction`] work on both formats. Select
+//! the format with [`ProtocolMaster::set_frame_format`](crate::protocol::ProtocolMaster::set_frame_format) or
+//! [`ProtocolReader::set_format`](crate::protocol::ProtocolReader::set_format).
+
+use crate::packet::{checksum, PacketError, PacketReader};
+use crate::protocol::Command;
+
+/// Markers and reserved byte which start a frame.
+pub const HEADER: [u8; 4] = [0xff, 0xff, 0xfd, 0x00];
+
+/// Instruction byte of a status packet, which is followed by the error byte and the parameters.
+pub const STATUS_INSTRUCTION: u8 = 0x55;
+
+/// Updates `crc` with `bytes`. The CRC of a frame covers everything from the header to the last parameter, as sent.
+pub fn crc16(crc: u16, bytes: &[u8]) -> u16 {
+    bytes.iter().fold(crc, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 })
+    })
+}
+
+/// Whether the parameters start with a 16-bit address and a 16-bit length in Protocol 2.0, or only with an address.
+fn widened_fields(instruction: u8) -> usize {
+    match Command::from_instruction(instruction) {
+        Some(Command::ReadRegister | Command::SyncRead | Command::SyncWrite) => 2,
+        Some(Command::WriteRegister | Command::RegWriteRegister) => 1,
+        _ => 0,
+    }
+}
+
+/// Writes the body of a frame, inserting a stuffing byte after every `ff ff fd` so that the body never contains
+/// the header.
+struct FrameWriter<'a> {
+    frame: &'a mut [u8],
+    position: usize,
+}
+
+impl FrameWriter<'_> {
+    const BODY_START: usize = 7;
+
+    fn push(&mut self, byte: u8) -> Result<(), PacketError> {
+        *self.frame.get_mut(self.position).ok_or(PacketError::InvalidLength)? = byte;
+        self.position += 1;
+        if self.position >= Self::BODY_START + 3 && self.frame[self.position - 3..self.position] == [0xff, 0xff, 0xfd] {
+            self.push(0xfd)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the SCS `packet` of a command, without the markers, into a Protocol 2.0 frame in `frame`. Returns the
+/// length of the frame, or fails with [`PacketError::InvalidLength`] if it does not fit in `frame`.
+pub fn encode(packet: &PacketReader, frame: &mut [u8]) -> Result<usize, PacketError> {
+    let id = packet.id()?;
+    let (instruction, parameters) = packet.data()?.split_first().ok_or(PacketError::InvalidLength)?;
+    let widened = widened_fields(*instruction);
+    if parameters.len() < widened {
+        return Err(PacketError::InvalidLength);
+    }
+    let mut writer = FrameWriter { frame, position: FrameWriter::BODY_START };
+    writer.push(*instruction)?;
+    for parameter in &parameters[..widened] {
+        writer.push(*parameter)?;
+        writer.push(0x00)?;
+    }
+    for parameter in &parameters[widened..] {
+        writer.push(*parameter)?;
+    }
+    let FrameWriter { frame, position } = writer;
+    let length = (position - FrameWriter::BODY_START + 2) as u16;
+    frame[..4].copy_from_slice(&HEADER);
+    frame[4] = id;
+    frame[5..7].copy_from_slice(&length.to_le_bytes());
+    let crc = crc16(0, &frame[..position]);
+    frame.get_mut(position..position + 2).ok_or(PacketError::InvalidLength)?.copy_from_slice(&crc.to_le_bytes());
+    Ok(position + 2)
+}
+
+/// Converts a frame received in `frame`, from the ID to the CRC, into an SCS packet in place, and returns the length
+/// of the packet. The instruction byte of a status packet is dropped, so responses hold the error byte and the
+/// parameters like SCS responses. A frame with a wrong CRC gets a wrong checksum. Fails with
+/// [`PacketError::InvalidLength`] if the length field does not match `frame`, or the packet cannot be represented in
+/// the SCS layout: an address or a length above 255, or more than 253 parameters.
+pub fn decode(frame: &mut [u8]) -> Result<usize, PacketError> {
+    let length = u16::from_le_bytes([*frame.get(1).ok_or(PacketError::InvalidHeader)?, *frame.get(2).ok_or(PacketError::InvalidHeader)?]) as usize;
+    if length < 3 || frame.len() != length + 3 {
+        return Err(PacketError::InvalidLength);
+    }
+    let crc_position = frame.len() - 2;
+    let crc_valid = crc16(crc16(0, &HEADER), &frame[..crc_position]) == u16::from_le_bytes([frame[crc_position], frame[crc_position + 1]]);
+
+    // Remove the stuffing bytes of the body.
+    let mut end = 3;
+    for index in 3..crc_position {
+        if !(end >= 6 && frame[end - 3..end] == [0xff, 0xff, 0xfd] && frame[index] == 0xfd) {
+            frame[end] = frame[index];
+            end += 1;
+        }
+    }
+
+    // Narrow the body into the SCS layout behind the ID and the length field. The bytes are only moved forward.
+    let instruction = frame[3];
+    let (start, widened) = if instruction == STATUS_INSTRUCTION { (4, 0) } else { (3, widened_fields(instruction)) };
+    if end - 4 < widened * 2 || (0..widened).any(|field| frame[5 + field * 2] != 0) {
+        return Err(PacketError::InvalidLength);
+    }
+    let mut position = 2;
+    for index in start..end {
+        if !(index > 3 && index < 4 + widened * 2 && (index - 4) % 2 == 1) {
+            frame[position] = frame[index];
+            position += 1;
+        }
+    }
+    if position - 2 + 1 > 255 {
+        return Err(PacketError::InvalidLength);
+    }
+    frame[1] = (position - 2 + 1) as u8;
+    let sum = checksum(&frame[..position]);
+    frame[position] = if crc_valid { sum } else { !sum };
+    Ok(position + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::{ParsedInstruction, PingCommand, ReadRegisterCommand, WriteRegisterCommand};
+
+    #[test]
+    fn test_protocol2_encode() {
+        let mut frame = [0; 32];
+        // PING of ID 1 from the Dynamixel Protocol 2.0 specification.
+        let length = encode(&PacketReader::new(&PingCommand::new(0x01).raw[2..]), &mut frame).unwrap();
+        assert_eq!(frame[..length], [0xff, 0xff, 0xfd, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4e]);
+
+        let length = encode(&PacketReader::new(&ReadRegisterCommand::new(0x01, 0x84, 4).raw[2..]), &mut frame).unwrap();
+        assert_eq!(frame[..length], [0xff, 0xff, 0xfd, 0x00, 0x01, 0x07, 0x00, 0x02, 0x84, 0x00, 0x04, 0x00, 0x1d, 0x15]);
+
+        // The data holds the header, which is stuffed.
+        let command = WriteRegisterCommand::<16>::builder(0x01).address(0x2a).data(&[0xff, 0xff, 0xfd, 0x01]).build().unwrap();
+        let length = encode(&command.reader(), &mut frame).unwrap();
+        assert_eq!(frame[5..length - 2], [0x0a, 0x00, 0x03, 0x2a, 0x00, 0xff, 0xff, 0xfd, 0xfd, 0x01]);
+        assert!(matches!(encode(&command.reader(), &mut frame[..length - 1]), Err(PacketError::InvalidLength)));
+
+        // The frame is converted back into the command.
+        let mut received = [0; 32];
+        received[..length - 4].copy_from_slice(&frame[4..length]);
+        let decoded = decode(&mut received[..length - 4]).unwrap();
+        assert_eq!(received[..decoded], command.packet()[2..]);
+        assert_eq!(
+            ParsedInstruction::parse(&PacketReader::new(&received[..decoded])).unwrap(),
+            ParsedInstruction::WriteRegister { address: 0x2a, data: &[0xff, 0xff, 0xfd, 0x01] },
+        );
+    }
+
+    #[test]
+    fn test_protocol2_decode() {
+        // Status of a PING from the specification: the model number and the firmware version.
+        let mut frame = [0x01, 0x07, 0x00, 0x55, 0x00, 0x06, 0x04, 0x26, 0x65, 0x5d];
+        let length = decode(&mut frame).unwrap();
+        let packet = PacketReader::new(&frame[..length]);
+        assert_eq!((packet.id().unwrap(), packet.data().unwrap()), (0x01, &[0x00, 0x06, 0x04, 0x26][..]));
+        assert!(packet.verify_checksum().is_ok());
+
+        let mut frame = [0x01, 0x07, 0x00, 0x55, 0x00, 0x06, 0x04, 0x26, 0x65, 0x5e];
+        let length = decode(&mut frame).unwrap();
+        assert!(PacketReader::new(&frame[..length]).verify_checksum().is_err());
+
+        // An address above 255 cannot be represented.
+        let mut frame = [0; 16];
+        let command = [0x01, 0x07, 0x00, 0x02, 0x00, 0x01, 0x02, 0x00];
+        frame[..8].copy_from_slice(&command);
+        let crc = crc16(crc16(0, &HEADER), &command);
+        frame[8..10].copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(decode(&mut frame[..10]), Err(PacketError::InvalidLength)));
+        assert!(matches!(decode(&mut frame[..9]), Err(PacketError::InvalidLength)));
+    }
+}
@@ -0,0 +1,267 @@
+//! Prioritized command queue with deadlines.
+//!
+//! [`CommandQueue`] buffers commands for a [`Bus`] and sends them in [`Priority`] order: safety commands
+//! first, then setpoints, telemetry reads and configuration writes. Within a priority, commands are sent
+//! in the order they were queued. A command can carry a maximum age after which it is dropped instead of
+//! sent late, and a setpoint queued for a register which already has one pending replaces it, so a
+//! congested bus skips stale setpoints instead of replaying them as delayed, jerky motion.
+
+use core::time::Duration;
+
+use crate::bus::{Bus, BusError, MAX_WRITE_LENGTH};
+use crate::device::{Instant, StatusBlock, Timer};
+use crate::policy::WritePolicy;
+use crate::protocol::{StreamReader, StreamWriter};
+
+/// Priorities from the lowest to the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Config,
+    Telemetry,
+    Setpoint,
+    Safety,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuedCommand {
+    /// Writes `length` bytes of `data` to the registers starting at `address`.
+    Write { id: u8, address: u8, data: [u8; MAX_WRITE_LENGTH], length: usize },
+    ReadStatus { id: u8 },
+}
+
+impl QueuedCommand {
+    /// Returns `None` if `data` is longer than `MAX_WRITE_LENGTH`.
+    pub fn write(id: u8, address: u8, data: &[u8]) -> Option<Self> {
+        if data.len() > MAX_WRITE_LENGTH {
+            return None;
+        }
+        let mut buffer = [0; MAX_WRITE_LENGTH];
+        buffer[..data.len()].copy_from_slice(data);
+        Some(QueuedCommand::Write { id, address, data: buffer, length: data.len() })
+    }
+    pub fn id(&self) -> u8 {
+        match self {
+            QueuedCommand::Write { id, .. } | QueuedCommand::ReadStatus { id } => *id,
+        }
+    }
+    /// Whether `other` targets the same registers, so the later one makes the earlier one obsolete.
+    fn supersedes(&self, other: &Self) -> bool {
+        match (self, other) {
+            (QueuedCommand::Write { id, address, length, .. }, QueuedCommand::Write { id: other_id, address: other_address, length: other_length, .. }) => {
+                id == other_id && address == other_address && length == other_length
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    /// The queue is full of commands with the same or a higher priority.
+    Full,
+}
+
+#[derive(Debug)]
+pub enum QueueEvent<E> {
+    Sent { command: QueuedCommand, priority: Priority },
+    /// The status read by a `ReadStatus` command.
+    Status { id: u8, status: StatusBlock },
+    /// The command was older than its maximum age and was not sent.
+    Expired { command: QueuedCommand, priority: Priority, age: Duration },
+    Failed { command: QueuedCommand, priority: Priority, error: E },
+}
+
+struct Entry<I> {
+    command: QueuedCommand,
+    priority: Priority,
+    queued_at: I,
+    max_age: Option<Duration>,
+    sequence: u32,
+}
+
+pub struct CommandQueue<T: Timer, const N: usize> {
+    entries: [Option<Entry<T::Instant>>; N],
+    next_sequence: u32,
+}
+
+impl<T: Timer, const N: usize> Default for CommandQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Timer, const N: usize> CommandQueue<T, N> {
+    pub fn new() -> Self {
+        Self {
+            entries: core::array::from_fn(|_| None),
+            next_sequence: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|entry| entry.is_some()).count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|entry| entry.is_none())
+    }
+    pub fn clear(&mut self) {
+        self.entries = core::array::from_fn(|_| None);
+    }
+
+    /// Queues `command`. A command with a `max_age` is dropped if it has not been sent within it.
+    ///
+    /// A pending write to the same registers with the same priority is replaced. If the queue is full, the oldest
+    /// command with the lowest priority below `priority` is evicted. The replaced or evicted command is returned.
+    pub fn push(&mut self, command: QueuedCommand, priority: Priority, max_age: Option<Duration>) -> Result<Option<QueuedCommand>, QueueError> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let entry = Entry { command, priority, queued_at: T::now(), max_age, sequence };
+
+        let superseded = self.entries.iter().position(|slot| {
+            slot.as_ref().is_some_and(|queued| queued.priority == priority && command.supersedes(&queued.command))
+        });
+        let slot = match superseded {
+            Some(index) => Some(index),
+            None => self.entries.iter().position(|slot| slot.is_none()).or_else(|| {
+                self.entries.iter().enumerate()
+                    .filter_map(|(index, slot)| slot.as_ref().map(|queued| (index, queued)))
+                    .filter(|(_, queued)| queued.priority < priority)
+                    .min_by_key(|(_, queued)| (queued.priority, queued.sequence))
+                    .map(|(index, _)| index)
+            }),
+        };
+        match slot {
+            Some(index) => Ok(self.entries[index].replace(entry).map(|previous| previous.command)),
+            None => Err(QueueError::Full),
+        }
+    }
+
+    /// Removes the command to send next: the oldest one with the highest priority.
+    fn pop(&mut self) -> Option<Entry<T::Instant>> {
+        let index = self.entries.iter().enumerate()
+            .filter_map(|(index, slot)| slot.as_ref().map(|queued| (index, queued)))
+            .min_by_key(|(_, queued)| (core::cmp::Reverse(queued.priority), queued.sequence))
+            .map(|(index, _)| index)?;
+        self.entries[index].take()
+    }
+
+    /// Sends the next command which has not expired. Expired commands on the way are reported and dropped.
+    /// Returns whether a command was sent.
+    pub fn service_one<R, W, const BUFFER_SIZE: usize, P, OnEvent>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, on_event: &mut OnEvent) -> bool
+        where R: StreamReader,
+              W: StreamWriter,
+              P: WritePolicy,
+              OnEvent: FnMut(QueueEvent<BusError<R, W>>),
+    {
+        while let Some(entry) = self.pop() {
+            let Entry { command, priority, queued_at, max_age, .. } = entry;
+            let age = queued_at.elapsed();
+            if max_age.is_some_and(|max_age| age > max_age) {
+                on_event(QueueEvent::Expired { command, priority, age });
+                continue;
+            }
+            let result = match command {
+                QueuedCommand::Write { id, address, data, length } => bus.write_register(id, address, &data[..length]),
+                QueuedCommand::ReadStatus { id } => bus.read_status_block(id).map(|status| on_event(QueueEvent::Status { id, status })),
+            };
+            match result {
+                Ok(()) => on_event(QueueEvent::Sent { command, priority }),
+                Err(error) => on_event(QueueEvent::Failed { command, priority, error }),
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Sends up to `max_commands` commands. Returns the number of commands sent.
+    pub fn service<R, W, const BUFFER_SIZE: usize, P, OnEvent>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, max_commands: usize, mut on_event: OnEvent) -> usize
+        where R: StreamReader,
+              W: StreamWriter,
+              P: WritePolicy,
+              OnEvent: FnMut(QueueEvent<BusError<R, W>>),
+    {
+        let mut sent = 0;
+        while sent < max_commands && self.service_one(bus, &mut on_event) {
+            sent += 1;
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::device::scs0009::{REGISTER_TARGET_POSITION_H, REGISTER_TORQUE_SWITCH, WORD_ORDER};
+    use crate::device::SimTimer;
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    extern crate std;
+    use std::vec::Vec;
+
+    fn position(id: u8, position: u16) -> QueuedCommand {
+        QueuedCommand::write(id, REGISTER_TARGET_POSITION_H.address, &WORD_ORDER.to_bytes(position)).unwrap()
+    }
+
+    #[test]
+    fn test_queue_push() {
+        let mut queue = CommandQueue::<SimTimer, 3>::new();
+        queue.push(QueuedCommand::ReadStatus { id: 1 }, Priority::Telemetry, None).unwrap();
+        queue.push(position(1, 0x100), Priority::Setpoint, None).unwrap();
+        // A newer setpoint for the same registers replaces the pending one.
+        assert_eq!(queue.push(position(1, 0x200), Priority::Setpoint, None), Ok(Some(position(1, 0x100))));
+        assert_eq!(queue.len(), 2);
+        queue.push(position(2, 0x100), Priority::Setpoint, None).unwrap();
+        // Full: the telemetry read makes room for a safety command, but nothing makes room for a setpoint.
+        let stop = QueuedCommand::write(1, REGISTER_TORQUE_SWITCH.address, &[0]).unwrap();
+        assert_eq!(queue.push(stop, Priority::Safety, None), Ok(Some(QueuedCommand::ReadStatus { id: 1 })));
+        assert_eq!(queue.push(position(3, 0x100), Priority::Setpoint, None), Err(QueueError::Full));
+        assert_eq!(queue.push(QueuedCommand::ReadStatus { id: 2 }, Priority::Telemetry, None), Err(QueueError::Full));
+        assert!(QueuedCommand::write(1, 0, &[0; MAX_WRITE_LENGTH + 1]).is_none());
+    }
+
+    #[test]
+    fn test_queue_service() {
+        SimTimer::reset();
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<2>::new(1, 2));
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_secs(1),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, SimTimer>::new(master_reader, master_writer, config);
+
+        let mut queue = CommandQueue::<SimTimer, 8>::new();
+        queue.push(QueuedCommand::write(1, REGISTER_TORQUE_SWITCH.address, &[1]).unwrap(), Priority::Config, None).unwrap();
+        queue.push(position(1, 0x300), Priority::Setpoint, Some(Duration::from_millis(10))).unwrap();
+        SimTimer::advance(Duration::from_millis(20));
+        queue.push(QueuedCommand::ReadStatus { id: 2 }, Priority::Telemetry, None).unwrap();
+        queue.push(position(2, 0x280), Priority::Setpoint, Some(Duration::from_millis(10))).unwrap();
+        queue.push(QueuedCommand::write(2, REGISTER_TORQUE_SWITCH.address, &[0]).unwrap(), Priority::Safety, None).unwrap();
+
+        let mut events = Vec::new();
+        assert_eq!(queue.service(&mut bus, 8, |event| events.push(event)), 4);
+        assert!(queue.is_empty());
+        let summary = events.iter().map(|event| match event {
+            QueueEvent::Sent { command, priority } => (command.id(), *priority, "sent"),
+            QueueEvent::Status { id, .. } => (*id, Priority::Telemetry, "status"),
+            QueueEvent::Expired { command, priority, .. } => (command.id(), *priority, "expired"),
+            QueueEvent::Failed { command, priority, .. } => (command.id(), *priority, "failed"),
+        }).collect::<Vec<_>>();
+        assert_eq!(summary, [
+            (2, Priority::Safety, "sent"),
+            (1, Priority::Setpoint, "expired"),
+            (2, Priority::Setpoint, "sent"),
+            (2, Priority::Telemetry, "status"),
+            (2, Priority::Telemetry, "sent"),
+            (1, Priority::Config, "sent"),
+        ]);
+
+        let emulator = thread.stop();
+        let target = |id| emulator.servo(id).unwrap().registers()[REGISTER_TARGET_POSITION_H.address as usize];
+        assert_eq!(target(2), 0x02);
+        assert_eq!(emulator.servo(1).unwrap().registers()[REGISTER_TORQUE_SWITCH.address as usize], 1);
+        assert_ne!(target(1), 0x03);
+    }
+}
@@ -0,0 +1,200 @@
+//! Recovery of servos which restarted.
+//!
+//! A dip of the supply voltage resets a servo: it stops answering for a moment and comes back with its RAM
+//! registers at the power-on values, i.e. limp and at the default speed. [`Bus`](crate::bus::Bus) keeps the
+//! volatile configuration written to each servo in a [`ShadowCache`]. When a servo which timed out answers
+//! again, the bus checks that the same servo answers at the ID, writes the cached configuration back and
+//! reports a [`ServoRestarted`] event, see [`Bus::take_restart`](crate::bus::Bus::take_restart).
+//!
+//! A restart between two transactions, without a timeout in between, goes unnoticed.
+
+use core::time::Duration;
+
+use crate::device::scs0009::{REGISTER_TARGET_SPEED_L, REGISTER_TORQUE_SWITCH, VOLATILE_REGISTERS};
+use crate::eventlog::Outcome;
+use crate::protocol::BROADCAST_ID;
+
+const SHADOW_BASE: u8 = REGISTER_TORQUE_SWITCH.address;
+const SHADOW_LENGTH: usize = (REGISTER_TARGET_SPEED_L.address - SHADOW_BASE) as usize + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreResult {
+    /// The cached configuration was written back.
+    Restored,
+    /// A servo with another ID or software version answered. Nothing was written.
+    Mismatch { id: u8, version: [u8; 2] },
+    /// The servo could not be checked or the configuration could not be written.
+    Failed(Outcome),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServoRestarted {
+    pub id: u8,
+    /// Time the servo answered again since the creation of the bus.
+    pub timestamp: Duration,
+    pub result: RestoreResult,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ShadowEntry {
+    id: u8,
+    values: [u8; SHADOW_LENGTH],
+    /// Bit n is set if `values[n]` was written.
+    valid: u8,
+    version: Option<[u8; 2]>,
+    lost: bool,
+    restarted: Option<ServoRestarted>,
+}
+
+/// Volatile configuration last written to up to `N` servos.
+pub struct ShadowCache<const N: usize> {
+    entries: [Option<ShadowEntry>; N],
+}
+
+impl<const N: usize> Default for ShadowCache<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_volatile(address: usize) -> bool {
+    VOLATILE_REGISTERS.iter().any(|register| register.address as usize == address)
+}
+
+impl<const N: usize> ShadowCache<N> {
+    pub const fn new() -> Self {
+        Self { entries: [None; N] }
+    }
+
+    fn entry(&self, id: u8) -> Option<&ShadowEntry> {
+        self.entries.iter().flatten().find(|entry| entry.id == id)
+    }
+    fn entry_mut(&mut self, id: u8) -> Option<&mut ShadowEntry> {
+        self.entries.iter_mut().flatten().find(|entry| entry.id == id)
+    }
+
+    /// Starts watching servo `id` for restarts. `version` is the software version the servo must report
+    /// when it comes back, if known. Returns false if the cache is full.
+    pub fn track(&mut self, id: u8, version: Option<[u8; 2]>) -> bool {
+        if let Some(entry) = self.entry_mut(id) {
+            entry.version = version.or(entry.version);
+            return true;
+        }
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(slot) => {
+                *slot = Some(ShadowEntry { id, values: [0; SHADOW_LENGTH], valid: 0, version, lost: false, restarted: None });
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn untrack(&mut self, id: u8) {
+        for slot in self.entries.iter_mut() {
+            if slot.is_some_and(|entry| entry.id == id) {
+                *slot = None;
+            }
+        }
+    }
+    pub fn is_tracked(&self, id: u8) -> bool {
+        self.entry(id).is_some()
+    }
+    /// Software version servo `id` must report after a restart.
+    pub fn version(&self, id: u8) -> Option<[u8; 2]> {
+        self.entry(id)?.version
+    }
+
+    /// Cached value of the volatile register at `address` of servo `id`.
+    pub fn value(&self, id: u8, address: u8) -> Option<u8> {
+        let entry = self.entry(id)?;
+        let offset = (address as usize).checked_sub(SHADOW_BASE as usize).filter(|offset| *offset < SHADOW_LENGTH)?;
+        (entry.valid & (1 << offset) != 0).then_some(entry.values[offset])
+    }
+
+    /// Caches the volatile registers in a write which reached the servo.
+    /// Servos are tracked from the first write to one of their volatile registers; broadcast writes update all of them.
+    pub fn record_write(&mut self, id: u8, address: u8, data: &[u8]) {
+        let touches_volatile = (0..data.len()).any(|offset| is_volatile(address as usize + offset));
+        if !touches_volatile {
+            return;
+        }
+        if id != BROADCAST_ID {
+            self.track(id, None);
+        }
+        for entry in self.entries.iter_mut().flatten().filter(|entry| id == BROADCAST_ID || entry.id == id) {
+            for (offset, value) in data.iter().enumerate() {
+                let register = address as usize + offset;
+                if is_volatile(register) {
+                    let index = register - SHADOW_BASE as usize;
+                    entry.values[index] = *value;
+                    entry.valid |= 1 << index;
+                }
+            }
+        }
+    }
+
+    /// Cached registers of servo `id` as `(address, value)` in the order they are restored.
+    pub fn saved(&self, id: u8) -> impl Iterator<Item = (u8, u8)> + '_ {
+        VOLATILE_REGISTERS.iter().filter_map(move |register| Some((register.address, self.value(id, register.address)?)))
+    }
+
+    /// Marks servo `id` as not answering.
+    pub(crate) fn mark_lost(&mut self, id: u8) {
+        if let Some(entry) = self.entry_mut(id) {
+            entry.lost = true;
+        }
+    }
+    /// Marks servo `id` as answering. Returns whether it was lost before.
+    pub(crate) fn mark_answered(&mut self, id: u8) -> bool {
+        match self.entry_mut(id) {
+            Some(entry) => core::mem::replace(&mut entry.lost, false),
+            None => false,
+        }
+    }
+    pub(crate) fn set_restarted(&mut self, event: ServoRestarted) {
+        if let Some(entry) = self.entry_mut(event.id) {
+            entry.restarted = Some(event);
+        }
+    }
+
+    /// Takes a pending restart event. Only the last restart of each servo is kept.
+    pub fn take_restart(&mut self) -> Option<ServoRestarted> {
+        self.entries.iter_mut().flatten().find_map(|entry| entry.restarted.take())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{REGISTER_TARGET_POSITION_H, REGISTER_TARGET_SPEED_H};
+    extern crate std;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_shadow_cache() {
+        let mut cache = ShadowCache::<2>::new();
+        // Target positions are not cached.
+        cache.record_write(1, REGISTER_TARGET_POSITION_H.address, &[0x01, 0x00]);
+        assert!(!cache.is_tracked(1));
+        cache.record_write(1, REGISTER_TARGET_POSITION_H.address, &[0x01, 0x00, 0x00, 0x00, 0x00, 0x10]);
+        cache.record_write(2, REGISTER_TORQUE_SWITCH.address, &[1]);
+        assert_eq!(cache.saved(1).collect::<Vec<_>>(), [(0x2c, 0x00), (0x2d, 0x00), (0x2e, 0x00), (0x2f, 0x10)]);
+        assert_eq!(cache.value(1, REGISTER_TARGET_POSITION_H.address), None);
+        // The cache is full.
+        cache.record_write(3, REGISTER_TORQUE_SWITCH.address, &[1]);
+        assert!(!cache.is_tracked(3));
+
+        cache.record_write(BROADCAST_ID, REGISTER_TORQUE_SWITCH.address, &[0]);
+        assert_eq!(cache.value(1, REGISTER_TORQUE_SWITCH.address), Some(0));
+        assert_eq!(cache.saved(2).collect::<Vec<_>>(), [(0x28, 0)]);
+        assert_eq!(cache.value(1, REGISTER_TARGET_SPEED_H.address), Some(0));
+
+        assert!(!cache.mark_answered(1));
+        cache.mark_lost(1);
+        assert!(cache.mark_answered(1));
+        assert!(!cache.mark_answered(1));
+        cache.untrack(1);
+        assert!(cache.track(3, Some([0x05, 0x04])));
+        assert_eq!(cache.version(3), Some([0x05, 0x04]));
+        assert_eq!(cache.saved(1).count(), 0);
+    }
+}
@@ -0,0 +1,200 @@
+//! Named joints.
+//!
+//! [`Robot`] maps joint names to servo controls and converts between joint angles in degrees and servo
+//! positions, with a direction, an offset and limits per joint. Applications command "shoulder" and "elbow"
+//! instead of IDs and raw positions. [`MultiBus`](crate::multibus::MultiBus) names joints at the register
+//! level instead, for robots spread over several buses.
+
+use crate::device::{AngleScale, PositionSpace, ServoControl};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointConfig {
+    pub name: &'static str,
+    /// Whether the joint angle grows toward smaller servo positions, e.g. for mirrored joints.
+    pub inverted: bool,
+    /// Servo angle at the joint zero, in degrees from the center of the servo.
+    pub offset: f32,
+    /// Lowest joint angle in degrees.
+    pub min: f32,
+    /// Highest joint angle in degrees.
+    pub max: f32,
+}
+
+impl JointConfig {
+    /// Joint with the zero at the center of the servo and the limits `min` to `max` degrees.
+    pub const fn new(name: &'static str, min: f32, max: f32) -> Self {
+        Self {
+            name,
+            inverted: false,
+            offset: 0.0,
+            min,
+            max,
+        }
+    }
+}
+
+pub struct Joint<S> {
+    pub config: JointConfig,
+    pub servo: S,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JointState {
+    pub name: &'static str,
+    /// Joint angle in degrees.
+    pub angle: f32,
+    /// Joint velocity in degrees per second.
+    pub velocity: f32,
+    /// Load in the direction of the joint, in the unit of the servo.
+    pub load: i16,
+}
+
+#[derive(Debug)]
+pub enum RobotError<E> {
+    /// No joint has the name at the index in the request.
+    UnknownJoint(usize),
+    DuplicateJoint(&'static str),
+    /// The angle is outside the joint limits or the range of the servo. Nothing was written.
+    OutOfLimits { joint: &'static str, angle: f32 },
+    ServoError { joint: &'static str, error: E },
+}
+
+pub struct Robot<S, const N: usize> {
+    joints: [Joint<S>; N],
+    scale: AngleScale,
+}
+
+impl<S, const N: usize> Robot<S, N>
+    where S: ServoControl<Position = u16, Speed = i16, Torque = i16>,
+{
+    /// Creates a robot from `joints` driven by servos with the position scale `scale`.
+    pub fn new(joints: [Joint<S>; N], scale: AngleScale) -> Result<Self, RobotError<S::Error>> {
+        for (index, joint) in joints.iter().enumerate() {
+            if joints[..index].iter().any(|other| other.config.name == joint.config.name) {
+                return Err(RobotError::DuplicateJoint(joint.config.name));
+            }
+        }
+        Ok(Self { joints, scale })
+    }
+
+    pub fn joint(&self, name: &str) -> Option<&Joint<S>> {
+        self.joints.iter().find(|joint| joint.config.name == name)
+    }
+    pub fn joint_mut(&mut self, name: &str) -> Option<&mut Joint<S>> {
+        self.joints.iter_mut().find(|joint| joint.config.name == name)
+    }
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.joints.iter().map(|joint| joint.config.name)
+    }
+    pub fn into_joints(self) -> [Joint<S>; N] {
+        self.joints
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.config.name == name)
+    }
+
+    fn to_position(&self, config: &JointConfig, angle: f32) -> Result<u16, RobotError<S::Error>> {
+        let out_of_limits = RobotError::OutOfLimits { joint: config.name, angle };
+        if !(config.min..=config.max).contains(&angle) {
+            return Err(out_of_limits);
+        }
+        let servo_angle = if config.inverted { -angle } else { angle } + config.offset;
+        self.scale.to_position(servo_angle).ok_or(out_of_limits)
+    }
+
+    /// Moves the named joints to the angles in degrees.
+    /// All angles are checked before the first one is written, so a request out of the limits moves no joint.
+    pub fn set_joint_angles(&mut self, angles: &[(&str, f32)]) -> Result<(), RobotError<S::Error>> {
+        for (request, (name, angle)) in angles.iter().enumerate() {
+            let index = self.index(name).ok_or(RobotError::UnknownJoint(request))?;
+            self.to_position(&self.joints[index].config, *angle)?;
+        }
+        for (name, angle) in angles {
+            let index = self.index(name).expect("checked above");
+            let position = self.to_position(&self.joints[index].config, *angle)?;
+            let joint = &mut self.joints[index];
+            joint.servo.set_target_position(position)
+                .map_err(|error| RobotError::ServoError { joint: joint.config.name, error })?;
+        }
+        Ok(())
+    }
+
+    /// Reads the angle, velocity and load of every joint, in the order of the joints.
+    pub fn joint_states(&mut self) -> Result<[JointState; N], RobotError<S::Error>> {
+        let mut states = [JointState::default(); N];
+        for (state, joint) in states.iter_mut().zip(self.joints.iter_mut()) {
+            let name = joint.config.name;
+            let servo_error = |error| RobotError::ServoError { joint: name, error };
+            joint.servo.update().map_err(servo_error)?;
+            let position = joint.servo.current_position().map_err(servo_error)?;
+            let speed = joint.servo.current_speed().map_err(servo_error)?;
+            let load = joint.servo.current_load().map_err(servo_error)?;
+            let sign = if joint.config.inverted { -1.0 } else { 1.0 };
+            *state = JointState {
+                name,
+                angle: (self.scale.to_degrees(position) - joint.config.offset) * sign,
+                velocity: self.scale.speed_to_degrees(speed) * sign,
+                load: if joint.config.inverted { load.saturating_neg() } else { load },
+            };
+        }
+        Ok(states)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{Scs0009ServoControl, ANGLE_SCALE, REGISTER_TARGET_POSITION_H, REGISTER_TARGET_POSITION_L, WORD_ORDER};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    use core::time::Duration;
+    extern crate std;
+    use std::sync::mpsc::{Receiver, Sender};
+
+    type TestServo = Scs0009ServoControl<Receiver<u8>, Sender<u8>, std::time::Instant>;
+
+    fn spawn_servo(id: u8) -> (TestServo, EmulatorThread<1>) {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<1>::new(id, 1));
+        (Scs0009ServoControl::new(id, master_reader, master_writer, ProtocolMasterConfig::default(), Duration::from_secs(1)), thread)
+    }
+
+    #[test]
+    fn test_robot() {
+        let (shoulder, shoulder_thread) = spawn_servo(1);
+        let (elbow, elbow_thread) = spawn_servo(2);
+        let elbow_config = JointConfig { inverted: true, offset: 30.0, ..JointConfig::new("elbow", 0.0, 120.0) };
+
+        let (first, _first_thread) = spawn_servo(3);
+        let (second, _second_thread) = spawn_servo(4);
+        assert!(matches!(Robot::new([
+            Joint { config: JointConfig::new("shoulder", -90.0, 90.0), servo: first },
+            Joint { config: JointConfig::new("shoulder", -90.0, 90.0), servo: second },
+        ], ANGLE_SCALE), Err(RobotError::DuplicateJoint("shoulder"))));
+        let mut robot = Robot::new([
+            Joint { config: JointConfig::new("shoulder", -90.0, 90.0), servo: shoulder },
+            Joint { config: elbow_config, servo: elbow },
+        ], ANGLE_SCALE).unwrap();
+        assert_eq!(robot.names().collect::<std::vec::Vec<_>>(), ["shoulder", "elbow"]);
+
+        // The emulated servos start at position 0x01ff, just below the center.
+        let states = robot.joint_states().unwrap();
+        assert_eq!(states[0].name, "shoulder");
+        assert!((states[0].angle + 0.15).abs() < 0.01, "{}", states[0].angle);
+        assert!((states[1].angle - 30.15).abs() < 0.01, "{}", states[1].angle);
+
+        assert!(matches!(robot.set_joint_angles(&[("shoulder", 10.0), ("elbow", 150.0)]), Err(RobotError::OutOfLimits { joint: "elbow", .. })));
+        assert!(matches!(robot.set_joint_angles(&[("shoulder", 10.0), ("wrist", 0.0)]), Err(RobotError::UnknownJoint(1))));
+        robot.set_joint_angles(&[("shoulder", 45.0), ("elbow", 90.0)]).unwrap();
+
+        let target = |emulator: &BusEmulator<1>, id| {
+            let registers = emulator.servo(id).unwrap().registers();
+            WORD_ORDER.from_bytes([registers[REGISTER_TARGET_POSITION_H.address as usize], registers[REGISTER_TARGET_POSITION_L.address as usize]])
+        };
+        // 45 degrees is 153.45 steps above the center.
+        assert_eq!(target(&shoulder_thread.stop(), 1), 665);
+        // Inverted 90 degrees with the offset is 60 degrees below the center.
+        assert_eq!(target(&elbow_thread.stop(), 2), 307);
+    }
+}
@@ -0,0 +1,479 @@
+//! Discovery of servos on the bus.
+//!
+//! [`Scanner`] optionally sends a broadcast PING first and collects the staggered responses of firmware
+//! which supports it. Otherwise it sweeps the ID range with unicast PINGs. The wait for each ID adapts to
+//! the latency measured on the servos found so far, so absent IDs cost a few milliseconds instead of the
+//! worst-case timeout. IDs already known from a previous inventory are probed before the rest of the range,
+//! and a robot with a fixed set of servos can skip the sweep entirely once all of them answered.
+
+use core::marker::PhantomData;
+use core::time::Duration;
+
+use crate::device::{timeout_after, Instant, Timer};
+use crate::protocol::{IdSet, ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, ProtocolReaderError, StreamReader, StreamWriter, BROADCAST_ID};
+#[cfg(feature = "async")]
+use crate::protocol::{StreamReaderAsync, StreamWriterAsync};
+#[cfg(feature = "async")]
+use crate::device::scs0009::REGISTER_VERSION_H;
+
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// IDs to probe.
+    pub ids: IdSet,
+    /// IDs expected on the bus, e.g. from a previous inventory. They are probed before the other IDs in `ids`.
+    pub known_ids: IdSet,
+    /// Skips the remaining IDs if every known ID answered.
+    pub known_only: bool,
+    /// Sends a broadcast PING before sweeping. If any servo answers it, the sweep is skipped.
+    pub broadcast_ping: bool,
+    /// How long to collect responses to the broadcast PING.
+    pub broadcast_window: Duration,
+    /// Timeout of each ID until the latency of a servo has been measured.
+    pub initial_timeout: Duration,
+    /// Lower bound of the adaptive timeout.
+    pub min_timeout: Duration,
+    /// The adaptive timeout is the largest measured latency multiplied by this factor.
+    pub latency_factor: u32,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        let mut ids = IdSet::new();
+        for id in 0..BROADCAST_ID {
+            ids.insert(id);
+        }
+        Self {
+            ids,
+            known_ids: IdSet::new(),
+            known_only: false,
+            broadcast_ping: false,
+            broadcast_window: Duration::from_millis(100),
+            initial_timeout: Duration::from_millis(10),
+            min_timeout: Duration::from_millis(2),
+            latency_factor: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// The servo responded after the given latency.
+    Found(Duration),
+    NotFound,
+    /// Another servo responded, most likely late to an earlier probe.
+    LateResponse(u8),
+}
+
+/// A servo found by [`Scanner::discover_async`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Discovered {
+    pub id: u8,
+    /// Software Version H register, which identifies the model on SCS firmware.
+    pub model: u8,
+    /// Software Version L register.
+    pub version: u8,
+}
+
+pub struct Scanner<const BUFFER_SIZE: usize, T: Timer> {
+    config: ScanConfig,
+    master: ProtocolMaster<BUFFER_SIZE>,
+    max_latency: Option<Duration>,
+    _timer: PhantomData<T>,
+}
+
+impl<const BUFFER_SIZE: usize, T: Timer> Scanner<BUFFER_SIZE, T> {
+    pub fn new(master_config: ProtocolMasterConfig, config: ScanConfig) -> Self {
+        Self {
+            config,
+            master: ProtocolMaster::new(master_config),
+            max_latency: None,
+            _timer: PhantomData,
+        }
+    }
+
+    pub fn config(&self) -> &ScanConfig {
+        &self.config
+    }
+
+    /// Returns the timeout used for the next probe.
+    pub fn timeout(&self) -> Duration {
+        match self.max_latency {
+            Some(latency) => (latency * self.config.latency_factor).max(self.config.min_timeout),
+            None => self.config.initial_timeout,
+        }
+    }
+
+    /// Splits the configured IDs into the known IDs and the rest.
+    fn candidates(&self) -> (IdSet, IdSet) {
+        let mut known = IdSet::new();
+        let mut remaining = self.config.ids;
+        for id in self.config.known_ids.iter().filter(|id| self.config.ids.contains(*id)) {
+            known.insert(id);
+            remaining.remove(id);
+        }
+        (known, remaining)
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        self.max_latency = Some(self.max_latency.map_or(latency, |max_latency| max_latency.max(latency)));
+    }
+
+    /// Doubles the largest measured latency after a late response, up to the latency at which the timeout reaches
+    /// the initial timeout. Measured latencies above that are kept.
+    fn record_late_response(&mut self) {
+        let limit = self.config.initial_timeout / self.config.latency_factor.max(1);
+        self.max_latency = self.max_latency.map(|latency| (latency * 2).min(limit).max(latency));
+    }
+
+    /// Pings a single ID.
+    pub fn probe<R: StreamReader, W: StreamWriter>(&mut self, reader: &mut R, writer: &mut W, id: u8) -> Result<ProbeResult, ProtocolHandlerError<R::Error, W::Error>> {
+        let start = T::now();
+        let result = self.master.ping(reader, writer, id, timeout_after::<T>(self.timeout()));
+        self.probe_result(start, result)
+    }
+
+    /// Converts the result of a transaction into a `ProbeResult`. Only transport errors are returned as errors.
+    fn probe_result<V, RE, WE>(&mut self, start: T::Instant, result: Result<V, ProtocolHandlerError<RE, WE>>) -> Result<ProbeResult, ProtocolHandlerError<RE, WE>> {
+        match result {
+            Ok(_) => {
+                let latency = start.elapsed();
+                self.record_latency(latency);
+                Ok(ProbeResult::Found(latency))
+            }
+            Err(ProtocolHandlerError::UnexpectedPacketId(other)) => Ok(ProbeResult::LateResponse(other)),
+            Err(ProtocolHandlerError::ReaderError(err)) | Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(err))) => Err(ProtocolHandlerError::ReaderError(err)),
+            Err(ProtocolHandlerError::WriterError(err)) => Err(ProtocolHandlerError::WriterError(err)),
+            Err(_) => Ok(ProbeResult::NotFound),
+        }
+    }
+
+    /// Returns a stream which reports each servo as soon as it is found, for UIs which render results incrementally.
+    ///
+    /// Each ID is probed by reading its version registers, so a discovered servo costs a single transaction.
+    /// If `broadcast_ping` is set and any servo answers it, only the IDs which answered are probed.
+    /// Otherwise the known IDs are probed first, as in `scan`.
+    /// The stream ends after the first transport error.
+    #[cfg(feature = "async")]
+    pub fn discover_async<'a, R: StreamReaderAsync, W: StreamWriterAsync>(&'a mut self, reader: &'a mut R, writer: &'a mut W) -> impl futures_core::Stream<Item = Result<Discovered, ProtocolHandlerError<R::Error, W::Error>>> + 'a {
+        let (known, remaining) = self.candidates();
+        let state = Discovery {
+            known,
+            missing_known: known.is_empty(),
+            remaining,
+            broadcast: self.config.broadcast_ping,
+            finished: false,
+            scanner: self,
+            reader,
+            writer,
+        };
+        futures_util::stream::unfold(state, |mut state| async move {
+            if state.finished {
+                return None;
+            }
+            match state.next().await {
+                Ok(Some(discovered)) => Some((Ok(discovered), state)),
+                Ok(None) => None,
+                Err(err) => {
+                    state.finished = true;
+                    Some((Err(err), state))
+                }
+            }
+        })
+    }
+
+    /// Scans the configured IDs and returns the IDs found.
+    /// `on_probe` is called with each probed ID and whether it was found. IDs found by the broadcast PING are reported as found.
+    /// The known IDs are probed first. IDs skipped because of `known_only` are not reported.
+    pub fn scan<R: StreamReader, W: StreamWriter, OnProbe: FnMut(u8, bool)>(&mut self, reader: &mut R, writer: &mut W, mut on_probe: OnProbe) -> Result<IdSet, ProtocolHandlerError<R::Error, W::Error>> {
+        let mut found = IdSet::new();
+        if self.config.broadcast_ping {
+            let ids = self.config.ids;
+            self.master.broadcast_ping(reader, writer, timeout_after::<T>(self.config.broadcast_window), |id| {
+                if ids.contains(id) && !found.contains(id) {
+                    found.insert(id);
+                    on_probe(id, true);
+                }
+            })?;
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+
+        // IDs which responded late to the probe of another ID. They are probed again after the sweep.
+        let mut late = IdSet::new();
+        let ids = self.config.ids;
+        let (known, remaining) = self.candidates();
+        for candidates in [known, remaining] {
+            for id in candidates.iter() {
+                match self.probe(reader, writer, id)? {
+                    ProbeResult::Found(_) => {
+                        found.insert(id);
+                        on_probe(id, true);
+                    }
+                    ProbeResult::NotFound => on_probe(id, false),
+                    ProbeResult::LateResponse(other) => {
+                        // The bus is slower than estimated.
+                        self.record_late_response();
+                        if ids.contains(other) && !found.contains(other) {
+                            late.insert(other);
+                        }
+                        on_probe(id, false);
+                    }
+                }
+            }
+            if self.config.known_only && !known.is_empty() && known.iter().all(|id| found.contains(id)) {
+                break;
+            }
+        }
+        for id in late.iter() {
+            if let ProbeResult::Found(_) = self.probe(reader, writer, id)? {
+                found.insert(id);
+                on_probe(id, true);
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(feature = "async")]
+struct Discovery<'a, const BUFFER_SIZE: usize, T: Timer, R, W> {
+    scanner: &'a mut Scanner<BUFFER_SIZE, T>,
+    reader: &'a mut R,
+    writer: &'a mut W,
+    known: IdSet,
+    /// Whether a known ID did not answer, or no IDs are known. The remaining IDs are only probed in that case if `known_only` is set.
+    missing_known: bool,
+    remaining: IdSet,
+    broadcast: bool,
+    finished: bool,
+}
+
+#[cfg(feature = "async")]
+impl<const BUFFER_SIZE: usize, T: Timer, R: StreamReaderAsync, W: StreamWriterAsync> Discovery<'_, BUFFER_SIZE, T, R, W> {
+    async fn next(&mut self) -> Result<Option<Discovered>, ProtocolHandlerError<R::Error, W::Error>> {
+        let scanner = &mut *self.scanner;
+        if self.broadcast {
+            self.broadcast = false;
+            let ids = scanner.config.ids;
+            let mut found = IdSet::new();
+            scanner.master.broadcast_ping_async(self.reader, self.writer, timeout_after::<T>(scanner.config.broadcast_window), |id| {
+                if ids.contains(id) {
+                    found.insert(id);
+                }
+            }).await?;
+            if !found.is_empty() {
+                self.known = IdSet::new();
+                self.remaining = found;
+                self.missing_known = true;
+            }
+        }
+        loop {
+            let id = if let Some(id) = self.known.first() {
+                self.known.remove(id);
+                id
+            } else if let Some(id) = self.remaining.first().filter(|_| self.missing_known || !scanner.config.known_only) {
+                self.remaining.remove(id);
+                id
+            } else {
+                return Ok(None);
+            };
+            let mut registers = [0; 2];
+            let start = T::now();
+            let result = scanner.master.read_register_async(self.reader, self.writer, id, REGISTER_VERSION_H.address, &mut registers, timeout_after::<T>(scanner.timeout())).await;
+            match scanner.probe_result(start, result)? {
+                ProbeResult::Found(_) => return Ok(Some(Discovered { id, model: registers[0], version: registers[1] })),
+                _ if scanner.config.known_ids.contains(id) => self.missing_known = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::SimTimer;
+    use crate::emulator::BusEmulator;
+    extern crate std;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    /// Runs a bus emulator in the same thread as the master. Every read advances the simulated clock.
+    struct EmulatorLink {
+        emulator: BusEmulator<4>,
+        requests: Receiver<u8>,
+        responses: Sender<u8>,
+        received: Receiver<u8>,
+    }
+    struct LinkReader<'a> {
+        link: &'a core::cell::RefCell<EmulatorLink>,
+    }
+    impl StreamReader for LinkReader<'_> {
+        type Error = ();
+        fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+            let link = &mut *self.link.borrow_mut();
+            for _ in 0..4 {
+                link.emulator.process(&mut link.requests, &mut link.responses).unwrap();
+            }
+            SimTimer::advance(Duration::from_micros(100));
+            StreamReader::read(&mut link.received, data)
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl StreamReaderAsync for LinkReader<'_> {
+        type Error = ();
+        async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+            match StreamReader::read(self, data) {
+                Ok(bytes_read) => Ok(bytes_read),
+                Err(nb::Error::WouldBlock) => Ok(0),
+                Err(nb::Error::Other(err)) => Err(err),
+            }
+        }
+    }
+    #[cfg(feature = "async")]
+    struct LinkWriter(Sender<u8>);
+    #[cfg(feature = "async")]
+    impl StreamWriterAsync for LinkWriter {
+        type Error = ();
+        async fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+            StreamWriter::write(&mut self.0, data).map_err(|_| ())
+        }
+    }
+
+    fn link(base_id: u8, count: usize) -> (core::cell::RefCell<EmulatorLink>, Sender<u8>) {
+        let (writer, requests) = channel();
+        let (responses, received) = channel();
+        let link = EmulatorLink { emulator: BusEmulator::new(base_id, count), requests, responses, received };
+        (core::cell::RefCell::new(link), writer)
+    }
+
+    fn scan_config(ids: core::ops::Range<u8>) -> ScanConfig {
+        let mut set = IdSet::new();
+        for id in ids {
+            set.insert(id);
+        }
+        ScanConfig { ids: set, ..Default::default() }
+    }
+
+    #[test]
+    fn test_scan_sweep() {
+        SimTimer::reset();
+        let (link, mut writer) = link(3, 3);
+        let mut reader = LinkReader { link: &link };
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), scan_config(0..10));
+        let mut probed = 0;
+        let found = scanner.scan(&mut reader, &mut writer, |_, _| probed += 1).unwrap();
+        assert_eq!(found, IdSet::from_ids(&[3, 4, 5]));
+        assert_eq!(probed, 10);
+        assert!(scanner.timeout() < scanner.config().initial_timeout);
+        // 3 IDs before the first response wait for the initial timeout, the rest only for the adaptive one.
+        assert!(SimTimer::time() < Duration::from_millis(3 * 10 + 7 * 2 + 5));
+    }
+
+    #[test]
+    fn test_scan_late_response() {
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), scan_config(0..10));
+        // Before any latency has been measured, the timeout is already the initial one.
+        scanner.record_late_response();
+        assert_eq!(scanner.timeout(), scanner.config().initial_timeout);
+        scanner.record_latency(Duration::from_micros(500));
+        scanner.record_late_response();
+        assert_eq!(scanner.timeout(), Duration::from_millis(4));
+        // Late responses do not raise the timeout above the initial one.
+        for _ in 0..10 {
+            scanner.record_late_response();
+        }
+        assert_eq!(scanner.timeout(), scanner.config().initial_timeout);
+    }
+
+    #[test]
+    fn test_scan_known_ids() {
+        SimTimer::reset();
+        let (link, mut writer) = link(3, 3);
+        let mut reader = LinkReader { link: &link };
+        let config = ScanConfig { known_ids: IdSet::from_ids(&[4, 3]), known_only: true, ..scan_config(0..10) };
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), config.clone());
+        let mut reported = std::vec::Vec::new();
+        let found = scanner.scan(&mut reader, &mut writer, |id, found| reported.push((id, found))).unwrap();
+        assert_eq!(found, IdSet::from_ids(&[3, 4]));
+        assert_eq!(reported, [(3, true), (4, true)]);
+        assert!(SimTimer::time() < Duration::from_millis(5));
+
+        // A known ID is missing, so the rest of the range is swept after the known IDs.
+        let config = ScanConfig { known_ids: IdSet::from_ids(&[3, 8]), ..config };
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), config);
+        reported.clear();
+        let found = scanner.scan(&mut reader, &mut writer, |id, found| reported.push((id, found))).unwrap();
+        assert_eq!(found, IdSet::from_ids(&[3, 4, 5]));
+        assert_eq!(reported[..3], [(3, true), (8, false), (0, false)]);
+        assert_eq!(reported.len(), 10);
+    }
+
+    #[test]
+    fn test_scan_broadcast_fallback() {
+        SimTimer::reset();
+        // The emulator does not answer broadcast PINGs, so the scanner falls back to sweeping.
+        let (link, mut writer) = link(1, 2);
+        let mut reader = LinkReader { link: &link };
+        let mut config = scan_config(0..4);
+        config.broadcast_ping = true;
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), config);
+        let found = scanner.scan(&mut reader, &mut writer, |_, _| {}).unwrap();
+        assert_eq!(found, IdSet::from_ids(&[1, 2]));
+    }
+
+    #[test]
+    fn test_scan_broadcast() {
+        use crate::testing::fixture::ScriptedBus;
+        struct ClockedReader<R>(R);
+        impl<R: StreamReader> StreamReader for ClockedReader<R> {
+            type Error = R::Error;
+            fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+                SimTimer::advance(Duration::from_millis(1));
+                self.0.read(data)
+            }
+        }
+
+        SimTimer::reset();
+        let bus = ScriptedBus::new(
+            &[0xff, 0xff, 0xfe, 0x02, 0x01, 0xfe],
+            &[0xff, 0xff, 0x01, 0x02, 0x00, 0xfc, 0xff, 0xff, 0x07, 0x02, 0x00, 0xf6],
+        );
+        let mut reader = ClockedReader(bus.reader());
+        let mut writer = bus.writer();
+        let mut config = scan_config(0..10);
+        config.broadcast_ping = true;
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), config);
+        let mut reported = std::vec::Vec::new();
+        let found = scanner.scan(&mut reader, &mut writer, |id, found| reported.push((id, found))).unwrap();
+        assert_eq!(found, IdSet::from_ids(&[1, 7]));
+        assert_eq!(reported, [(1, true), (7, true)]);
+        assert!(bus.transmit_completed());
+        assert_eq!(bus.receive_remaining(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_discover_async() {
+        use futures_util::StreamExt;
+
+        SimTimer::reset();
+        let (link, writer) = link(3, 2);
+        let mut reader = LinkReader { link: &link };
+        let mut writer = LinkWriter(writer);
+        let mut scanner = Scanner::<16, SimTimer>::new(ProtocolMasterConfig::default(), scan_config(0..6));
+        let stream = scanner.discover_async(&mut reader, &mut writer);
+        let mut stream = core::pin::pin!(stream);
+        let mut next = || {
+            let mut context = core::task::Context::from_waker(core::task::Waker::noop());
+            loop {
+                if let core::task::Poll::Ready(item) = stream.poll_next_unpin(&mut context) {
+                    return item;
+                }
+            }
+        };
+        assert_eq!(next().unwrap().unwrap(), Discovered { id: 3, model: 0x05, version: 0x04 });
+        assert_eq!(next().unwrap().unwrap(), Discovered { id: 4, model: 0x05, version: 0x04 });
+        assert!(next().is_none());
+    }
+}
@@ -0,0 +1,239 @@
+//! Scripted faults for the emulator.
+//!
+//! A scenario is a text file describing faults of the emulated servos over time, so the resilience of an application
+//! can be tested reproducibly against the same failures:
+//!
+//! ```text
+//! # Lines starting with '#' are comments. Events are applied in the order of their time.
+//! 10s    3  brownout 200ms   # Servo 3 stops answering for 200 ms and restarts with its RAM registers reset.
+//! 12s    5  noise 0.2        # 20 % of the responses of servo 5 have a wrong checksum.
+//! 15s    5  silent           # Servo 5 stops answering.
+//! 20s    5  clear            # Servo 5 answers normally again.
+//! 1.5s   2  voltage 45       # Servo 2 measures 4.5 V and reports a voltage alarm.
+//! 2500ms 2  temperature 80   # Servo 2 measures 80 degC.
+//! ```
+//!
+//! [`ScenarioPlayer`] applies the events to a [`BusEmulator`] as the simulated time advances, and
+//! [`Simulation::start_with_scenario`](crate::simulate::Simulation::start_with_scenario) runs a scenario in real
+//! time. The checksum noise is pseudo-random with a fixed seed per servo, so the same requests see the same faults.
+
+extern crate std;
+use core::time::Duration;
+use std::vec::Vec;
+
+use crate::device::scs0009::{REGISTER_CURRENT_TEMPERATURE, REGISTER_CURRENT_VOLTAGE};
+use crate::emulator::{BusEmulator, EmulatedServo};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Stops answering.
+    Silent,
+    /// Restarts like after a dip of the supply voltage and answers again.
+    Restart,
+    /// Sends the given fraction of the responses with a wrong checksum.
+    ChecksumNoise(f32),
+    /// Sets the measured voltage in 0.1 V.
+    Voltage(u8),
+    /// Sets the measured temperature in degC.
+    Temperature(u8),
+    /// Answers normally again. The measured voltage and temperature are kept.
+    Clear,
+}
+
+impl Fault {
+    pub fn apply(&self, servo: &mut EmulatedServo) {
+        match *self {
+            Fault::Silent => servo.faults_mut().silent = true,
+            Fault::Restart => {
+                servo.restart();
+                servo.faults_mut().silent = false;
+            }
+            Fault::ChecksumNoise(ratio) => servo.faults_mut().checksum_noise = ratio,
+            Fault::Voltage(voltage) => servo.registers_mut()[REGISTER_CURRENT_VOLTAGE.address as usize] = voltage,
+            Fault::Temperature(temperature) => servo.registers_mut()[REGISTER_CURRENT_TEMPERATURE.address as usize] = temperature,
+            Fault::Clear => *servo.faults_mut() = Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub line: usize,
+    /// Time since the start of the scenario.
+    pub at: Duration,
+    pub id: u8,
+    pub fault: Fault,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    /// Events sorted by their time.
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScenarioError {
+    /// The time is not a number followed by `s` or `ms`.
+    InvalidTime { line: usize },
+    InvalidId { line: usize },
+    UnknownFault { line: usize },
+    /// The value of the fault is missing or out of range.
+    InvalidValue { line: usize },
+}
+
+fn parse_time(text: &str, line: usize) -> Result<Duration, ScenarioError> {
+    let (value, scale) = if let Some(value) = text.strip_suffix("ms") {
+        (value, 0.001)
+    } else if let Some(value) = text.strip_suffix('s') {
+        (value, 1.0)
+    } else {
+        return Err(ScenarioError::InvalidTime { line });
+    };
+    value.parse::<f64>().ok()
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .map(|value| Duration::from_secs_f64(value * scale))
+        .ok_or(ScenarioError::InvalidTime { line })
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        let mut scenario = Scenario::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let content = line.split_once('#').map_or(line, |(content, _)| content);
+            let fields = content.split_whitespace().collect::<Vec<_>>();
+            let (at, id, fault, value) = match fields[..] {
+                [] => continue,
+                [at, id, fault] => (at, id, fault, None),
+                [at, id, fault, value] => (at, id, fault, Some(value)),
+                _ => return Err(ScenarioError::UnknownFault { line: line_number }),
+            };
+            let at = parse_time(at, line_number)?;
+            let id = id.parse::<u8>().map_err(|_| ScenarioError::InvalidId { line: line_number })?;
+            let invalid_value = ScenarioError::InvalidValue { line: line_number };
+            let byte = || value.and_then(|value| value.parse::<u8>().ok()).ok_or(invalid_value.clone());
+            let mut push = |at, fault| scenario.events.push(Event { line: line_number, at, id, fault });
+            match (fault, value) {
+                ("silent", None) => push(at, Fault::Silent),
+                ("restart", None) => push(at, Fault::Restart),
+                ("clear", None) => push(at, Fault::Clear),
+                ("brownout", value) => {
+                    let duration = value.map_or(Ok(Duration::ZERO), |value| parse_time(value, line_number))?;
+                    push(at, Fault::Silent);
+                    push(at + duration, Fault::Restart);
+                }
+                ("noise", Some(value)) => {
+                    let ratio = value.parse::<f32>().ok().filter(|ratio| (0.0..=1.0).contains(ratio)).ok_or(invalid_value.clone())?;
+                    push(at, Fault::ChecksumNoise(ratio));
+                }
+                ("voltage", _) => push(at, Fault::Voltage(byte()?)),
+                ("temperature", _) => push(at, Fault::Temperature(byte()?)),
+                ("silent" | "restart" | "clear" | "noise", _) => return Err(invalid_value),
+                _ => return Err(ScenarioError::UnknownFault { line: line_number }),
+            }
+        }
+        // Stable, so events at the same time keep the order of the file.
+        scenario.events.sort_by_key(|event| event.at);
+        Ok(scenario)
+    }
+}
+
+/// Applies the events of a [`Scenario`] to an emulator as the simulated time advances.
+pub struct ScenarioPlayer {
+    scenario: Scenario,
+    next: usize,
+    elapsed: Duration,
+}
+
+impl ScenarioPlayer {
+    pub fn new(scenario: Scenario) -> Self {
+        Self { scenario, next: 0, elapsed: Duration::ZERO }
+    }
+
+    /// Time since the start of the scenario.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.scenario.events.len()
+    }
+
+    /// Advances the time by `elapsed` and applies the events which became due. Events of IDs which are not on the
+    /// bus are skipped.
+    pub fn advance<const MAX_SERVOS: usize>(&mut self, emulator: &mut BusEmulator<MAX_SERVOS>, elapsed: Duration) {
+        self.elapsed += elapsed;
+        while let Some(event) = self.scenario.events.get(self.next).filter(|event| event.at <= self.elapsed) {
+            if let Some(servo) = emulator.servo_mut(event.id) {
+                event.fault.apply(servo);
+            }
+            self.next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::REGISTER_TORQUE_SWITCH;
+    use crate::packet::PacketReader;
+    use crate::protocol::PingCommand;
+
+    const SCENARIO: &str = "
+        # Faults of the bus.
+        1s    3 brownout 200ms
+        500ms 5 noise 1      # Every response.
+        2s    5 clear
+        1.5s  2 voltage 45
+    ";
+
+    fn ping(servo: &mut EmulatedServo) -> Option<[u8; 6]> {
+        let command = PingCommand::new(servo.id());
+        let mut response = [0; 6];
+        servo.handle_packet(&PacketReader::new(&command.raw[2..]), &mut response)?;
+        Some(response)
+    }
+
+    #[test]
+    fn test_parse() {
+        let scenario = Scenario::parse(SCENARIO).unwrap();
+        let events = scenario.events.iter().map(|event| (event.at.as_millis(), event.id, event.fault)).collect::<Vec<_>>();
+        assert_eq!(events, [
+            (500, 5, Fault::ChecksumNoise(1.0)),
+            (1000, 3, Fault::Silent),
+            (1200, 3, Fault::Restart),
+            (1500, 2, Fault::Voltage(45)),
+            (2000, 5, Fault::Clear),
+        ]);
+        assert_eq!(Scenario::parse("1 3 silent"), Err(ScenarioError::InvalidTime { line: 1 }));
+        assert_eq!(Scenario::parse("\n1s 300 silent"), Err(ScenarioError::InvalidId { line: 2 }));
+        assert_eq!(Scenario::parse("1s 3 explode"), Err(ScenarioError::UnknownFault { line: 1 }));
+        assert_eq!(Scenario::parse("1s 3 noise 2"), Err(ScenarioError::InvalidValue { line: 1 }));
+        assert_eq!(Scenario::parse("1s 3 voltage"), Err(ScenarioError::InvalidValue { line: 1 }));
+    }
+
+    #[test]
+    fn test_player() {
+        let mut emulator = BusEmulator::<5>::new(1, 5);
+        let mut player = ScenarioPlayer::new(Scenario::parse(SCENARIO).unwrap());
+        let clean = ping(emulator.servo_mut(5).unwrap()).unwrap();
+        emulator.servo_mut(3).unwrap().registers_mut()[REGISTER_TORQUE_SWITCH.address as usize] = 1;
+
+        player.advance(&mut emulator, Duration::from_millis(1000));
+        let noisy = ping(emulator.servo_mut(5).unwrap()).unwrap();
+        assert_eq!(noisy[..5], clean[..5]);
+        assert_ne!(noisy[5], clean[5]);
+        assert_eq!(ping(emulator.servo_mut(3).unwrap()), None);
+
+        // The brownout ends with a restart, which turns the torque off.
+        player.advance(&mut emulator, Duration::from_millis(600));
+        let servo = emulator.servo_mut(3).unwrap();
+        assert!(ping(servo).is_some());
+        assert_eq!(servo.registers()[REGISTER_TORQUE_SWITCH.address as usize], 0);
+        assert!(emulator.servo(2).unwrap().alarms().voltage());
+        assert!(!player.is_finished());
+
+        player.advance(&mut emulator, Duration::from_millis(400));
+        assert_eq!(ping(emulator.servo_mut(5).unwrap()), Some(clean));
+        assert!(player.is_finished());
+    }
+}
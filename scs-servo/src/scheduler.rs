@@ -0,0 +1,169 @@
+//! Interleaving of motion commands with telemetry polls.
+//!
+//! A control loop which polls the status of every servo keeps the bus busy, and a motion command has to wait for a
+//! free slot. [`Scheduler`] shares a [`Bus`] between the motion commands of a [`CommandQueue`] and round-robin
+//! status polls of the servos. The motion commands pending take the slots first, and a poll only gets one after
+//! `motion_per_poll` of them in a row, so a motion command waits for at most one poll while telemetry still gets a
+//! share of a congested bus:
+//!
+//! ```ignore
+//! let mut scheduler = Scheduler::<Instant, 8>::new(IdSet::from_ids(&[1, 2, 3]), 3);
+//! loop {
+//!     if let Some(position) = next_setpoint() {
+//!         scheduler.push(QueuedCommand::write(1, REGISTER_TARGET_POSITION_H.address, &scs0009::WORD_ORDER.to_bytes(position)).unwrap(), Priority::Setpoint, None)?;
+//!     }
+//!     scheduler.service_one(&mut bus, &mut |event| log(event));
+//! }
+//! ```
+
+use core::time::Duration;
+
+use crate::bus::{Bus, BusError};
+use crate::device::Timer;
+use crate::policy::WritePolicy;
+use crate::protocol::{IdSet, StreamReader, StreamWriter};
+use crate::queue::{CommandQueue, Priority, QueueError, QueueEvent, QueuedCommand};
+
+/// Scheduler of the commands of a [`CommandQueue`] of up to `N` commands and of the status polls of the servos.
+pub struct Scheduler<T: Timer, const N: usize> {
+    queue: CommandQueue<T, N>,
+    telemetry: IdSet,
+    motion_per_poll: u8,
+    // Commands sent since the last poll.
+    burst: u8,
+    // ID polled last.
+    last_poll: Option<u8>,
+}
+
+impl<T: Timer, const N: usize> Scheduler<T, N> {
+    /// Polls the status of the servos in `telemetry` whenever no command is pending, and after `motion_per_poll`
+    /// commands in a row otherwise. A `motion_per_poll` of zero is taken as one.
+    pub fn new(telemetry: IdSet, motion_per_poll: u8) -> Self {
+        Self {
+            queue: CommandQueue::new(),
+            telemetry,
+            motion_per_poll: motion_per_poll.max(1),
+            burst: 0,
+            last_poll: None,
+        }
+    }
+
+    /// Queues a command, see [`CommandQueue::push`].
+    pub fn push(&mut self, command: QueuedCommand, priority: Priority, max_age: Option<Duration>) -> Result<Option<QueuedCommand>, QueueError> {
+        self.queue.push(command, priority, max_age)
+    }
+    pub fn queue(&self) -> &CommandQueue<T, N> {
+        &self.queue
+    }
+    pub fn queue_mut(&mut self) -> &mut CommandQueue<T, N> {
+        &mut self.queue
+    }
+    /// The servos whose status is polled, e.g. to stop polling a servo which was switched off.
+    pub fn telemetry_mut(&mut self) -> &mut IdSet {
+        &mut self.telemetry
+    }
+
+    /// The servo to poll after the last one.
+    fn next_poll(&self) -> Option<u8> {
+        let after = self.last_poll.map_or(0, |id| id as usize + 1);
+        (after..=255).chain(0..after).map(|id| id as u8).find(|id| self.telemetry.contains(*id))
+    }
+
+    /// Sends the next command, or polls the status of the next servo if it is its turn or no command is pending.
+    /// Events are reported as by [`CommandQueue::service_one`], polls as [`QueuedCommand::ReadStatus`] commands with
+    /// [`Priority::Telemetry`]. Returns false if there was nothing to do.
+    pub fn service_one<R, W, const BUFFER_SIZE: usize, P, OnEvent>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, on_event: &mut OnEvent) -> bool
+        where R: StreamReader,
+              W: StreamWriter,
+              P: WritePolicy,
+              OnEvent: FnMut(QueueEvent<BusError<R, W>>),
+    {
+        let poll = self.next_poll();
+        if (self.burst < self.motion_per_poll || poll.is_none()) && self.queue.service_one(bus, on_event) {
+            self.burst = self.burst.saturating_add(1);
+            return true;
+        }
+        let Some(id) = poll else {
+            return false;
+        };
+        self.burst = 0;
+        self.last_poll = Some(id);
+        let command = QueuedCommand::ReadStatus { id };
+        let priority = Priority::Telemetry;
+        match bus.read_status_block(id) {
+            Ok(status) => {
+                on_event(QueueEvent::Status { id, status });
+                on_event(QueueEvent::Sent { command, priority });
+            },
+            Err(error) => on_event(QueueEvent::Failed { command, priority, error }),
+        }
+        true
+    }
+
+    /// Runs up to `max_slots` slots of [`Self::service_one`]. Returns the number of slots used.
+    pub fn service<R, W, const BUFFER_SIZE: usize, P, OnEvent>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, max_slots: usize, mut on_event: OnEvent) -> usize
+        where R: StreamReader,
+              W: StreamWriter,
+              P: WritePolicy,
+              OnEvent: FnMut(QueueEvent<BusError<R, W>>),
+    {
+        let mut used = 0;
+        while used < max_slots && self.service_one(bus, &mut on_event) {
+            used += 1;
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::device::scs0009::{REGISTER_TARGET_POSITION_H, REGISTER_TARGET_SPEED_H};
+    use crate::device::SimTimer;
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::loopback::LoopbackBus;
+    extern crate std;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_scheduler() {
+        let mut emulator = BusEmulator::<2>::new(1, 2);
+        let mut loopback = LoopbackBus::new();
+        loopback.add_slave(IdSet::from_ids(&[1, 2]), |packet, buffer| emulator.handle_packet(packet, buffer));
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_secs(1),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, SimTimer>::new(loopback.reader(), loopback.writer(), config);
+
+        let mut scheduler = Scheduler::<SimTimer, 8>::new(IdSet::from_ids(&[1, 2]), 2);
+        for (id, address) in [(1, REGISTER_TARGET_POSITION_H.address), (2, REGISTER_TARGET_POSITION_H.address), (1, REGISTER_TARGET_SPEED_H.address)] {
+            let command = QueuedCommand::write(id, address, &[0x01, 0x00]).unwrap();
+            scheduler.push(command, Priority::Setpoint, None).unwrap();
+        }
+        let mut events = Vec::new();
+        assert_eq!(scheduler.service(&mut bus, 6, |event| events.push(event)), 6);
+        let summary = events.iter().filter_map(|event| match event {
+            QueueEvent::Sent { command, priority } => Some((command.id(), *priority)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        // A poll after every two commands, then only polls once no command is pending.
+        assert_eq!(summary, [
+            (1, Priority::Setpoint),
+            (2, Priority::Setpoint),
+            (1, Priority::Telemetry),
+            (1, Priority::Setpoint),
+            (2, Priority::Telemetry),
+            (1, Priority::Telemetry),
+        ]);
+        assert!(scheduler.queue().is_empty());
+
+        // Nothing to do without servos to poll.
+        scheduler.telemetry_mut().remove(1);
+        scheduler.telemetry_mut().remove(2);
+        assert!(!scheduler.service_one(&mut bus, &mut |_| {}));
+    }
+}
@@ -0,0 +1,233 @@
+//! Startup self-test.
+//!
+//! [`self_test`] checks each servo before a robot starts moving:
+//!
+//! 1. Communication: the status block is read.
+//! 2. Voltage: the supply voltage is within the configured range.
+//! 3. Torque: the torque switch is set and read back.
+//! 4. Motion: the servo is moved by a few steps away from the nearer position limit and back again.
+//!
+//! A check is skipped when an earlier one failed. The torque switch is restored to its previous value
+//! at the end, so a servo which was limp before the test is limp afterwards.
+
+use core::time::Duration;
+
+use crate::bus::{Bus, BusError};
+use crate::device::scs0009::{REGISTER_LOWER_POSITION_LIMIT_H, REGISTER_TARGET_POSITION_H, REGISTER_TORQUE_SWITCH, WORD_ORDER};
+use crate::device::{Instant, Timer};
+use crate::policy::WritePolicy;
+use crate::protocol::{StreamReader, StreamWriter};
+
+#[derive(Debug, Clone)]
+pub struct SelfTestConfig {
+    /// Accepted supply voltage range in 0.1 V.
+    pub min_voltage: u8,
+    pub max_voltage: u8,
+    /// Size of the test motion in position steps. 0 skips the motion check.
+    pub motion: u16,
+    /// Distance from the target within which the motion counts as reached.
+    pub tolerance: u16,
+    /// Time the servo has to reach the target.
+    pub motion_timeout: Duration,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            min_voltage: 45,
+            max_voltage: 70,
+            motion: 20,
+            tolerance: 3,
+            motion_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckResult {
+    Passed,
+    Failed,
+    /// An earlier check failed, or the check is disabled.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServoReport {
+    pub id: u8,
+    pub communication: CheckResult,
+    pub voltage: CheckResult,
+    pub torque: CheckResult,
+    pub motion: CheckResult,
+    /// Supply voltage in 0.1 V.
+    pub measured_voltage: Option<u8>,
+    /// Distance from the target when the motion check ended.
+    pub motion_error: Option<u16>,
+}
+
+impl ServoReport {
+    fn new(id: u8) -> Self {
+        Self {
+            id,
+            communication: CheckResult::Skipped,
+            voltage: CheckResult::Skipped,
+            torque: CheckResult::Skipped,
+            motion: CheckResult::Skipped,
+            measured_voltage: None,
+            motion_error: None,
+        }
+    }
+
+    /// Whether no check failed.
+    pub fn passed(&self) -> bool {
+        [self.communication, self.voltage, self.torque, self.motion].iter().all(|result| *result != CheckResult::Failed)
+    }
+}
+
+fn check(passed: bool) -> CheckResult {
+    if passed { CheckResult::Passed } else { CheckResult::Failed }
+}
+
+/// Moves servo `id` to `target` and waits until it is within the tolerance.
+/// Returns the remaining distance, which exceeds the tolerance if the servo did not get there in time.
+fn move_to<R, W, T, const BUFFER_SIZE: usize, P>(bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, id: u8, target: u16, config: &SelfTestConfig) -> Result<u16, BusError<R, W>>
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          P: WritePolicy,
+{
+    bus.write_register(id, REGISTER_TARGET_POSITION_H.address, &WORD_ORDER.to_bytes(target))?;
+    let start = T::now();
+    loop {
+        let distance = bus.read_status_block(id)?.position.abs_diff(target);
+        if distance <= config.tolerance || start.elapsed() >= config.motion_timeout {
+            return Ok(distance);
+        }
+    }
+}
+
+fn test_motion<R, W, T, const BUFFER_SIZE: usize, P>(bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, report: &mut ServoReport, position: u16, config: &SelfTestConfig) -> Result<(), BusError<R, W>>
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          P: WritePolicy,
+{
+    let id = report.id;
+    let mut limits = [0; 4];
+    bus.read_register(id, REGISTER_LOWER_POSITION_LIMIT_H.address, &mut limits)?;
+    let lower = WORD_ORDER.from_bytes([limits[0], limits[1]]);
+    let upper = WORD_ORDER.from_bytes([limits[2], limits[3]]);
+    // Move away from the nearer limit. Servos with less room than the motion are not moved.
+    let target = if position.saturating_add(config.motion) <= upper {
+        position + config.motion
+    } else if position >= lower.saturating_add(config.motion) {
+        position - config.motion
+    } else {
+        return Ok(());
+    };
+    let distance = move_to(bus, id, target, config)?;
+    report.motion_error = Some(distance);
+    report.motion = check(distance <= config.tolerance);
+    move_to(bus, id, position, config)?;
+    Ok(())
+}
+
+/// Runs the checks on servo `id`.
+pub fn test_servo<R, W, T, const BUFFER_SIZE: usize, P>(bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, id: u8, config: &SelfTestConfig) -> ServoReport
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          P: WritePolicy,
+{
+    let mut report = ServoReport::new(id);
+    let status = bus.read_status_block(id);
+    report.communication = check(status.is_ok());
+    let status = match status {
+        Ok(status) => status,
+        Err(_) => return report,
+    };
+    report.measured_voltage = Some(status.voltage);
+    report.voltage = check((config.min_voltage..=config.max_voltage).contains(&status.voltage));
+    if report.voltage == CheckResult::Failed {
+        return report;
+    }
+
+    let mut torque = [0];
+    if bus.read_register(id, REGISTER_TORQUE_SWITCH.address, &mut torque).is_err() {
+        report.torque = CheckResult::Failed;
+        return report;
+    }
+    let mut enabled = [0];
+    let result = bus.write_register(id, REGISTER_TORQUE_SWITCH.address, &[1])
+        .and_then(|_| bus.read_register(id, REGISTER_TORQUE_SWITCH.address, &mut enabled));
+    report.torque = check(result.is_ok() && enabled == [1]);
+    if report.torque == CheckResult::Passed && config.motion > 0 && test_motion(bus, &mut report, status.position, config).is_err() {
+        report.motion = CheckResult::Failed;
+    }
+    bus.write_register(id, REGISTER_TORQUE_SWITCH.address, &torque).ok();
+    report
+}
+
+/// Runs the checks on each servo in `ids` and passes the reports to `on_report`.
+/// Returns whether all servos passed.
+pub fn self_test<R, W, T, const BUFFER_SIZE: usize, P, OnReport>(bus: &mut Bus<R, W, T, BUFFER_SIZE, P>, ids: &[u8], config: &SelfTestConfig, mut on_report: OnReport) -> bool
+    where R: StreamReader,
+          W: StreamWriter,
+          T: Timer,
+          P: WritePolicy,
+          OnReport: FnMut(&ServoReport),
+{
+    let mut passed = true;
+    for &id in ids {
+        let report = test_servo(bus, id, config);
+        passed &= report.passed();
+        on_report(&report);
+    }
+    passed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::device::scs0009::{REGISTER_CURRENT_VOLTAGE, REGISTER_UPPER_POSITION_LIMIT_H};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    extern crate std;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_self_test() {
+        let mut emulator = BusEmulator::<3>::new(1, 3);
+        emulator.servo_mut(2).unwrap().registers_mut()[REGISTER_CURRENT_VOLTAGE.address as usize] = 40;
+        // Servo 3 has no room to move.
+        let registers = emulator.servo_mut(3).unwrap().registers_mut();
+        registers[REGISTER_LOWER_POSITION_LIMIT_H.address as usize..][..2].copy_from_slice(&WORD_ORDER.to_bytes(0x01ff));
+        registers[REGISTER_UPPER_POSITION_LIMIT_H.address as usize..][..2].copy_from_slice(&WORD_ORDER.to_bytes(0x0200));
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn_moving(emulator);
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(50),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        let mut reports = Vec::new();
+        let passed = self_test(&mut bus, &[1, 2, 3, 4], &SelfTestConfig::default(), |report| reports.push(report.clone()));
+        assert!(!passed);
+
+        assert!(reports[0].passed());
+        assert_eq!(reports[0].motion, CheckResult::Passed);
+        assert!(reports[0].motion_error.unwrap() <= 3);
+        assert_eq!((reports[1].voltage, reports[1].measured_voltage, reports[1].torque), (CheckResult::Failed, Some(40), CheckResult::Skipped));
+        assert!(reports[2].passed());
+        assert_eq!((reports[2].torque, reports[2].motion), (CheckResult::Passed, CheckResult::Skipped));
+        assert_eq!((reports[3].communication, reports[3].voltage), (CheckResult::Failed, CheckResult::Skipped));
+
+        let emulator = thread.stop();
+        // Servo 1 went back to where it started and is limp again.
+        let servo = emulator.servo(1).unwrap();
+        assert!(servo.position().abs_diff(0x01ff) <= 3);
+        assert_eq!(servo.registers()[REGISTER_TORQUE_SWITCH.address as usize], 0);
+    }
+}
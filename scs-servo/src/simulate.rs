@@ -0,0 +1,73 @@
+//! In-process bus simulation.
+//!
+//! [`Simulation`] runs a [`BusEmulator`] with its motion model on a background thread behind a pair of
+//! channels, so the library and the tools can be tried without any hardware: the receiver and the sender
+//! returned by [`Simulation::start`] go wherever a reader and a writer are expected. The examples of this
+//! crate run on it. With [`Simulation::start_with_scenario`], the servos fail as scripted by a [`Scenario`].
+
+extern crate std;
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::emulator::BusEmulator;
+use crate::scenario::{Scenario, ScenarioPlayer};
+use crate::testing::emulator_thread::EmulatorThread;
+
+/// Maximum number of servos in a simulation.
+pub const MAX_SIMULATED_SERVOS: usize = 32;
+
+pub struct Simulation {
+    thread: EmulatorThread<MAX_SIMULATED_SERVOS>,
+}
+
+impl Simulation {
+    /// Starts `count` servos with consecutive IDs from `base_id`.
+    /// Returns the simulation and the reader and the writer of the master side.
+    pub fn start(base_id: u8, count: usize) -> (Self, Receiver<u8>, Sender<u8>) {
+        Self::start_with(BusEmulator::new(base_id, count))
+    }
+
+    /// Runs `emulator`, e.g. one whose registers were prepared for a scenario.
+    pub fn start_with(emulator: BusEmulator<MAX_SIMULATED_SERVOS>) -> (Self, Receiver<u8>, Sender<u8>) {
+        Self::start_with_scenario(emulator, Scenario::default())
+    }
+
+    /// Runs `emulator` and applies the events of `scenario` in real time from now.
+    pub fn start_with_scenario(emulator: BusEmulator<MAX_SIMULATED_SERVOS>, scenario: Scenario) -> (Self, Receiver<u8>, Sender<u8>) {
+        let mut player = ScenarioPlayer::new(scenario);
+        let (thread, reader, writer) = EmulatorThread::spawn_with(emulator, move |emulator, elapsed| {
+            player.advance(emulator, elapsed);
+            emulator.update(elapsed);
+        });
+        (Self { thread }, reader, writer)
+    }
+
+    /// Stops the simulation and returns the emulator, e.g. to inspect the registers.
+    pub fn stop(self) -> BusEmulator<MAX_SIMULATED_SERVOS> {
+        self.thread.stop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, BusConfig, BusMode};
+    use crate::protocol::ProtocolMasterConfig;
+    use core::time::Duration;
+
+    #[test]
+    fn test_simulation() {
+        let (simulation, reader, writer) = Simulation::start(3, 2);
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(50),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(reader, writer, config);
+        bus.ping(3).unwrap();
+        bus.ping(4).unwrap();
+        assert!(bus.ping(5).is_err());
+        bus.write_register(4, 0x28, &[1]).unwrap();
+        let emulator = simulation.stop();
+        assert_eq!(emulator.servo(4).unwrap().registers()[0x28], 1);
+    }
+}
@@ -0,0 +1,193 @@
+//! Non-volatile storage for EEPROM-class registers.
+//!
+//! A servo keeps its configuration (ID, baud rate, limits, ...) in EEPROM.
+//! [`RegisterBank`](crate::bank::RegisterBank) uses [`NonVolatileStorage`] to load these registers
+//! at start-up and to persist them when the master writes to them.
+//!
+//! On embedded targets the trait is expected to be implemented on top of the
+//! on-chip flash or an external EEPROM:
+//!
+//! - `read` copies bytes out of the persisted image (or a RAM mirror of it).
+//! - `write` only updates a RAM mirror and marks the affected page dirty,
+//!   because flash must be erased before it can be reprogrammed.
+//! - `commit` erases and reprograms the dirty pages. It is called once the
+//!   master has finished a write transaction, so wear is bounded by the number
+//!   of write commands rather than the number of bytes written.
+
+pub trait NonVolatileStorage {
+    type Error;
+    /// Reads `data.len()` bytes starting at `address`.
+    fn read(&mut self, address: u8, data: &mut [u8]) -> Result<(), Self::Error>;
+    /// Stages `data` to be stored at `address`. The data may not be persisted until `commit` is called.
+    fn write(&mut self, address: u8, data: &[u8]) -> Result<(), Self::Error>;
+    /// Persists all staged writes.
+    fn commit(&mut self) -> Result<(), Self::Error>;
+}
+
+/// No storage: nothing is loaded, and writes are dropped.
+impl NonVolatileStorage for () {
+    type Error = core::convert::Infallible;
+    fn read(&mut self, _address: u8, _data: &mut [u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn write(&mut self, _address: u8, _data: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    OutOfRange,
+}
+
+fn check_range(address: u8, length: usize, size: usize) -> Result<core::ops::Range<usize>, StorageError> {
+    let start = address as usize;
+    let end = start + length;
+    if end > size {
+        Err(StorageError::OutOfRange)
+    } else {
+        Ok(start..end)
+    }
+}
+
+/// Volatile storage which keeps the image in RAM. Useful for tests and emulators which do not need persistence.
+pub struct MemoryStorage<const SIZE: usize> {
+    image: [u8; SIZE],
+}
+
+impl<const SIZE: usize> MemoryStorage<SIZE> {
+    pub fn new() -> Self {
+        Self { image: [0; SIZE] }
+    }
+    pub fn from_image(image: [u8; SIZE]) -> Self {
+        Self { image }
+    }
+    pub fn image(&self) -> &[u8; SIZE] {
+        &self.image
+    }
+}
+
+impl<const SIZE: usize> Default for MemoryStorage<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> NonVolatileStorage for MemoryStorage<SIZE> {
+    type Error = StorageError;
+    fn read(&mut self, address: u8, data: &mut [u8]) -> Result<(), Self::Error> {
+        let range = check_range(address, data.len(), SIZE)?;
+        data.copy_from_slice(&self.image[range]);
+        Ok(())
+    }
+    fn write(&mut self, address: u8, data: &[u8]) -> Result<(), Self::Error> {
+        let range = check_range(address, data.len(), SIZE)?;
+        self.image[range].copy_from_slice(data);
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+extern crate std;
+
+/// Storage backed by a file on the host file system.
+///
+/// The whole image is kept in memory and written back to the file on `commit`.
+#[cfg(feature = "std")]
+pub struct FileStorage {
+    path: std::path::PathBuf,
+    image: std::vec::Vec<u8>,
+    dirty: bool,
+}
+
+#[cfg(feature = "std")]
+impl FileStorage {
+    /// Opens the storage file. If the file does not exist, the storage is initialized with `size` bytes of zero.
+    pub fn open<P: AsRef<std::path::Path>>(path: P, size: usize) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut image = match std::fs::read(&path) {
+            Ok(image) => image,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => std::vec::Vec::new(),
+            Err(err) => return Err(err),
+        };
+        image.resize(size, 0);
+        Ok(Self { path, image, dirty: false })
+    }
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "std")]
+fn out_of_range() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, "storage access out of range")
+}
+
+#[cfg(feature = "std")]
+impl NonVolatileStorage for FileStorage {
+    type Error = std::io::Error;
+    fn read(&mut self, address: u8, data: &mut [u8]) -> Result<(), Self::Error> {
+        let range = check_range(address, data.len(), self.image.len()).map_err(|_| out_of_range())?;
+        data.copy_from_slice(&self.image[range]);
+        Ok(())
+    }
+    fn write(&mut self, address: u8, data: &[u8]) -> Result<(), Self::Error> {
+        let range = check_range(address, data.len(), self.image.len()).map_err(|_| out_of_range())?;
+        if self.image[range.clone()] != *data {
+            self.image[range].copy_from_slice(data);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<(), Self::Error> {
+        if self.dirty {
+            std::fs::write(&self.path, &self.image)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::<16>::new();
+        storage.write(0x04, &[0x12, 0x34]).unwrap();
+        storage.commit().unwrap();
+        let mut data = [0; 2];
+        storage.read(0x04, &mut data).unwrap();
+        assert_eq!(data, [0x12, 0x34]);
+        assert!(matches!(storage.write(0x0f, &[0x00, 0x00]), Err(StorageError::OutOfRange)));
+        assert!(matches!(storage.read(0x10, &mut data), Err(StorageError::OutOfRange)));
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let path = std::env::temp_dir().join(std::format!("scs-servo-storage-{}.bin", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        {
+            let mut storage = FileStorage::open(&path, 0x28).unwrap();
+            storage.write(0x05, &[0x07]).unwrap();
+            assert!(!path.exists());
+            storage.commit().unwrap();
+            assert!(storage.write(0x28, &[0x00]).is_err());
+        }
+        {
+            let mut storage = FileStorage::open(&path, 0x28).unwrap();
+            let mut data = [0];
+            storage.read(0x05, &mut data).unwrap();
+            assert_eq!(data, [0x07]);
+        }
+        std::fs::remove_file(&path).ok();
+    }
+}
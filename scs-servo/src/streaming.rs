@@ -0,0 +1,398 @@
+//! Write-ahead streaming of setpoints.
+//!
+//! Waiting for the response of every write limits the update rate to one round trip per servo, which is too slow
+//! for 200 Hz and more on a rig with several servos. [`SetpointStream`] sends setpoint [`Frame`]s at a fixed period
+//! instead and drains the responses whenever it is polled, without waiting for them.
+//!
+//! The write-ahead is bounded: a frame is held back while more than [`StreamConfig::max_outstanding`] packets are
+//! still expected back, and a frame the transport would block on is finished before the next one starts. Only the
+//! latest frame submitted is kept, so a congested bus skips stale setpoints and the back-pressure shows up as
+//! [`StreamStatus::Throttled`] and [`StreamStatus::Blocked`] instead of a growing delay.
+
+use core::time::Duration;
+
+use crate::device::{Instant, Timer};
+use crate::packet::PacketError;
+use crate::protocol::{ProtocolHandlerError, ProtocolReader, ProtocolReaderError, StreamReader, StreamWriter, SyncWriteCommand, WriteRegisterCommand, STANDARD_BUFFER_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// Time between the starts of consecutive frames.
+    pub period: Duration,
+    /// Number of packets expected back (responses and echoes) above which the next frame is held back.
+    pub max_outstanding: usize,
+    /// The packets still expected back are counted as lost once nothing has been received for this long.
+    pub response_timeout: Duration,
+    /// Whether the adapter echoes back the transmitted packets.
+    pub echo_back: bool,
+}
+
+/// Commands sent together at one tick of the stream.
+#[derive(Debug, Clone)]
+pub struct Frame<const SIZE: usize> {
+    raw: [u8; SIZE],
+    len: usize,
+    packets: usize,
+    responses: usize,
+}
+
+impl<const SIZE: usize> Default for Frame<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> Frame<SIZE> {
+    pub fn new() -> Self {
+        Self { raw: [0; SIZE], len: 0, packets: 0, responses: 0 }
+    }
+
+    /// Number of bytes of the frame.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// The packets of the frame, back to back.
+    pub fn bytes(&self) -> &[u8] {
+        &self.raw[..self.len]
+    }
+    /// Number of responses the servos send for the frame.
+    pub fn responses(&self) -> usize {
+        self.responses
+    }
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.packets = 0;
+        self.responses = 0;
+    }
+
+    fn push(&mut self, packet: &[u8], responses: usize) -> Result<(), PacketError> {
+        let end = self.len + packet.len();
+        if end > SIZE {
+            return Err(PacketError::InvalidLength);
+        }
+        self.raw[self.len..end].copy_from_slice(packet);
+        self.len = end;
+        self.packets += 1;
+        self.responses += responses;
+        Ok(())
+    }
+
+    /// Adds a WRITE command. `responds` is whether the servo answers it, see
+    /// [`Bus::responds_to_writes`](crate::bus::Bus::responds_to_writes).
+    /// Fails with [`PacketError::InvalidLength`] if the frame is full.
+    pub fn push_write<const N: usize>(&mut self, command: &WriteRegisterCommand<N>, responds: bool) -> Result<(), PacketError> {
+        self.push(command.packet(), responds as usize)
+    }
+    /// Adds a SYNC WRITE command, which is not answered.
+    /// Fails with [`PacketError::InvalidLength`] if the frame is full.
+    pub fn push_sync_write<const N: usize>(&mut self, command: &SyncWriteCommand<N>) -> Result<(), PacketError> {
+        self.push(command.packet(), 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStatus {
+    /// No frame is waiting to be sent.
+    Idle,
+    /// The next frame is not due yet.
+    Waiting,
+    /// A frame has been sent completely.
+    Sent,
+    /// The transport would block, so the rest of the frame is sent by the next polls.
+    Blocked,
+    /// Too many packets are expected back, so the next frame is held back.
+    Throttled,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub sent: u32,
+    /// Frames replaced by a newer one before they were sent.
+    pub replaced: u32,
+    /// Valid packets received, including echoes.
+    pub received: u32,
+    /// Packets expected back which were corrupted or did not arrive.
+    pub lost: u32,
+    /// Polls which returned [`StreamStatus::Blocked`] or [`StreamStatus::Throttled`].
+    pub backpressure: u32,
+}
+
+/// Sends the latest submitted frame at a fixed period without waiting for the responses.
+///
+/// The echoes of the frames are received into a buffer of `BUFFER_SIZE` bytes, which must hold the longest command
+/// of a frame if the adapter echoes back.
+pub struct SetpointStream<T: Timer, const SIZE: usize, const BUFFER_SIZE: usize = STANDARD_BUFFER_SIZE> {
+    config: StreamConfig,
+    reader: ProtocolReader<BUFFER_SIZE>,
+    /// The frame being sent and the number of its bytes written.
+    sending: Option<(Frame<SIZE>, usize)>,
+    next: Option<Frame<SIZE>>,
+    outstanding: usize,
+    epoch: T::Instant,
+    /// Time since `epoch` when the next frame is due.
+    due: Duration,
+    /// Time since `epoch` when a frame was last sent or a packet last received.
+    last_activity: Duration,
+    stats: StreamStats,
+}
+
+impl<T: Timer, const SIZE: usize, const BUFFER_SIZE: usize> SetpointStream<T, SIZE, BUFFER_SIZE> {
+    pub fn new(config: StreamConfig) -> Self {
+        Self {
+            config,
+            reader: ProtocolReader::new(),
+            sending: None,
+            next: None,
+            outstanding: 0,
+            epoch: T::now(),
+            due: Duration::ZERO,
+            last_activity: Duration::ZERO,
+            stats: StreamStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &StreamConfig {
+        &self.config
+    }
+    pub fn stats(&self) -> &StreamStats {
+        &self.stats
+    }
+    /// Number of packets expected back and not received yet.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+    /// Whether a frame is partially sent or the write-ahead limit is reached, so a new frame would be delayed.
+    pub fn is_congested(&self) -> bool {
+        self.sending.is_some() || self.outstanding >= self.config.max_outstanding
+    }
+
+    /// Submits the frame to send at the next tick. Returns the frame which was waiting and is now replaced.
+    pub fn submit(&mut self, frame: Frame<SIZE>) -> Option<Frame<SIZE>> {
+        let replaced = self.next.replace(frame);
+        if replaced.is_some() {
+            self.stats.replaced = self.stats.replaced.saturating_add(1);
+        }
+        replaced
+    }
+
+    /// Drains the received packets and sends the next frame if it is due. Never blocks.
+    pub fn poll<R: StreamReader, W: StreamWriter>(&mut self, reader: &mut R, writer: &mut W) -> Result<StreamStatus, ProtocolHandlerError<R::Error, W::Error>> {
+        self.drain(reader)?;
+        let now = self.epoch.elapsed();
+        if self.outstanding > 0 && now.saturating_sub(self.last_activity) > self.config.response_timeout {
+            self.lose(self.outstanding);
+        }
+        if self.sending.is_none() {
+            let Some(frame) = &self.next else {
+                return Ok(StreamStatus::Idle);
+            };
+            if now < self.due {
+                return Ok(StreamStatus::Waiting);
+            }
+            // A frame larger than the limit is still sent once nothing is outstanding.
+            if self.outstanding > 0 && self.outstanding + self.expected(frame) > self.config.max_outstanding {
+                self.stats.backpressure = self.stats.backpressure.saturating_add(1);
+                return Ok(StreamStatus::Throttled);
+            }
+            // Keeps the cadence unless the stream has fallen behind by a whole period.
+            self.due = if now >= self.due + self.config.period { now + self.config.period } else { self.due + self.config.period };
+            self.sending = self.next.take().map(|frame| (frame, 0));
+        }
+        self.send(writer)
+    }
+
+    /// Number of packets expected back for `frame`.
+    fn expected(&self, frame: &Frame<SIZE>) -> usize {
+        frame.responses + if self.config.echo_back { frame.packets } else { 0 }
+    }
+
+    fn send<R, W: StreamWriter>(&mut self, writer: &mut W) -> Result<StreamStatus, ProtocolHandlerError<R, W::Error>> {
+        let Some((frame, written)) = &mut self.sending else {
+            return Ok(StreamStatus::Idle);
+        };
+        while *written < frame.len() {
+            match writer.write(&frame.bytes()[*written..]) {
+                Ok(0) | Err(nb::Error::WouldBlock) => {
+                    self.stats.backpressure = self.stats.backpressure.saturating_add(1);
+                    return Ok(StreamStatus::Blocked);
+                }
+                Ok(bytes_written) => *written += bytes_written,
+                Err(nb::Error::Other(err)) => {
+                    self.sending = None;
+                    return Err(ProtocolHandlerError::WriterError(err));
+                }
+            }
+        }
+        let expected = self.sending.take().map_or(0, |(frame, _)| self.expected(&frame));
+        self.outstanding += expected;
+        self.last_activity = self.epoch.elapsed();
+        self.stats.sent = self.stats.sent.saturating_add(1);
+        Ok(StreamStatus::Sent)
+    }
+
+    fn drain<R: StreamReader, W>(&mut self, reader: &mut R) -> Result<(), ProtocolHandlerError<R::Error, W>> {
+        loop {
+            match self.reader.read(reader) {
+                Ok(true) => {
+                    self.last_activity = self.epoch.elapsed();
+                    loop {
+                        if self.reader.packet().is_some_and(|packet| packet.verify_checksum().is_ok()) {
+                            self.outstanding = self.outstanding.saturating_sub(1);
+                            self.stats.received = self.stats.received.saturating_add(1);
+                            break;
+                        }
+                        self.lose(1);
+                        // The corrupted response may have swallowed the start of the next one.
+                        if !self.reader.resync() {
+                            break;
+                        }
+                    }
+                }
+                Ok(false) => return Ok(()),
+                Err(ProtocolReaderError::ReaderError(err)) => return Err(ProtocolHandlerError::ReaderError(err)),
+                Err(_) => self.lose(1),
+            }
+        }
+    }
+
+    /// Writes off `count` packets expected back.
+    fn lose(&mut self, count: usize) {
+        self.outstanding = self.outstanding.saturating_sub(count);
+        self.stats.lost = self.stats.lost.saturating_add(count as u32);
+        self.reader.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{REGISTER_TARGET_POSITION_H, WORD_ORDER};
+    use crate::device::SimTimer;
+    use crate::emulator::BusEmulator;
+    use crate::protocol::{sync_write_command_size, write_command_size};
+    extern crate std;
+    use std::vec::Vec;
+
+    const CONFIG: StreamConfig = StreamConfig {
+        period: Duration::from_millis(5),
+        max_outstanding: 2,
+        response_timeout: Duration::from_millis(20),
+        echo_back: false,
+    };
+
+    fn frame(positions: [u16; 2]) -> Frame<32> {
+        let mut frame = Frame::new();
+        for (id, position) in (1..).zip(positions) {
+            let command = WriteRegisterCommand::<{ write_command_size(2) }>::builder(id)
+                .address(REGISTER_TARGET_POSITION_H.address)
+                .data(&WORD_ORDER.to_bytes(position))
+                .build()
+                .unwrap();
+            frame.push_write(&command, true).unwrap();
+        }
+        frame
+    }
+
+    /// Accepts up to `capacity` bytes and blocks once they are taken.
+    struct SlowWriter {
+        written: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl StreamWriter for SlowWriter {
+        type Error = ();
+        fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+            let length = data.len().min(self.capacity);
+            if length == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.written.extend_from_slice(&data[..length]);
+            self.capacity -= length;
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn test_stream_throttled() {
+        SimTimer::reset();
+        let (mut master_writer, mut emulator_reader) = std::sync::mpsc::channel();
+        let (mut emulator_writer, mut master_reader) = std::sync::mpsc::channel();
+        let mut emulator = BusEmulator::<2>::new(1, 2);
+        let mut stream = SetpointStream::<SimTimer, 32>::new(CONFIG);
+        let target = |emulator: &BusEmulator<2>, id| {
+            let registers = emulator.servo(id).unwrap().registers();
+            WORD_ORDER.from_bytes([registers[REGISTER_TARGET_POSITION_H.address as usize], registers[REGISTER_TARGET_POSITION_H.address as usize + 1]])
+        };
+
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Idle);
+        stream.submit(frame([0x100, 0x180]));
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Sent);
+        assert_eq!(stream.outstanding(), 2);
+
+        // Only the latest frame is sent, at the next tick, and not before the responses of the previous one arrived.
+        assert!(stream.submit(frame([0x110, 0x190])).is_none());
+        assert!(stream.submit(frame([0x120, 0x1a0])).is_some());
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Waiting);
+        SimTimer::advance(Duration::from_millis(5));
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Throttled);
+        assert!(stream.is_congested());
+        for _ in 0..16 {
+            emulator.process(&mut emulator_reader, &mut emulator_writer).unwrap();
+        }
+        assert_eq!((target(&emulator, 1), target(&emulator, 2)), (0x100, 0x180));
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Sent);
+        for _ in 0..16 {
+            emulator.process(&mut emulator_reader, &mut emulator_writer).unwrap();
+        }
+        assert_eq!((target(&emulator, 1), target(&emulator, 2)), (0x120, 0x1a0));
+
+        // Responses which never arrive are written off after the timeout.
+        emulator.servo_mut(2).unwrap().faults_mut().silent = true;
+        SimTimer::advance(Duration::from_millis(5));
+        stream.submit(frame([0x130, 0x1b0]));
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Sent);
+        for _ in 0..16 {
+            emulator.process(&mut emulator_reader, &mut emulator_writer).unwrap();
+        }
+        SimTimer::advance(Duration::from_millis(5));
+        stream.submit(frame([0x140, 0x1c0]));
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Throttled);
+        SimTimer::advance(Duration::from_millis(25));
+        assert_eq!(stream.poll(&mut master_reader, &mut master_writer).unwrap(), StreamStatus::Sent);
+        assert_eq!(*stream.stats(), StreamStats { sent: 4, replaced: 1, received: 5, lost: 1, backpressure: 2 });
+    }
+
+    #[test]
+    fn test_stream_blocked() {
+        SimTimer::reset();
+        let (_writer, mut reader) = std::sync::mpsc::channel::<u8>();
+        let mut writer = SlowWriter { written: Vec::new(), capacity: 10 };
+        let mut stream = SetpointStream::<SimTimer, 32>::new(StreamConfig { max_outstanding: 0, ..CONFIG });
+        let mut frame = Frame::<32>::new();
+        let command = SyncWriteCommand::<{ sync_write_command_size(2, 2) }>::builder(REGISTER_TARGET_POSITION_H.address, 2)
+            .servo(1, &[0x01, 0x00])
+            .servo(2, &[0x02, 0x00])
+            .build()
+            .unwrap();
+        frame.push_sync_write(&command).unwrap();
+        assert!(frame.push_sync_write(&command).is_ok());
+        assert!(matches!(frame.push_sync_write(&command), Err(PacketError::InvalidLength)));
+
+        stream.submit(frame.clone());
+        assert_eq!(stream.poll(&mut reader, &mut writer).unwrap(), StreamStatus::Blocked);
+        assert!(stream.is_congested());
+        // The frame being sent is finished first, even if it is overdue.
+        SimTimer::advance(Duration::from_millis(20));
+        stream.submit(Frame::new());
+        writer.capacity = 100;
+        assert_eq!(stream.poll(&mut reader, &mut writer).unwrap(), StreamStatus::Sent);
+        assert_eq!(writer.written, frame.bytes());
+        // Broadcasts are not answered, so the stream is not throttled.
+        assert_eq!(stream.outstanding(), 0);
+        assert_eq!(stream.poll(&mut reader, &mut writer).unwrap(), StreamStatus::Sent);
+        assert_eq!(stream.stats().backpressure, 1);
+    }
+}
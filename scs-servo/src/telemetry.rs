@@ -0,0 +1,435 @@
+//! Time-synchronized telemetry.
+//!
+//! [`TelemetryPoller`] reads the status block of a fixed set of servos once per cycle and collects the
+//! samples into a [`TelemetryFrame`], one row with all joints. Every sample is timestamped against the
+//! same monotonic clock, which starts when the poller is created, so frames from different cycles and
+//! samples within a frame can be compared directly, e.g. for kinematic logging.
+//!
+//! With an [`AdaptiveRate`], the poller polls every cycle while a servo is moving and doubles the number of cycles
+//! between polls while all servos are idle, up to a slow keep-alive period. [`TelemetryPoller::wake`] returns to the
+//! full rate at once, e.g. after a new target was written.
+//!
+//! [`TelemetryWatcher`] evaluates conditions on the frames, such as a position change above a threshold,
+//! a temperature crossing a limit or an alarm bit being set, and reports only the changes, so applications
+//! do not have to compare every sample with the previous one themselves.
+
+use core::time::Duration;
+
+use crate::bus::{Bus, BusError};
+use crate::device::scs0009::{AlarmFlags, SPEED_ENCODING};
+use crate::device::{Instant, StatusBlock, Timer};
+use crate::protocol::{ProtocolHandlerError, ProtocolReaderError, StreamReader, StreamWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub id: u8,
+    /// Time since the start of the poller, taken at the middle of the transaction.
+    pub timestamp: Duration,
+    /// `None` if the servo did not answer in this cycle.
+    pub status: Option<StatusBlock>,
+    /// Alarm flags in the error byte of the response. Empty if the servo did not answer.
+    pub alarms: AlarmFlags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryFrame<const N: usize> {
+    /// Index of the cycle. Skips the cycles which were missed because polling took longer than the period.
+    pub cycle: u32,
+    /// Scheduled start of the cycle since the start of the poller.
+    pub start: Duration,
+    pub samples: [Sample; N],
+}
+
+impl<const N: usize> TelemetryFrame<N> {
+    /// Time between the first and the last sample which has a status.
+    pub fn skew(&self) -> Duration {
+        let mut timestamps = self.samples.iter().filter(|sample| sample.status.is_some()).map(|sample| sample.timestamp);
+        let first = match timestamps.next() {
+            Some(first) => first,
+            None => return Duration::ZERO,
+        };
+        let (min, max) = timestamps.fold((first, first), |(min, max), timestamp| (min.min(timestamp), max.max(timestamp)));
+        max - min
+    }
+    /// Whether every servo answered in this cycle.
+    pub fn is_complete(&self) -> bool {
+        self.samples.iter().all(|sample| sample.status.is_some())
+    }
+}
+
+/// Polling rate which follows the motion of the servos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveRate {
+    /// Longest time between polls while all servos are idle. Rounded down to a multiple of the period of the poller.
+    pub idle_period: Duration,
+    /// Speed magnitude above which a servo is moving, in raw steps.
+    pub speed_threshold: u16,
+    /// Position change between two polls above which a servo is moving, in steps.
+    pub position_threshold: u16,
+}
+
+pub struct TelemetryPoller<T: Timer, const N: usize> {
+    ids: [u8; N],
+    period: Duration,
+    epoch: T::Instant,
+    next_cycle: u32,
+    adaptive: Option<AdaptiveRate>,
+    /// Cycles from one poll to the next.
+    stride: u32,
+    positions: [Option<u16>; N],
+}
+
+impl<T: Timer, const N: usize> TelemetryPoller<T, N> {
+    /// Creates a poller which samples `ids` in order every `period`. A zero period polls back to back.
+    pub fn new(ids: [u8; N], period: Duration) -> Self {
+        Self {
+            ids,
+            period,
+            epoch: T::now(),
+            next_cycle: 0,
+            adaptive: None,
+            stride: 1,
+            positions: [None; N],
+        }
+    }
+
+    /// Adapts the polling rate to the motion of the servos. Has no effect with a zero period.
+    pub fn with_adaptive_rate(mut self, rate: AdaptiveRate) -> Self {
+        self.adaptive = Some(rate);
+        self
+    }
+
+    /// Time between the current polls, which is longer than the period while the servos are idle.
+    pub fn current_period(&self) -> Duration {
+        self.period * self.stride
+    }
+
+    /// Polls at the full rate again from the next cycle.
+    pub fn wake(&mut self) {
+        self.next_cycle = self.next_cycle - self.stride + 1;
+        self.stride = 1;
+    }
+
+    fn update_stride(&mut self, samples: &[Sample; N]) {
+        let Some(rate) = self.adaptive else { return };
+        let mut moving = false;
+        for (sample, position) in samples.iter().zip(self.positions.iter_mut()) {
+            let Some(status) = sample.status else { continue };
+            let moved = position.replace(status.position).is_some_and(|previous| previous.abs_diff(status.position) > rate.position_threshold);
+            moving |= moved || status.speed.to_signed(SPEED_ENCODING).unsigned_abs() > rate.speed_threshold;
+        }
+        let max_stride = match self.period.as_nanos() {
+            0 => 1,
+            period => (rate.idle_period.as_nanos() / period).clamp(1, u32::MAX as u128) as u32,
+        };
+        self.stride = if moving { 1 } else { (self.stride * 2).min(max_stride) };
+    }
+
+    pub fn ids(&self) -> &[u8; N] {
+        &self.ids
+    }
+    /// Time since the start of the poller on the clock the samples are timestamped with.
+    pub fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+
+    /// Waits for the start of the next cycle and samples all servos.
+    /// Servos which do not answer are reported without a status. Transport errors are returned.
+    pub fn poll<R: StreamReader, W: StreamWriter, const BUFFER_SIZE: usize>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE>) -> Result<TelemetryFrame<N>, BusError<R, W>> {
+        let mut cycle = self.next_cycle;
+        if !self.period.is_zero() {
+            // Skip the cycles which have already passed.
+            let current = (self.now().as_nanos() / self.period.as_nanos()) as u32;
+            if current > cycle {
+                cycle = current + 1;
+            }
+            while self.now() < self.period * cycle {}
+        }
+        let start = if self.period.is_zero() { self.now() } else { self.period * cycle };
+
+        let mut samples = [Sample { id: 0, timestamp: Duration::ZERO, status: None, alarms: AlarmFlags(0) }; N];
+        for (sample, id) in samples.iter_mut().zip(self.ids) {
+            let before = self.now();
+            let result = bus.read_status_block(id);
+            let after = self.now();
+            let status = match result {
+                Ok(status) => Some(status),
+                Err(ProtocolHandlerError::ReaderError(err)) | Err(ProtocolHandlerError::ProtocolReaderError(ProtocolReaderError::ReaderError(err))) => return Err(ProtocolHandlerError::ReaderError(err)),
+                Err(ProtocolHandlerError::WriterError(err)) => return Err(ProtocolHandlerError::WriterError(err)),
+                Err(_) => None,
+            };
+            let alarms = match status {
+                Some(_) => AlarmFlags(bus.response_status().map_or(0, |status| status.bits())),
+                None => AlarmFlags(0),
+            };
+            *sample = Sample { id, timestamp: before + (after - before) / 2, status, alarms };
+        }
+        self.update_stride(&samples);
+        self.next_cycle = cycle + self.stride;
+        Ok(TelemetryFrame { cycle, start, samples })
+    }
+
+    /// Polls like [`poll`](Self::poll) and passes the frame to `watcher`, which reports the changes to `on_change`.
+    pub fn poll_watched<R, W, const BUFFER_SIZE: usize, const M: usize, OnChange>(&mut self, bus: &mut Bus<R, W, T, BUFFER_SIZE>, watcher: &mut TelemetryWatcher<N, M>, on_change: OnChange) -> Result<TelemetryFrame<N>, BusError<R, W>>
+        where R: StreamReader,
+              W: StreamWriter,
+              OnChange: FnMut(TelemetryChange),
+    {
+        let frame = self.poll(bus)?;
+        watcher.update(&frame, on_change);
+        Ok(frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// The position moved more than `delta` steps away from the position of the previous report.
+    PositionChanged { delta: u16 },
+    /// The temperature rose to `threshold` degC or above, or fell below it again.
+    TemperatureCrossed { threshold: u8 },
+    /// An alarm in `mask` was set.
+    AlarmSet { mask: AlarmFlags },
+}
+
+/// Condition on the samples of servo `id`, or of all servos if `id` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watch {
+    pub id: Option<u8>,
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    PositionChanged { from: u16, to: u16 },
+    /// Reported for the first sample too if it is at or above the threshold.
+    TemperatureCrossed { temperature: u8, above: bool },
+    /// The alarms in the mask which were newly set.
+    AlarmSet { alarms: AlarmFlags },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryChange {
+    /// Index returned by `TelemetryWatcher::add`.
+    pub watch: usize,
+    pub id: u8,
+    /// Timestamp of the sample.
+    pub timestamp: Duration,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchError {
+    /// All `M` watches are in use.
+    Full,
+}
+
+/// State of a watch for one servo as of its previous report.
+#[derive(Debug, Clone, Copy, Default)]
+struct Baseline {
+    position: Option<u16>,
+    above: Option<bool>,
+    alarms: u8,
+}
+
+/// Up to `M` watches on the samples of a [`TelemetryPoller`] of `N` servos.
+pub struct TelemetryWatcher<const N: usize, const M: usize> {
+    watches: [Option<(Watch, [Baseline; N])>; M],
+}
+
+impl<const N: usize, const M: usize> Default for TelemetryWatcher<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const M: usize> TelemetryWatcher<N, M> {
+    pub fn new() -> Self {
+        Self {
+            watches: [None; M],
+        }
+    }
+
+    /// Adds `watch` and returns its index.
+    pub fn add(&mut self, watch: Watch) -> Result<usize, WatchError> {
+        let index = self.watches.iter().position(|slot| slot.is_none()).ok_or(WatchError::Full)?;
+        self.watches[index] = Some((watch, [Baseline::default(); N]));
+        Ok(index)
+    }
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        self.watches.get_mut(index)?.take().map(|(watch, _)| watch)
+    }
+
+    /// Evaluates the watches on `frame` and reports the changes to `on_change`.
+    /// Servos which did not answer in this cycle are skipped and keep their baselines.
+    pub fn update<OnChange: FnMut(TelemetryChange)>(&mut self, frame: &TelemetryFrame<N>, mut on_change: OnChange) {
+        for (index, (watch, baselines)) in self.watches.iter_mut().enumerate().filter_map(|(index, slot)| slot.as_mut().map(|slot| (index, slot))) {
+            for (sample, baseline) in frame.samples.iter().zip(baselines.iter_mut()) {
+                let status = match sample.status {
+                    Some(status) if watch.id.is_none_or(|id| id == sample.id) => status,
+                    _ => continue,
+                };
+                let change = match watch.condition {
+                    Condition::PositionChanged { delta } => match baseline.position {
+                        Some(from) if from.abs_diff(status.position) > delta => {
+                            baseline.position = Some(status.position);
+                            Some(Change::PositionChanged { from, to: status.position })
+                        }
+                        Some(_) => None,
+                        None => {
+                            baseline.position = Some(status.position);
+                            None
+                        }
+                    },
+                    Condition::TemperatureCrossed { threshold } => {
+                        let above = status.temperature >= threshold;
+                        let previous = baseline.above.replace(above);
+                        if previous.unwrap_or(false) != above {
+                            Some(Change::TemperatureCrossed { temperature: status.temperature, above })
+                        } else {
+                            None
+                        }
+                    }
+                    Condition::AlarmSet { mask } => {
+                        let alarms = sample.alarms.bits() & mask.bits();
+                        let set = alarms & !baseline.alarms;
+                        baseline.alarms = alarms;
+                        if set != 0 { Some(Change::AlarmSet { alarms: AlarmFlags(set) }) } else { None }
+                    }
+                };
+                if let Some(change) = change {
+                    on_change(TelemetryChange { watch: index, id: sample.id, timestamp: sample.timestamp, change });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{BusConfig, BusMode};
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    extern crate std;
+
+    #[test]
+    fn test_telemetry_poller() {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<3>::new(1, 3));
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        let period = Duration::from_millis(50);
+        // ID 9 is not on the bus.
+        let mut poller = TelemetryPoller::<std::time::Instant, 4>::new([1, 2, 9, 3], period);
+        let mut previous: Option<TelemetryFrame<4>> = None;
+        for _ in 0..3 {
+            let frame = poller.poll(&mut bus).unwrap();
+            assert_eq!(frame.samples.map(|sample| sample.id), [1, 2, 9, 3]);
+            assert!(frame.samples[2].status.is_none());
+            assert!(!frame.is_complete());
+            assert_eq!(frame.samples[0].status.unwrap().position, 0x01ff);
+            assert!(frame.samples.windows(2).all(|pair| pair[0].timestamp < pair[1].timestamp));
+            assert!(frame.samples[0].timestamp >= frame.start);
+            assert!(frame.skew() < period);
+            if let Some(previous) = previous {
+                assert!(frame.cycle > previous.cycle);
+                assert_eq!(frame.start, period * frame.cycle);
+            }
+            previous = Some(frame);
+        }
+
+        thread.stop();
+    }
+
+    #[test]
+    fn test_telemetry_adaptive_rate() {
+        use crate::device::scs0009::{REGISTER_TARGET_POSITION_H, REGISTER_TORQUE_SWITCH};
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn_moving(BusEmulator::<2>::new(1, 2));
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        let period = Duration::from_millis(20);
+        let rate = AdaptiveRate { idle_period: Duration::from_millis(80), speed_threshold: 0, position_threshold: 0 };
+        let mut poller = TelemetryPoller::<std::time::Instant, 2>::new([1, 2], period).with_adaptive_rate(rate);
+
+        // The idle servos are polled less and less often.
+        let cycles = (0..4).map(|_| poller.poll(&mut bus).unwrap().cycle).collect::<std::vec::Vec<_>>();
+        assert_eq!(cycles, [0, 2, 6, 10]);
+        assert_eq!(poller.current_period(), Duration::from_millis(80));
+
+        // A servo starts to move, and the poller returns to the full rate until it stops.
+        bus.write_register(2, REGISTER_TORQUE_SWITCH.address, &[0x01]).unwrap();
+        // Target Position 0x0100 and Target Period 500 ms.
+        bus.write_register(2, REGISTER_TARGET_POSITION_H.address, &[0x01, 0x00, 0x01, 0xf4]).unwrap();
+        poller.wake();
+        let first = poller.poll(&mut bus).unwrap();
+        assert!(first.cycle < 14);
+        let second = poller.poll(&mut bus).unwrap();
+        assert_eq!(second.cycle, first.cycle + 1);
+        assert_eq!(poller.current_period(), period);
+
+        thread.stop();
+    }
+
+    #[test]
+    fn test_telemetry_watcher() {
+        use crate::device::scs0009::{REGISTER_CURRENT_POSITION_H, REGISTER_CURRENT_TEMPERATURE, WORD_ORDER};
+        // Position of servo 1 and temperature of servo 2.
+        let registers = std::sync::Arc::new(std::sync::Mutex::new((0x01ffu16, 30u8)));
+        let registers_clone = registers.clone();
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn_with(BusEmulator::<2>::new(1, 2), move |emulator, _| {
+            let (position, temperature) = *registers_clone.lock().unwrap();
+            emulator.servo_mut(1).unwrap().registers_mut()[REGISTER_CURRENT_POSITION_H.address as usize..][..2].copy_from_slice(&WORD_ORDER.to_bytes(position));
+            emulator.servo_mut(2).unwrap().registers_mut()[REGISTER_CURRENT_TEMPERATURE.address as usize] = temperature;
+        });
+
+        let config = BusConfig {
+            master: ProtocolMasterConfig::default(),
+            timeout: Duration::from_millis(20),
+            mode: BusMode::Normal,
+        };
+        let mut bus = Bus::<_, _, std::time::Instant>::new(master_reader, master_writer, config);
+        let mut poller = TelemetryPoller::<std::time::Instant, 2>::new([1, 2], Duration::ZERO);
+        let mut watcher = TelemetryWatcher::<2, 3>::new();
+        let position = watcher.add(Watch { id: Some(1), condition: Condition::PositionChanged { delta: 10 } }).unwrap();
+        let temperature = watcher.add(Watch { id: None, condition: Condition::TemperatureCrossed { threshold: 60 } }).unwrap();
+        let mut overheat = AlarmFlags(0);
+        overheat.set_overheat(true);
+        let alarm = watcher.add(Watch { id: None, condition: Condition::AlarmSet { mask: overheat } }).unwrap();
+        assert_eq!(watcher.add(Watch { id: None, condition: Condition::PositionChanged { delta: 1 } }), Err(WatchError::Full));
+
+        let mut poll = |watcher: &mut TelemetryWatcher<2, 3>| {
+            let mut changes = std::vec::Vec::new();
+            poller.poll_watched(&mut bus, watcher, |change| changes.push((change.watch, change.id, change.change))).unwrap();
+            changes
+        };
+        assert_eq!(poll(&mut watcher), []);
+        // Below the delta.
+        registers.lock().unwrap().0 = 0x0205;
+        assert_eq!(poll(&mut watcher), []);
+        *registers.lock().unwrap() = (0x0210, 85);
+        assert_eq!(poll(&mut watcher), [
+            (position, 1, Change::PositionChanged { from: 0x01ff, to: 0x0210 }),
+            (temperature, 2, Change::TemperatureCrossed { temperature: 85, above: true }),
+            (alarm, 2, Change::AlarmSet { alarms: overheat }),
+        ]);
+        // Changes are reported once.
+        assert_eq!(poll(&mut watcher), []);
+        registers.lock().unwrap().1 = 50;
+        assert_eq!(poll(&mut watcher), [(temperature, 2, Change::TemperatureCrossed { temperature: 50, above: false })]);
+        assert_eq!(watcher.remove(position).map(|watch| watch.id), Some(Some(1)));
+        registers.lock().unwrap().0 = 0x0100;
+        assert_eq!(poll(&mut watcher), []);
+
+        thread.stop();
+    }
+}
@@ -0,0 +1,253 @@
+//! Canonical request and response frames of the protocol.
+//!
+//! Each [`Vector`] is a request as sent by [`ProtocolMaster`](crate::protocol::ProtocolMaster) and the frames the
+//! servos answer with, byte for byte. Alternative transports, the wasm build and third-party slaves check their
+//! frames against them with [`check_request`] and [`check_response`], or the panicking [`assert_request`] and
+//! [`assert_response`] in tests. [`VECTORS`] lists all of them.
+//!
+//! The register values in the responses are examples, so a slave with other register contents only matches the
+//! framing of the responses to reads.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vector {
+    pub name: &'static str,
+    pub request: &'static [u8],
+    /// Responses in the order the servos send them. Empty if the request is not answered.
+    pub responses: &'static [&'static [u8]],
+}
+
+/// PING to ID 1.
+pub const PING: Vector = Vector {
+    name: "ping",
+    request: &[0xff, 0xff, 0x01, 0x02, 0x01, 0xfb],
+    responses: &[&[0xff, 0xff, 0x01, 0x02, 0x00, 0xfc]],
+};
+/// PING to ID 1 answered with the overload flag in the error byte.
+pub const PING_OVERLOAD: Vector = Vector {
+    name: "ping with overload",
+    request: &[0xff, 0xff, 0x01, 0x02, 0x01, 0xfb],
+    responses: &[&[0xff, 0xff, 0x01, 0x02, 0x20, 0xdc]],
+};
+/// READ of the 2 bytes of Current Position of ID 1, which is 0x01ff.
+pub const READ: Vector = Vector {
+    name: "read",
+    request: &[0xff, 0xff, 0x01, 0x04, 0x02, 0x38, 0x02, 0xbe],
+    responses: &[&[0xff, 0xff, 0x01, 0x04, 0x00, 0x01, 0xff, 0xfa]],
+};
+/// WRITE of 0x01ff to Target Position of ID 1.
+pub const WRITE: Vector = Vector {
+    name: "write",
+    request: &[0xff, 0xff, 0x01, 0x05, 0x03, 0x2a, 0x01, 0xff, 0xcc],
+    responses: &[&[0xff, 0xff, 0x01, 0x02, 0x00, 0xfc]],
+};
+/// WRITE to the broadcast ID, which no servo answers.
+pub const BROADCAST_WRITE: Vector = Vector {
+    name: "broadcast write",
+    request: &[0xff, 0xff, 0xfe, 0x04, 0x03, 0x28, 0x00, 0xd2],
+    responses: &[],
+};
+/// REG WRITE of 0x01ff to Target Position of ID 1, applied by the next ACTION.
+pub const REG_WRITE: Vector = Vector {
+    name: "reg write",
+    request: &[0xff, 0xff, 0x01, 0x05, 0x04, 0x2a, 0x01, 0xff, 0xcb],
+    responses: &[&[0xff, 0xff, 0x01, 0x02, 0x00, 0xfc]],
+};
+/// ACTION, always broadcast and not answered.
+pub const ACTION: Vector = Vector {
+    name: "action",
+    request: &[0xff, 0xff, 0xfe, 0x02, 0x05, 0xfa],
+    responses: &[],
+};
+/// SYNC READ of the 2 bytes of Current Position of ID 3 and 1. The servos answer in the order of the request.
+pub const SYNC_READ: Vector = Vector {
+    name: "sync read",
+    request: &[0xff, 0xff, 0xfe, 0x06, 0x82, 0x38, 0x02, 0x03, 0x01, 0x3b],
+    responses: &[
+        &[0xff, 0xff, 0x03, 0x04, 0x00, 0x01, 0x00, 0xf7],
+        &[0xff, 0xff, 0x01, 0x04, 0x00, 0x02, 0x00, 0xf8],
+    ],
+};
+/// SYNC WRITE of 0x0100 to ID 1 and 0x0200 to ID 2 at Target Position, which no servo answers.
+pub const SYNC_WRITE: Vector = Vector {
+    name: "sync write",
+    request: &[0xff, 0xff, 0xfe, 0x0a, 0x83, 0x2a, 0x02, 0x01, 0x01, 0x00, 0x02, 0x02, 0x00, 0x42],
+    responses: &[],
+};
+/// SCS0009 quirk: words are big-endian and the speed is sign-magnitude with the sign in bit 15, so a Target Speed
+/// of -0x0123 is written as 0x81 0x23.
+pub const SCS0009_NEGATIVE_SPEED: Vector = Vector {
+    name: "scs0009 negative speed",
+    request: &[0xff, 0xff, 0x01, 0x05, 0x03, 0x2e, 0x81, 0x23, 0x24],
+    responses: &[&[0xff, 0xff, 0x01, 0x02, 0x00, 0xfc]],
+};
+/// SCS0009 quirk: the load is sign-magnitude with the sign in bit 10, so 0x04 0x10 read from Current Load is -0x10.
+pub const SCS0009_NEGATIVE_LOAD: Vector = Vector {
+    name: "scs0009 negative load",
+    request: &[0xff, 0xff, 0x01, 0x04, 0x02, 0x3c, 0x02, 0xba],
+    responses: &[&[0xff, 0xff, 0x01, 0x04, 0x00, 0x04, 0x10, 0xe6]],
+};
+
+pub const VECTORS: &[Vector] = &[
+    PING,
+    PING_OVERLOAD,
+    READ,
+    WRITE,
+    BROADCAST_WRITE,
+    REG_WRITE,
+    ACTION,
+    SYNC_READ,
+    SYNC_WRITE,
+    SCS0009_NEGATIVE_SPEED,
+    SCS0009_NEGATIVE_LOAD,
+];
+
+/// First difference between a frame and its vector. A missing or extra byte is `None` on the shorter side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub vector: &'static str,
+    /// Index of the response, `None` for the request.
+    pub response: Option<usize>,
+    pub offset: usize,
+    pub expected: Option<u8>,
+    pub actual: Option<u8>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.response {
+            Some(index) => write!(f, "{}: response {}", self.vector, index)?,
+            None => write!(f, "{}: request", self.vector)?,
+        }
+        write!(f, " differs at byte {}: expected ", self.offset)?;
+        match self.expected {
+            Some(byte) => write!(f, "{:02x}", byte)?,
+            None => write!(f, "the end")?,
+        }
+        f.write_str(", got ")?;
+        match self.actual {
+            Some(byte) => write!(f, "{:02x}", byte),
+            None => write!(f, "the end"),
+        }
+    }
+}
+
+fn compare(vector: &Vector, response: Option<usize>, expected: &[u8], actual: &[u8]) -> Result<(), Mismatch> {
+    let offset = expected.iter().zip(actual).position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected.len().min(actual.len()));
+    if offset == expected.len() && offset == actual.len() {
+        return Ok(());
+    }
+    Err(Mismatch { vector: vector.name, response, offset, expected: expected.get(offset).copied(), actual: actual.get(offset).copied() })
+}
+
+/// Checks the frame a master transmitted for `vector`.
+pub fn check_request(vector: &Vector, transmitted: &[u8]) -> Result<(), Mismatch> {
+    compare(vector, None, vector.request, transmitted)
+}
+
+/// Checks response `index` a slave transmitted for `vector`. An unexpected response is a mismatch at byte 0.
+pub fn check_response(vector: &Vector, index: usize, transmitted: &[u8]) -> Result<(), Mismatch> {
+    compare(vector, Some(index), vector.responses.get(index).copied().unwrap_or(&[]), transmitted)
+}
+
+/// Panics with the first difference if `transmitted` is not the request of `vector`.
+#[track_caller]
+pub fn assert_request(vector: &Vector, transmitted: &[u8]) {
+    if let Err(mismatch) = check_request(vector, transmitted) {
+        panic!("{}", mismatch);
+    }
+}
+
+/// Panics with the first difference if `transmitted` is not response `index` of `vector`.
+#[track_caller]
+pub fn assert_response(vector: &Vector, index: usize, transmitted: &[u8]) {
+    if let Err(mismatch) = check_response(vector, index, transmitted) {
+        panic!("{}", mismatch);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::raw::{RawLoad, RawSpeed};
+    use crate::device::scs0009::{LOAD_ENCODING, SPEED_ENCODING};
+    use crate::packet::PacketReader;
+    use crate::protocol::*;
+    extern crate std;
+    use std::sync::mpsc::channel;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_vectors_are_valid_frames() {
+        for vector in VECTORS {
+            for frame in core::iter::once(vector.request).chain(vector.responses.iter().copied()) {
+                assert_eq!(frame[..2], [0xff, 0xff], "{}", vector.name);
+                let reader = PacketReader::new(&frame[2..]);
+                assert!(reader.verify_checksum().is_ok(), "{}", vector.name);
+                assert_eq!(reader.length().unwrap() as usize + 4, frame.len(), "{}", vector.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check_request(&PING, PING.request), Ok(()));
+        let mismatch = check_request(&PING, &[0xff, 0xff, 0x02]).unwrap_err();
+        assert_eq!(mismatch, Mismatch { vector: "ping", response: None, offset: 2, expected: Some(0x01), actual: Some(0x02) });
+        let mismatch = check_response(&ACTION, 0, &[0xff]).unwrap_err();
+        assert_eq!((mismatch.offset, mismatch.expected, mismatch.actual), (0, None, Some(0xff)));
+        assert_eq!(std::format!("{}", check_response(&PING, 0, &PING.responses[0][..5]).unwrap_err()), "ping: response 0 differs at byte 5: expected fc, got the end");
+    }
+
+    #[test]
+    fn test_master_conforms() {
+        assert_request(&PING, &PingCommand::new(0x01).raw);
+        assert_request(&READ, &ReadRegisterCommand::new(0x01, 0x38, 2).raw);
+        assert_request(&ACTION, &ActionCommand::new(BROADCAST_ID).raw);
+        let write = |address: u8, data: &[u8]| WriteRegisterCommand::<16>::builder(0x01).address(address).data(data).build().unwrap();
+        assert_request(&WRITE, write(0x2a, &[0x01, 0xff]).packet());
+        assert_request(&SCS0009_NEGATIVE_SPEED, write(0x2e, &RawSpeed::from_signed(-0x0123, SPEED_ENCODING).0.to_be_bytes()).packet());
+        let command = RegWriteRegisterCommand::<16>::builder(0x01).address(0x2a).data(&[0x01, 0xff]).build().unwrap();
+        assert_request(&REG_WRITE, command.packet());
+        let command = SyncWriteCommand::<{ sync_write_command_size(2, 2) }>::builder(0x2a, 2)
+            .servo(0x01, &[0x01, 0x00])
+            .servo(0x02, &[0x02, 0x00])
+            .build()
+            .unwrap();
+        assert_request(&SYNC_WRITE, command.packet());
+
+        // The responses are decoded as described.
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, _slave_reader) = channel();
+        let (slave_writer, mut master_reader) = channel();
+        let respond = |vector: &Vector| vector.responses.iter().flat_map(|response| response.iter()).for_each(|byte| slave_writer.send(*byte).unwrap());
+        respond(&PING_OVERLOAD);
+        assert!(master.ping(&mut master_reader, &mut master_writer, 0x01, || false).unwrap().overload());
+        respond(&SCS0009_NEGATIVE_LOAD);
+        let mut data = [0; 2];
+        master.read_register(&mut master_reader, &mut master_writer, 0x01, 0x3c, &mut data, || false).unwrap();
+        assert_eq!(RawLoad(u16::from_be_bytes(data)).to_signed(LOAD_ENCODING), -0x10);
+    }
+
+    #[test]
+    fn test_slave_conforms() {
+        let mut slave = ProtocolSlave::<256>::new(ProtocolSlaveConfig { ids: IdSet::from_ids(&[0x01, 0x03]) });
+        let (mut master_writer, mut slave_reader) = channel();
+        let (mut slave_writer, master_reader) = channel();
+        StreamWriter::write(&mut master_writer, SYNC_READ.request).unwrap();
+        // The request is received first, then the slave answers for each of its IDs.
+        for _ in 0..3 {
+            slave.process(&mut slave_reader, &mut slave_writer, |packet, buffer| {
+                let id = packet.id().unwrap();
+                let response = SYNC_READ.responses.iter().find(|response| response[2] == id).unwrap();
+                buffer[..response.len()].copy_from_slice(response);
+                Some(response.len())
+            }).unwrap();
+        }
+        let transmitted = master_reader.try_iter().collect::<Vec<_>>();
+        assert_response(&SYNC_READ, 0, &transmitted[..8]);
+        assert_response(&SYNC_READ, 1, &transmitted[8..]);
+    }
+}
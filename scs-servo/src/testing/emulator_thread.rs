@@ -0,0 +1,80 @@
+//! Bus emulator on a background thread.
+//!
+//! [`EmulatorThread`] runs a [`BusEmulator`] behind a pair of channels until it is stopped or the master side is
+//! dropped, so a test talks to the emulated servos through the same reader and writer as to a real bus:
+//!
+//! ```ignore
+//! let (emulator, reader, writer) = EmulatorThread::spawn(BusEmulator::<2>::new(1, 2));
+//! let mut bus = Bus::<_, _, std::time::Instant>::new(reader, writer, config);
+//! bus.ping(2)?;
+//! let emulator = emulator.stop();
+//! ```
+
+extern crate std;
+use core::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::emulator::BusEmulator;
+
+pub struct EmulatorThread<const N: usize> {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<BusEmulator<N>>>,
+}
+
+impl<const N: usize> EmulatorThread<N> {
+    /// Runs `emulator`. The servos do not move.
+    /// Returns the thread and the reader and the writer of the master side.
+    pub fn spawn(emulator: BusEmulator<N>) -> (Self, Receiver<u8>, Sender<u8>) {
+        Self::spawn_with(emulator, |_, _| {})
+    }
+
+    /// Runs `emulator` and moves the servos in real time.
+    pub fn spawn_moving(emulator: BusEmulator<N>) -> (Self, Receiver<u8>, Sender<u8>) {
+        Self::spawn_with(emulator, |emulator, elapsed| emulator.update(elapsed))
+    }
+
+    /// Runs `emulator` and calls `step` with the time since its previous call before each poll of the bus,
+    /// e.g. to change the registers while the master reads them.
+    pub fn spawn_with<F>(mut emulator: BusEmulator<N>, mut step: F) -> (Self, Receiver<u8>, Sender<u8>)
+    where
+        F: FnMut(&mut BusEmulator<N>, Duration) + Send + 'static,
+    {
+        let (master_writer, mut emulator_reader) = channel();
+        let (mut emulator_writer, master_reader) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut last_step = std::time::Instant::now();
+            // The thread also ends when the master side is dropped.
+            while !stop_clone.load(Ordering::Relaxed) {
+                let now = std::time::Instant::now();
+                step(&mut emulator, now - last_step);
+                last_step = now;
+                if emulator.process(&mut emulator_reader, &mut emulator_writer).is_err() {
+                    break;
+                }
+                std::thread::yield_now();
+            }
+            emulator
+        });
+        (Self { stop, thread: Some(thread) }, master_reader, master_writer)
+    }
+
+    /// Stops the thread and returns the emulator, e.g. to inspect the registers.
+    pub fn stop(mut self) -> BusEmulator<N> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.take().expect("not stopped yet").join().expect("the emulator thread panicked")
+    }
+}
+
+impl<const N: usize> Drop for EmulatorThread<N> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Mock transport with injected bus faults.
+//!
+//! [`FaultyBus`] answers the requests of a master with a responder, e.g. [`BusEmulator::handle_packet`], and damages
+//! the responses as scheduled: corrupted bytes, dropped bytes, duplicated frames, delays and silence. The faults are
+//! keyed by the number of the request, so a test of error handling sees the same bytes on every run:
+//!
+//! ```ignore
+//! let bus = FaultyBus::new(|packet, buffer| emulator.handle_packet(packet, buffer));
+//! bus.inject(0, Fault::Corrupt { offset: 5, mask: 0x01 });  // Wrong checksum in the first response.
+//! bus.inject(1, Fault::Delay { polls: 100 });               // The second response arrives late.
+//! ```
+//!
+//! [`BusEmulator::handle_packet`]: crate::emulator::BusEmulator::handle_packet
+
+extern crate std;
+use std::collections::VecDeque;
+use std::vec::Vec;
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use crate::packet::PacketReader;
+use crate::protocol::{StreamReader, StreamWriter, MAX_PACKET_SIZE};
+
+/// Damage done to the response to a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// XORs byte `offset` of the response, markers included, with `mask`.
+    Corrupt { offset: usize, mask: u8 },
+    /// Removes byte `offset` of the response, markers included.
+    Drop { offset: usize },
+    /// Sends the response twice.
+    Duplicate,
+    /// Holds the response back for `polls` reads, which return `WouldBlock`.
+    Delay { polls: usize },
+    /// Discards the response.
+    Silence,
+}
+
+struct FaultState<F> {
+    responder: F,
+    faults: Vec<(usize, Fault)>,
+    transmitted: Vec<u8>,
+    requests: usize,
+    receive: VecDeque<u8>,
+    delay: usize,
+}
+
+impl<F: FnMut(&PacketReader, &mut [u8]) -> Option<usize>> FaultState<F> {
+    // Answers the requests completed by the bytes transmitted so far.
+    fn process(&mut self) {
+        loop {
+            let Some(start) = self.transmitted.windows(3).position(|bytes| bytes[0] == 0xff && bytes[1] == 0xff && bytes[2] != 0xff) else {
+                return;
+            };
+            self.transmitted.drain(..start);
+            let Some(&length) = self.transmitted.get(3) else {
+                return;
+            };
+            let end = 4 + length as usize;
+            if self.transmitted.len() < end {
+                return;
+            }
+            let request = self.transmitted.drain(..end).collect::<Vec<_>>();
+            self.respond(&request);
+        }
+    }
+
+    fn respond(&mut self, request: &[u8]) {
+        let index = self.requests;
+        self.requests += 1;
+        let mut buffer = [0; MAX_PACKET_SIZE + 2];
+        let Some(length) = (self.responder)(&PacketReader::new(&request[2..]), &mut buffer) else {
+            return;
+        };
+        let mut response = buffer[..length].to_vec();
+        for fault in self.faults.iter().filter(|(request, _)| *request == index).map(|(_, fault)| *fault) {
+            match fault {
+                Fault::Corrupt { offset, mask } => {
+                    if let Some(byte) = response.get_mut(offset) {
+                        *byte ^= mask;
+                    }
+                }
+                Fault::Drop { offset } => {
+                    if offset < response.len() {
+                        response.remove(offset);
+                    }
+                }
+                Fault::Duplicate => response.extend_from_slice(&buffer[..length]),
+                Fault::Delay { polls } => self.delay += polls,
+                Fault::Silence => response.clear(),
+            }
+        }
+        self.receive.extend(response);
+    }
+}
+
+/// Mock transport which answers requests with a responder and injects the scheduled faults into the responses.
+/// The responder has the signature of the handler of [`ProtocolSlave::process`](crate::protocol::ProtocolSlave::process).
+pub struct FaultyBus<F> {
+    state: RefCell<FaultState<F>>,
+}
+
+impl<F: FnMut(&PacketReader, &mut [u8]) -> Option<usize>> FaultyBus<F> {
+    pub fn new(responder: F) -> Self {
+        Self {
+            state: RefCell::new(FaultState {
+                responder,
+                faults: Vec::new(),
+                transmitted: Vec::new(),
+                requests: 0,
+                receive: VecDeque::new(),
+                delay: 0,
+            }),
+        }
+    }
+    /// Applies `fault` to the response to request number `request`, counted from zero over all the requests
+    /// written, answered or not. Several faults of a request are applied in the order they were injected.
+    pub fn inject(&self, request: usize, fault: Fault) {
+        self.state.borrow_mut().faults.push((request, fault));
+    }
+    pub fn reader(&self) -> FaultyReader<'_, F> {
+        FaultyReader { bus: self }
+    }
+    pub fn writer(&self) -> FaultyWriter<'_, F> {
+        FaultyWriter { bus: self }
+    }
+    /// Number of complete requests written so far.
+    pub fn requests(&self) -> usize {
+        self.state.borrow().requests
+    }
+    /// Returns the number of received bytes which have not been read yet.
+    pub fn receive_remaining(&self) -> usize {
+        self.state.borrow().receive.len()
+    }
+}
+
+pub struct FaultyReader<'a, F> {
+    bus: &'a FaultyBus<F>,
+}
+pub struct FaultyWriter<'a, F> {
+    bus: &'a FaultyBus<F>,
+}
+
+impl<F> StreamReader for FaultyReader<'_, F> {
+    type Error = Infallible;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let mut state = self.bus.state.borrow_mut();
+        if state.delay > 0 {
+            state.delay -= 1;
+            return Err(nb::Error::WouldBlock);
+        }
+        let length = data.len().min(state.receive.len());
+        if length == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        for (byte, received) in data.iter_mut().zip(state.receive.drain(..length)) {
+            *byte = received;
+        }
+        Ok(length)
+    }
+}
+
+impl<F: FnMut(&PacketReader, &mut [u8]) -> Option<usize>> StreamWriter for FaultyWriter<'_, F> {
+    type Error = Infallible;
+    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+        let mut state = self.bus.state.borrow_mut();
+        state.transmitted.extend_from_slice(data);
+        state.process();
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::BusEmulator;
+    use crate::packet::PacketError;
+    use crate::protocol::{ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig};
+
+    fn polls(limit: usize) -> impl FnMut() -> bool {
+        let mut polls = 0;
+        move || {
+            polls += 1;
+            polls > limit
+        }
+    }
+
+    #[test]
+    fn test_faulty_bus() {
+        let mut emulator = BusEmulator::<1>::new(1, 1);
+        let bus = FaultyBus::new(|packet: &PacketReader, buffer: &mut [u8]| emulator.handle_packet(packet, buffer));
+        bus.inject(0, Fault::Corrupt { offset: 5, mask: 0x01 });
+        bus.inject(1, Fault::Drop { offset: 4 });
+        bus.inject(2, Fault::Delay { polls: 5 });
+        bus.inject(3, Fault::Duplicate);
+        bus.inject(4, Fault::Silence);
+        bus.inject(5, Fault::Delay { polls: 50 });
+        let (mut reader, mut writer) = (bus.reader(), bus.writer());
+        // A new master for each request, so that no partial response is carried over.
+        let mut ping = |limit| ProtocolMaster::<16>::new(ProtocolMasterConfig::default()).ping(&mut reader, &mut writer, 1, polls(limit));
+
+        assert!(matches!(ping(10), Err(ProtocolHandlerError::PacketError(PacketError::InvalidChecksum))));
+        assert!(matches!(ping(10), Err(ProtocolHandlerError::TimedOut)));
+        assert!(ping(10).is_ok());
+        assert!(ping(10).is_ok());
+        assert_eq!(bus.receive_remaining(), 6);
+        bus.reader().read(&mut [0; 6]).unwrap();
+        assert!(matches!(ping(10), Err(ProtocolHandlerError::TimedOut)));
+        // The late response is still on its way when the master gives up.
+        assert!(matches!(ping(10), Err(ProtocolHandlerError::TimedOut)));
+        assert_eq!(bus.receive_remaining(), 6);
+        assert_eq!(bus.requests(), 6);
+    }
+}
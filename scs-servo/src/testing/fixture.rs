@@ -0,0 +1,296 @@
+//! Replay of recorded bus transactions.
+//!
+//! A fixture is a text file describing transactions between a master and the servos:
+//!
+//! ```text
+//! # Lines starting with '#' are comments.
+//! @echo_back                                   # The adapter echoes back the transmitted bytes.
+//! > ff ff 01 04 02 03 03 f2                    # Bytes sent by the master. Starts a new transaction.
+//! < ff ff 01 04 02 03 03 f2                    # Bytes received from the bus. May span multiple lines.
+//! < ff ff 01 05 00 05 04 01 ef
+//! = 05 04 01                                   # Expected outcome: data read, `ok`, `timeout` or `error`.
+//! ```
+//!
+//! [`Fixture::replay`] decodes each transmitted frame back into a `ProtocolMaster` call, runs it against
+//! a [`ScriptedBus`] which checks the transmitted bytes and plays back the received ones, and reports
+//! whether the outcome matches the expectation.
+
+extern crate std;
+use std::string::String;
+use std::vec::Vec;
+use core::cell::RefCell;
+
+use crate::packet::PacketReader;
+use crate::protocol::{ParsedInstruction, ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, StreamReader, StreamWriter, WriteRegisterCommand};
+
+/// Number of times the timeout predicate is polled without receiving data before a transaction times out.
+const TIMEOUT_POLLS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Data(Vec<u8>),
+    Ok,
+    TimedOut,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub line: usize,
+    pub annotation: String,
+    pub transmit: Vec<u8>,
+    pub receive: Vec<u8>,
+    pub expectation: Option<Expectation>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    pub echo_back: bool,
+    pub transactions: Vec<Transaction>,
+}
+
+#[derive(Debug)]
+pub enum FixtureError {
+    InvalidHex { line: usize },
+    UnknownDirective { line: usize },
+    MissingTransmit { line: usize },
+}
+
+fn parse_hex(text: &str, line: usize) -> Result<Vec<u8>, FixtureError> {
+    text.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| FixtureError::InvalidHex { line }))
+        .collect()
+}
+
+impl Fixture {
+    pub fn parse(text: &str) -> Result<Self, FixtureError> {
+        let mut fixture = Fixture::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let (content, annotation) = match line.split_once('#') {
+                Some((content, annotation)) => (content.trim(), annotation.trim()),
+                None => (line.trim(), ""),
+            };
+            let Some(kind) = content.chars().next() else {
+                continue;
+            };
+            let rest = content[1..].trim();
+            match kind {
+                '@' => match rest {
+                    "echo_back" => fixture.echo_back = true,
+                    _ => return Err(FixtureError::UnknownDirective { line: line_number }),
+                },
+                '>' => fixture.transactions.push(Transaction {
+                    line: line_number,
+                    annotation: annotation.into(),
+                    transmit: parse_hex(rest, line_number)?,
+                    receive: Vec::new(),
+                    expectation: None,
+                }),
+                '<' => {
+                    let transaction = fixture.transactions.last_mut().ok_or(FixtureError::MissingTransmit { line: line_number })?;
+                    transaction.receive.extend(parse_hex(rest, line_number)?);
+                },
+                '=' => {
+                    let transaction = fixture.transactions.last_mut().ok_or(FixtureError::MissingTransmit { line: line_number })?;
+                    transaction.expectation = Some(match rest {
+                        "ok" => Expectation::Ok,
+                        "timeout" => Expectation::TimedOut,
+                        "error" => Expectation::Error,
+                        data => Expectation::Data(parse_hex(data, line_number)?),
+                    });
+                },
+                _ => return Err(FixtureError::UnknownDirective { line: line_number }),
+            }
+        }
+        Ok(fixture)
+    }
+
+    /// Replays all transactions through `master`. The master configuration is taken from the fixture.
+    pub fn replay<const BUFFER_SIZE: usize>(&self) -> Vec<ReplayResult> {
+        let mut master = ProtocolMaster::<BUFFER_SIZE>::new(ProtocolMasterConfig::builder().echo_back(self.echo_back).build());
+        self.transactions.iter().map(|transaction| replay_transaction(&mut master, transaction)).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The master transmitted a byte which differs from the recorded one.
+    UnexpectedTransmit { offset: usize, expected: Option<u8>, actual: u8 },
+    /// The recorded request is not a command the replay knows how to issue.
+    UnsupportedInstruction,
+}
+
+struct ScriptState {
+    transmit: Vec<u8>,
+    transmit_position: usize,
+    receive: Vec<u8>,
+    receive_position: usize,
+}
+
+/// Mock transport which expects the master to transmit the given bytes and then plays back the received bytes.
+pub struct ScriptedBus {
+    state: RefCell<ScriptState>,
+}
+
+impl ScriptedBus {
+    pub fn new(transmit: &[u8], receive: &[u8]) -> Self {
+        Self {
+            state: RefCell::new(ScriptState {
+                transmit: transmit.to_vec(),
+                transmit_position: 0,
+                receive: receive.to_vec(),
+                receive_position: 0,
+            }),
+        }
+    }
+    pub fn reader(&self) -> ScriptedReader<'_> {
+        ScriptedReader { bus: self }
+    }
+    pub fn writer(&self) -> ScriptedWriter<'_> {
+        ScriptedWriter { bus: self }
+    }
+    /// Returns whether all expected bytes were transmitted.
+    pub fn transmit_completed(&self) -> bool {
+        let state = self.state.borrow();
+        state.transmit_position == state.transmit.len()
+    }
+    /// Returns the number of received bytes which have not been read yet.
+    pub fn receive_remaining(&self) -> usize {
+        let state = self.state.borrow();
+        state.receive.len() - state.receive_position
+    }
+}
+
+pub struct ScriptedReader<'a> {
+    bus: &'a ScriptedBus,
+}
+pub struct ScriptedWriter<'a> {
+    bus: &'a ScriptedBus,
+}
+
+impl StreamReader for ScriptedReader<'_> {
+    type Error = ScriptError;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let mut state = self.bus.state.borrow_mut();
+        // Nothing is received before the request has been sent completely.
+        if state.transmit_position < state.transmit.len() || state.receive_position == state.receive.len() {
+            return Err(nb::Error::WouldBlock);
+        }
+        let start = state.receive_position;
+        let length = data.len().min(state.receive.len() - start);
+        data[..length].copy_from_slice(&state.receive[start..start + length]);
+        state.receive_position += length;
+        Ok(length)
+    }
+}
+
+impl StreamWriter for ScriptedWriter<'_> {
+    type Error = ScriptError;
+    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+        let mut state = self.bus.state.borrow_mut();
+        for &byte in data {
+            let offset = state.transmit_position;
+            let expected = state.transmit.get(offset).copied();
+            if expected != Some(byte) {
+                return Err(nb::Error::Other(ScriptError::UnexpectedTransmit { offset, expected, actual: byte }));
+            }
+            state.transmit_position += 1;
+        }
+        Ok(data.len())
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplayResult {
+    pub line: usize,
+    pub annotation: String,
+    pub expectation: Option<Expectation>,
+    pub outcome: Result<Vec<u8>, ProtocolHandlerError<ScriptError, ScriptError>>,
+}
+
+impl ReplayResult {
+    /// Returns whether the outcome matches the expectation. Transactions without an expectation must succeed.
+    pub fn is_expected(&self) -> bool {
+        match (&self.expectation, &self.outcome) {
+            (Some(Expectation::Data(expected)), Ok(data)) => expected == data,
+            (Some(Expectation::Ok) | None, Ok(_)) => true,
+            (Some(Expectation::TimedOut), Err(ProtocolHandlerError::TimedOut)) => true,
+            (Some(Expectation::Error), Err(ProtocolHandlerError::TimedOut)) => false,
+            (Some(Expectation::Error), Err(ProtocolHandlerError::WriterError(_))) => false,
+            (Some(Expectation::Error), Err(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+fn replay_transaction<const BUFFER_SIZE: usize>(master: &mut ProtocolMaster<BUFFER_SIZE>, transaction: &Transaction) -> ReplayResult {
+    let bus = ScriptedBus::new(&transaction.transmit, &transaction.receive);
+    let mut reader = bus.reader();
+    let mut writer = bus.writer();
+    let mut polls = 0;
+    let timeout = move || {
+        polls += 1;
+        polls > TIMEOUT_POLLS
+    };
+    let request = PacketReader::new(transaction.transmit.get(2..).unwrap_or(&[]));
+    let outcome = match (request.id(), ParsedInstruction::parse(&request)) {
+        (Ok(id), Ok(ParsedInstruction::ReadRegister { address, length })) => {
+            let mut buffer = std::vec![0; length as usize];
+            master.read_register(&mut reader, &mut writer, id, address, &mut buffer, timeout).map(|_| buffer)
+        },
+        (Ok(id), Ok(ParsedInstruction::WriteRegister { address, data })) => {
+            let command = WriteRegisterCommand::<260>::builder(id).address(address).data(data).build().unwrap();
+            master.write_register(&mut reader, &mut writer, &command, timeout).map(|_| Vec::new())
+        },
+        _ => Err(ProtocolHandlerError::WriterError(ScriptError::UnsupportedInstruction)),
+    };
+    ReplayResult {
+        line: transaction.line,
+        annotation: transaction.annotation.clone(),
+        expectation: transaction.expectation.clone(),
+        outcome,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check_fixture(text: &str) {
+        let fixture = Fixture::parse(text).unwrap();
+        assert!(!fixture.transactions.is_empty());
+        for result in fixture.replay::<260>() {
+            assert!(result.is_expected(), "line {} ({}): expected {:?}, got {:?}", result.line, result.annotation, result.expectation, result.outcome);
+        }
+    }
+
+    #[test]
+    fn test_fixture_scs0009_basic() {
+        check_fixture(include_str!("../../fixtures/scs0009_basic.txt"));
+    }
+
+    #[test]
+    fn test_fixture_scs0009_echo_back() {
+        check_fixture(include_str!("../../fixtures/scs0009_echo_back.txt"));
+    }
+
+    #[test]
+    fn test_fixture_scs0009_faults() {
+        check_fixture(include_str!("../../fixtures/scs0009_faults.txt"));
+    }
+
+    #[test]
+    fn test_fixture_parse_error() {
+        assert!(matches!(Fixture::parse("< ff ff"), Err(FixtureError::MissingTransmit { line: 1 })));
+        assert!(matches!(Fixture::parse("\n> ff fg"), Err(FixtureError::InvalidHex { line: 2 })));
+        assert!(matches!(Fixture::parse("@unknown"), Err(FixtureError::UnknownDirective { line: 1 })));
+    }
+
+    #[test]
+    fn test_scripted_bus_detects_unexpected_transmit() {
+        let fixture = Fixture::parse("> ff ff 01 04 02 03 03 f3 # wrong checksum\n< ff ff 01 05 00 05 04 01 ef").unwrap();
+        let results = fixture.replay::<16>();
+        assert!(matches!(results[0].outcome, Err(ProtocolHandlerError::WriterError(ScriptError::UnexpectedTransmit { offset: 7, expected: Some(0xf3), actual: 0xf2 }))));
+    }
+}
@@ -0,0 +1,200 @@
+//! In-process bus between a master and slaves.
+//!
+//! [`LoopbackBus`] connects the reader and writer of a [`ProtocolMaster`](crate::protocol::ProtocolMaster) to one or
+//! more [`ProtocolSlave`]s, each with its packet handler, e.g. [`BusEmulator::handle_packet`]. The slaves run on the
+//! thread of the master whenever it reads, so end-to-end tests and examples need neither hardware nor threads:
+//!
+//! ```ignore
+//! let mut emulator = BusEmulator::<2>::new(1, 2);
+//! let mut bus = LoopbackBus::new();
+//! bus.add_slave(IdSet::from_ids(&[1, 2]), |packet, buffer| emulator.handle_packet(packet, buffer));
+//! let (mut reader, mut writer) = (bus.reader(), bus.writer());
+//! master.ping(&mut reader, &mut writer, 1, || false)?;
+//! ```
+//!
+//! [`BusEmulator::handle_packet`]: crate::emulator::BusEmulator::handle_packet
+
+extern crate std;
+use std::boxed::Box;
+use std::collections::VecDeque;
+use std::vec::Vec;
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use crate::packet::PacketReader;
+use crate::protocol::{IdSet, ProtocolSlave, ProtocolSlaveConfig, StreamReader, StreamWriter, MAX_PACKET_SIZE};
+
+/// Size of the buffers of the slaves, which hold a whole packet.
+const SLAVE_BUFFER_SIZE: usize = MAX_PACKET_SIZE + 2;
+
+type PacketHandler<'a> = Box<dyn FnMut(&PacketReader, &mut [u8]) -> Option<usize> + 'a>;
+
+struct LoopbackSlave<'a> {
+    slave: ProtocolSlave<SLAVE_BUFFER_SIZE>,
+    handler: PacketHandler<'a>,
+    // Bytes written by the master which the slave has not read yet.
+    receive: VecDeque<u8>,
+}
+
+#[derive(Default)]
+struct LoopbackState<'a> {
+    slaves: Vec<LoopbackSlave<'a>>,
+    // Bytes on their way to the master.
+    receive: VecDeque<u8>,
+    echo_back: bool,
+}
+
+impl LoopbackState<'_> {
+    // Lets each slave handle the bytes written so far and queues its responses for the master.
+    fn run_slaves(&mut self) {
+        for slave in self.slaves.iter_mut() {
+            let mut reader = QueueStream(&mut slave.receive);
+            let mut writer = QueueStream(&mut self.receive);
+            loop {
+                let remaining = reader.0.len();
+                // Receiving, handling and responding each take a step of the slave.
+                for _ in 0..3 {
+                    // The queues never fail.
+                    let _ = slave.slave.process(&mut reader, &mut writer, &mut slave.handler);
+                }
+                if reader.0.is_empty() || reader.0.len() == remaining {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+struct QueueStream<'a>(&'a mut VecDeque<u8>);
+
+impl StreamReader for QueueStream<'_> {
+    type Error = Infallible;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let length = data.len().min(self.0.len());
+        if length == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        for (byte, received) in data.iter_mut().zip(self.0.drain(..length)) {
+            *byte = received;
+        }
+        Ok(length)
+    }
+}
+
+impl StreamWriter for QueueStream<'_> {
+    type Error = Infallible;
+    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+        self.0.extend(data);
+        Ok(data.len())
+    }
+}
+
+/// Bus connecting a master to slaves in the same process. Every slave receives the bytes the master writes, and the
+/// master receives the responses of all slaves.
+#[derive(Default)]
+pub struct LoopbackBus<'a> {
+    state: RefCell<LoopbackState<'a>>,
+}
+
+impl<'a> LoopbackBus<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a slave owning `ids`, whose requests are handled by `handler`. See [`ProtocolSlave::process`].
+    pub fn add_slave<Handler: FnMut(&PacketReader, &mut [u8]) -> Option<usize> + 'a>(&mut self, ids: IdSet, handler: Handler) {
+        self.state.get_mut().slaves.push(LoopbackSlave {
+            slave: ProtocolSlave::new(ProtocolSlaveConfig { ids }),
+            handler: Box::new(handler),
+            receive: VecDeque::new(),
+        });
+    }
+    /// Makes the master receive the bytes it writes before the responses, as a half-duplex adapter does.
+    pub fn set_echo_back(&mut self, echo_back: bool) {
+        self.state.get_mut().echo_back = echo_back;
+    }
+    pub fn reader(&self) -> LoopbackReader<'_, 'a> {
+        LoopbackReader { bus: self }
+    }
+    pub fn writer(&self) -> LoopbackWriter<'_, 'a> {
+        LoopbackWriter { bus: self }
+    }
+    /// Returns the number of bytes on their way to the master which it has not read yet.
+    pub fn receive_remaining(&self) -> usize {
+        self.state.borrow().receive.len()
+    }
+}
+
+/// Reader of the master, which runs the slaves before taking their responses.
+pub struct LoopbackReader<'b, 'a> {
+    bus: &'b LoopbackBus<'a>,
+}
+/// Writer of the master, which delivers the bytes to every slave.
+pub struct LoopbackWriter<'b, 'a> {
+    bus: &'b LoopbackBus<'a>,
+}
+
+impl StreamReader for LoopbackReader<'_, '_> {
+    type Error = Infallible;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let mut state = self.bus.state.borrow_mut();
+        state.run_slaves();
+        QueueStream(&mut state.receive).read(data)
+    }
+}
+
+impl StreamWriter for LoopbackWriter<'_, '_> {
+    type Error = Infallible;
+    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+        let mut state = self.bus.state.borrow_mut();
+        if state.echo_back {
+            state.receive.extend(data);
+        }
+        for slave in state.slaves.iter_mut() {
+            slave.receive.extend(data);
+        }
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::REGISTER_TARGET_POSITION_H;
+    use crate::emulator::BusEmulator;
+    use crate::protocol::{sync_write_command_size, write_command_size, ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig, SyncWriteCommand, WriteRegisterCommand};
+
+    fn polls(limit: usize) -> impl FnMut() -> bool {
+        let mut polls = 0;
+        move || {
+            polls += 1;
+            polls > limit
+        }
+    }
+
+    #[test]
+    fn test_loopback_bus() {
+        let mut first = BusEmulator::<1>::new(1, 1);
+        let mut second = BusEmulator::<1>::new(2, 1);
+        let mut bus = LoopbackBus::new();
+        bus.add_slave(IdSet::from_ids(&[1]), |packet: &PacketReader, buffer: &mut [u8]| first.handle_packet(packet, buffer));
+        bus.add_slave(IdSet::from_ids(&[2]), |packet: &PacketReader, buffer: &mut [u8]| second.handle_packet(packet, buffer));
+        bus.set_echo_back(true);
+        let mut master = ProtocolMaster::<64>::new(ProtocolMasterConfig::builder().echo_back(true).build());
+        let (mut reader, mut writer) = (bus.reader(), bus.writer());
+
+        master.ping(&mut reader, &mut writer, 1, polls(100)).unwrap();
+        master.ping(&mut reader, &mut writer, 2, polls(100)).unwrap();
+        assert!(matches!(master.ping(&mut reader, &mut writer, 3, polls(100)), Err(ProtocolHandlerError::TimedOut)));
+
+        let command = WriteRegisterCommand::<{ write_command_size(2) }>::builder(1).address(REGISTER_TARGET_POSITION_H.address).data(&[0x01, 0x23]).build().unwrap();
+        master.write_register(&mut reader, &mut writer, &command, polls(100)).unwrap();
+        let sync = SyncWriteCommand::<{ sync_write_command_size(2, 2) }>::builder(REGISTER_TARGET_POSITION_H.address, 2).servo(1, &[0x02, 0x00]).servo(2, &[0x03, 0x00]).build().unwrap();
+        master.sync_write(&mut reader, &mut writer, &sync, polls(100)).unwrap();
+        let mut data = [0; 2];
+        master.read_register(&mut reader, &mut writer, 1, REGISTER_TARGET_POSITION_H.address, &mut data, polls(100)).unwrap();
+        assert_eq!(data, [0x02, 0x00]);
+        master.read_register(&mut reader, &mut writer, 2, REGISTER_TARGET_POSITION_H.address, &mut data, polls(100)).unwrap();
+        assert_eq!(data, [0x03, 0x00]);
+        assert_eq!(bus.receive_remaining(), 0);
+    }
+}
@@ -0,0 +1,27 @@
+//! Test support for users of this crate.
+
+pub mod conformance;
+#[cfg(any(feature = "proptest", test))]
+pub mod strategies;
+
+#[cfg(feature = "std")]
+pub mod fixture;
+#[cfg(feature = "std")]
+pub mod faults;
+#[cfg(feature = "std")]
+pub mod loopback;
+#[cfg(feature = "std")]
+pub mod emulator_thread;
+
+/// Runs `future` to completion by polling it in a loop, for tests of the async API without an executor.
+/// The streams of the future must not pend forever, as nothing wakes it.
+#[cfg(feature = "async")]
+pub fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let mut context = core::task::Context::from_waker(core::task::Waker::noop());
+    loop {
+        if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! proptest strategies for packets and commands, and round-trip checks between the builders and the parsers.
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn my_round_trip(fields in scs_servo::testing::strategies::packet_fields()) {
+//!         scs_servo::testing::strategies::check_packet_round_trip(&fields)?;
+//!     }
+//! }
+//! ```
+
+extern crate std;
+use std::vec::Vec;
+
+use proptest::prelude::*;
+
+use crate::packet::{PacketReader, PacketWriter};
+use crate::protocol::{Command, ProtocolReader, ReadRegisterCommand, StreamReader, WriteRegisterCommand, BROADCAST_ID};
+
+/// Maximum number of parameter bytes in a packet. The length field (parameters + instruction + checksum) is a single byte.
+pub const MAX_PARAMETERS: usize = 253;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketFields {
+    pub id: u8,
+    pub instruction: u8,
+    pub parameters: Vec<u8>,
+}
+
+/// Valid servo IDs including the broadcast ID.
+pub fn id() -> impl Strategy<Value = u8> {
+    0..=BROADCAST_ID
+}
+
+pub fn packet_fields() -> impl Strategy<Value = PacketFields> {
+    (id(), any::<u8>(), proptest::collection::vec(any::<u8>(), 0..=MAX_PARAMETERS))
+        .prop_map(|(id, instruction, parameters)| PacketFields { id, instruction, parameters })
+}
+
+/// (id, address, length) of a READ command.
+pub fn read_register_fields() -> impl Strategy<Value = (u8, u8, u8)> {
+    (id(), any::<u8>(), any::<u8>())
+}
+
+/// (id, address, data) of a WRITE command.
+pub fn write_register_fields() -> impl Strategy<Value = (u8, u8, Vec<u8>)> {
+    (id(), any::<u8>(), proptest::collection::vec(any::<u8>(), 0..=MAX_PARAMETERS - 1))
+}
+
+/// Builds a frame including the markers into `buffer` and returns its length.
+pub fn build_packet(fields: &PacketFields, buffer: &mut [u8]) -> usize {
+    let length = fields.parameters.len() + 6;
+    buffer[0] = 0xff;
+    buffer[1] = 0xff;
+    let mut writer = PacketWriter::new(&mut buffer[2..length]);
+    writer.set_id(fields.id).unwrap();
+    writer.set_length(fields.parameters.len() as u8 + 2).unwrap();
+    let data = writer.data_mut().unwrap();
+    data[0] = fields.instruction;
+    data[1..].copy_from_slice(&fields.parameters);
+    writer.update_checksum().unwrap();
+    length
+}
+
+struct SliceReader<'a> {
+    data: &'a [u8],
+}
+impl StreamReader for SliceReader<'_> {
+    type Error = ();
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let length = data.len().min(self.data.len());
+        data[..length].copy_from_slice(&self.data[..length]);
+        self.data = &self.data[length..];
+        Ok(length)
+    }
+}
+
+/// Parses a frame including the markers with `ProtocolReader` and returns the decoded fields.
+pub fn parse_packet(frame: &[u8]) -> Result<PacketFields, TestCaseError> {
+    let mut reader = ProtocolReader::<260>::new();
+    let completed = reader.read(&mut SliceReader { data: frame }).map_err(|err| TestCaseError::fail(std::format!("{:?}", err)))?;
+    prop_assert!(completed, "incomplete packet");
+    let packet = reader.packet().unwrap();
+    parse_packet_body(&packet)
+}
+
+fn parse_packet_body(packet: &PacketReader) -> Result<PacketFields, TestCaseError> {
+    prop_assert!(packet.verify_checksum().is_ok(), "checksum mismatch");
+    let data = packet.data().map_err(|err| TestCaseError::fail(std::format!("{:?}", err)))?;
+    prop_assert!(!data.is_empty());
+    Ok(PacketFields {
+        id: packet.id().unwrap(),
+        instruction: data[0],
+        parameters: data[1..].to_vec(),
+    })
+}
+
+/// Checks that building a packet with `PacketWriter` and parsing it with `ProtocolReader` yields the same fields.
+pub fn check_packet_round_trip(fields: &PacketFields) -> Result<(), TestCaseError> {
+    let mut buffer = [0u8; 260];
+    let length = build_packet(fields, &mut buffer);
+    let parsed = parse_packet(&buffer[..length])?;
+    prop_assert_eq!(&parsed, fields);
+    Ok(())
+}
+
+pub fn check_read_register_round_trip(id: u8, address: u8, length: u8) -> Result<(), TestCaseError> {
+    let command = ReadRegisterCommand::new(id, address, length);
+    let parsed = parse_packet(&command.raw)?;
+    prop_assert_eq!(parsed, PacketFields { id, instruction: Command::ReadRegister as u8, parameters: std::vec![address, length] });
+    Ok(())
+}
+
+pub fn check_write_register_round_trip(id: u8, address: u8, data: &[u8]) -> Result<(), TestCaseError> {
+    let command = WriteRegisterCommand::<260>::builder(id).address(address).data(data).build().unwrap();
+    let parsed = parse_packet(command.packet())?;
+    let mut parameters = std::vec![address];
+    parameters.extend_from_slice(data);
+    prop_assert_eq!(parsed, PacketFields { id, instruction: Command::WriteRegister as u8, parameters });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn packet_round_trip(fields in packet_fields()) {
+            check_packet_round_trip(&fields)?;
+        }
+
+        #[test]
+        fn read_register_round_trip((id, address, length) in read_register_fields()) {
+            check_read_register_round_trip(id, address, length)?;
+        }
+
+        #[test]
+        fn write_register_round_trip((id, address, data) in write_register_fields()) {
+            check_write_register_round_trip(id, address, &data)?;
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Thermal headroom estimation and duty-cycle advice.
+//!
+//! [`ThermalModel`] tracks the temperature and load of a servo with a first-order model: the temperature
+//! approaches a steady state proportional to the load with the thermal time constant of the motor. The
+//! measured temperature trend is tracked as well, so a wrong model still sees a servo heating up.
+//! The model predicts the temperature a horizon ahead and turns the remaining headroom into a throttle
+//! factor. [`ThermalGuard`] wraps a [`ServoControl`] and applies the factor to the commanded speeds and periods.
+
+use core::time::Duration;
+
+use crate::device::scs0009::LOAD_ENCODING;
+use crate::device::{Instant, RawLoad, ServoControl, StatusSource, Timer};
+
+const FULL_LOAD: f32 = 1023.0;
+/// Weight of a new sample in the smoothed load and temperature trend.
+const SMOOTHING: f32 = 0.2;
+/// Throttle changes smaller than this are not written to the servo.
+const THROTTLE_HYSTERESIS: f32 = 0.05;
+
+#[derive(Debug, Clone)]
+pub struct ThermalConfig {
+    /// Temperature the servo must stay below, in degC. Usually the upper temperature limit of the servo.
+    pub limit: u8,
+    /// Ambient temperature in degC.
+    pub ambient: u8,
+    /// Temperature rise above ambient the servo settles at under full load, in degC.
+    pub full_load_rise: u8,
+    /// Thermal time constant of the servo.
+    pub time_constant: Duration,
+    /// How far ahead the temperature is predicted.
+    pub horizon: Duration,
+    /// Predicted headroom below which throttling starts, in degC.
+    pub soft_margin: u8,
+    /// Lowest throttle factor applied.
+    pub min_throttle: f32,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            limit: 65,
+            ambient: 25,
+            full_load_rise: 60,
+            time_constant: Duration::from_secs(300),
+            horizon: Duration::from_secs(30),
+            soft_margin: 10,
+            min_throttle: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThermalModel {
+    config: ThermalConfig,
+    temperature: Option<f32>,
+    /// Smoothed load ratio in 0..=1.
+    load: f32,
+    /// Smoothed measured temperature trend in degC/s.
+    trend: f32,
+    last_sample: Option<Duration>,
+}
+
+impl ThermalModel {
+    pub fn new(config: ThermalConfig) -> Self {
+        Self {
+            config,
+            temperature: None,
+            load: 0.0,
+            trend: 0.0,
+            last_sample: None,
+        }
+    }
+
+    pub fn config(&self) -> &ThermalConfig {
+        &self.config
+    }
+
+    /// Feeds a sample taken at `now` on a monotonic clock.
+    pub fn observe(&mut self, now: Duration, temperature: u8, load: RawLoad) {
+        let temperature = temperature as f32;
+        let load = load.magnitude(LOAD_ENCODING) as f32 / FULL_LOAD;
+        if let (Some(previous), Some(last_sample)) = (self.temperature, self.last_sample) {
+            let elapsed = now.saturating_sub(last_sample).as_secs_f32();
+            if elapsed > 0.0 {
+                let trend = (temperature - previous) / elapsed;
+                self.trend += (trend - self.trend) * SMOOTHING;
+            }
+            self.load += (load - self.load) * SMOOTHING;
+        } else {
+            self.load = load;
+        }
+        self.temperature = Some(temperature);
+        self.last_sample = Some(now);
+    }
+
+    /// Temperature the servo settles at under the current load, in degC.
+    pub fn steady_state_temperature(&self) -> f32 {
+        self.config.ambient as f32 + self.config.full_load_rise as f32 * self.load
+    }
+
+    /// Temperature predicted `horizon` ahead, in degC. The higher of the model and the measured trend is used.
+    pub fn predicted_temperature(&self) -> Option<f32> {
+        let temperature = self.temperature?;
+        let horizon = self.config.horizon.as_secs_f32();
+        let progress = (horizon / self.config.time_constant.as_secs_f32()).min(1.0);
+        let model = temperature + (self.steady_state_temperature() - temperature) * progress;
+        let trend = temperature + self.trend * horizon;
+        Some(model.max(trend))
+    }
+
+    /// Predicted distance to the temperature limit, in degC. Negative once the limit is expected to be exceeded.
+    pub fn headroom(&self) -> Option<f32> {
+        self.predicted_temperature().map(|predicted| self.config.limit as f32 - predicted)
+    }
+
+    /// Factor to scale the commanded speeds with, from `min_throttle` to 1.
+    pub fn throttle(&self) -> f32 {
+        match self.headroom() {
+            Some(headroom) if headroom < self.config.soft_margin as f32 => {
+                (headroom / self.config.soft_margin.max(1) as f32).clamp(self.config.min_throttle, 1.0)
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+/// Wraps a servo control and throttles the commanded motion when the servo trends toward its temperature limit.
+///
+/// Each `update` feeds the temperature and load to the model. When the throttle factor changes, the last
+/// commanded speed and period are written again with the new factor. Speeds are scaled down and periods
+/// are stretched. A speed of 0, which means no limit, is replaced by `rated_speed` while throttling.
+pub struct ThermalGuard<S: ServoControl, T: Timer> {
+    inner: S,
+    model: ThermalModel,
+    rated_speed: i16,
+    epoch: T::Instant,
+    applied_throttle: f32,
+    requested_speed: Option<i16>,
+    requested_period: Option<u16>,
+}
+
+impl<S, T> ThermalGuard<S, T>
+    where S: ServoControl<Speed = i16, Period = u16> + StatusSource,
+          T: Timer,
+{
+    /// `rated_speed` is the speed register value used in place of an unlimited speed while throttling.
+    pub fn new(inner: S, config: ThermalConfig, rated_speed: i16) -> Self {
+        Self {
+            inner,
+            model: ThermalModel::new(config),
+            rated_speed,
+            epoch: T::now(),
+            applied_throttle: 1.0,
+            requested_speed: None,
+            requested_period: None,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+    pub fn model(&self) -> &ThermalModel {
+        &self.model
+    }
+    /// Throttle factor applied to the commanded motion.
+    pub fn throttle(&self) -> f32 {
+        self.applied_throttle
+    }
+
+    fn throttled_speed(&self, speed: i16) -> i16 {
+        if self.applied_throttle >= 1.0 {
+            return speed;
+        }
+        let speed = if speed == 0 { self.rated_speed } else { speed };
+        ((speed as f32 * self.applied_throttle) as i16).max(1)
+    }
+    fn throttled_period(&self, period: u16) -> u16 {
+        if self.applied_throttle >= 1.0 || period == 0 {
+            return period;
+        }
+        (period as f32 / self.applied_throttle).min(u16::MAX as f32) as u16
+    }
+}
+
+impl<S, T> ServoControl for ThermalGuard<S, T>
+    where S: ServoControl<Speed = i16, Period = u16> + StatusSource,
+          T: Timer,
+{
+    type Error = S::Error;
+    type Id = S::Id;
+    type Period = u16;
+    type Position = S::Position;
+    type Speed = i16;
+    type Torque = S::Torque;
+
+    fn min_speed(&self) -> Self::Speed {
+        self.inner.min_speed()
+    }
+    fn max_speed(&self) -> Self::Speed {
+        self.inner.max_speed()
+    }
+    fn max_period(&self) -> Self::Period {
+        self.inner.max_period()
+    }
+    fn to_speed(&self, speed: f64) -> Result<Self::Speed, Self::Error> {
+        self.inner.to_speed(speed)
+    }
+    fn to_period(&self, period: f64) -> Result<Self::Period, Self::Error> {
+        self.inner.to_period(period)
+    }
+
+    fn id(&self) -> Self::Id {
+        self.inner.id()
+    }
+    fn set_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        self.inner.set_id(id)
+    }
+
+    fn output_enable(&mut self) -> Result<(), Self::Error> {
+        self.inner.output_enable()
+    }
+    fn output_disable(&mut self) -> Result<(), Self::Error> {
+        self.inner.output_disable()
+    }
+    fn position_lower_limit(&mut self) -> Result<Self::Position, Self::Error> {
+        self.inner.position_lower_limit()
+    }
+    fn position_upper_limit(&mut self) -> Result<Self::Position, Self::Error> {
+        self.inner.position_upper_limit()
+    }
+
+    fn target_position(&mut self) -> Result<Self::Position, Self::Error> {
+        self.inner.target_position()
+    }
+    fn set_target_position(&mut self, position: Self::Position) -> Result<(), Self::Error> {
+        self.inner.set_target_position(position)
+    }
+
+    /// Returns the period as commanded, before throttling.
+    fn target_period(&mut self) -> Result<Self::Period, Self::Error> {
+        match self.requested_period {
+            Some(period) => Ok(period),
+            None => self.inner.target_period(),
+        }
+    }
+    fn set_target_period(&mut self, period: Self::Period) -> Result<(), Self::Error> {
+        self.inner.set_target_period(self.throttled_period(period))?;
+        self.requested_period = Some(period);
+        Ok(())
+    }
+
+    /// Returns the speed as commanded, before throttling.
+    fn target_speed(&mut self) -> Result<Self::Speed, Self::Error> {
+        match self.requested_speed {
+            Some(speed) => Ok(speed),
+            None => self.inner.target_speed(),
+        }
+    }
+    fn set_target_speed(&mut self, speed: Self::Speed) -> Result<(), Self::Error> {
+        self.inner.set_target_speed(self.throttled_speed(speed))?;
+        self.requested_speed = Some(speed);
+        Ok(())
+    }
+
+    fn current_position(&mut self) -> Result<Self::Position, Self::Error> {
+        self.inner.current_position()
+    }
+    fn current_speed(&mut self) -> Result<Self::Speed, Self::Error> {
+        self.inner.current_speed()
+    }
+    fn current_load(&mut self) -> Result<Self::Torque, Self::Error> {
+        self.inner.current_load()
+    }
+
+    fn update(&mut self) -> Result<(), Self::Error> {
+        self.inner.update()?;
+        if let Some(status) = self.inner.status() {
+            self.model.observe(self.epoch.elapsed(), status.temperature, status.load);
+        }
+        let throttle = self.model.throttle();
+        if (throttle - self.applied_throttle).abs() >= THROTTLE_HYSTERESIS || (throttle >= 1.0 && self.applied_throttle < 1.0) {
+            self.applied_throttle = throttle;
+            if let Some(speed) = self.requested_speed {
+                self.inner.set_target_speed(self.throttled_speed(speed))?;
+            }
+            if let Some(period) = self.requested_period {
+                self.inner.set_target_period(self.throttled_period(period))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{Scs0009ServoControl, REGISTER_CURRENT_LOAD_H, REGISTER_CURRENT_LOAD_L, REGISTER_CURRENT_TEMPERATURE, REGISTER_TARGET_SPEED_H, WORD_ORDER};
+    use crate::device::SimTimer;
+    use crate::emulator::BusEmulator;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+    extern crate std;
+
+    #[test]
+    fn test_thermal_model() {
+        let mut model = ThermalModel::new(ThermalConfig::default());
+        assert_eq!(model.throttle(), 1.0);
+        // Idle and cool.
+        model.observe(Duration::ZERO, 30, RawLoad(0));
+        assert!(model.headroom().unwrap() > 10.0);
+        assert_eq!(model.throttle(), 1.0);
+        // Full load settles far above the limit, so the prediction approaches it.
+        for second in 1..=10 {
+            model.observe(Duration::from_secs(second), 55, RawLoad(0x400 | 1023));
+        }
+        assert!(model.steady_state_temperature() > 65.0);
+        assert!(model.headroom().unwrap() < 10.0);
+        let throttle = model.throttle();
+        assert!((0.2..1.0).contains(&throttle), "{}", throttle);
+        // Past the limit, the throttle bottoms out.
+        model.observe(Duration::from_secs(11), 70, RawLoad(1023));
+        assert_eq!(model.throttle(), 0.2);
+    }
+
+    #[test]
+    fn test_thermal_model_trend() {
+        // The model expects no heating without load, but the measured trend does.
+        let mut model = ThermalModel::new(ThermalConfig::default());
+        for second in 0..20 {
+            model.observe(Duration::from_secs(second), 40 + second as u8, RawLoad(0));
+        }
+        assert!(model.predicted_temperature().unwrap() > 65.0);
+        assert!(model.throttle() < 1.0);
+    }
+
+    #[test]
+    fn test_thermal_guard() {
+        SimTimer::reset();
+        let registers = std::sync::Arc::new(std::sync::Mutex::new((30u8, 0u16)));
+        let registers_clone = registers.clone();
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn_with(BusEmulator::<1>::new(1, 1), move |emulator, _| {
+            let (temperature, load) = *registers_clone.lock().unwrap();
+            let servo = emulator.servo_mut(1).unwrap().registers_mut();
+            servo[REGISTER_CURRENT_TEMPERATURE.address as usize] = temperature;
+            servo[REGISTER_CURRENT_LOAD_H.address as usize] = (load >> 8) as u8;
+            servo[REGISTER_CURRENT_LOAD_L.address as usize] = load as u8;
+        });
+
+        let control = Scs0009ServoControl::<_, _, std::time::Instant>::new(1, master_reader, master_writer, ProtocolMasterConfig::default(), Duration::from_secs(1));
+        let mut guard = ThermalGuard::<_, SimTimer>::new(control, ThermalConfig::default(), 3000);
+        guard.set_target_speed(1000).unwrap();
+        guard.update().unwrap();
+        assert_eq!(guard.throttle(), 1.0);
+
+        *registers.lock().unwrap() = (62, 1023);
+        for _ in 0..5 {
+            SimTimer::advance(Duration::from_secs(1));
+            guard.update().unwrap();
+        }
+        let throttle = guard.throttle();
+        assert!(throttle < 1.0);
+        assert_eq!(guard.target_speed().unwrap(), 1000);
+        assert_eq!(guard.inner_mut().target_speed().unwrap(), (1000.0 * throttle) as i16);
+
+        // Cooled down, the commanded speed is restored.
+        *registers.lock().unwrap() = (30, 0);
+        for _ in 0..30 {
+            SimTimer::advance(Duration::from_secs(1));
+            guard.update().unwrap();
+        }
+        assert_eq!(guard.throttle(), 1.0);
+        assert_eq!(guard.inner_mut().target_speed().unwrap(), 1000);
+
+        let emulator = thread.stop();
+        let speed = &emulator.servo(1).unwrap().registers()[REGISTER_TARGET_SPEED_H.address as usize..][..2];
+        assert_eq!(WORD_ORDER.from_bytes([speed[0], speed[1]]), 1000);
+    }
+}
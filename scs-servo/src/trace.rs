@@ -0,0 +1,181 @@
+//! Register access traces for reproducible bug reports.
+//!
+//! [`Scs0009ServoControl::start_trace`](crate::device::scs0009::Scs0009ServoControl::start_trace) records every
+//! register transaction of a servo control into a [`RegisterTrace`], serializable with the `serde` feature. The trace
+//! can be attached to a bug report, and [`RegisterTrace::replay`] runs it again against a [`BusEmulator`] to check
+//! whether the servo or the application misbehaves.
+
+extern crate std;
+use core::time::Duration;
+use std::boxed::Box;
+use std::vec::Vec;
+
+use crate::device::Instant;
+use crate::emulator::BusEmulator;
+use crate::eventlog::Outcome;
+use crate::packet::PacketReader;
+use crate::protocol::{ReadRegisterCommand, WriteRegisterCommand, MAX_PACKET_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    /// End of the transaction since the start of the trace.
+    pub timestamp: Duration,
+    pub id: u8,
+    pub access: Access,
+    /// First register read or written.
+    pub address: u8,
+    /// Number of registers read or written.
+    pub length: u8,
+    /// The bytes written, or the bytes read if the read succeeded.
+    pub data: Vec<u8>,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegisterTrace {
+    pub entries: Vec<TraceEntry>,
+}
+
+/// A replayed transaction which turned out differently from the recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the entry in the trace.
+    pub index: usize,
+    pub outcome: Outcome,
+    /// The bytes read from the emulator.
+    pub data: Vec<u8>,
+}
+
+impl RegisterTrace {
+    /// Runs the transactions against `emulator` in order, advancing its motion model by the time between them, and
+    /// returns the ones whose outcome or data read differ from the recording. Transactions which were corrupted on
+    /// the bus are replayed as if they had been received intact.
+    pub fn replay<const MAX_SERVOS: usize>(&self, emulator: &mut BusEmulator<MAX_SERVOS>) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        let mut now = self.entries.first().map_or(Duration::ZERO, |entry| entry.timestamp);
+        for (index, entry) in self.entries.iter().enumerate() {
+            emulator.update(entry.timestamp.saturating_sub(now));
+            now = now.max(entry.timestamp);
+            let (outcome, data) = replay_entry(emulator, entry);
+            let data_differs = entry.access == Access::Read && outcome == Outcome::Ok && data != entry.data;
+            if outcome != entry.outcome || data_differs {
+                divergences.push(Divergence { index, outcome, data });
+            }
+        }
+        divergences
+    }
+}
+
+fn replay_entry<const MAX_SERVOS: usize>(emulator: &mut BusEmulator<MAX_SERVOS>, entry: &TraceEntry) -> (Outcome, Vec<u8>) {
+    let mut response = [0; MAX_PACKET_SIZE + 2];
+    let length = match entry.access {
+        Access::Read => {
+            let command = ReadRegisterCommand::new(entry.id, entry.address, entry.length);
+            emulator.handle_packet(&PacketReader::new(&command.raw[2..]), &mut response)
+        }
+        Access::Write => {
+            let Ok(command) = WriteRegisterCommand::<{ MAX_PACKET_SIZE + 2 }>::builder(entry.id).address(entry.address).data(&entry.data).build() else {
+                return (Outcome::Rejected, Vec::new());
+            };
+            emulator.handle_packet(&PacketReader::new(&command.packet()[2..]), &mut response)
+        }
+    };
+    let Some(length) = length else {
+        return (Outcome::TimedOut, Vec::new());
+    };
+    let packet = PacketReader::new(&response[2..length]);
+    match (packet.verify_checksum(), packet.data()) {
+        // The status flags precede the registers read.
+        (Ok(()), Ok(data)) if entry.access == Access::Read => (Outcome::Ok, data.get(1..).unwrap_or_default().to_vec()),
+        (Ok(()), Ok(_)) => (Outcome::Ok, Vec::new()),
+        _ => (Outcome::InvalidPacket, Vec::new()),
+    }
+}
+
+/// Records the transactions of a servo control.
+pub(crate) struct Tracer {
+    start: Box<dyn Instant + Send>,
+    trace: RegisterTrace,
+}
+
+impl Tracer {
+    pub(crate) fn new<I: Instant + Send + 'static>(start: I) -> Self {
+        Self { start: Box::new(start), trace: RegisterTrace::default() }
+    }
+    pub(crate) fn trace(&self) -> &RegisterTrace {
+        &self.trace
+    }
+    pub(crate) fn into_trace(self) -> RegisterTrace {
+        self.trace
+    }
+    pub(crate) fn record(&mut self, id: u8, access: Access, address: u8, data: &[u8], outcome: Outcome) {
+        self.trace.entries.push(TraceEntry {
+            timestamp: self.start.elapsed(),
+            id,
+            access,
+            address,
+            length: data.len().min(u8::MAX as usize) as u8,
+            data: if access == Access::Write || outcome == Outcome::Ok { data.to_vec() } else { Vec::new() },
+            outcome,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::scs0009::{Scs0009ServoControl, REGISTER_TORQUE_SWITCH};
+    use crate::device::ServoControl;
+    use crate::protocol::ProtocolMasterConfig;
+    use crate::testing::emulator_thread::EmulatorThread;
+
+    fn record() -> RegisterTrace {
+        let (thread, master_reader, master_writer) = EmulatorThread::spawn(BusEmulator::<2>::new(1, 2));
+        let config = ProtocolMasterConfig::default();
+        let mut servo = Scs0009ServoControl::<_, _, std::time::Instant>::new(1, master_reader, master_writer, config, Duration::from_millis(100));
+        assert!(servo.trace().is_none());
+        servo.start_trace();
+        servo.output_enable().unwrap();
+        servo.set_target_position(0x180).unwrap();
+        servo.limits().unwrap();
+        servo.target_position().unwrap();
+        thread.stop();
+        servo.take_trace().unwrap()
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = record();
+        let summary = trace.entries.iter().map(|entry| (entry.access, entry.address, entry.length, entry.outcome)).collect::<Vec<_>>();
+        assert_eq!(summary[..2], [
+            (Access::Write, REGISTER_TORQUE_SWITCH.address, 1, Outcome::Ok),
+            (Access::Write, 0x2a, 2, Outcome::Ok),
+        ]);
+        assert_eq!(trace.entries.last().unwrap().data, [0x01, 0x80]);
+
+        assert_eq!(trace.replay(&mut BusEmulator::<2>::new(1, 2)), []);
+        // A servo which ignores the writes reproduces differently.
+        let mut emulator = BusEmulator::<2>::new(1, 2);
+        emulator.servo_mut(1).unwrap().faults_mut().silent = true;
+        let divergences = trace.replay(&mut emulator);
+        assert_eq!(divergences.len(), trace.entries.len());
+        assert!(divergences.iter().all(|divergence| divergence.outcome == Outcome::TimedOut));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_trace_serde() {
+        let trace = record();
+        let json = serde_json::to_string(&trace).unwrap();
+        assert_eq!(serde_json::from_str::<RegisterTrace>(&json).unwrap(), trace);
+    }
+}
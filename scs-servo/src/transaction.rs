@@ -0,0 +1,157 @@
+//! Queued transactions.
+//!
+//! Configuring a servo takes a dozen reads and writes. Instead of a call and its error handling per register, callers
+//! queue the transactions in a [`TransactionQueue`] and hand it to [`ProtocolMaster::flush`], which runs them one after
+//! another and returns the result of each. A failed transaction does not hold up the ones after it. Each transaction
+//! is still a request and its response, so reading several ranges of one servo in fewer transactions is left to
+//! [`ProtocolMaster::read_register_ranges`].
+
+use core::time::Duration;
+
+use crate::device::{timeout_after, Timer};
+use crate::protocol::{DirectionControl, PacketHooks, ProtocolHandlerError, ProtocolMaster, StatsCounter, StreamReader, StreamWriter, WritePacket};
+#[cfg(feature = "async")]
+use crate::protocol::{StreamReaderAsync, StreamWriterAsync};
+
+/// A request queued in a [`TransactionQueue`].
+pub enum Transaction<'a> {
+    Ping { id: u8 },
+    /// Reads `buffer.len()` bytes starting at `address` into `buffer`.
+    Read { id: u8, address: u8, buffer: &'a mut [u8] },
+    /// Sends a WRITE command, which may be a broadcast.
+    Write { command: &'a dyn WritePacket },
+}
+
+impl Transaction<'_> {
+    pub fn id(&self) -> u8 {
+        match self {
+            Transaction::Ping { id } | Transaction::Read { id, .. } => *id,
+            Transaction::Write { command } => command.id(),
+        }
+    }
+}
+
+/// Up to `N` transactions, run in the order they were queued by [`ProtocolMaster::flush`].
+pub struct TransactionQueue<'a, const N: usize> {
+    items: [Option<Transaction<'a>>; N],
+    len: usize,
+}
+
+impl<'a, const N: usize> TransactionQueue<'a, N> {
+    pub fn new() -> Self {
+        Self { items: core::array::from_fn(|_| None), len: 0 }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+    pub fn clear(&mut self) {
+        self.items.iter_mut().for_each(|item| *item = None);
+        self.len = 0;
+    }
+
+    /// Queues `transaction` and returns its index in the results of [`ProtocolMaster::flush`], or `None` if the queue
+    /// is full.
+    pub fn push(&mut self, transaction: Transaction<'a>) -> Option<usize> {
+        if self.is_full() {
+            return None;
+        }
+        self.items[self.len] = Some(transaction);
+        self.len += 1;
+        Some(self.len - 1)
+    }
+    pub fn ping(&mut self, id: u8) -> Option<usize> {
+        self.push(Transaction::Ping { id })
+    }
+    pub fn read(&mut self, id: u8, address: u8, buffer: &'a mut [u8]) -> Option<usize> {
+        self.push(Transaction::Read { id, address, buffer })
+    }
+    pub fn write(&mut self, command: &'a dyn WritePacket) -> Option<usize> {
+        self.push(Transaction::Write { command })
+    }
+
+    fn take(&mut self) -> impl Iterator<Item = (usize, Transaction<'a>)> + '_ {
+        self.len = 0;
+        self.items.iter_mut().enumerate().filter_map(|(index, item)| item.take().map(|item| (index, item)))
+    }
+}
+
+impl<const N: usize> Default for TransactionQueue<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Result of each transaction of a queue by its index. The entries past the queued transactions are `None`.
+pub type TransactionResults<RE, WE, const N: usize> = [Option<Result<(), ProtocolHandlerError<RE, WE>>>; N];
+
+impl<const BUFFER_SIZE: usize, Stats: StatsCounter, Direction: DirectionControl, Hooks: PacketHooks> ProtocolMaster<BUFFER_SIZE, Stats, Direction, Hooks> {
+    /// Runs the transactions of `queue` in order and empties it. Each transaction gets a deadline `timeout` after it
+    /// starts, measured by `T`, and is retried as configured. Responses are checked as by the single-transaction methods.
+    pub fn flush<T: Timer, R: StreamReader, W: StreamWriter, const N: usize>(&mut self, reader: &mut R, writer: &mut W, queue: &mut TransactionQueue<'_, N>, timeout: Duration) -> TransactionResults<R::Error, W::Error, N> {
+        let mut results = core::array::from_fn(|_| None);
+        for (index, transaction) in queue.take() {
+            let result = match transaction {
+                Transaction::Ping { id } => self.ping(reader, writer, id, timeout_after::<T>(timeout)).map(|_| ()),
+                Transaction::Read { id, address, buffer } => self.read_register(reader, writer, id, address, buffer, timeout_after::<T>(timeout)).map(|_| ()),
+                Transaction::Write { command } => self.write_register(reader, writer, command, timeout_after::<T>(timeout)),
+            };
+            results[index] = Some(result);
+        }
+        results
+    }
+
+    /// Async version of [`Self::flush`].
+    #[cfg(feature = "async")]
+    pub async fn flush_async<T: Timer, R: StreamReaderAsync, W: StreamWriterAsync, const N: usize>(&mut self, reader: &mut R, writer: &mut W, queue: &mut TransactionQueue<'_, N>, timeout: Duration) -> TransactionResults<R::Error, W::Error, N> {
+        let mut results = core::array::from_fn(|_| None);
+        for (index, transaction) in queue.take() {
+            let result = match transaction {
+                Transaction::Ping { id } => self.ping_async(reader, writer, id, timeout_after::<T>(timeout)).await.map(|_| ()),
+                Transaction::Read { id, address, buffer } => self.read_register_async(reader, writer, id, address, buffer, timeout_after::<T>(timeout)).await.map(|_| ()),
+                Transaction::Write { command } => self.write_register_async(reader, writer, command, timeout_after::<T>(timeout)).await,
+            };
+            results[index] = Some(result);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use super::*;
+    use crate::protocol::{write_command_size, ProtocolMasterConfig, WriteRegisterCommand};
+
+    #[test]
+    fn test_transaction_queue_flush() {
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let (mut master_writer, slave_reader) = std::sync::mpsc::channel();
+        let (slave_writer, mut master_reader) = std::sync::mpsc::channel();
+        // Responses to the read of ID 1, the write and the second read. Nobody answers the ping of ID 2.
+        for byte in [0xff, 0xff, 0x01, 0x03, 0x00, 0x12, 0xe9, 0xff, 0xff, 0x01, 0x02, 0x00, 0xfc, 0xff, 0xff, 0x01, 0x03, 0x00, 0x34, 0xc7] {
+            slave_writer.send(byte).unwrap();
+        }
+
+        let command = WriteRegisterCommand::<{ write_command_size(1) }>::builder(0x01).address(0x28).data(&[0x01]).build().unwrap();
+        let (mut first, mut second) = ([0; 1], [0; 1]);
+        let mut queue = TransactionQueue::<4>::new();
+        assert_eq!(queue.read(0x01, 0x2a, &mut first), Some(0));
+        assert_eq!(queue.write(&command), Some(1));
+        assert_eq!(queue.read(0x01, 0x2b, &mut second), Some(2));
+        assert_eq!(queue.ping(0x02), Some(3));
+        assert!(queue.is_full());
+        assert_eq!(queue.ping(0x03), None);
+
+        let results = master.flush::<std::time::Instant, _, _, 4>(&mut master_reader, &mut master_writer, &mut queue, Duration::from_millis(10));
+        assert!(queue.is_empty());
+        assert!(matches!(results, [Some(Ok(())), Some(Ok(())), Some(Ok(())), Some(Err(ProtocolHandlerError::TimedOut))]));
+        assert_eq!((first, second), ([0x12], [0x34]));
+        assert_eq!(slave_reader.try_iter().count(), 8 + command.len() + 8 + 6);
+    }
+}
@@ -0,0 +1,92 @@
+//! [`embedded_io_async`] streams, e.g. the UART drivers of Embassy, for the async
+//! [`ProtocolMaster`](crate::protocol::ProtocolMaster).
+//!
+//! ```ignore
+//! let (tx, rx) = uart.split();
+//! let (mut reader, mut writer) = (EmbeddedIo(rx), EmbeddedIo(tx));
+//! master.read_register_async(&mut reader, &mut writer, id, address, &mut data, deadline).await?;
+//! ```
+
+use crate::protocol::{StreamReaderAsync, StreamWriterAsync};
+
+/// Stream over an [`embedded_io_async::Read`] or [`embedded_io_async::Write`].
+///
+/// Every write is flushed before it completes, so that a buffered UART has sent the whole request before the master
+/// waits for the response.
+pub struct EmbeddedIo<T>(pub T);
+
+impl<T> EmbeddedIo<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: embedded_io_async::Read> StreamReaderAsync for EmbeddedIo<T> {
+    type Error = T::Error;
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(data).await
+    }
+}
+
+impl<T: embedded_io_async::Write> StreamWriterAsync for EmbeddedIo<T> {
+    type Error = T::Error;
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        let bytes_written = self.0.write(data).await?;
+        self.0.flush().await?;
+        Ok(bytes_written)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::{ProtocolMaster, ProtocolMasterConfig};
+    use crate::testing::block_on;
+    use crate::testing::conformance::{self, READ};
+    extern crate std;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// Loopback UART which answers with the queued bytes and records whether the transmitted ones were flushed.
+    #[derive(Default)]
+    struct Uart {
+        received: VecDeque<u8>,
+        transmitted: Vec<u8>,
+        flushed: usize,
+    }
+    impl embedded_io_async::ErrorType for Uart {
+        type Error = core::convert::Infallible;
+    }
+    impl embedded_io_async::Read for Uart {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let length = buf.len().min(self.received.len());
+            for (slot, byte) in buf.iter_mut().zip(self.received.drain(..length)) {
+                *slot = byte;
+            }
+            Ok(length)
+        }
+    }
+    impl embedded_io_async::Write for Uart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.transmitted.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.flushed = self.transmitted.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_embedded_io() {
+        let mut reader = EmbeddedIo(Uart { received: READ.responses[0].iter().copied().collect(), ..Default::default() });
+        let mut writer = EmbeddedIo(Uart::default());
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+        let mut data = [0; 2];
+        block_on(master.read_register_async(&mut reader, &mut writer, 0x01, 0x38, &mut data, || false)).unwrap();
+        assert_eq!(data, [0x01, 0xff]);
+        let uart = writer.into_inner();
+        conformance::assert_request(&READ, &uart.transmitted);
+        assert_eq!(uart.flushed, uart.transmitted.len());
+    }
+}
@@ -0,0 +1,8 @@
+//! Adapters from the I/O traits of other crates to the stream traits of [`protocol`](crate::protocol).
+
+#[cfg(feature = "embedded-io-async")]
+pub mod embedded_io;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(feature = "serialport")]
+pub mod serialport;
@@ -0,0 +1,274 @@
+//! [`serialport`] ports for the blocking [`ProtocolMaster`](crate::protocol::ProtocolMaster) on desktops.
+//!
+//! The reader and the writer of a transaction are the same port. [`SerialPortStream`] owns the port and hands out
+//! [`SerialReader`]s and [`SerialWriter`]s which borrow it, so the port can still be reconfigured between
+//! transactions, e.g. to change the baud rate.
+//!
+//! The stream counts the time spent in the reads and writes of the port in [`TransportStats`], in total and per
+//! transaction, to tell the time waiting for the servos from the time the port blocks.
+//!
+//! ```ignore
+//! let port = SerialPortStream::new(serialport::new("/dev/ttyUSB0", 1_000_000).open()?)?;
+//! let (mut reader, mut writer) = port.split();
+//! master.ping(&mut reader, &mut writer, id, timeout_after::<std::time::Instant>(timeout))?;
+//! ```
+
+extern crate std;
+use core::cell::{Cell, RefCell, RefMut};
+use core::time::Duration;
+use std::boxed::Box;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Instant;
+
+use serialport::SerialPort;
+
+use crate::protocol::{ControlLine, ControlLines, StreamReader, StreamWriter};
+
+/// Time a read waits for data before it returns no data, so that the master can check its deadline.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Time spent in the reads and writes of the port.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransportStats {
+    pub writes: u32,
+    pub bytes_written: usize,
+    pub write_time: Duration,
+    pub reads: u32,
+    /// Reads which returned no data within the poll interval.
+    pub empty_reads: u32,
+    pub bytes_read: usize,
+    pub read_time: Duration,
+}
+
+impl TransportStats {
+    fn record_write(&mut self, bytes_written: usize, time: Duration) {
+        self.writes += 1;
+        self.bytes_written += bytes_written;
+        self.write_time += time;
+    }
+    fn record_read(&mut self, bytes_read: usize, time: Duration) {
+        self.reads += 1;
+        if bytes_read == 0 {
+            self.empty_reads += 1;
+        }
+        self.bytes_read += bytes_read;
+        self.read_time += time;
+    }
+}
+
+/// Called with the stats of each transaction and the time from its first write to its last read.
+pub type TransactionHook = fn(&TransportStats, Duration);
+
+pub struct SerialPortStream {
+    port: RefCell<Box<dyn SerialPort>>,
+    stats: Cell<TransportStats>,
+    hook: Cell<Option<TransactionHook>>,
+    /// Start and stats of the transaction in progress, while a hook is set.
+    transaction: Cell<Option<(Instant, TransportStats)>>,
+}
+
+impl SerialPortStream {
+    /// Sets the timeout of `port` to [`DEFAULT_POLL_INTERVAL`].
+    pub fn new(port: Box<dyn SerialPort>) -> serialport::Result<Self> {
+        let stream = Self {
+            port: RefCell::new(port),
+            stats: Cell::new(TransportStats::default()),
+            hook: Cell::new(None),
+            transaction: Cell::new(None),
+        };
+        stream.set_poll_interval(DEFAULT_POLL_INTERVAL)?;
+        Ok(stream)
+    }
+
+    /// Sets the time a read or write waits on the port. The deadline of a transaction is checked only in between, so
+    /// it is overrun by up to this interval. Shorter intervals also notice the end of a response sooner, e.g. for the
+    /// adaptive timeout of the [`Scanner`](crate::scan::Scanner).
+    pub fn set_poll_interval(&self, interval: Duration) -> serialport::Result<()> {
+        self.port.borrow_mut().set_timeout(interval)
+    }
+
+    /// The port, to configure it between transactions. Panics while a read or write is in progress.
+    pub fn port(&self) -> RefMut<'_, Box<dyn SerialPort>> {
+        self.port.borrow_mut()
+    }
+    pub fn into_inner(self) -> Box<dyn SerialPort> {
+        self.finish_transaction();
+        self.port.into_inner()
+    }
+
+    /// Time spent in the port since the stream was created or the stats were taken.
+    pub fn stats(&self) -> TransportStats {
+        self.stats.get()
+    }
+    pub fn take_stats(&self) -> TransportStats {
+        self.stats.take()
+    }
+
+    /// Sets the hook called with the stats of each transaction, or removes it with `None`. A transaction starts with
+    /// a write which follows a read, so a command and its response are one transaction.
+    pub fn set_transaction_hook(&self, hook: Option<TransactionHook>) {
+        self.finish_transaction();
+        self.hook.set(hook);
+    }
+    /// Reports the transaction in progress to the hook, e.g. after the last command.
+    pub fn finish_transaction(&self) {
+        if let (Some(hook), Some((start, stats))) = (self.hook.get(), self.transaction.take()) {
+            hook(&stats, start.elapsed());
+        }
+    }
+
+    fn record(&self, started: Instant, write: bool, bytes: usize) {
+        let time = started.elapsed();
+        let mut stats = self.stats.get();
+        let mut transaction = self.transaction.get();
+        if self.hook.get().is_some() {
+            if write && transaction.is_some_and(|(_, stats)| stats.reads > 0) {
+                self.finish_transaction();
+                transaction = None;
+            }
+            if write && transaction.is_none() {
+                transaction = Some((started, TransportStats::default()));
+            }
+        }
+        for stats in core::iter::once(&mut stats).chain(transaction.as_mut().map(|(_, stats)| stats)) {
+            if write {
+                stats.record_write(bytes, time);
+            } else {
+                stats.record_read(bytes, time);
+            }
+        }
+        self.stats.set(stats);
+        self.transaction.set(transaction);
+    }
+
+    pub fn reader(&self) -> SerialReader<'_> {
+        SerialReader { stream: self }
+    }
+    pub fn writer(&self) -> SerialWriter<'_> {
+        SerialWriter { stream: self }
+    }
+    pub fn split(&self) -> (SerialReader<'_>, SerialWriter<'_>) {
+        (self.reader(), self.writer())
+    }
+}
+
+/// Whether `err` only means that the port was not ready within the poll interval.
+fn is_not_ready(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted)
+}
+
+fn read(port: &mut impl Read, data: &mut [u8]) -> nb::Result<usize, std::io::Error> {
+    match port.read(data) {
+        Ok(bytes_read) => Ok(bytes_read),
+        Err(err) if is_not_ready(&err) => Err(nb::Error::WouldBlock),
+        Err(err) => Err(nb::Error::Other(err)),
+    }
+}
+
+fn write(port: &mut impl Write, data: &[u8]) -> nb::Result<usize, std::io::Error> {
+    match port.write(data) {
+        Ok(bytes_written) => Ok(bytes_written),
+        Err(err) if is_not_ready(&err) => Err(nb::Error::WouldBlock),
+        Err(err) => Err(nb::Error::Other(err)),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SerialReader<'a> {
+    stream: &'a SerialPortStream,
+}
+
+impl StreamReader for SerialReader<'_> {
+    type Error = std::io::Error;
+    fn read(&mut self, data: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        let started = Instant::now();
+        let result = read(&mut *self.stream.port.borrow_mut(), data);
+        self.stream.record(started, false, *result.as_ref().unwrap_or(&0));
+        result
+    }
+    /// Clears the input buffer of the port.
+    fn purge(&mut self) -> Result<(), Self::Error> {
+        Ok(self.stream.port.borrow_mut().clear(serialport::ClearBuffer::Input)?)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SerialWriter<'a> {
+    stream: &'a SerialPortStream,
+}
+
+impl StreamWriter for SerialWriter<'_> {
+    type Error = std::io::Error;
+    fn write(&mut self, data: &[u8]) -> nb::Result<usize, Self::Error> {
+        let started = Instant::now();
+        let result = write(&mut *self.stream.port.borrow_mut(), data);
+        self.stream.record(started, true, *result.as_ref().unwrap_or(&0));
+        result
+    }
+}
+
+/// The DTR and RTS lines of the port, e.g. to pulse them with
+/// [`ProtocolMaster::pulse_control_line`](crate::protocol::ProtocolMaster::pulse_control_line).
+impl ControlLines for SerialWriter<'_> {
+    type Error = serialport::Error;
+    fn set_line(&mut self, line: ControlLine, asserted: bool) -> Result<(), Self::Error> {
+        let mut port = self.stream.port.borrow_mut();
+        match line {
+            ControlLine::Dtr => port.write_data_terminal_ready(asserted),
+            ControlLine::Rts => port.write_request_to_send(asserted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Port which answers each call with the next result.
+    struct Port(VecDeque<std::io::Result<usize>>);
+    impl Read for Port {
+        fn read(&mut self, _data: &mut [u8]) -> std::io::Result<usize> {
+            self.0.pop_front().unwrap()
+        }
+    }
+    impl Write for Port {
+        fn write(&mut self, _data: &[u8]) -> std::io::Result<usize> {
+            self.0.pop_front().unwrap()
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let mut port = Port(VecDeque::from([
+            Ok(3),
+            Err(ErrorKind::TimedOut.into()),
+            Err(ErrorKind::Interrupted.into()),
+            Err(ErrorKind::BrokenPipe.into()),
+        ]));
+        assert!(matches!(read(&mut port, &mut [0; 4]), Ok(3)));
+        assert!(matches!(read(&mut port, &mut [0; 4]), Err(nb::Error::WouldBlock)));
+        assert!(matches!(write(&mut port, &[0; 4]), Err(nb::Error::WouldBlock)));
+        assert!(matches!(read(&mut port, &mut [0; 4]), Err(nb::Error::Other(err)) if err.kind() == ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = TransportStats::default();
+        stats.record_write(8, Duration::from_micros(100));
+        stats.record_read(0, Duration::from_millis(1));
+        stats.record_read(6, Duration::from_micros(300));
+        assert_eq!(stats, TransportStats {
+            writes: 1,
+            bytes_written: 8,
+            write_time: Duration::from_micros(100),
+            reads: 2,
+            empty_reads: 1,
+            bytes_read: 6,
+            read_time: Duration::from_micros(1300),
+        });
+    }
+}
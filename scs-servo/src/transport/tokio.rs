@@ -0,0 +1,103 @@
+//! [`tokio::io`] streams, e.g. the ports of `tokio-serial`, for the async
+//! [`ProtocolMaster`](crate::protocol::ProtocolMaster).
+//!
+//! ```ignore
+//! let port = tokio_serial::new("/dev/ttyUSB0", 1_000_000).open_native_async()?;
+//! let (reader, writer) = tokio::io::split(port);
+//! let (mut reader, mut writer) = (TokioIo::new(reader), TokioIo::new(writer));
+//! master.read_register_async(&mut reader, &mut writer, id, address, &mut data, timeout_after::<std::time::Instant>(timeout)).await?;
+//! ```
+
+extern crate std;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::task::Poll;
+use core::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::protocol::{StreamReaderAsync, StreamWriterAsync};
+
+/// Time a read waits for data before it returns no data, so that the master can check its deadline.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Stream over an [`AsyncRead`] or [`AsyncWrite`]. Needs a tokio runtime with the time driver enabled.
+///
+/// Every write is flushed before it completes, so that the whole request is sent before the master waits for the
+/// response.
+pub struct TokioIo<T> {
+    inner: T,
+    poll_interval: Duration,
+}
+
+impl<T> TokioIo<T> {
+    pub fn new(inner: T) -> Self {
+        Self::with_poll_interval(inner, DEFAULT_POLL_INTERVAL)
+    }
+    pub fn with_poll_interval(inner: T, poll_interval: Duration) -> Self {
+        Self { inner, poll_interval }
+    }
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> StreamReaderAsync for TokioIo<T> {
+    type Error = std::io::Error;
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, Self::Error> {
+        let inner = &mut self.inner;
+        let read = poll_fn(|context| {
+            let mut buffer = ReadBuf::new(data);
+            match Pin::new(&mut *inner).poll_read(context, &mut buffer) {
+                Poll::Ready(result) => Poll::Ready(result.map(|()| buffer.filled().len())),
+                Poll::Pending => Poll::Pending,
+            }
+        });
+        tokio::time::timeout(self.poll_interval, read).await.unwrap_or(Ok(0))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> StreamWriterAsync for TokioIo<T> {
+    type Error = std::io::Error;
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        let bytes_written = poll_fn(|context| Pin::new(&mut self.inner).poll_write(context, data)).await?;
+        poll_fn(|context| Pin::new(&mut self.inner).poll_flush(context)).await?;
+        Ok(bytes_written)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::timeout_after;
+    use crate::protocol::{ProtocolHandlerError, ProtocolMaster, ProtocolMasterConfig};
+    use crate::testing::conformance::{self, READ};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_tokio_io() {
+        let (master, mut slave) = tokio::io::duplex(64);
+        let (reader, writer) = tokio::io::split(master);
+        let (mut reader, mut writer) = (TokioIo::new(reader), TokioIo::new(writer));
+        let mut master = ProtocolMaster::<16>::new(ProtocolMasterConfig::default());
+
+        let slave = tokio::spawn(async move {
+            let mut request = [0; 8];
+            slave.read_exact(&mut request).await.unwrap();
+            conformance::assert_request(&READ, &request);
+            slave.write_all(READ.responses[0]).await.unwrap();
+            slave
+        });
+        let mut data = [0; 2];
+        master.read_register_async(&mut reader, &mut writer, 0x01, 0x38, &mut data, timeout_after::<std::time::Instant>(Duration::from_secs(1))).await.unwrap();
+        assert_eq!(data, [0x01, 0xff]);
+
+        // A read which stays pending does not keep the master from its deadline.
+        let _slave = slave.await.unwrap();
+        let result = master.read_register_async(&mut reader, &mut writer, 0x01, 0x38, &mut data, timeout_after::<std::time::Instant>(Duration::from_millis(50))).await;
+        assert!(matches!(result, Err(ProtocolHandlerError::TimedOut)));
+    }
+}